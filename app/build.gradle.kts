@@ -10,7 +10,7 @@ android {
 
     defaultConfig {
         applicationId = "org.linebender.vellocomposeapp"
-        minSdk = 30
+        minSdk = 33
         targetSdk = 34
         versionCode = 1
         versionName = "1.0"
@@ -22,6 +22,8 @@ import androidx.compose.runtime.getValue
 import androidx.compose.ui.Modifier
 import androidx.compose.ui.tooling.preview.Preview
 import androidx.compose.ui.unit.dp
+import org.linebender.vello.LogLevel
+import org.linebender.vello.LoggingConfig
 import org.linebender.vello.Vello
 import org.linebender.vello.compose.VariableFontsVelloSurface
 import org.linebender.vello.compose.VelloContext
@@ -32,7 +34,9 @@ class MainActivity : ComponentActivity() {
     override fun onCreate(savedInstanceState: Bundle?) {
         super.onCreate(savedInstanceState)
         enableEdgeToEdge()
-        Vello.initRust()
+        Vello.configureLogging(
+            LoggingConfig(targetLevels = mapOf("vello_jni" to LogLevel.Debug))
+        )
         setContent {
             VelloComposeTheme {
                 VelloContext {
@@ -59,7 +63,6 @@ fun Greeting(name: String, modifier: Modifier = Modifier) {
 @Preview(showBackground = true)
 @Composable
 fun GreetingPreview() {
-    Vello.initRust()
     VelloComposeTheme {
         Greeting("Android Testing again")
     }
@@ -78,78 +81,78 @@ fun CustomView() {
         label = "fontWeight"
     )
     VariableFontsVelloSurface(
-        "00:00:10", 70f, modifier = Modifier
+        "00:00:10", 24f, modifier = Modifier
             .fillMaxWidth()
             .height(60.dp)
     )
     Text("In Between")
     VariableFontsVelloSurface(
-        "00:00:20", 70f, weight, modifier = Modifier
+        "00:00:20", 24f, weight, modifier = Modifier
             .fillMaxWidth()
             .height(60.dp)
     )
     VariableFontsVelloSurface(
-        "00:00:20", 70f, weight, modifier = Modifier
+        "00:00:20", 24f, weight, modifier = Modifier
             .fillMaxWidth()
             .height(60.dp)
     )
     VariableFontsVelloSurface(
-        "00:00:20", 70f, weight, modifier = Modifier
+        "00:00:20", 24f, weight, modifier = Modifier
             .fillMaxWidth()
             .height(60.dp)
     )
     VariableFontsVelloSurface(
-        "00:00:20", 70f, weight, modifier = Modifier
+        "00:00:20", 24f, weight, modifier = Modifier
             .fillMaxWidth()
             .height(60.dp)
     )
     VariableFontsVelloSurface(
-        "00:00:20", 70f, weight, modifier = Modifier
+        "00:00:20", 24f, weight, modifier = Modifier
             .fillMaxWidth()
             .height(60.dp)
     )
     VariableFontsVelloSurface(
-        "00:00:20", 70f, weight, modifier = Modifier
+        "00:00:20", 24f, weight, modifier = Modifier
             .fillMaxWidth()
             .height(60.dp)
     )
     VariableFontsVelloSurface(
-        "00:00:20", 70f, weight, modifier = Modifier
+        "00:00:20", 24f, weight, modifier = Modifier
             .fillMaxWidth()
             .height(60.dp)
     )
     VariableFontsVelloSurface(
-        "00:00:20", 70f, weight, modifier = Modifier
+        "00:00:20", 24f, weight, modifier = Modifier
             .fillMaxWidth()
             .height(60.dp)
     )
     VariableFontsVelloSurface(
-        "00:00:20", 70f, weight, modifier = Modifier
+        "00:00:20", 24f, weight, modifier = Modifier
             .fillMaxWidth()
             .height(60.dp)
     )
     VariableFontsVelloSurface(
-        "00:00:20", 70f, weight, modifier = Modifier
+        "00:00:20", 24f, weight, modifier = Modifier
             .fillMaxWidth()
             .height(60.dp)
     )
     VariableFontsVelloSurface(
-        "00:00:20", 70f, weight, modifier = Modifier
+        "00:00:20", 24f, weight, modifier = Modifier
             .fillMaxWidth()
             .height(60.dp)
     )
     VariableFontsVelloSurface(
-        "00:00:20", 70f, weight, modifier = Modifier
+        "00:00:20", 24f, weight, modifier = Modifier
             .fillMaxWidth()
             .height(60.dp)
     )
     VariableFontsVelloSurface(
-        "00:00:20", 70f, weight, modifier = Modifier
+        "00:00:20", 24f, weight, modifier = Modifier
             .fillMaxWidth()
             .height(60.dp)
     )
     VariableFontsVelloSurface(
-        "00:00:20", 70f, weight, modifier = Modifier
+        "00:00:20", 24f, weight, modifier = Modifier
             .fillMaxWidth()
             .height(60.dp)
     )
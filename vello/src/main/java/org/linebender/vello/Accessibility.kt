@@ -0,0 +1,74 @@
+package org.linebender.vello
+
+import android.graphics.RectF
+
+/**
+ * What an [AccessibilityNode] represents.
+ *
+ * The order must match `AccessRole` in `accessibility.rs`.
+ */
+enum class AccessibilityRole {
+    /** Text which can be read and selected, but not edited. */
+    Label,
+
+    /** Editable text. */
+    TextInput,
+
+    /** One line of the text of its parent. */
+    TextRun,
+}
+
+/**
+ * Something an accessibility service can ask an [AccessibilityNode] to do.
+ *
+ * The order must match `AccessAction` in `accessibility.rs`.
+ */
+enum class AccessibilityAction {
+    Focus,
+    SetSelection,
+    SetText,
+}
+
+/**
+ * A node of the accessibility tree of a [VelloSurface], as read by
+ * [VelloSurface.accessibilityTree].
+ *
+ * [bounds] are in pixels from the top left of the surface. [textStart] and [textEnd] are the
+ * indices of [text] in the text of the whole surface.
+ */
+data class AccessibilityNode(
+    val role: AccessibilityRole,
+    val text: String,
+    val bounds: RectF,
+    val textStart: Int,
+    val textEnd: Int,
+    /** The anchor and focus of the selection, if this node shows one. */
+    val selection: Pair<Int, Int>?,
+    val actions: Set<AccessibilityAction>,
+    val children: List<AccessibilityNode>,
+) {
+    /** Called by Rust, which can't easily create Kotlin collections. */
+    internal constructor(
+        role: Int,
+        text: String,
+        left: Float,
+        top: Float,
+        right: Float,
+        bottom: Float,
+        textStart: Int,
+        textEnd: Int,
+        anchor: Int,
+        focus: Int,
+        actions: Int,
+        children: Array<AccessibilityNode>,
+    ) : this(
+        AccessibilityRole.entries[role],
+        text,
+        RectF(left, top, right, bottom),
+        textStart,
+        textEnd,
+        if (anchor >= 0) Pair(anchor, focus) else null,
+        AccessibilityAction.entries.filter { actions and (1 shl it.ordinal) != 0 }.toSet(),
+        children.toList(),
+    )
+}
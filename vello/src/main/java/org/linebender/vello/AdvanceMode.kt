@@ -0,0 +1,29 @@
+package org.linebender.vello
+
+/**
+ * How the glyphs of a [VariableFontSurface] are spaced.
+ *
+ * Animating axes such as `"wght"` changes how wide each glyph is, so with [Natural] spacing the
+ * text moves as the axes animate. The other modes give each glyph a cell which stays the same
+ * width, with the glyph centred in it.
+ *
+ * Selections and hit testing still use the natural spacing.
+ */
+sealed class AdvanceMode {
+    /** Use the widths at the current axis values. */
+    data object Natural : AdvanceMode()
+
+    /**
+     * Use the widths the glyphs would have with the [axes] set to these values, such as
+     * `mapOf("wght" to 400f)`.
+     *
+     * Axes which aren't listed keep their current values, so shouldn't be animated.
+     */
+    data class Reference(val axes: Map<String, Float>) : AdvanceMode()
+
+    /**
+     * Give every glyph the width of the widest glyph of the text, at the minimum or maximum of
+     * any axis.
+     */
+    data object Monospace : AdvanceMode()
+}
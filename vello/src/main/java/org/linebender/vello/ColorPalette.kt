@@ -0,0 +1,20 @@
+package org.linebender.vello
+
+/**
+ * The colours which colour glyphs, such as emoji, are drawn with.
+ *
+ * [index] chooses a palette from the font's `CPAL` table, and fonts which don't have that
+ * palette use their first one. Each entry of [overrides] replaces the colour of the palette
+ * entry at its key with an ARGB colour int.
+ */
+data class ColorPalette(
+    val index: Int = 0,
+    val overrides: Map<Int, Int> = emptyMap(),
+) {
+    init {
+        require(index in 0..0xFFFF) { "Palette index must fit in 16 bits, got $index" }
+        require(overrides.keys.all { it in 0 until 0xFFFF }) {
+            "Palette entries must be below 0xFFFF, got ${overrides.keys}"
+        }
+    }
+}
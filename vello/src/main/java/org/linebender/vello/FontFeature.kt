@@ -0,0 +1,27 @@
+package org.linebender.vello
+
+/**
+ * An OpenType feature setting, such as `FontFeature("pnum")` for proportional figures.
+ *
+ * [value] is usually 1 to enable the feature or 0 to disable it, but some features (such as
+ * stylistic alternates) take larger values.
+ *
+ * The features supported by text surfaces are listed by [Vello.supportedFontFeatures].
+ */
+data class FontFeature(val tag: String, val value: Int = 1) {
+    init {
+        require(tag.length == 4 && tag.all { it.code < 128 }) {
+            "Feature tags must be four ASCII characters, got \"$tag\""
+        }
+        require(value >= 0) { "Feature values must not be negative, got $value" }
+    }
+
+    /** The tag packed as an OpenType `Tag`. */
+    internal val packed: Int
+        get() = tag.fold(0) { acc, char -> (acc shl 8) or char.code }
+
+    internal companion object {
+        fun unpack(tag: Int): String =
+            String(CharArray(4) { ((tag shr (24 - it * 8)) and 0xff).toChar() })
+    }
+}
@@ -0,0 +1,36 @@
+package org.linebender.vello
+
+/**
+ * A frame which Vello's render thread has submitted and presented.
+ *
+ * @property frameId The id of the frame, which matches [FrameTimings.frameId]. This is -1 if
+ * nothing was rendered for [requestIds], such as when all of their surfaces had been destroyed.
+ * @property requestIds The render requests from Kotlin which this frame completed. This is empty
+ * for frames which the render thread produced by itself, such as for animations.
+ * @property surfaceIds The surfaces which were presented in this frame.
+ * @property coalesced Whether several requests were merged into this frame, so that the
+ * intermediate states of their surfaces were never shown.
+ * @property presentNanos The CPU time spent presenting the surfaces, or -1 if nothing was rendered.
+ * @property totalNanos The CPU time of the whole frame, or -1 if nothing was rendered.
+ */
+class PresentedFrame(
+    val frameId: Long,
+    val requestIds: List<Long>,
+    val surfaceIds: List<Long>,
+    val coalesced: Boolean,
+    val presentNanos: Long,
+    val totalNanos: Long,
+) {
+    override fun toString(): String =
+        "PresentedFrame(frameId=$frameId, requestIds=$requestIds, surfaceIds=$surfaceIds, " +
+                "coalesced=$coalesced, presentNanos=$presentNanos, totalNanos=$totalNanos)"
+}
+
+/**
+ * Receives each frame presented by Vello.
+ *
+ * This is called on Vello's render thread, so must be thread safe and should return quickly.
+ */
+fun interface FramePresentedListener {
+    fun onFramePresented(frame: PresentedFrame)
+}
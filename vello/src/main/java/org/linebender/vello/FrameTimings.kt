@@ -0,0 +1,84 @@
+package org.linebender.vello
+
+/**
+ * A part of a frame rendered by Vello, which is timed.
+ *
+ * The order must match `Phase` in `stats.rs`.
+ */
+enum class FramePhase {
+    /** Building the scene of each surface, and combining them into a single atlas scene. */
+    SceneBuild,
+
+    /** Encoding and submitting the Vello render of the atlas. */
+    Render,
+
+    /** Acquiring each surface's next texture, and encoding the copy from the atlas into it. */
+    Blit,
+
+    /** Submitting the blit commands. */
+    Submit,
+
+    /** Presenting each surface. */
+    Present,
+
+    /** The CPU time of the whole frame. */
+    Total,
+
+    /**
+     * The GPU time from the start of the Vello render until the end of the blits.
+     *
+     * Only available where the device supports timestamp queries.
+     */
+    Gpu,
+}
+
+/**
+ * A summary of the durations of a [FramePhase] over recent frames.
+ */
+data class PhaseStatistics(
+    val count: Int,
+    val meanNanos: Long,
+    val p50Nanos: Long,
+    val p90Nanos: Long,
+    val p99Nanos: Long,
+    val maxNanos: Long,
+)
+
+/**
+ * The timing statistics of recent frames, from [Vello.frameStatistics].
+ */
+class FrameStatistics internal constructor(private val phases: List<PhaseStatistics>) {
+    operator fun get(phase: FramePhase): PhaseStatistics = phases[phase.ordinal]
+
+    override fun toString(): String =
+        FramePhase.entries.joinToString(prefix = "FrameStatistics(", postfix = ")") {
+            "$it=${get(it)}"
+        }
+}
+
+/**
+ * The timings of a single frame, reported to a [FrameTimingListener].
+ *
+ * GPU timings are read back asynchronously, so [gpuNanos] is the GPU time of frame [gpuFrameId],
+ * which is generally an earlier frame. Both are -1 if no GPU timing became available.
+ */
+data class FrameTimings(
+    val frameId: Long,
+    val sceneBuildNanos: Long,
+    val renderNanos: Long,
+    val blitNanos: Long,
+    val submitNanos: Long,
+    val presentNanos: Long,
+    val totalNanos: Long,
+    val gpuFrameId: Long,
+    val gpuNanos: Long,
+)
+
+/**
+ * Receives the timings of each frame rendered by Vello.
+ *
+ * This is called on Vello's render thread, so must be thread safe and should return quickly.
+ */
+fun interface FrameTimingListener {
+    fun onFrame(timings: FrameTimings)
+}
@@ -0,0 +1,48 @@
+package org.linebender.vello
+
+/**
+ * The kind of a [GpuEvent].
+ *
+ * The order must match `GpuEvent::kind` in `recovery.rs`.
+ */
+enum class GpuEventKind {
+    /** A surface was outdated or lost, and was reconfigured. [GpuEvent.id] is the surface's id. */
+    SurfaceReconfigured,
+
+    /**
+     * A surface wasn't updated in a frame, so will be rendered again.
+     * [GpuEvent.id] is the surface's id.
+     */
+    FrameSkipped,
+
+    /**
+     * A graphics device was lost, so will be recreated before the next frame.
+     *
+     * [GpuEvent.id] is the device's index, and [GpuEvent.message] is the reason it was lost.
+     */
+    DeviceLost,
+
+    /** A lost device was recreated, and its surfaces will be rendered again. */
+    DeviceRecovered,
+
+    /** Recreating a lost device failed, which will be retried. */
+    DeviceRecoveryFailed,
+}
+
+/**
+ * An event in Vello's recovery from a lost surface or graphics device.
+ */
+data class GpuEvent(
+    val kind: GpuEventKind,
+    val id: Long,
+    val message: String?,
+)
+
+/**
+ * Receives the [GpuEvent]s of Vello.
+ *
+ * This is called on Vello's render thread, so must be thread safe and should return quickly.
+ */
+fun interface GpuEventListener {
+    fun onEvent(event: GpuEvent)
+}
@@ -0,0 +1,40 @@
+package org.linebender.vello
+
+/**
+ * The verbosity of logs from Vello's Rust code.
+ *
+ * The order must match `log::LevelFilter`.
+ */
+enum class LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+/**
+ * How Vello's logs are written to logcat, passed to [Vello.configureLogging].
+ */
+data class LoggingConfig(
+    /** The level of logs from targets which aren't in [targetLevels]. */
+    val defaultLevel: LogLevel = LogLevel.Info,
+    /**
+     * Levels for specific log targets, which are generally Rust module paths such as
+     * `vello_jni::ffi` or `wgpu_core`.
+     *
+     * These also apply within that module, with the most specific target taking priority.
+     */
+    val targetLevels: Map<String, LogLevel> = emptyMap(),
+    /** The logcat tag to use for all logs, or null to use the module path of each log. */
+    val tag: String? = null,
+    /**
+     * Whether to forward everything written to stdout and stderr into logcat.
+     *
+     * This replaces the stdout and stderr file descriptors of the whole process, so also affects
+     * other native libraries. Passing a config with this disabled after it was enabled restores
+     * the original file descriptors.
+     */
+    val forwardStdio: Boolean = false,
+)
@@ -0,0 +1,45 @@
+package org.linebender.vello
+
+import java.lang.invoke.VarHandle
+import java.nio.ByteBuffer
+import java.nio.ByteOrder
+
+/**
+ * Values of a surface which are written into memory shared with Vello's render thread, so that
+ * changing them doesn't need a JNI call.
+ *
+ * The layout and protocol must match `params.rs`: a sequence number, which is odd whilst the
+ * values are being written, followed by a float for each of [targets].
+ *
+ * The render thread applies every slot whenever any of them changes, so each slot must be [set]
+ * to the property's current value before the block is registered.
+ */
+internal class ParameterBlock(val targets: List<AnimationTarget>) {
+    init {
+        require(targets.all { it.components == 1 }) {
+            "Parameter blocks only support scalar targets"
+        }
+    }
+
+    val buffer: ByteBuffer =
+        ByteBuffer.allocateDirect(4 * (1 + targets.size)).order(ByteOrder.nativeOrder())
+
+    private var sequence = 0
+
+    /**
+     * Set the value of the target at [index] in [targets].
+     *
+     * This must only be called from one thread at a time.
+     */
+    fun set(index: Int, value: Float) {
+        sequence += 1
+        buffer.putInt(0, sequence)
+        // The render thread must see the odd sequence number before any of the new value.
+        VarHandle.storeStoreFence()
+        buffer.putFloat(4 * (1 + index), value)
+        // And all of the new value before the even sequence number.
+        VarHandle.releaseFence()
+        sequence += 1
+        buffer.putInt(0, sequence)
+    }
+}
@@ -0,0 +1,38 @@
+package org.linebender.vello
+
+/**
+ * The kind of a pointer event sent to a [VelloSurface].
+ *
+ * The order must match `PointerEventKind` in `input.rs`.
+ */
+enum class PointerEventKind {
+    Down,
+    Move,
+    Up,
+
+    /**
+     * The gesture was cancelled (such as by a parent scrolling), so the pointer is no longer
+     * interacting with the surface.
+     */
+    Cancel,
+}
+
+/**
+ * What was under a pointer when it changed over a [VelloSurface].
+ *
+ * [x] and [y] are in pixels from the top left of the surface.
+ */
+data class PointerHit(
+    val kind: PointerEventKind,
+    val pointerId: Long,
+    val x: Float,
+    val y: Float,
+    /**
+     * What was under the pointer, or null if there was nothing (or the event was a
+     * [PointerEventKind.Cancel]).
+     *
+     * For a [VariableFontSurface], this is the index in [VariableFontSurface.text] of the start of
+     * the glyph cluster under the pointer.
+     */
+    val hitId: Long?,
+)
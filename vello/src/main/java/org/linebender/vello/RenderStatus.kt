@@ -0,0 +1,31 @@
+package org.linebender.vello
+
+/**
+ * The outcome of [Vello.renderAndWait].
+ *
+ * The order must match `RenderStatus` in `command.rs`.
+ */
+enum class RenderStatus {
+    /** Every changed surface was presented, and the GPU has finished rendering them. */
+    Success,
+
+    /**
+     * The frame wasn't finished within the timeout.
+     *
+     * It will still be rendered, and reported to [Vello.framePresentedListener].
+     */
+    Timeout,
+
+    /** A changed surface wasn't presented, such as because it had been destroyed. */
+    SurfaceLost,
+
+    /** A graphics device was lost, so the frame may not have been rendered. */
+    DeviceLost,
+
+    /**
+     * The render thread stopped before finishing the frame, so it won't be rendered.
+     *
+     * The frame isn't reported to [Vello.framePresentedListener].
+     */
+    Stopped,
+}
@@ -0,0 +1,31 @@
+package org.linebender.vello
+
+/**
+ * Why Vello couldn't render to a surface.
+ *
+ * The order must match `CreateSurfaceError` in `context.rs`.
+ */
+enum class SurfaceCreationError {
+    /** The platform refused to create a graphics surface for the window. */
+    CreateSurface,
+
+    /** No GPU could present to the surface. */
+    NoCompatibleDevice,
+
+    /** The surface doesn't support any of the texture formats which Vello can render to. */
+    UnsupportedFormat,
+
+    /** The surface doesn't support any alpha modes which Vello can use. */
+    UnsupportedAlphaMode,
+}
+
+/**
+ * Thrown by [Vello.createSurface] when no configuration of the surface works.
+ */
+class SurfaceCreationException(val error: SurfaceCreationError, message: String) :
+    Exception(message) {
+    /** Called from Rust, with the index of the [SurfaceCreationError]. */
+    @Suppress("unused")
+    private constructor(error: Int, message: String) :
+            this(SurfaceCreationError.entries[error], message)
+}
@@ -0,0 +1,19 @@
+package org.linebender.vello
+
+/**
+ * The base direction of each paragraph of a [VariableFontSurface].
+ *
+ * This decides the order of runs of text in different directions, such as an English word in a
+ * Hebrew sentence, and which side the lines are aligned to.
+ *
+ * The order must match `TextDirection` in `direction.rs`.
+ */
+enum class TextDirection {
+    /**
+     * Use the direction of the first letter of each paragraph which has a direction, as in the
+     * Unicode Bidirectional Algorithm. Paragraphs without any are left to right.
+     */
+    Auto,
+    Ltr,
+    Rtl,
+}
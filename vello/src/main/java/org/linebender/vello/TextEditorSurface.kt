@@ -0,0 +1,319 @@
+package org.linebender.vello
+
+import android.text.InputType
+import android.view.KeyEvent
+import android.view.View
+import android.view.inputmethod.BaseInputConnection
+import android.view.inputmethod.EditorInfo
+import android.view.inputmethod.ExtractedText
+import android.view.inputmethod.ExtractedTextRequest
+import android.view.inputmethod.InputConnection
+import android.view.inputmethod.InputMethodManager
+
+/**
+ * A key which edits the text of a [TextEditorSurface], or moves its caret.
+ *
+ * The order must match `EditKey` in `editor.rs`.
+ */
+enum class EditKey {
+    /** Delete the selection, or the grapheme before the caret. */
+    Backspace,
+
+    /** Delete the selection, or the grapheme after the caret. */
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    LineStart,
+    LineEnd,
+    TextStart,
+    TextEnd,
+    WordLeft,
+    WordRight,
+
+    /** Delete the selection, or the word before the caret. */
+    BackspaceWord,
+
+    /** Delete the selection, or the word after the caret. */
+    DeleteWord,
+
+    /** Insert a line break, if the editor is multi-line. */
+    Enter,
+    SelectAll,
+    Undo,
+    Redo,
+}
+
+/**
+ * The text of a [TextEditorSurface], as last reported by Vello's render thread.
+ *
+ * All offsets are indices into [text]. The selection started at [anchor], and the caret is at
+ * [focus]. [composingStart] and [composingEnd] are -1 if the input method isn't composing.
+ */
+data class EditorState(
+    val text: String,
+    val anchor: Int,
+    val focus: Int,
+    val composingStart: Int = -1,
+    val composingEnd: Int = -1,
+) {
+    val selectionStart: Int get() = minOf(anchor, focus)
+    val selectionEnd: Int get() = maxOf(anchor, focus)
+}
+
+/**
+ * A [VelloSurface] showing editable text.
+ *
+ * The text, selection and undo history are owned by Vello's render thread, which also draws the
+ * caret and the underline of text which is being composed. Edits are forwarded from an input
+ * method using [createInputConnection], or from hardware keys using [onKeyEvent]. Vello reports
+ * the result of each batch of edits back to [state] on the main thread.
+ *
+ * The font size is in sp, which are converted to pixels with the scale set by [setTextScale].
+ */
+class TextEditorSurface(
+    val surface: VelloSurface,
+    text: String,
+    fontSize: Float,
+    val multiline: Boolean = false,
+) {
+    /**
+     * The latest state reported by Vello.
+     *
+     * This lags behind edits which haven't been rendered yet.
+     */
+    var state: EditorState = EditorState(text, text.length, text.length)
+        private set
+
+    private var onChange: ((EditorState) -> Unit)? = null
+
+    /** The view whose input method is told about changes to [state]. */
+    private var inputView: View? = null
+
+    init {
+        surface.vello.makeTextEditor(surface.id, text, multiline, fontSize)
+        surface.vello.setEditorListener(surface.id, ::onEditorChanged)
+    }
+
+    /** Call [func] on the main thread whenever the text or selection changes. */
+    fun onChange(func: ((EditorState) -> Unit)?) {
+        onChange = func
+    }
+
+    /**
+     * Replace the composing text (or the selection) with [text].
+     *
+     * [newCursorPosition] has the same meaning as in [InputConnection.commitText].
+     */
+    fun commitText(text: String, newCursorPosition: Int = 1) {
+        edit(Vello.EDIT_COMMIT, text, newCursorPosition)
+    }
+
+    /** Replace all of the text with [text], which can be undone. */
+    fun setText(text: String) {
+        press(EditKey.SelectAll)
+        commitText(text)
+    }
+
+    /** Replace the composing text (or the selection) with [text], which is underlined. */
+    fun setComposingText(text: String, newCursorPosition: Int = 1) {
+        edit(Vello.EDIT_COMPOSE, text, newCursorPosition)
+    }
+
+    fun setComposingRegion(start: Int, end: Int) {
+        edit(Vello.EDIT_COMPOSING_REGION, null, start, end)
+    }
+
+    fun finishComposingText() {
+        edit(Vello.EDIT_FINISH_COMPOSING, null)
+    }
+
+    /** Delete [before] characters before the selection, and [after] characters after it. */
+    fun deleteSurroundingText(before: Int, after: Int) {
+        edit(Vello.EDIT_DELETE_SURROUNDING, null, before, after)
+    }
+
+    fun select(anchor: Int, focus: Int = anchor) {
+        edit(Vello.EDIT_SET_SELECTION, null, anchor, focus)
+    }
+
+    /**
+     * Move the caret to the text closest to ([x], [y]), in pixels from the top left of the
+     * surface, or extend the selection to it if [extend] is set.
+     */
+    fun selectAtPoint(x: Float, y: Float, extend: Boolean = false) {
+        surface.vello.selectAtPoint(surface.id, x, y, extend)
+        surface.vello.markDirty(surface.id)
+    }
+
+    /**
+     * Set the [density] of the display and the user's [fontScale] setting, which convert the
+     * font size to pixels. The text is laid out again if they change.
+     */
+    fun setTextScale(density: Float, fontScale: Float) {
+        surface.vello.setTextScale(surface.id, density, fontScale)
+        surface.vello.markDirty(surface.id)
+    }
+
+    /** Press [key], extending the selection rather than moving the caret if [extend] is set. */
+    fun press(key: EditKey, extend: Boolean = false) {
+        edit(Vello.EDIT_KEY, null, key.ordinal, if (extend) 1 else 0)
+    }
+
+    /**
+     * Apply a hardware key press, returning whether it was used.
+     *
+     * Printable characters are committed as text, and navigation and deletion keys are mapped
+     * to an [EditKey].
+     */
+    fun onKeyEvent(event: KeyEvent): Boolean {
+        if (event.action != KeyEvent.ACTION_DOWN) return false
+        val key = editKey(event)
+        if (key != null) {
+            press(key, event.isShiftPressed)
+            return true
+        }
+        val char = event.unicodeChar
+        if (char == 0 || event.isCtrlPressed) return false
+        commitText(String(Character.toChars(char)))
+        return true
+    }
+
+    private fun editKey(event: KeyEvent): EditKey? {
+        val ctrl = event.isCtrlPressed
+        return when (event.keyCode) {
+            KeyEvent.KEYCODE_DEL -> if (ctrl) EditKey.BackspaceWord else EditKey.Backspace
+            KeyEvent.KEYCODE_FORWARD_DEL -> if (ctrl) EditKey.DeleteWord else EditKey.Delete
+            KeyEvent.KEYCODE_DPAD_LEFT -> if (ctrl) EditKey.WordLeft else EditKey.Left
+            KeyEvent.KEYCODE_DPAD_RIGHT -> if (ctrl) EditKey.WordRight else EditKey.Right
+            KeyEvent.KEYCODE_DPAD_UP -> EditKey.Up
+            KeyEvent.KEYCODE_DPAD_DOWN -> EditKey.Down
+            KeyEvent.KEYCODE_MOVE_HOME -> if (ctrl) EditKey.TextStart else EditKey.LineStart
+            KeyEvent.KEYCODE_MOVE_END -> if (ctrl) EditKey.TextEnd else EditKey.LineEnd
+            KeyEvent.KEYCODE_ENTER, KeyEvent.KEYCODE_NUMPAD_ENTER ->
+                if (multiline) EditKey.Enter else null
+
+            KeyEvent.KEYCODE_A -> if (ctrl) EditKey.SelectAll else null
+            KeyEvent.KEYCODE_Z -> when {
+                !ctrl -> null
+                event.isShiftPressed -> EditKey.Redo
+                else -> EditKey.Undo
+            }
+
+            KeyEvent.KEYCODE_Y -> if (ctrl) EditKey.Redo else null
+            else -> null
+        }
+    }
+
+    /**
+     * Create the connection an input method uses to edit this surface, for
+     * [View.onCreateInputConnection].
+     *
+     * [view] is told about changes to the selection, so should be the view which has focus
+     * whilst editing.
+     */
+    fun createInputConnection(view: View, outAttrs: EditorInfo): InputConnection {
+        inputView = view
+        outAttrs.inputType = InputType.TYPE_CLASS_TEXT or
+                if (multiline) InputType.TYPE_TEXT_FLAG_MULTI_LINE else 0
+        outAttrs.imeOptions = if (multiline) {
+            EditorInfo.IME_ACTION_NONE
+        } else {
+            EditorInfo.IME_ACTION_DONE
+        }
+        outAttrs.initialSelStart = state.selectionStart
+        outAttrs.initialSelEnd = state.selectionEnd
+        return EditorInputConnection(view)
+    }
+
+    private fun edit(op: Int, text: String?, first: Int = 0, second: Int = 0) {
+        surface.vello.editText(surface.id, op, text, first, second)
+        surface.vello.markDirty(surface.id)
+    }
+
+    private fun onEditorChanged(newState: EditorState) {
+        state = newState
+        inputView?.let { view ->
+            val imm = view.context.getSystemService(InputMethodManager::class.java)
+            imm.updateSelection(
+                view,
+                newState.selectionStart,
+                newState.selectionEnd,
+                newState.composingStart,
+                newState.composingEnd
+            )
+        }
+        onChange?.invoke(newState)
+    }
+
+    /**
+     * Forwards the edits of an input method to Vello.
+     *
+     * Queries are answered from [state], as the text is owned by the render thread.
+     */
+    private inner class EditorInputConnection(view: View) : BaseInputConnection(view, false) {
+        override fun commitText(text: CharSequence, newCursorPosition: Int): Boolean {
+            this@TextEditorSurface.commitText(text.toString(), newCursorPosition)
+            return true
+        }
+
+        override fun setComposingText(text: CharSequence, newCursorPosition: Int): Boolean {
+            this@TextEditorSurface.setComposingText(text.toString(), newCursorPosition)
+            return true
+        }
+
+        override fun setComposingRegion(start: Int, end: Int): Boolean {
+            this@TextEditorSurface.setComposingRegion(start, end)
+            return true
+        }
+
+        override fun finishComposingText(): Boolean {
+            this@TextEditorSurface.finishComposingText()
+            return true
+        }
+
+        override fun deleteSurroundingText(beforeLength: Int, afterLength: Int): Boolean {
+            this@TextEditorSurface.deleteSurroundingText(beforeLength, afterLength)
+            return true
+        }
+
+        override fun setSelection(start: Int, end: Int): Boolean {
+            select(start, end)
+            return true
+        }
+
+        override fun sendKeyEvent(event: KeyEvent): Boolean = onKeyEvent(event)
+
+        override fun performContextMenuAction(id: Int): Boolean {
+            if (id != android.R.id.selectAll) return false
+            press(EditKey.SelectAll)
+            return true
+        }
+
+        override fun getTextBeforeCursor(n: Int, flags: Int): CharSequence {
+            val end = state.selectionStart
+            return state.text.substring(maxOf(0, end - n), end)
+        }
+
+        override fun getTextAfterCursor(n: Int, flags: Int): CharSequence {
+            val start = state.selectionEnd
+            return state.text.substring(start, minOf(state.text.length, start + n))
+        }
+
+        override fun getSelectedText(flags: Int): CharSequence? {
+            if (state.selectionStart == state.selectionEnd) return null
+            return state.text.substring(state.selectionStart, state.selectionEnd)
+        }
+
+        override fun getExtractedText(request: ExtractedTextRequest, flags: Int): ExtractedText {
+            return ExtractedText().apply {
+                text = state.text
+                startOffset = 0
+                selectionStart = state.selectionStart
+                selectionEnd = state.selectionEnd
+                this.flags = if (multiline) 0 else ExtractedText.FLAG_SINGLE_LINE
+            }
+        }
+    }
+}
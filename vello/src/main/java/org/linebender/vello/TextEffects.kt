@@ -0,0 +1,59 @@
+package org.linebender.vello
+
+/**
+ * How the corners of a [TextStroke] are drawn.
+ *
+ * The order must match `JOINS` in `effects.rs`.
+ */
+enum class StrokeJoin {
+    Bevel,
+    Miter,
+    Round,
+}
+
+/**
+ * An outline drawn over the edges of the glyphs, [width] pixels wide.
+ *
+ * [color] is an ARGB colour int.
+ */
+data class TextStroke(
+    val width: Float,
+    val color: Int,
+    val join: StrokeJoin = StrokeJoin.Miter,
+) {
+    init {
+        require(width > 0f) { "Stroke width must be positive, got $width" }
+    }
+}
+
+/**
+ * A copy of the glyphs drawn under them, moved by ([offsetX], [offsetY]) pixels and spread out
+ * over [blurRadius] pixels.
+ *
+ * [color] is an ARGB colour int.
+ */
+data class TextShadow(
+    val offsetX: Float,
+    val offsetY: Float,
+    val blurRadius: Float,
+    val color: Int,
+) {
+    init {
+        require(blurRadius >= 0f) { "Blur radius must not be negative, got $blurRadius" }
+    }
+
+    companion object {
+        /** A glow, which is a shadow directly under the glyphs. */
+        fun glow(blurRadius: Float, color: Int) = TextShadow(0f, 0f, blurRadius, color)
+    }
+}
+
+/**
+ * The outline and shadows of a [VariableFontSurface].
+ *
+ * The [shadows] are drawn in order under the glyphs, and the [stroke] over them.
+ */
+data class TextEffects(
+    val stroke: TextStroke? = null,
+    val shadows: List<TextShadow> = emptyList(),
+)
@@ -0,0 +1,20 @@
+package org.linebender.vello
+
+/**
+ * How the glyphs of a [VariableFontSurface] are drawn.
+ *
+ * The order must match `TextRendering` in `hinting.rs`.
+ */
+enum class TextRendering {
+    /** Draw smooth, unhinted outlines at their exact positions, which suits moving text. */
+    Smooth,
+
+    /**
+     * Draw hinted outlines, with baselines and glyph origins snapped to pixels, which makes small
+     * static text sharper.
+     *
+     * Whilst the font size or an axis is changing, such as during an animation, the text is drawn
+     * as with [Smooth], so that the glyphs don't jump between shapes and pixels.
+     */
+    Hinted,
+}
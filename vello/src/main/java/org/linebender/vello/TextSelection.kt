@@ -0,0 +1,9 @@
+package org.linebender.vello
+
+/**
+ * The selected text of a [VariableFontSurface], as read by [VariableFontSurface.selection].
+ *
+ * [anchor] and [focus] are indices into [VariableFontSurface.text]. The selection started at
+ * [anchor], and the caret is at [focus]; [text] is empty if they are equal.
+ */
+data class SelectedText(val anchor: Int, val focus: Int, val text: String)
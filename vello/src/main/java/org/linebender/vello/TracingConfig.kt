@@ -0,0 +1,22 @@
+package org.linebender.vello
+
+/**
+ * Where the tracing spans of Vello's render pipeline are recorded, passed to
+ * [Vello.configureTracing].
+ *
+ * Spans cover the JNI entry points, the render thread's handling of commands, and each phase of
+ * rendering a frame, with fields such as the ids and sizes of the surfaces involved.
+ */
+data class TracingConfig(
+    /** Log the duration of each span to logcat. This is verbose, so is mostly useful briefly. */
+    val logcat: Boolean = false,
+    /** Emit ATrace sections for each span, which are shown in Perfetto and systrace. */
+    val systemTrace: Boolean = true,
+    /**
+     * A file to write a trace to in the Chrome trace event format, for loading into
+     * `ui.perfetto.dev`.
+     *
+     * The trace should be completed with [Vello.finishChromeTrace].
+     */
+    val chromeTracePath: String? = null,
+)
@@ -3,8 +3,12 @@ package org.linebender.vello
 import android.view.Surface
 import androidx.compose.runtime.MonotonicFrameClock
 import androidx.compose.ui.platform.AndroidUiDispatcher
+import kotlinx.coroutines.CompletableDeferred
 import kotlinx.coroutines.CoroutineScope
 import kotlinx.coroutines.launch
+import java.nio.ByteBuffer
+import java.util.Locale
+import java.util.concurrent.atomic.AtomicInteger
 
 /**
  * The base class for a Surface controlled by Vello.
@@ -38,19 +42,51 @@ class VelloSurface internal constructor(
         this.width = width;
         this.height = height;
 //        onResize?.invoke(width, height)
-        // TODO: Reconfigure the underlying wgpu `Surface`
-        // TODO: Rerender this surface immediately
-        // This does mean that work can't be shared, but that's OK
+        // The surface is rendered again at the new size in Vello's next frame.
+        vello.resizeSurface(id, width, height)
     }
 
     fun cleanUp() {
-        // TODO: Schedule this surface for cleanup
+        vello.destroySurface(id)
     }
 
     fun onRender(onFrame: (Long) -> Unit) {
         vello.callbacks.add(Callback(onFrame, id))
     }
 
+    /**
+     * Send a pointer event over this surface to Vello, to find what is under it.
+     *
+     * The result is passed to the function set with [onPointerHit]. [x] and [y] are in pixels
+     * from the top left of the surface.
+     */
+    fun pointerEvent(
+        kind: PointerEventKind,
+        pointerId: Long,
+        x: Float,
+        y: Float,
+        pressure: Float = 1f
+    ) {
+        vello.pointerEvent(id, kind, pointerId, x, y, pressure)
+    }
+
+    /**
+     * Call [func] on the main thread with the result of each [pointerEvent].
+     */
+    fun onPointerHit(func: ((PointerHit) -> Unit)?) {
+        vello.setPointerHitListener(id, func)
+    }
+
+    /**
+     * Read the accessibility tree of this surface's content, such as to publish it as semantics.
+     *
+     * This waits for Vello's render thread, so reflects every change sent before it. Returns
+     * null if the surface has no content yet.
+     */
+    fun accessibilityTree(): AccessibilityNode? {
+        return vello.getAccessibilityTree(id)
+    }
+
     // TODO: Consider a Debug finalise to ensure that cleanup is called?
 }
 
@@ -59,7 +95,8 @@ internal data class Callback(val callback: (Long) -> Unit, val surfaceId: Long)
 /**
  * A [VelloSurface] supporting variable font input.
  *
- * Currently only supports Roboto Flex.
+ * Currently only supports Roboto Flex. [fontSize] is in sp, which are converted to pixels with
+ * the scale set by [setTextScale].
  */
 class VariableFontSurface(
     val surface: VelloSurface,
@@ -77,14 +114,19 @@ class VariableFontSurface(
     private var textChanged = false
     private var renderScheduled = false
 
+    /** The font size and weight, which are read by the render thread without a JNI call. */
+    private val parameters = ParameterBlock(listOf(AnimationTarget.FontSize, WEIGHT))
+
     fun setWeight(weight: Float) {
         fontWeight = weight
-        scheduleRender()
+        parameters.set(WEIGHT_SLOT, weight)
+        surface.vello.markDirty(surface.id)
     }
 
     fun setFontSize(size: Float) {
         fontSize = size
-        scheduleRender()
+        parameters.set(FONT_SIZE_SLOT, size)
+        surface.vello.markDirty(surface.id)
     }
 
     fun setText(newText: String) {
@@ -93,8 +135,186 @@ class VariableFontSurface(
         scheduleRender()
     }
 
+    /**
+     * Select the text between the indices [anchor] and [focus] of [text].
+     *
+     * If they are equal, a blinking caret is shown at [focus] instead.
+     */
+    fun select(anchor: Int, focus: Int = anchor) {
+        flushText()
+        surface.vello.setSelection(surface.id, anchor, focus, utf16 = true)
+        surface.vello.markDirty(surface.id)
+    }
+
+    /**
+     * Select the text between the byte offsets [anchor] and [focus] of the UTF-8 encoding of
+     * [text].
+     */
+    fun selectBytes(anchor: Int, focus: Int = anchor) {
+        flushText()
+        surface.vello.setSelection(surface.id, anchor, focus, utf16 = false)
+        surface.vello.markDirty(surface.id)
+    }
+
+    /**
+     * Move the caret to the text closest to ([x], [y]), in pixels from the top left of the
+     * surface.
+     *
+     * If [extend] is set, the text between the existing selection's anchor and the new caret
+     * is selected, such as whilst dragging.
+     */
+    fun selectAtPoint(x: Float, y: Float, extend: Boolean = false) {
+        flushText()
+        surface.vello.selectAtPoint(surface.id, x, y, extend)
+        surface.vello.markDirty(surface.id)
+    }
+
+    /** Stop showing the selection and caret. */
+    fun clearSelection() {
+        surface.vello.clearSelection(surface.id)
+        surface.vello.markDirty(surface.id)
+    }
+
+    /**
+     * Read the current selection, such as to copy it.
+     *
+     * This waits for Vello's render thread, as selections made with [selectAtPoint] are only
+     * known there. Returns null if nothing is selected and the caret isn't shown.
+     */
+    fun selection(): SelectedText? {
+        flushText()
+        return surface.vello.getSelection(surface.id)
+    }
+
+    /**
+     * Set the OpenType [features] of the text, such as `FontFeature("pnum")`.
+     *
+     * If [start] and [end] are set, the features only apply to that range of the current [text],
+     * and take precedence over those of the whole text. Otherwise, they replace the features of
+     * the whole text.
+     *
+     * @throws IllegalArgumentException if the font doesn't support one of the features, which
+     *   can be checked with [Vello.supportedFontFeatures].
+     */
+    fun setFontFeatures(features: List<FontFeature>, start: Int? = null, end: Int? = null) {
+        require((start == null) == (end == null)) { "Both ends of the span must be set" }
+        if (start != null && end != null) {
+            require(start in 0..end) { "Invalid feature span $start..$end" }
+        }
+        // Spans are relative to the text Vello has.
+        flushText()
+        surface.vello.setFontFeatures(surface.id, features, start ?: -1, end ?: -1)
+        surface.vello.markDirty(surface.id)
+    }
+
+    /**
+     * Set the [density] of the display and the user's [fontScale] setting, which convert
+     * [fontSize] to pixels, such as from Compose's `LocalDensity`.
+     */
+    fun setTextScale(density: Float, fontScale: Float) {
+        surface.vello.setTextScale(surface.id, density, fontScale)
+        surface.vello.markDirty(surface.id)
+    }
+
+    /** Set the base direction of each paragraph of the text. */
+    fun setTextDirection(direction: TextDirection) {
+        surface.vello.setTextDirection(surface.id, direction)
+        surface.vello.markDirty(surface.id)
+    }
+
+    /**
+     * Set the language the text is shaped for, such as to use the Serbian forms of Cyrillic
+     * letters, or use the default shaping of each script if [locale] is null.
+     */
+    fun setLocale(locale: Locale?) {
+        surface.vello.setLocale(surface.id, locale)
+        surface.vello.markDirty(surface.id)
+    }
+
+    /** Set whether the glyphs are hinted, which makes small static text sharper. */
+    fun setTextRendering(rendering: TextRendering) {
+        surface.vello.setTextRendering(surface.id, rendering)
+        surface.vello.markDirty(surface.id)
+    }
+
+    /** Set the outline and shadows drawn with the text, replacing any previous effects. */
+    fun setTextEffects(effects: TextEffects) {
+        surface.vello.setTextEffects(surface.id, effects)
+        surface.vello.markDirty(surface.id)
+    }
+
+    /** Set the palette of colour glyphs, such as emoji, replacing any previous palette. */
+    fun setColorPalette(palette: ColorPalette) {
+        surface.vello.setColorPalette(surface.id, palette)
+        surface.vello.markDirty(surface.id)
+    }
+
+    /** Set how the glyphs are spaced, such as to stop the text moving as its weight animates. */
+    fun setAdvanceMode(mode: AdvanceMode) {
+        surface.vello.setAdvanceMode(surface.id, mode)
+        surface.vello.markDirty(surface.id)
+    }
+
+    /** Read the accessibility tree of the text, with a child for each line. */
+    fun accessibilityTree(): AccessibilityNode? {
+        flushText()
+        return surface.accessibilityTree()
+    }
+
+    /** Send the latest [text] now, so that the render thread has it before other changes. */
+    private fun flushText() {
+        if (textChanged) {
+            textChanged = false
+            surface.vello.updateVariableFontText(surface.id, text)
+        }
+    }
+
     init {
         surface.vello.makeVariableFontSurface(surface.id, text, fontSize, fontWeight);
+        // Every slot is applied whenever any of them changes, so none can be left as zero.
+        parameters.set(FONT_SIZE_SLOT, fontSize)
+        parameters.set(WEIGHT_SLOT, fontWeight)
+        surface.vello.registerParameterBlock(surface.id, parameters)
+    }
+
+    /**
+     * Start [animation] on the render thread.
+     *
+     * This replaces any running animation of the same [AnimationTarget] on this surface.
+     * [onEnd] is called on the main thread when the animation ends, with `completed = false` if
+     * it was replaced (or its surface was destroyed) rather than running to completion.
+     * Cancelled animations do not call [onEnd].
+     *
+     * @return An id which can be passed to [cancelAnimation].
+     */
+    fun animate(animation: VelloAnimation, onEnd: ((completed: Boolean) -> Unit)? = null): Long {
+        return surface.vello.startAnimation(surface.id, animation) { end ->
+            // The last value stays in place in Rust, so update our copy to match.
+            // Otherwise, the next change to either parameter would revert it.
+            val value = end.value?.get(0)
+            if (value != null) {
+                val target = animation.target
+                if (target == AnimationTarget.FontSize) {
+                    fontSize = value
+                    parameters.set(FONT_SIZE_SLOT, value)
+                } else if (target == WEIGHT) {
+                    fontWeight = value
+                    parameters.set(WEIGHT_SLOT, value)
+                }
+            }
+            if (end.reason != AnimationEndReason.Cancelled) {
+                onEnd?.invoke(end.reason == AnimationEndReason.Completed)
+            }
+        }
+    }
+
+    /**
+     * Stop an animation started with [animate], leaving its property at its current value.
+     *
+     * [fontSize] and [fontWeight] are updated to that value once the render thread reports it.
+     */
+    fun cancelAnimation(animationId: Long) {
+        surface.vello.cancelAnimation(surface.id, animationId)
     }
 
     private fun scheduleRender() {
@@ -102,14 +322,55 @@ class VariableFontSurface(
         surface.onRender { _ ->
             renderScheduled = false
             // Marshalling text is potentially expensive, so only do it if needed.
-            if (textChanged) {
-                surface.vello.updateVariableFontText(surface.id, text)
-            }
-            surface.vello.updateVariableFontParameters(surface.id, fontSize, fontWeight)
+            flushText()
         }
         renderScheduled = true
     }
 
+    private companion object {
+        val WEIGHT = AnimationTarget.Axis("wght")
+        const val FONT_SIZE_SLOT = 0
+        const val WEIGHT_SLOT = 1
+    }
+
+}
+
+/**
+ * How Vello's render thread decides when to produce frames.
+ */
+enum class FramePacing {
+    /**
+     * Frames are produced when requested from the Compose frame clock on the main thread.
+     *
+     * A stall on the main thread will therefore also stall Vello rendering.
+     */
+    ComposeFrameClock,
+
+    /**
+     * The render thread paces itself from the display's vsync, using `AChoreographer`.
+     *
+     * Updates from Kotlin are picked up at the next vsync, and surfaces animated with
+     * [VariableFontSurface.animate] keep rendering even if the main thread is stalled.
+     */
+    RenderThreadVsync,
+}
+
+/**
+ * A debug overlay which Vello draws on top of each surface.
+ *
+ * The order must match `DebugMode` in `debug.rs`.
+ */
+enum class DebugOverlay {
+    Off,
+
+    /**
+     * Show the surface's id, size and atlas slot, the frame rate, a graph of recent CPU frame
+     * times and the occupancy of the atlas.
+     */
+    Hud,
+
+    /** Show the layout of the whole atlas, with each surface's allocation outlined. */
+    AtlasLayout,
 }
 
 /**
@@ -117,7 +378,11 @@ class VariableFontSurface(
  */
 // TODO: The rules of thread safety in Java are a bit unclear to me. This might need a whole
 // sprinkling of synchronised(lock)
-class Vello(private val coroutineScope: CoroutineScope) {
+class Vello(
+    private val coroutineScope: CoroutineScope,
+    val framePacing: FramePacing = FramePacing.ComposeFrameClock,
+    val config: VelloConfig = VelloConfig(),
+) {
     /** A pointer to the state of this renderer in Rust */
     private var state: Long = 0
 
@@ -127,16 +392,59 @@ class Vello(private val coroutineScope: CoroutineScope) {
     internal var callbacks = mutableListOf<Callback>()
     internal var scratchCallbacks = mutableListOf<Callback>()
 
-    fun createSurface(surface: Surface, width: Int, height: Int): VelloSurface {
+    /** The surfaces which have changed since the last frame, and so need to be rendered. */
+    private val dirtySurfaces = LinkedHashSet<Long>()
+
+    /** The id of the next call to `doRender`, which is reported back in [PresentedFrame]. */
+    @Volatile
+    private var nextRequestId: Long = 1
+
+    /**
+     * The number of render requests which haven't yet been presented.
+     *
+     * New requests are held back whilst this is at [MAX_REQUESTS_IN_FLIGHT], so that the main
+     * thread can't get ahead of the render thread.
+     */
+    private val requestsInFlight = AtomicInteger()
+
+    /** Waiters for the frame presenting each request id (or a later one). */
+    private val presentedWaiters = mutableListOf<Pair<Long, CompletableDeferred<PresentedFrame>>>()
+
+    private var nextAnimationId: Long = 1
+    private val animationCallbacks = HashMap<Long, (AnimationEnd) -> Unit>()
+
+    private val pointerHitListeners = HashMap<Long, (PointerHit) -> Unit>()
+    private val editorListeners = HashMap<Long, (EditorState) -> Unit>()
+
+    /**
+     * Start rendering to [surface].
+     *
+     * @param presentModes Overrides [VelloConfig.presentModes] for this surface.
+     * @throws SurfaceCreationException If Vello can't render to [surface].
+     */
+    fun createSurface(
+        surface: Surface,
+        width: Int,
+        height: Int,
+        presentModes: List<PresentMode>? = null
+    ): VelloSurface {
         val id = nextSurfaceId
         // Overflow handling: long, so overflow implausible
         nextSurfaceId += 1
-        newSurface(state, surface, id, width, height)
+        newSurface(state, surface, id, width, height, presentModes?.ordinals())
         return VelloSurface(surface, this, id, width, height)
     }
 
     init {
-        state = initialise()
+        state = initialise(
+            framePacing == FramePacing.RenderThreadVsync,
+            config.antialiasing.ordinal,
+            config.backend.ordinal,
+            config.powerPreference.ordinal,
+            config.presentModes.ordinals(),
+            config.validation,
+            config.initThreads,
+        )
         coroutineScope.launch {
             mainLoop()
         }
@@ -151,34 +459,371 @@ class Vello(private val coroutineScope: CoroutineScope) {
                 // TODO: Are there any locking requirements?
                 // TODO: If nothing to do, don't do anything
                 clock.withFrameNanos { frameTimeNanos ->
-                    val localCallbacks = callbacks
-                    callbacks = scratchCallbacks
-                    scratchCallbacks = localCallbacks
-                    while (localCallbacks.size > updatedSurfaces.size) {
+                    runCallbacks(frameTimeNanos)
+                    if (dirtySurfaces.isEmpty() ||
+                        requestsInFlight.get() >= MAX_REQUESTS_IN_FLIGHT
+                    ) {
+                        // Dirty surfaces are kept until the render thread catches up.
+                        return@withFrameNanos
+                    }
+                    while (dirtySurfaces.size > updatedSurfaces.size) {
                         var newSize = updatedSurfaces.size * 2;
-                        if (localCallbacks.size > newSize) {
-                            newSize = localCallbacks.size + updatedSurfaces.size
+                        if (dirtySurfaces.size > newSize) {
+                            newSize = dirtySurfaces.size + updatedSurfaces.size
                         }
                         updatedSurfaces = LongArray(newSize)
                     }
-                    for (i in updatedSurfaces.indices) {
-                        if (updatedSurfaces[i] == 0L) {
-                            break
-                        }
-                        updatedSurfaces[i] = 0
-                    }
-                    for (i in 0 until localCallbacks.size) {
-                        // TODO: Maybe callback can disable update for this Surface?
-                        localCallbacks[i].callback(frameTimeNanos)
-                        updatedSurfaces[i] = localCallbacks[i].surfaceId
-                    }
-                    doRender(state, updatedSurfaces, localCallbacks.size)
-                    localCallbacks.clear()
+                    dirtySurfaces.forEachIndexed { i, surfaceId -> updatedSurfaces[i] = surfaceId }
+                    doRender(state, updatedSurfaces, dirtySurfaces.size, takeRequestId())
+                    dirtySurfaces.clear()
                 }
             }
         }
     }
 
+    /** Run the pending callbacks, marking their surfaces as dirty. */
+    private fun runCallbacks(frameTimeNanos: Long) {
+        val localCallbacks = callbacks
+        callbacks = scratchCallbacks
+        scratchCallbacks = localCallbacks
+        for (i in 0 until localCallbacks.size) {
+            // TODO: Maybe callback can disable update for this Surface?
+            localCallbacks[i].callback(frameTimeNanos)
+            dirtySurfaces.add(localCallbacks[i].surfaceId)
+        }
+        localCallbacks.clear()
+    }
+
+    /** Get the id for a new render request, which is in flight until it is presented. */
+    private fun takeRequestId(): Long {
+        val requestId = nextRequestId
+        nextRequestId += 1
+        requestsInFlight.incrementAndGet()
+        return requestId
+    }
+
+    /**
+     * Render all pending changes to surfaces immediately, and block until the GPU has finished
+     * rendering them.
+     *
+     * This is intended for tests and screenshots, which need to know that the changes are
+     * visible. It must be called on the main thread, and blocks it for up to [timeoutMillis].
+     * The frame is also reported to [framePresentedListener] and [awaitFramePresented].
+     */
+    fun renderAndWait(timeoutMillis: Long = 1000): RenderStatus {
+        require(timeoutMillis >= 0) { "The timeout must not be negative" }
+        runCallbacks(System.nanoTime())
+        val surfaces = dirtySurfaces.toLongArray()
+        dirtySurfaces.clear()
+        val status = renderAndWait(state, surfaces, surfaces.size, takeRequestId(), timeoutMillis)
+        return RenderStatus.entries[status].also {
+            if (it == RenderStatus.Stopped) {
+                // The request will never be presented, so mustn't hold back later requests.
+                requestsInFlight.decrementAndGet()
+            }
+        }
+    }
+
+    internal fun startAnimation(
+        surfaceId: Long,
+        animation: VelloAnimation,
+        onEnd: (AnimationEnd) -> Unit
+    ): Long {
+        val id = nextAnimationId
+        nextAnimationId += 1
+        animationCallbacks[id] = onEnd
+        val easing = animation.easing
+        val (easingKind, easingParameters) = when (easing) {
+            VelloEasing.Linear -> Pair(EASING_LINEAR, FloatArray(0))
+            is VelloEasing.CubicBezier -> Pair(
+                EASING_CUBIC_BEZIER,
+                floatArrayOf(easing.x1, easing.y1, easing.x2, easing.y2)
+            )
+        }
+        val target = animation.target
+        val targetKind = target.ffiKind
+        val values = FloatArray(animation.keyframes.size * target.components)
+        animation.keyframes.forEachIndexed { i, keyframe ->
+            keyframe.values.copyInto(values, i * target.components)
+        }
+        startAnimation(
+            state,
+            surfaceId,
+            id,
+            targetKind,
+            if (target is AnimationTarget.Axis) target.packed else 0,
+            FloatArray(animation.keyframes.size) { animation.keyframes[it].fraction },
+            values,
+            animation.durationMillis * 1_000_000,
+            easingKind,
+            easingParameters,
+            animation.repeatMode.ordinal,
+            animation.iterations
+        )
+        return id
+    }
+
+    /**
+     * Stop the animation [animationId], whose callback is then called with the value it was
+     * left at.
+     */
+    internal fun cancelAnimation(surfaceId: Long, animationId: Long) {
+        cancelAnimation(state, surfaceId, animationId)
+    }
+
+    /**
+     * Called by Rust, on the render thread, when an animation has ended.
+     */
+    @Suppress("unused")
+    private fun onAnimationEnded(
+        surfaceId: Long,
+        animationId: Long,
+        reason: Int,
+        value: FloatArray?
+    ) {
+        val end = AnimationEnd(AnimationEndReason.entries[reason], value)
+        coroutineScope.launch {
+            animationCallbacks.remove(animationId)?.invoke(end)
+        }
+    }
+
+    internal fun pointerEvent(
+        surfaceId: Long,
+        kind: PointerEventKind,
+        pointerId: Long,
+        x: Float,
+        y: Float,
+        pressure: Float
+    ) {
+        pointerEvent(state, surfaceId, kind.ordinal, pointerId, x, y, pressure)
+    }
+
+    internal fun setPointerHitListener(surfaceId: Long, listener: ((PointerHit) -> Unit)?) {
+        if (listener == null) {
+            pointerHitListeners.remove(surfaceId)
+        } else {
+            pointerHitListeners[surfaceId] = listener
+        }
+    }
+
+    /**
+     * Called by Rust, on the render thread, with the result of each pointer event.
+     *
+     * [hitId] is -1 if there was nothing under the pointer.
+     */
+    @Suppress("unused")
+    private fun onPointerHit(
+        surfaceId: Long,
+        pointerId: Long,
+        kind: Int,
+        x: Float,
+        y: Float,
+        hitId: Long
+    ) {
+        val hit = PointerHit(
+            PointerEventKind.entries[kind],
+            pointerId,
+            x,
+            y,
+            hitId.takeIf { it >= 0 }
+        )
+        coroutineScope.launch {
+            pointerHitListeners[surfaceId]?.invoke(hit)
+        }
+    }
+
+    internal fun setEditorListener(surfaceId: Long, listener: (EditorState) -> Unit) {
+        editorListeners[surfaceId] = listener
+    }
+
+    /**
+     * Called by Rust, on the render thread, with the new state of an editor after it changed.
+     *
+     * [composingStart] and [composingEnd] are -1 if there is no composing region.
+     */
+    @Suppress("unused")
+    private fun onEditorChanged(
+        surfaceId: Long,
+        text: String,
+        anchor: Int,
+        focus: Int,
+        composingStart: Int,
+        composingEnd: Int
+    ) {
+        val state = EditorState(text, anchor, focus, composingStart, composingEnd)
+        coroutineScope.launch {
+            editorListeners[surfaceId]?.invoke(state)
+        }
+    }
+
+    /**
+     * Called on the render thread after each frame, whilst set.
+     */
+    @Volatile
+    var frameTimingListener: FrameTimingListener? = null
+        set(value) {
+            field = value
+            setFrameTimingCallbackEnabled(state, value != null)
+        }
+
+    /**
+     * Get the timing statistics of recently rendered frames.
+     */
+    fun frameStatistics(): FrameStatistics {
+        val values = LongArray(FramePhase.entries.size * STATISTICS_PER_PHASE)
+        getFrameStatistics(state, values)
+        return FrameStatistics(FramePhase.entries.map { phase ->
+            val offset = phase.ordinal * STATISTICS_PER_PHASE
+            PhaseStatistics(
+                count = values[offset].toInt(),
+                meanNanos = values[offset + 1],
+                p50Nanos = values[offset + 2],
+                p90Nanos = values[offset + 3],
+                p99Nanos = values[offset + 4],
+                maxNanos = values[offset + 5],
+            )
+        })
+    }
+
+    /**
+     * Get a histogram of the durations of [phase] in recently rendered frames.
+     *
+     * Each of the [bucketCount] buckets is [bucketWidthNanos] wide, except for the last, which
+     * also counts all longer durations.
+     */
+    fun frameTimeHistogram(phase: FramePhase, bucketWidthNanos: Long, bucketCount: Int): IntArray {
+        val buckets = IntArray(bucketCount)
+        getFrameTimeHistogram(state, phase.ordinal, bucketWidthNanos, buckets)
+        return buckets
+    }
+
+    fun resetFrameStatistics() {
+        resetFrameStatistics(state)
+    }
+
+    /**
+     * The debug overlay drawn on top of each surface.
+     *
+     * Changes are shown the next time each surface is rendered.
+     */
+    var debugOverlay: DebugOverlay = DebugOverlay.Off
+        set(value) {
+            field = value
+            setDebugOverlay(state, value.ordinal)
+        }
+
+    /**
+     * Called by Rust, on the render thread, after each frame if [frameTimingListener] is set.
+     */
+    @Suppress("unused")
+    private fun onFrameTimings(
+        frameId: Long,
+        sceneBuildNanos: Long,
+        renderNanos: Long,
+        blitNanos: Long,
+        submitNanos: Long,
+        presentNanos: Long,
+        totalNanos: Long,
+        gpuFrameId: Long,
+        gpuNanos: Long
+    ) {
+        frameTimingListener?.onFrame(
+            FrameTimings(
+                frameId,
+                sceneBuildNanos,
+                renderNanos,
+                blitNanos,
+                submitNanos,
+                presentNanos,
+                totalNanos,
+                gpuFrameId,
+                gpuNanos
+            )
+        )
+    }
+
+    /**
+     * Called on the render thread for every presented frame, whilst set.
+     *
+     * Without a listener, frames are still reported to [awaitFramePresented].
+     */
+    @Volatile
+    var framePresentedListener: FramePresentedListener? = null
+        set(value) {
+            field = value
+            setFramePresentedCallbackEnabled(state, value != null)
+        }
+
+    /**
+     * Wait until the next render request has been presented.
+     *
+     * This includes all changes to surfaces made before this is called, so can be used by tests
+     * to wait until those changes are visible. If there are no changes, this waits until the
+     * next change is presented.
+     */
+    suspend fun awaitFramePresented(): PresentedFrame {
+        val presented = CompletableDeferred<PresentedFrame>()
+        synchronized(presentedWaiters) {
+            presentedWaiters.add(Pair(nextRequestId, presented))
+        }
+        return presented.await()
+    }
+
+    /**
+     * Called by Rust, on the render thread, after each frame which completed a render request,
+     * and after every frame if [framePresentedListener] is set.
+     */
+    @Suppress("unused")
+    private fun onFramePresented(
+        frameId: Long,
+        requestIds: LongArray,
+        surfaceIds: LongArray,
+        coalesced: Boolean,
+        presentNanos: Long,
+        totalNanos: Long
+    ) {
+        requestsInFlight.addAndGet(-requestIds.size)
+        val frame = PresentedFrame(
+            frameId,
+            requestIds.toList(),
+            surfaceIds.toList(),
+            coalesced,
+            presentNanos,
+            totalNanos
+        )
+        val latestRequest = requestIds.maxOrNull()
+        if (latestRequest != null) {
+            synchronized(presentedWaiters) {
+                presentedWaiters.removeAll { (requestId, presented) ->
+                    (requestId <= latestRequest).also { if (it) presented.complete(frame) }
+                }
+            }
+        }
+        framePresentedListener?.onFramePresented(frame)
+    }
+
+    /**
+     * Called on the render thread when Vello recovers (or fails to recover) from a lost surface
+     * or graphics device.
+     */
+    @Volatile
+    var gpuEventListener: GpuEventListener? = null
+
+    /**
+     * Destroy Vello's graphics devices, as if they had been lost, to test recovery.
+     */
+    fun simulateDeviceLoss() {
+        simulateDeviceLoss(state)
+    }
+
+    /**
+     * Called by Rust, on the render thread, for each [GpuEvent].
+     */
+    @Suppress("unused")
+    private fun onGpuEvent(kind: Int, id: Long, message: String?) {
+        gpuEventListener?.onEvent(GpuEvent(GpuEventKind.entries[kind], id, message))
+    }
+
     fun cleanup() {
         // TODO: Deallocate on the Rust side.
         // TODO: Defer? cleanup until all managed VelloSurfaces are cleaned up.
@@ -188,7 +833,15 @@ class Vello(private val coroutineScope: CoroutineScope) {
 
     // This is defined in Rust code, so Kotlin doesn't know about it
     @Suppress("KotlinJniMissingFunction")
-    private external fun initialise(): Long
+    private external fun initialise(
+        selfPaced: Boolean,
+        antialiasing: Int,
+        backend: Int,
+        powerPreference: Int,
+        presentModes: IntArray,
+        validation: Boolean,
+        initThreads: Int,
+    ): Long
 
     @Suppress("KotlinJniMissingFunction")
     private external fun newSurface(
@@ -196,16 +849,45 @@ class Vello(private val coroutineScope: CoroutineScope) {
         surface: Surface,
         surfaceId: Long,
         width: Int,
-        height: Int
+        height: Int,
+        presentModes: IntArray?
     )
 
     @Suppress("KotlinJniMissingFunction")
     private external fun doRender(
         state: Long,
         updatedSurfaces: LongArray,
-        updatedSurfacesCount: Int
+        updatedSurfacesCount: Int,
+        requestId: Long
     )
 
+    @Suppress("KotlinJniMissingFunction")
+    private external fun renderAndWait(
+        state: Long,
+        updatedSurfaces: LongArray,
+        updatedSurfacesCount: Int,
+        requestId: Long,
+        timeoutMillis: Long
+    ): Int
+
+    @Suppress("KotlinJniMissingFunction")
+    private external fun resizeSurface(state: Long, surfaceId: Long, width: Int, height: Int)
+
+    internal fun resizeSurface(surfaceId: Long, width: Int, height: Int) {
+        resizeSurface(state, surfaceId, width, height)
+    }
+
+    @Suppress("KotlinJniMissingFunction")
+    private external fun destroySurface(state: Long, surfaceId: Long)
+
+    internal fun destroySurface(surfaceId: Long) {
+        // Updates which haven't been sent yet would be ignored anyway.
+        callbacks.removeAll { it.surfaceId == surfaceId }
+        pointerHitListeners.remove(surfaceId)
+        editorListeners.remove(surfaceId)
+        destroySurface(state, surfaceId)
+    }
+
     @Suppress("KotlinJniMissingFunction")
     private external fun makeVariableFontSurface(
         state: Long,
@@ -225,15 +907,242 @@ class Vello(private val coroutineScope: CoroutineScope) {
     }
 
     @Suppress("KotlinJniMissingFunction")
-    private external fun updateVariableFontParameters(
+    private external fun makeTextEditor(
         state: Long,
         surfaceId: Long,
-        fontSize: Float,
-        fontWeight: Float
+        text: String,
+        multiline: Boolean,
+        fontSize: Float
+    )
+
+    internal fun makeTextEditor(surfaceId: Long, text: String, multiline: Boolean, fontSize: Float) {
+        makeTextEditor(state, surfaceId, text, multiline, fontSize)
+    }
+
+    @Suppress("KotlinJniMissingFunction")
+    private external fun editText(
+        state: Long,
+        surfaceId: Long,
+        op: Int,
+        text: String?,
+        first: Int,
+        second: Int
     )
 
-    internal fun updateVariableFontParameters(surfaceId: Long, fontSize: Float, fontWeight: Float) {
-        updateVariableFontParameters(state, surfaceId, fontSize, fontWeight)
+    /**
+     * Edit the editor [surfaceId], where [op] is one of the `EDIT_` constants.
+     *
+     * See `Java_org_linebender_vello_Vello_editText` in `ffi.rs` for the meaning of the
+     * other parameters.
+     */
+    internal fun editText(surfaceId: Long, op: Int, text: String?, first: Int, second: Int) {
+        editText(state, surfaceId, op, text, first, second)
+    }
+
+    /**
+     * Render [surfaceId] in the next frame, without running any callbacks.
+     */
+    internal fun markDirty(surfaceId: Long) {
+        dirtySurfaces.add(surfaceId)
+    }
+
+    @Suppress("KotlinJniMissingFunction")
+    private external fun setSelection(
+        state: Long,
+        surfaceId: Long,
+        anchor: Int,
+        focus: Int,
+        utf16: Boolean
+    )
+
+    internal fun setSelection(surfaceId: Long, anchor: Int, focus: Int, utf16: Boolean) {
+        require(anchor >= 0 && focus >= 0) { "Selection offsets must not be negative" }
+        setSelection(state, surfaceId, anchor, focus, utf16)
+    }
+
+    @Suppress("KotlinJniMissingFunction")
+    private external fun selectAtPoint(
+        state: Long,
+        surfaceId: Long,
+        x: Float,
+        y: Float,
+        extend: Boolean
+    )
+
+    internal fun selectAtPoint(surfaceId: Long, x: Float, y: Float, extend: Boolean) {
+        selectAtPoint(state, surfaceId, x, y, extend)
+    }
+
+    @Suppress("KotlinJniMissingFunction")
+    private external fun clearSelection(state: Long, surfaceId: Long)
+
+    internal fun clearSelection(surfaceId: Long) {
+        clearSelection(state, surfaceId)
+    }
+
+    @Suppress("KotlinJniMissingFunction")
+    private external fun getSelection(state: Long, surfaceId: Long, offsets: IntArray): String?
+
+    internal fun getSelection(surfaceId: Long): SelectedText? {
+        val offsets = IntArray(2)
+        val text = getSelection(state, surfaceId, offsets) ?: return null
+        return SelectedText(offsets[0], offsets[1], text)
+    }
+
+    @Suppress("KotlinJniMissingFunction")
+    private external fun setFontFeatures(
+        state: Long,
+        surfaceId: Long,
+        tags: IntArray,
+        values: IntArray,
+        start: Int,
+        end: Int
+    )
+
+    internal fun setFontFeatures(surfaceId: Long, features: List<FontFeature>, start: Int, end: Int) {
+        setFontFeatures(
+            state,
+            surfaceId,
+            features.map { it.packed }.toIntArray(),
+            features.map { it.value }.toIntArray(),
+            start,
+            end
+        )
+    }
+
+    @Suppress("KotlinJniMissingFunction")
+    private external fun setAdvanceMode(
+        state: Long,
+        surfaceId: Long,
+        mode: Int,
+        tags: IntArray,
+        values: FloatArray
+    )
+
+    internal fun setAdvanceMode(surfaceId: Long, mode: AdvanceMode) {
+        val axes = (mode as? AdvanceMode.Reference)?.axes?.entries?.toList().orEmpty()
+        setAdvanceMode(
+            state,
+            surfaceId,
+            when (mode) {
+                AdvanceMode.Natural -> ADVANCE_NATURAL
+                is AdvanceMode.Reference -> ADVANCE_REFERENCE
+                AdvanceMode.Monospace -> ADVANCE_MONOSPACE
+            },
+            axes.map { AnimationTarget.Axis(it.key).packed }.toIntArray(),
+            axes.map { it.value }.toFloatArray()
+        )
+    }
+
+    @Suppress("KotlinJniMissingFunction")
+    private external fun setTextEffects(
+        state: Long,
+        surfaceId: Long,
+        strokeWidth: Float,
+        strokeJoin: Int,
+        strokeColor: Int,
+        shadowGeometry: FloatArray,
+        shadowColors: IntArray
+    )
+
+    internal fun setTextEffects(surfaceId: Long, effects: TextEffects) {
+        val stroke = effects.stroke
+        setTextEffects(
+            state,
+            surfaceId,
+            stroke?.width ?: 0f,
+            stroke?.join?.ordinal ?: 0,
+            stroke?.color ?: 0,
+            effects.shadows.flatMap { listOf(it.offsetX, it.offsetY, it.blurRadius) }
+                .toFloatArray(),
+            effects.shadows.map { it.color }.toIntArray()
+        )
+    }
+
+    @Suppress("KotlinJniMissingFunction")
+    private external fun setTextScale(
+        state: Long,
+        surfaceId: Long,
+        density: Float,
+        fontScale: Float
+    )
+
+    internal fun setTextScale(surfaceId: Long, density: Float, fontScale: Float) {
+        setTextScale(state, surfaceId, density, fontScale)
+    }
+
+    @Suppress("KotlinJniMissingFunction")
+    private external fun setTextDirection(state: Long, surfaceId: Long, direction: Int)
+
+    internal fun setTextDirection(surfaceId: Long, direction: TextDirection) {
+        setTextDirection(state, surfaceId, direction.ordinal)
+    }
+
+    @Suppress("KotlinJniMissingFunction")
+    private external fun setLocale(state: Long, surfaceId: Long, locale: String?)
+
+    internal fun setLocale(surfaceId: Long, locale: Locale?) {
+        setLocale(state, surfaceId, locale?.toLanguageTag())
+    }
+
+    @Suppress("KotlinJniMissingFunction")
+    private external fun setTextRendering(state: Long, surfaceId: Long, rendering: Int)
+
+    internal fun setTextRendering(surfaceId: Long, rendering: TextRendering) {
+        setTextRendering(state, surfaceId, rendering.ordinal)
+    }
+
+    @Suppress("KotlinJniMissingFunction")
+    private external fun setColorPalette(
+        state: Long,
+        surfaceId: Long,
+        index: Int,
+        entries: IntArray,
+        colors: IntArray
+    )
+
+    internal fun setColorPalette(surfaceId: Long, palette: ColorPalette) {
+        val overrides = palette.overrides.entries.toList()
+        setColorPalette(
+            state,
+            surfaceId,
+            palette.index,
+            overrides.map { it.key }.toIntArray(),
+            overrides.map { it.value }.toIntArray()
+        )
+    }
+
+    @Suppress("KotlinJniMissingFunction")
+    private external fun getAccessibilityTree(state: Long, surfaceId: Long): AccessibilityNode?
+
+    internal fun getAccessibilityTree(surfaceId: Long): AccessibilityNode? {
+        return getAccessibilityTree(state, surfaceId)
+    }
+
+    @Suppress("KotlinJniMissingFunction")
+    private external fun registerParameterBlock(
+        state: Long,
+        surfaceId: Long,
+        buffer: ByteBuffer,
+        targets: IntArray,
+        axisTags: IntArray
+    )
+
+    private val AnimationTarget.ffiKind: Int
+        get() = when (this) {
+            AnimationTarget.FontSize -> TARGET_FONT_SIZE
+            is AnimationTarget.Axis -> TARGET_AXIS
+            AnimationTarget.Color -> TARGET_COLOR
+        }
+
+    internal fun registerParameterBlock(surfaceId: Long, block: ParameterBlock) {
+        registerParameterBlock(
+            state,
+            surfaceId,
+            block.buffer,
+            block.targets.map { it.ffiKind }.toIntArray(),
+            block.targets.map { if (it is AnimationTarget.Axis) it.packed else 0 }.toIntArray()
+        )
     }
 
     @Suppress("KotlinJniMissingFunction")
@@ -243,22 +1152,150 @@ class Vello(private val coroutineScope: CoroutineScope) {
         updateVariableFontText(state, surfaceId, text)
     }
 
+    @Suppress("KotlinJniMissingFunction")
+    private external fun startAnimation(
+        state: Long,
+        surfaceId: Long,
+        animationId: Long,
+        target: Int,
+        axisTag: Int,
+        fractions: FloatArray,
+        values: FloatArray,
+        durationNanos: Long,
+        easing: Int,
+        easingParameters: FloatArray,
+        repeatMode: Int,
+        iterations: Int
+    )
+
+    @Suppress("KotlinJniMissingFunction")
+    private external fun cancelAnimation(state: Long, surfaceId: Long, animationId: Long)
+
+    @Suppress("KotlinJniMissingFunction")
+    private external fun getFrameStatistics(state: Long, out: LongArray)
+
+    @Suppress("KotlinJniMissingFunction")
+    private external fun getFrameTimeHistogram(
+        state: Long,
+        phase: Int,
+        bucketWidthNanos: Long,
+        out: IntArray
+    )
+
+    @Suppress("KotlinJniMissingFunction")
+    private external fun resetFrameStatistics(state: Long)
+
+    @Suppress("KotlinJniMissingFunction")
+    private external fun setFrameTimingCallbackEnabled(state: Long, enabled: Boolean)
+
+    @Suppress("KotlinJniMissingFunction")
+    private external fun setDebugOverlay(state: Long, mode: Int)
+
+    @Suppress("KotlinJniMissingFunction")
+    private external fun simulateDeviceLoss(state: Long)
+
+    @Suppress("KotlinJniMissingFunction")
+    private external fun pointerEvent(
+        state: Long,
+        surfaceId: Long,
+        kind: Int,
+        pointerId: Long,
+        x: Float,
+        y: Float,
+        pressure: Float
+    )
+
+    @Suppress("KotlinJniMissingFunction")
+    private external fun setFramePresentedCallbackEnabled(state: Long, enabled: Boolean)
+
     companion object {
+        // These must match the constants in `ffi.rs`
+        private const val TARGET_FONT_SIZE = 0
+        private const val TARGET_AXIS = 1
+        private const val TARGET_COLOR = 2
+        private const val EASING_LINEAR = 0
+        private const val EASING_CUBIC_BEZIER = 1
+        internal const val EDIT_COMMIT = 0
+        internal const val EDIT_COMPOSE = 1
+        internal const val EDIT_COMPOSING_REGION = 2
+        internal const val EDIT_FINISH_COMPOSING = 3
+        internal const val EDIT_DELETE_SURROUNDING = 4
+        internal const val EDIT_SET_SELECTION = 5
+        internal const val EDIT_KEY = 6
+        private const val ADVANCE_NATURAL = 0
+        private const val ADVANCE_REFERENCE = 1
+        private const val ADVANCE_MONOSPACE = 2
+        private const val STATISTICS_PER_PHASE = 6
+
+        /** How many render requests can be sent before the first of them is presented. */
+        private const val MAX_REQUESTS_IN_FLIGHT = 2
+
         // Used to load the 'vello' library on application startup.
         init {
             System.loadLibrary("vello_jni")
         }
 
         /**
-         * A native method to initialise Rust context (primarily related to logging).
+         * Configure how Vello's logs are written to logcat.
          *
-         * Calling this multiple times is permitted, and only the first call with have an effect.
+         * This can be called multiple times, and each call replaces the previous configuration.
+         * Nothing is logged until this is first called.
+         */
+        fun configureLogging(config: LoggingConfig = LoggingConfig()) {
+            val targets = config.targetLevels.entries.toList()
+            configureLogging(
+                config.defaultLevel.ordinal,
+                targets.map { it.key }.toTypedArray(),
+                targets.map { it.value.ordinal }.toIntArray(),
+                config.tag,
+                config.forwardStdio,
+            )
+        }
+
+        /**
+         * Set up where Vello's tracing spans are recorded.
          *
-         * You might wish to customise the stdout/stderr behaviour, so may not wish to
-         * call this.
+         * This can only be called once per process.
+         */
+        fun configureTracing(config: TracingConfig = TracingConfig()) {
+            configureTracing(config.logcat, config.systemTrace, config.chromeTracePath)
+        }
+
+        /**
+         * Complete the Chrome trace file requested in [configureTracing], after which it can be
+         * loaded. Spans after this are not written to the file.
          */
         @Suppress("KotlinJniMissingFunction") // This is defined in Rust code
         @JvmStatic
-        external fun initRust()
+        external fun finishChromeTrace()
+
+        /** The tags of the OpenType features which text surfaces support. */
+        fun supportedFontFeatures(): Set<String> {
+            return getSupportedFontFeatures().map { FontFeature.unpack(it) }.toSet()
+        }
+
+        @Suppress("KotlinJniMissingFunction") // This is defined in Rust code
+        @JvmStatic
+        private external fun getSupportedFontFeatures(): IntArray
+
+        @Suppress("KotlinJniMissingFunction") // This is defined in Rust code
+        @JvmStatic
+        private external fun configureTracing(
+            logcat: Boolean,
+            systemTrace: Boolean,
+            chromeTracePath: String?,
+        )
+
+        @Suppress("KotlinJniMissingFunction") // This is defined in Rust code
+        @JvmStatic
+        private external fun configureLogging(
+            defaultLevel: Int,
+            targets: Array<String>,
+            targetLevels: IntArray,
+            tag: String?,
+            forwardStdio: Boolean,
+        )
     }
 }
+
+private fun List<PresentMode>.ordinals(): IntArray = IntArray(size) { this[it].ordinal }
@@ -0,0 +1,143 @@
+package org.linebender.vello
+
+/**
+ * The property of a [VelloSurface] controlled by a [VelloAnimation].
+ */
+sealed class AnimationTarget {
+    /** The font size, in sp. Uses [Keyframe.scalar]. */
+    data object FontSize : AnimationTarget()
+
+    /**
+     * A font variation axis, such as `"wght"` or `"wdth"`. Uses [Keyframe.scalar].
+     *
+     * Animating `"wght"` controls the same value as [VariableFontSurface.fontWeight].
+     */
+    data class Axis(val tag: String) : AnimationTarget() {
+        init {
+            require(tag.length == 4 && tag.all { it.code < 128 }) {
+                "Axis tags must be four ASCII characters, got \"$tag\""
+            }
+        }
+
+        /** The tag packed as an OpenType `Tag`. */
+        internal val packed: Int
+            get() = tag.fold(0) { acc, char -> (acc shl 8) or char.code }
+    }
+
+    /** The colour of the content. Uses [Keyframe.color]. */
+    data object Color : AnimationTarget()
+
+    internal val components: Int
+        get() = if (this == Color) 4 else 1
+}
+
+/**
+ * A value at a point in a [VelloAnimation].
+ *
+ * [fraction] is how far through a single iteration of the animation this value applies, from 0 to 1.
+ */
+class Keyframe private constructor(val fraction: Float, internal val values: FloatArray) {
+    companion object {
+        fun scalar(fraction: Float, value: Float) = Keyframe(fraction, floatArrayOf(value))
+
+        /** A keyframe for [AnimationTarget.Color], using an ARGB colour int. */
+        fun color(fraction: Float, argb: Int) = Keyframe(
+            fraction,
+            floatArrayOf(
+                ((argb shr 16) and 0xFF) / 255f,
+                ((argb shr 8) and 0xFF) / 255f,
+                (argb and 0xFF) / 255f,
+                ((argb ushr 24) and 0xFF) / 255f,
+            )
+        )
+    }
+}
+
+/**
+ * The curve used to map time to progress through an iteration of a [VelloAnimation].
+ */
+sealed class VelloEasing {
+    data object Linear : VelloEasing()
+
+    /** Equivalent to Compose's `CubicBezierEasing`. */
+    data class CubicBezier(val x1: Float, val y1: Float, val x2: Float, val y2: Float) :
+        VelloEasing()
+
+    companion object {
+        val FastOutSlowIn = CubicBezier(0.4f, 0.0f, 0.2f, 1.0f)
+        val LinearOutSlowIn = CubicBezier(0.0f, 0.0f, 0.2f, 1.0f)
+        val FastOutLinearIn = CubicBezier(0.4f, 0.0f, 1.0f, 1.0f)
+    }
+}
+
+enum class VelloRepeatMode {
+    /** Each iteration starts again from the first keyframe. */
+    Restart,
+
+    /** Every other iteration plays backwards. */
+    Reverse,
+}
+
+/**
+ * A description of an animation which is evaluated entirely on Vello's render thread.
+ *
+ * Once started with [VariableFontSurface.animate], no further calls from Kotlin are needed for the
+ * animation to play.
+ *
+ * @param iterations The number of times to play the animation, or [INFINITE] to repeat forever.
+ */
+class VelloAnimation(
+    val target: AnimationTarget,
+    val keyframes: List<Keyframe>,
+    val durationMillis: Long,
+    val easing: VelloEasing = VelloEasing.Linear,
+    val repeatMode: VelloRepeatMode = VelloRepeatMode.Restart,
+    val iterations: Int = 1,
+) {
+    init {
+        require(keyframes.isNotEmpty()) { "Animations need at least one keyframe" }
+        require(keyframes.all { it.values.size == target.components }) {
+            "Keyframe values don't match animation target $target"
+        }
+        require(durationMillis >= 0) { "Animation duration must not be negative" }
+    }
+
+    companion object {
+        const val INFINITE = -1
+
+        /** A convenience for the common case of animating between two values. */
+        fun between(
+            target: AnimationTarget,
+            from: Float,
+            to: Float,
+            durationMillis: Long,
+            easing: VelloEasing = VelloEasing.Linear,
+            repeatMode: VelloRepeatMode = VelloRepeatMode.Restart,
+            iterations: Int = 1,
+        ) = VelloAnimation(
+            target,
+            listOf(Keyframe.scalar(0f, from), Keyframe.scalar(1f, to)),
+            durationMillis,
+            easing,
+            repeatMode,
+            iterations
+        )
+    }
+}
+
+/** Why an animation stopped running. The order must match `EndReason` in `animation.rs`. */
+internal enum class AnimationEndReason {
+    Completed,
+
+    /** Replaced by another animation of the same target, or its surface was destroyed. */
+    Replaced,
+    Cancelled,
+}
+
+/**
+ * An animation stopping, reported by the render thread.
+ *
+ * [value] is what the animation left its property at, which the property keeps until it is next
+ * changed, or null if the animation never ran.
+ */
+internal class AnimationEnd(val reason: AnimationEndReason, val value: FloatArray?)
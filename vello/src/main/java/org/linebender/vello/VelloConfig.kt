@@ -0,0 +1,75 @@
+package org.linebender.vello
+
+/**
+ * The antialiasing method used when rendering.
+ *
+ * The order must match `AntialiasingMethod` in `config.rs`.
+ */
+enum class AntialiasingMethod {
+    /** Analytic area antialiasing, which is the highest quality for most content. */
+    Area,
+    Msaa8,
+    Msaa16,
+}
+
+/**
+ * The graphics API used by Vello.
+ *
+ * The order must match `GraphicsBackend` in `config.rs`.
+ */
+enum class GraphicsBackend {
+    /** The best supported API, which is Vulkan on Android. */
+    Primary,
+    Vulkan,
+    Gles,
+}
+
+/**
+ * Which GPU to prefer, on devices with more than one.
+ *
+ * The order must match `POWER_PREFERENCES` in `config.rs`.
+ */
+enum class PowerPreference {
+    None,
+    LowPower,
+    HighPerformance,
+}
+
+/**
+ * How frames are presented to a surface.
+ *
+ * The order must match `PRESENT_MODES` in `config.rs`.
+ */
+enum class PresentMode {
+    /** Frames are queued, and shown at each vsync. This is supported by every surface. */
+    Fifo,
+
+    /** Like [Fifo], but a late frame is shown immediately, which may cause tearing. */
+    FifoRelaxed,
+
+    /** Frames are shown immediately, which may cause tearing. */
+    Immediate,
+
+    /** Frames are shown at each vsync, with newer frames replacing queued ones. */
+    Mailbox,
+}
+
+/**
+ * The configuration of the renderer of a [Vello].
+ */
+data class VelloConfig(
+    val antialiasing: AntialiasingMethod = AntialiasingMethod.Area,
+    val backend: GraphicsBackend = GraphicsBackend.Primary,
+    val powerPreference: PowerPreference = PowerPreference.None,
+    /**
+     * The present modes to use for surfaces, in order of preference.
+     *
+     * Each surface uses the first mode it supports, or [PresentMode.Fifo] if it supports none.
+     * This can be overridden in [Vello.createSurface].
+     */
+    val presentModes: List<PresentMode> = listOf(PresentMode.Mailbox, PresentMode.Fifo),
+    /** Enable wgpu's API validation, which is useful when debugging but slow. */
+    val validation: Boolean = false,
+    /** The number of threads used to compile shaders at startup, or 0 to let Vello choose. */
+    val initThreads: Int = 0,
+)
@@ -1,20 +1,74 @@
 package org.linebender.vello.compose
 
+import android.util.Log
 import androidx.compose.foundation.AndroidExternalSurface
 import androidx.compose.runtime.Composable
 import androidx.compose.runtime.CompositionLocalProvider
 import androidx.compose.runtime.DisposableEffect
 import androidx.compose.runtime.SideEffect
+import androidx.compose.runtime.mutableStateOf
 import androidx.compose.runtime.remember
 import androidx.compose.runtime.rememberCoroutineScope
 import androidx.compose.runtime.rememberUpdatedState
+import androidx.compose.runtime.snapshotFlow
 import androidx.compose.runtime.staticCompositionLocalOf
+import androidx.compose.foundation.focusable
+import androidx.compose.foundation.gestures.awaitEachGesture
+import androidx.compose.foundation.gestures.awaitFirstDown
+import androidx.compose.foundation.gestures.drag
+import androidx.compose.runtime.MutableState
 import androidx.compose.ui.Modifier
+import androidx.compose.ui.focus.FocusEventModifierNode
+import androidx.compose.ui.focus.FocusRequester
+import androidx.compose.ui.focus.FocusState
+import androidx.compose.ui.focus.focusRequester
+import androidx.compose.ui.geometry.Offset
+import androidx.compose.ui.input.key.onKeyEvent
+import androidx.compose.ui.input.pointer.PointerInputScope
+import androidx.compose.ui.input.pointer.changedToDownIgnoreConsumed
+import androidx.compose.ui.input.pointer.changedToUpIgnoreConsumed
+import androidx.compose.ui.input.pointer.pointerInput
+import androidx.compose.ui.node.ModifierNodeElement
+import androidx.compose.ui.platform.LocalDensity
+import androidx.compose.ui.platform.PlatformTextInputMethodRequest
+import androidx.compose.ui.platform.PlatformTextInputModifierNode
+import androidx.compose.ui.platform.establishTextInputSession
+import androidx.compose.ui.semantics.editableText
+import androidx.compose.ui.semantics.semantics
+import androidx.compose.ui.semantics.setSelection
+import androidx.compose.ui.semantics.setText
+import androidx.compose.ui.semantics.text
+import androidx.compose.ui.semantics.textSelectionRange
+import androidx.compose.ui.text.AnnotatedString
+import androidx.compose.ui.text.TextRange
+import androidx.compose.ui.unit.Density
+import androidx.compose.ui.unit.sp
+import kotlinx.coroutines.Job
 import kotlinx.coroutines.channels.Channel
+import kotlinx.coroutines.flow.filterNotNull
+import kotlinx.coroutines.flow.first
+import kotlinx.coroutines.launch
 import kotlinx.coroutines.selects.select
+import org.linebender.vello.AccessibilityAction
+import org.linebender.vello.AccessibilityNode
+import org.linebender.vello.AccessibilityRole
+import org.linebender.vello.AdvanceMode
+import org.linebender.vello.ColorPalette
+import org.linebender.vello.EditorState
+import org.linebender.vello.FontFeature
+import org.linebender.vello.FramePacing
+import org.linebender.vello.PointerEventKind
+import org.linebender.vello.PointerHit
+import org.linebender.vello.SurfaceCreationException
+import org.linebender.vello.TextDirection
+import org.linebender.vello.TextEditorSurface
+import org.linebender.vello.TextEffects
+import org.linebender.vello.TextRendering
 import org.linebender.vello.VariableFontSurface
 import org.linebender.vello.Vello
+import org.linebender.vello.VelloConfig
 import org.linebender.vello.VelloSurface
+import java.util.Locale
 import java.util.concurrent.ArrayBlockingQueue
 import java.util.concurrent.BlockingQueue
 import kotlin.concurrent.thread
@@ -23,9 +77,16 @@ private val LocalVello = staticCompositionLocalOf<Vello?> { null }
 
 /**
  * The context
+ *
+ * [framePacing] cannot be changed after the context is created.
  */
 @Composable
-fun VelloContext(allowNested: Boolean = false, content: @Composable() () -> Unit) {
+fun VelloContext(
+    allowNested: Boolean = false,
+    framePacing: FramePacing = FramePacing.ComposeFrameClock,
+    config: VelloConfig = VelloConfig(),
+    content: @Composable() () -> Unit
+) {
     if (!allowNested && LocalVello.current != null) {
         // https://stackoverflow.com/a/71871643
         // This ensures that the exception isn't swallowed by Compose's machinery
@@ -37,7 +98,7 @@ fun VelloContext(allowNested: Boolean = false, content: @Composable() () -> Unit
         }
     }
     val scope = rememberCoroutineScope();
-    val vello = remember { Vello(scope) }
+    val vello = remember { Vello(scope, framePacing, config) }
     CompositionLocalProvider(LocalVello provides vello) {
         content()
     }
@@ -50,12 +111,25 @@ fun VelloContext(allowNested: Boolean = false, content: @Composable() () -> Unit
     }
 }
 
+/**
+ * A surface showing [text] in Roboto Flex, drawn by Vello.
+ *
+ * [fontSize] is in sp, and is converted to pixels with [LocalDensity] in the same way as for
+ * Compose's `Text`, so follows the user's font size setting.
+ */
 @Composable
 fun VariableFontsVelloSurface(
     text: String,
     fontSize: Float,
     fontWeight: Float = 400f,
-    modifier: Modifier = Modifier
+    modifier: Modifier = Modifier,
+    fontFeatures: List<FontFeature> = emptyList(),
+    advanceMode: AdvanceMode = AdvanceMode.Natural,
+    textDirection: TextDirection = TextDirection.Auto,
+    locale: Locale? = null,
+    textRendering: TextRendering = TextRendering.Smooth,
+    textEffects: TextEffects = TextEffects(),
+    colorPalette: ColorPalette = ColorPalette(),
 ) {
     val textChannel = remember { Channel<String>(Channel.CONFLATED) }
     VariableFontsSendChannel(textChannel, text)
@@ -66,20 +140,85 @@ fun VariableFontsVelloSurface(
     val weightChannel = remember { Channel<Float>(Channel.CONFLATED) }
     VariableFontsSendChannel(weightChannel, fontWeight)
 
+    val featuresChannel = remember { Channel<List<FontFeature>>(Channel.CONFLATED) }
+    VariableFontsSendChannel(featuresChannel, fontFeatures)
+
+    val advanceModeChannel = remember { Channel<AdvanceMode>(Channel.CONFLATED) }
+    VariableFontsSendChannel(advanceModeChannel, advanceMode)
+
+    val directionChannel = remember { Channel<TextDirection>(Channel.CONFLATED) }
+    VariableFontsSendChannel(directionChannel, textDirection)
+
+    val localeChannel = remember { Channel<Locale?>(Channel.CONFLATED) }
+    VariableFontsSendChannel(localeChannel, locale)
+
+    val renderingChannel = remember { Channel<TextRendering>(Channel.CONFLATED) }
+    VariableFontsSendChannel(renderingChannel, textRendering)
+
+    val effectsChannel = remember { Channel<TextEffects>(Channel.CONFLATED) }
+    VariableFontsSendChannel(effectsChannel, textEffects)
+
+    val paletteChannel = remember { Channel<ColorPalette>(Channel.CONFLATED) }
+    VariableFontsSendChannel(paletteChannel, colorPalette)
+
+    val textScale = LocalDensity.current.textScale(fontSize)
+    val scaleChannel = remember { Channel<TextScale>(Channel.CONFLATED) }
+    VariableFontsSendChannel(scaleChannel, textScale)
+
     /// In theory, we shouldn't actually need to remember these, because the channels will be sent
     // them. However, that doesn't work in the case of a recreated surface (e.g. for scrolling
     // on/off screen?)
     val currentText = rememberUpdatedState(text);
     val currentFontSize = rememberUpdatedState(fontSize);
     val currentFontWeight = rememberUpdatedState(fontWeight);
+    val currentFontFeatures = rememberUpdatedState(fontFeatures)
+    val currentAdvanceMode = rememberUpdatedState(advanceMode)
+    val currentTextDirection = rememberUpdatedState(textDirection)
+    val currentLocale = rememberUpdatedState(locale)
+    val currentTextRendering = rememberUpdatedState(textRendering)
+    val currentTextEffects = rememberUpdatedState(textEffects)
+    val currentColorPalette = rememberUpdatedState(colorPalette)
+    val currentTextScale = rememberUpdatedState(textScale)
+    val tree = remember { mutableStateOf<AccessibilityNode?>(null) }
+    val textSurface = remember { mutableStateOf<VariableFontSurface?>(null) }
+    val semantics = Modifier.accessibilityTree(
+        tree.value,
+        onSetSelection = { anchor, focus -> textSurface.value?.select(anchor, focus) },
+    )
 
-    BaseVelloSurface(modifier) {
+    BaseVelloSurface(modifier.then(semantics)) {
         val vfSurface = VariableFontSurface(
             this,
             currentText.value,
             currentFontSize.value,
             currentFontWeight.value
         )
+        if (currentTextScale.value != TextScale()) {
+            vfSurface.setTextScale(currentTextScale.value)
+        }
+        if (currentFontFeatures.value.isNotEmpty()) {
+            vfSurface.setFontFeatures(currentFontFeatures.value)
+        }
+        if (currentAdvanceMode.value != AdvanceMode.Natural) {
+            vfSurface.setAdvanceMode(currentAdvanceMode.value)
+        }
+        if (currentTextDirection.value != TextDirection.Auto) {
+            vfSurface.setTextDirection(currentTextDirection.value)
+        }
+        if (currentLocale.value != null) {
+            vfSurface.setLocale(currentLocale.value)
+        }
+        if (currentTextRendering.value != TextRendering.Smooth) {
+            vfSurface.setTextRendering(currentTextRendering.value)
+        }
+        if (currentTextEffects.value != TextEffects()) {
+            vfSurface.setTextEffects(currentTextEffects.value)
+        }
+        if (currentColorPalette.value != ColorPalette()) {
+            vfSurface.setColorPalette(currentColorPalette.value)
+        }
+        textSurface.value = vfSurface
+        tree.value = vfSurface.accessibilityTree()
         while (true) {
             select {
                 weightChannel.onReceive { weight ->
@@ -88,8 +227,36 @@ fun VariableFontsVelloSurface(
                 fontSizeChannel.onReceive { fontSize ->
                     vfSurface.setFontSize(fontSize)
                 }
+                scaleChannel.onReceive { scale ->
+                    vfSurface.setTextScale(scale)
+                    tree.value = vfSurface.accessibilityTree()
+                }
+                featuresChannel.onReceive { features ->
+                    vfSurface.setFontFeatures(features)
+                }
+                advanceModeChannel.onReceive { mode ->
+                    vfSurface.setAdvanceMode(mode)
+                }
+                directionChannel.onReceive { direction ->
+                    vfSurface.setTextDirection(direction)
+                    // Right-to-left text is aligned to the other side.
+                    tree.value = vfSurface.accessibilityTree()
+                }
+                localeChannel.onReceive { locale ->
+                    vfSurface.setLocale(locale)
+                }
+                renderingChannel.onReceive { rendering ->
+                    vfSurface.setTextRendering(rendering)
+                }
+                effectsChannel.onReceive { effects ->
+                    vfSurface.setTextEffects(effects)
+                }
+                paletteChannel.onReceive { palette ->
+                    vfSurface.setColorPalette(palette)
+                }
                 textChannel.onReceive { text ->
                     vfSurface.setText(text)
+                    tree.value = vfSurface.accessibilityTree()
                 }
             }
 
@@ -102,25 +269,245 @@ private fun <V> VariableFontsSendChannel(channel: Channel<V>, value: V) {
     SideEffect { channel.trySend(value) }
 }
 
+/** The display density and font scale which Vello converts font sizes in sp to pixels with. */
+private data class TextScale(val density: Float = 1f, val fontScale: Float = 1f)
+
+/**
+ * The scale which converts [fontSize] to pixels in the same way as Compose's `Text`.
+ *
+ * Large font scales are non-linear on newer versions of Android, so the scale is measured at
+ * [fontSize] rather than taken from [Density.fontScale].
+ */
+private fun Density.textScale(fontSize: Float): TextScale {
+    val fontScale = if (fontSize > 0f) fontSize.sp.toDp().value / fontSize else fontScale
+    return TextScale(density, fontScale)
+}
+
+private fun VariableFontSurface.setTextScale(scale: TextScale) =
+    setTextScale(scale.density, scale.fontScale)
+
+/**
+ * An editable text field, drawn by Vello, which starts with [initialText].
+ *
+ * Tapping the field focuses it and moves the caret, and dragging selects text. Whilst focused,
+ * the input method's edits and hardware keys are applied by Vello. [onChange] is called on the
+ * main thread with the text and selection whenever they change. [fontSize] is in sp, which
+ * follows the user's font size setting.
+ */
+@Composable
+fun VelloTextEditor(
+    initialText: String,
+    fontSize: Float,
+    modifier: Modifier = Modifier,
+    multiline: Boolean = false,
+    onChange: ((EditorState) -> Unit)? = null,
+) {
+    val editor = remember { mutableStateOf<TextEditorSurface?>(null) }
+    val focusRequester = remember { FocusRequester() }
+    val currentOnChange = rememberUpdatedState(onChange)
+    val currentTextScale = rememberUpdatedState(LocalDensity.current.textScale(fontSize))
+    val tree = remember { mutableStateOf<AccessibilityNode?>(null) }
+    val editorModifier = Modifier
+        .accessibilityTree(
+            tree.value,
+            onSetSelection = { anchor, focus -> editor.value?.select(anchor, focus) },
+            onSetText = { text -> editor.value?.setText(text) },
+        )
+        .pointerInput(Unit) {
+            awaitEachGesture {
+                val down = awaitFirstDown()
+                focusRequester.requestFocus()
+                editor.value?.selectAtPoint(down.position.x, down.position.y)
+                drag(down.id) { change ->
+                    editor.value?.selectAtPoint(change.position.x, change.position.y, true)
+                    change.consume()
+                }
+            }
+        }
+        .onKeyEvent { event -> editor.value?.onKeyEvent(event.nativeKeyEvent) ?: false }
+        .then(TextEditorInputElement(editor))
+        .focusRequester(focusRequester)
+        .focusable()
+    BaseVelloSurface(modifier.then(editorModifier)) {
+        val surface = TextEditorSurface(this, initialText, fontSize, multiline)
+        surface.onChange { state ->
+            tree.value = accessibilityTree()
+            currentOnChange.value?.invoke(state)
+        }
+        editor.value = surface
+        tree.value = accessibilityTree()
+        try {
+            // The text is laid out again whenever the density or font scale changes.
+            snapshotFlow { currentTextScale.value }.collect { scale ->
+                surface.setTextScale(scale.density, scale.fontScale)
+                tree.value = accessibilityTree()
+            }
+        } finally {
+            editor.value = null
+        }
+    }
+}
+
+/**
+ * Publish the root of [tree] as the semantics of this node, so that accessibility services can
+ * read the text of a surface.
+ *
+ * The actions of [tree] which have a handler here are published too. The lines of the text are
+ * not published as separate nodes, as Compose has no virtual semantics children.
+ */
+private fun Modifier.accessibilityTree(
+    tree: AccessibilityNode?,
+    onSetSelection: ((anchor: Int, focus: Int) -> Unit)? = null,
+    onSetText: ((String) -> Unit)? = null,
+): Modifier {
+    if (tree == null) return this
+    return semantics {
+        if (tree.role == AccessibilityRole.TextInput) {
+            editableText = AnnotatedString(tree.text)
+        } else {
+            text = AnnotatedString(tree.text)
+        }
+        tree.selection?.let { (anchor, focus) -> textSelectionRange = TextRange(anchor, focus) }
+        if (onSetSelection != null && AccessibilityAction.SetSelection in tree.actions) {
+            setSelection { start, end, _ ->
+                onSetSelection(start, end)
+                true
+            }
+        }
+        if (onSetText != null && AccessibilityAction.SetText in tree.actions) {
+            setText { text ->
+                onSetText(text.text)
+                true
+            }
+        }
+    }
+}
+
+/** Connects the input method to the editor in [editor] whilst the editor is focused. */
+private data class TextEditorInputElement(
+    val editor: MutableState<TextEditorSurface?>
+) : ModifierNodeElement<TextEditorInputNode>() {
+    override fun create() = TextEditorInputNode(editor)
+
+    override fun update(node: TextEditorInputNode) {
+        node.editor = editor
+    }
+}
+
+private class TextEditorInputNode(
+    var editor: MutableState<TextEditorSurface?>
+) : Modifier.Node(), PlatformTextInputModifierNode, FocusEventModifierNode {
+    private var session: Job? = null
+
+    override fun onFocusEvent(focusState: FocusState) {
+        if (focusState.isFocused == (session != null)) return
+        session?.cancel()
+        session = if (focusState.isFocused) {
+            coroutineScope.launch {
+                // The field can be focused before its surface has been created.
+                val target = snapshotFlow { editor.value }.filterNotNull().first()
+                establishTextInputSession {
+                    startInputMethod(PlatformTextInputMethodRequest { outAttrs ->
+                        target.createInputConnection(view, outAttrs)
+                    })
+                }
+            }
+        } else {
+            null
+        }
+    }
+}
+
 /**
  * A View which creates and manages the lifetime of a [VelloSurface].
  *
  * Must be called inside a [VelloContext].
+ *
+ * If [onPointerHit] is set, pointer events over the surface are sent to Vello, and it is called
+ * with what was under the pointer for each of them.
  */
 @Composable
-fun BaseVelloSurface(modifier: Modifier = Modifier, withSurface: suspend VelloSurface.() -> Unit) {
+fun BaseVelloSurface(
+    modifier: Modifier = Modifier,
+    onPointerHit: ((PointerHit) -> Unit)? = null,
+    withSurface: suspend VelloSurface.() -> Unit
+) {
     val vello = LocalVello.current
         ?: throw IllegalStateException("Tried to use Vello outside of a `VelloContext`");
-    AndroidExternalSurface(modifier) {
+    val currentSurface = remember { mutableStateOf<VelloSurface?>(null) }
+    val currentOnPointerHit = rememberUpdatedState(onPointerHit)
+    val pointerModifier = if (onPointerHit != null) {
+        Modifier.pointerInput(Unit) { forwardPointerEvents { currentSurface.value } }
+    } else {
+        Modifier
+    }
+    AndroidExternalSurface(modifier.then(pointerModifier)) {
         onSurface { surface, initialWidth, initialHeight ->
-            val velloSurface = vello.createSurface(surface, initialWidth, initialHeight)
+            val velloSurface = try {
+                vello.createSurface(surface, initialWidth, initialHeight)
+            } catch (e: SurfaceCreationException) {
+                // Leave this surface blank, rather than crashing the whole app.
+                Log.e("VelloCompose", "Couldn't render to surface", e)
+                return@onSurface
+            }
             surface.onChanged { newWidth, newHeight ->
                 velloSurface.resize(newWidth, newHeight)
             }
             surface.onDestroyed {
+                currentSurface.value = null
                 velloSurface.cleanUp()
             }
+            velloSurface.onPointerHit { hit -> currentOnPointerHit.value?.invoke(hit) }
+            currentSurface.value = velloSurface
             withSurface(velloSurface)
         }
     }
-}
\ No newline at end of file
+}
+
+/**
+ * Send the pointer events of this pointer input to the surface returned by [surface].
+ *
+ * Pointers which are consumed by another gesture whilst pressed (such as a parent scrolling), or
+ * which are still pressed when this pointer input is reset, are cancelled.
+ */
+private suspend fun PointerInputScope.forwardPointerEvents(surface: () -> VelloSurface?) {
+    // The last position of each pressed pointer
+    val pressed = HashMap<Long, Offset>()
+    try {
+        awaitPointerEventScope {
+            while (true) {
+                val event = awaitPointerEvent()
+                val target = surface() ?: continue
+                for (change in event.changes) {
+                    val pointerId = change.id.value
+                    val kind = when {
+                        change.changedToDownIgnoreConsumed() -> PointerEventKind.Down
+                        change.changedToUpIgnoreConsumed() -> PointerEventKind.Up
+                        change.isConsumed && pointerId in pressed -> PointerEventKind.Cancel
+                        else -> PointerEventKind.Move
+                    }
+                    when (kind) {
+                        PointerEventKind.Down -> pressed[pointerId] = change.position
+                        PointerEventKind.Move -> pressed.replace(pointerId, change.position)
+                        PointerEventKind.Up, PointerEventKind.Cancel -> pressed.remove(pointerId)
+                    }
+                    target.pointerEvent(
+                        kind,
+                        pointerId,
+                        change.position.x,
+                        change.position.y,
+                        change.pressure
+                    )
+                }
+            }
+        }
+    } finally {
+        // These gestures will never finish, so make sure that Vello knows they have ended.
+        val target = surface()
+        if (target != null) {
+            for ((pointerId, position) in pressed) {
+                target.pointerEvent(PointerEventKind.Cancel, pointerId, position.x, position.y, 0f)
+            }
+        }
+    }
+}
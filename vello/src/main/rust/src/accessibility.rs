@@ -0,0 +1,203 @@
+//! Accessibility trees describing the content of surfaces.
+//!
+//! Vello only puts pixels on the screen, so services such as TalkBack can't read the text of a
+//! surface. Each surface instead describes its content as a tree of [`AccessNode`]s, which
+//! Kotlin reads and publishes as Compose semantics.
+
+use std::ops::Range;
+
+use parley::Layout;
+use vello::{
+    kurbo::{Affine, Rect},
+    peniko::Brush,
+};
+
+use crate::{
+    direction::DirectedText,
+    selection::{byte_to_utf16, TextSelection},
+};
+
+/// What an [`AccessNode`] represents.
+///
+/// The discriminants are used in the FFI, so must match `AccessibilityRole` in Kotlin.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessRole {
+    /// Text which can be read and selected, but not edited.
+    Label = 0,
+    /// Editable text.
+    TextInput = 1,
+    /// One line of the text of its parent.
+    TextRun = 2,
+}
+
+/// Something an accessibility service can ask an [`AccessNode`] to do.
+///
+/// The discriminants are used in the FFI, so must match `AccessibilityAction` in Kotlin.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessAction {
+    /// Give the node input focus.
+    Focus = 0,
+    /// Change the selected text.
+    SetSelection = 1,
+    /// Replace all of the text.
+    SetText = 2,
+}
+
+impl AccessAction {
+    /// Pack `actions` into a bit set, with each action at the bit of its discriminant.
+    pub fn bits(actions: &[Self]) -> i32 {
+        actions
+            .iter()
+            .fold(0, |bits, action| bits | (1 << *action as i32))
+    }
+}
+
+/// A node of the accessibility tree of a surface.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AccessNode {
+    pub role: AccessRole,
+    /// The text of this node, which for the root of a surface is all of its text.
+    pub text: String,
+    /// Where this node is drawn, in pixels from the top left of the surface.
+    pub bounds: Rect,
+    /// The UTF-16 range of `text` within the text of the surface.
+    pub text_range: Range<usize>,
+    /// The UTF-16 anchor and focus of the selection, if this node shows one.
+    pub selection: Option<(usize, usize)>,
+    pub actions: Vec<AccessAction>,
+    pub children: Vec<AccessNode>,
+}
+
+/// Describe the text of `layout`, drawn moved by `transform`, with a child for each line.
+///
+/// `layout` must have been built from `directed`.
+pub(crate) fn text_tree(
+    layout: &Layout<Brush>,
+    directed: &DirectedText<'_>,
+    transform: Affine,
+    role: AccessRole,
+    selection: Option<&TextSelection>,
+) -> AccessNode {
+    let text = directed.source();
+    let utf16 = |index| byte_to_utf16(text, index);
+    let children: Vec<_> = layout
+        .lines()
+        .map(|line| {
+            let metrics = line.metrics();
+            let range = line.text_range();
+            let range = directed.to_text(range.start)..directed.to_text(range.end);
+            let bounds = Rect::new(
+                metrics.offset.into(),
+                metrics.min_coord.into(),
+                (metrics.offset + metrics.advance).into(),
+                metrics.max_coord.into(),
+            );
+            AccessNode {
+                role: AccessRole::TextRun,
+                // Line breaks are read out as a pause between the lines anyway.
+                text: text[range.clone()].trim_end_matches('\n').to_string(),
+                bounds: transform.transform_rect_bbox(bounds),
+                text_range: utf16(range.start)..utf16(range.end),
+                selection: None,
+                actions: Vec::new(),
+                children: Vec::new(),
+            }
+        })
+        .collect();
+    // The lines include trailing whitespace, so can be wider than the layout.
+    let bounds = children
+        .iter()
+        .map(|line: &AccessNode| line.bounds)
+        .reduce(|all, line| all.union(line))
+        .unwrap_or_else(|| transform.transform_rect_bbox(Rect::ZERO));
+    let mut actions = vec![AccessAction::SetSelection];
+    if role == AccessRole::TextInput {
+        actions.extend([AccessAction::Focus, AccessAction::SetText]);
+    }
+    AccessNode {
+        role,
+        text: text.to_string(),
+        bounds,
+        text_range: 0..utf16(text.len()),
+        selection: selection.map(|selection| (utf16(selection.anchor), utf16(selection.focus))),
+        actions,
+        children,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use crate::{
+        command::SurfaceUpdate, selection::SelectionChange, test_contexts, test_variable_font,
+        SurfaceKind,
+    };
+
+    use super::{AccessAction, AccessRole};
+
+    #[test]
+    fn text_has_a_node_per_line() {
+        let (mut font_ctx, mut layout_ctx) = test_contexts();
+        let mut kind = test_variable_font("12\n34:5", 40.);
+        let selection = SelectionChange::Bytes {
+            anchor: 1,
+            focus: 4,
+        };
+        kind.set_selection(selection, Instant::now(), &mut font_ctx, &mut layout_ctx);
+        let tree = kind
+            .accessibility_tree(&mut font_ctx, &mut layout_ctx)
+            .unwrap();
+        assert_eq!(tree.role, AccessRole::Label);
+        assert_eq!(tree.text, "12\n34:5");
+        assert_eq!(tree.text_range, 0..7);
+        assert_eq!(tree.selection, Some((1, 4)));
+        assert_eq!(tree.actions, [AccessAction::SetSelection]);
+        let lines = tree
+            .children
+            .iter()
+            .map(|line| (line.role, line.text.as_str(), line.text_range.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                (AccessRole::TextRun, "12", 0..3),
+                (AccessRole::TextRun, "34:5", 3..7)
+            ]
+        );
+        let [first, second] = &tree.children[..] else {
+            unreachable!();
+        };
+        // The lines are stacked, and the second is wider as it has more text.
+        assert!(first.bounds.y1 <= second.bounds.y0);
+        assert!(first.bounds.width() < second.bounds.width());
+        assert_eq!(tree.bounds, first.bounds.union(second.bounds));
+    }
+
+    #[test]
+    fn editors_are_text_inputs() {
+        let (mut font_ctx, mut layout_ctx) = test_contexts();
+        let mut kind = SurfaceKind::Unset;
+        assert_eq!(
+            kind.accessibility_tree(&mut font_ctx, &mut layout_ctx),
+            None
+        );
+        SurfaceUpdate::SetTextEditor {
+            text: "12:34".to_string(),
+            multiline: false,
+            size: 40.,
+        }
+        .apply(&mut kind);
+        let tree = kind
+            .accessibility_tree(&mut font_ctx, &mut layout_ctx)
+            .unwrap();
+        assert_eq!(tree.role, AccessRole::TextInput);
+        assert_eq!(tree.selection, Some((5, 5)));
+        assert_eq!(
+            AccessAction::bits(&tree.actions),
+            0b111,
+            "Editors support every action"
+        );
+        assert_eq!(tree.children.len(), 1);
+    }
+}
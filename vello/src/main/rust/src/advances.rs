@@ -0,0 +1,229 @@
+//! Glyph positions which don't move whilst the variation axes of the font are animated.
+//!
+//! Axes such as `wght` change the advance of each glyph, so text laid out naturally gets wider
+//! and narrower as they are animated, moving every glyph after the first. An [`AdvanceMode`]
+//! instead gives each glyph a cell whose width doesn't depend on the current axis values, and
+//! centres the glyph in its cell, so only the outlines of the glyphs change.
+
+use parley::{FontVariation, Layout, PositionedLayoutItem};
+use vello::{
+    peniko::Brush,
+    skrifa::{
+        instance::{Location, Size},
+        FontRef, GlyphId, MetadataProvider, Tag,
+    },
+};
+
+/// How the glyphs of a variable font surface are spaced.
+///
+/// Only drawing uses the fixed cells; selections and hit testing still use the natural layout,
+/// which is close enough to find the glyph under a finger.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum AdvanceMode {
+    /// Use the advances at the current axis values, so the text moves as they are animated.
+    #[default]
+    Natural,
+    /// Use the advances the glyphs would have with these axis values.
+    ///
+    /// Axes which aren't listed keep their current values, so should not be animated.
+    Reference(Vec<FontVariation>),
+    /// Give every glyph the width of the widest glyph of the text, at either end of each axis.
+    ///
+    /// Each axis is measured at its minimum and maximum with the other axes at their current
+    /// values, so animating several axes at once can still change the width of the cells.
+    Monospace,
+}
+
+/// The `x` position of each glyph of `layout` in `mode`, in the order in which they are drawn.
+///
+/// `variations` are the current axis values of `layout`. Returns `None` for
+/// [`AdvanceMode::Natural`], as the layout's own positions should be used.
+pub(crate) fn glyph_positions(
+    layout: &Layout<Brush>,
+    mode: &AdvanceMode,
+    variations: &[FontVariation],
+) -> Option<Vec<f32>> {
+    let cells = match mode {
+        AdvanceMode::Natural => return None,
+        AdvanceMode::Reference(reference) => {
+            // Later settings for an axis take precedence.
+            let settings = variations.iter().chain(reference).collect::<Vec<_>>();
+            let mut cells = Vec::new();
+            for_each_run(layout, |font, size, glyphs| {
+                let Some(font) = font else {
+                    cells.extend(glyphs.iter().map(|&(_, natural)| natural));
+                    return;
+                };
+                let location = font.axes().location(settings.iter().copied().map(setting));
+                let metrics = font.glyph_metrics(Size::new(size), &location);
+                cells.extend(
+                    glyphs
+                        .iter()
+                        .map(|&(id, natural)| metrics.advance_width(id).unwrap_or(natural)),
+                );
+            });
+            cells
+        }
+        AdvanceMode::Monospace => {
+            let mut widest = 0_f32;
+            let mut count = 0;
+            for_each_run(layout, |font, size, glyphs| {
+                count += glyphs.len();
+                let Some(font) = font else {
+                    widest = glyphs
+                        .iter()
+                        .fold(widest, |widest, &(_, natural)| widest.max(natural));
+                    return;
+                };
+                for location in extreme_locations(font, variations) {
+                    let metrics = font.glyph_metrics(Size::new(size), &location);
+                    for &(id, natural) in glyphs {
+                        widest = widest.max(metrics.advance_width(id).unwrap_or(natural));
+                    }
+                }
+            });
+            vec![widest; count]
+        }
+    };
+    let mut cells = cells.into_iter();
+    let mut positions = Vec::with_capacity(cells.len());
+    for line in layout.lines() {
+        // The cells of each line start where the line's text does.
+        let mut cell_start = None;
+        for item in line.items() {
+            let PositionedLayoutItem::GlyphRun(glyph_run) = item else {
+                continue;
+            };
+            let cell_start = cell_start.get_or_insert(glyph_run.offset());
+            for glyph in glyph_run.glyphs() {
+                let cell = cells.next().unwrap_or(glyph.advance);
+                positions.push(*cell_start + (cell - glyph.advance) / 2. + glyph.x);
+                *cell_start += cell;
+            }
+        }
+    }
+    Some(positions)
+}
+
+/// Call `f` with the font, font size, and the id and natural advance of each glyph, for each
+/// glyph run of `layout` in drawing order.
+///
+/// The font is `None` if it can't be read, in which case the natural advances should be used.
+fn for_each_run(
+    layout: &Layout<Brush>,
+    mut f: impl FnMut(Option<&FontRef<'_>>, f32, &[(GlyphId, f32)]),
+) {
+    for line in layout.lines() {
+        for item in line.items() {
+            let PositionedLayoutItem::GlyphRun(glyph_run) = item else {
+                continue;
+            };
+            let run = glyph_run.run();
+            let glyphs = glyph_run
+                .glyphs()
+                .map(|glyph| (GlyphId::new(glyph.id.into()), glyph.advance))
+                .collect::<Vec<_>>();
+            let font = run.font();
+            let font = FontRef::from_index(font.data.as_ref(), font.index).ok();
+            f(font.as_ref(), run.font_size(), &glyphs);
+        }
+    }
+}
+
+/// The locations of `font` with each of its axes at its minimum and maximum, and the other axes
+/// at their values in `variations`, followed by the location of `variations` itself.
+fn extreme_locations(font: &FontRef<'_>, variations: &[FontVariation]) -> Vec<Location> {
+    let axes = font.axes();
+    let mut locations = Vec::new();
+    for axis in axes.iter() {
+        for value in [axis.min_value(), axis.max_value()] {
+            let extreme = (axis.tag(), value);
+            locations.push(axes.location(variations.iter().map(setting).chain([extreme])));
+        }
+    }
+    locations.push(axes.location(variations.iter().map(setting)));
+    locations
+}
+
+fn setting(variation: &FontVariation) -> (Tag, f32) {
+    (Tag::from_u32(variation.tag), variation.value)
+}
+
+#[cfg(test)]
+mod tests {
+    use parley::{FontVariation, PositionedLayoutItem};
+
+    use crate::{
+        animation::{AnimatedValue, AnimationTarget},
+        command::SurfaceUpdate,
+        test_contexts, test_variable_font, WGHT,
+    };
+
+    use super::{glyph_positions, AdvanceMode};
+
+    /// The centre of each glyph of "10:27" at `weight`, and the width of the natural layout.
+    fn centres(mode: &AdvanceMode, weight: f32) -> (Vec<f32>, f32) {
+        let (mut font_ctx, mut layout_ctx) = test_contexts();
+        let mut kind = test_variable_font("10:27", 40.);
+        kind.apply_animated(AnimationTarget::Axis(WGHT), AnimatedValue::Scalar(weight));
+        SurfaceUpdate::SetAdvanceMode(mode.clone()).apply(&mut kind);
+        let layout = kind.layout(&mut font_ctx, &mut layout_ctx).unwrap();
+        let axes = [FontVariation {
+            tag: WGHT,
+            value: weight,
+        }];
+        let mut positions = glyph_positions(&layout, mode, &axes);
+        let mut centres = Vec::new();
+        for line in layout.lines() {
+            for item in line.items() {
+                let PositionedLayoutItem::GlyphRun(glyph_run) = item else {
+                    continue;
+                };
+                let mut x = glyph_run.offset();
+                for glyph in glyph_run.glyphs() {
+                    let gx = match &mut positions {
+                        Some(positions) => positions.remove(0),
+                        None => x + glyph.x,
+                    };
+                    centres.push(gx - glyph.x + glyph.advance / 2.);
+                    x += glyph.advance;
+                }
+            }
+        }
+        (centres, layout.width())
+    }
+
+    #[test]
+    fn glyphs_stay_still_as_weight_changes() {
+        let (thin, thin_width) = centres(&AdvanceMode::Natural, 100.);
+        let (black, black_width) = centres(&AdvanceMode::Natural, 900.);
+        assert!(thin_width < black_width, "{thin_width} < {black_width}");
+        assert_ne!(thin[1..], black[1..]);
+
+        let reference = AdvanceMode::Reference(vec![FontVariation {
+            tag: WGHT,
+            value: 400.,
+        }]);
+        let monospace = AdvanceMode::Monospace;
+        for mode in [reference, monospace] {
+            let (thin, _) = centres(&mode, 100.);
+            let (black, _) = centres(&mode, 900.);
+            assert_eq!(thin.len(), 5);
+            for (thin, black) in thin.iter().zip(&black) {
+                assert!((thin - black).abs() < 0.01, "{mode:?}: {thin} != {black}");
+            }
+        }
+    }
+
+    #[test]
+    fn monospace_cells_are_equal() {
+        let (centres, natural_width) = centres(&AdvanceMode::Monospace, 400.);
+        let cells = centres
+            .windows(2)
+            .map(|pair| pair[1] - pair[0])
+            .collect::<Vec<_>>();
+        assert!(cells.iter().all(|cell| (cell - cells[0]).abs() < 0.01));
+        // ':' is much narrower than the digits, so is given a wider cell than it needs.
+        assert!(cells[0] * 5. > natural_width);
+    }
+}
@@ -0,0 +1,464 @@
+//! Animations which are described once from Kotlin, then evaluated by the render thread each frame.
+//!
+//! This avoids the several JNI calls per frame which would otherwise be needed to animate
+//! a surface's parameters from the Compose side.
+
+use std::time::{Duration, Instant};
+
+use parley::swash::Tag;
+use vello::peniko::Color;
+
+use crate::SurfaceId;
+
+/// An identifier for an animation, chosen by the Kotlin side.
+pub type AnimationId = i64;
+
+/// The property of a surface which an [`Animation`] controls.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnimationTarget {
+    /// The font size, in sp.
+    FontSize,
+    /// A font variation axis, such as `wght` or `wdth`.
+    Axis(Tag),
+    /// The colour of the text.
+    Color,
+}
+
+impl AnimationTarget {
+    /// The number of floats needed to describe a single value of this target.
+    pub fn components(self) -> usize {
+        match self {
+            AnimationTarget::FontSize | AnimationTarget::Axis(_) => 1,
+            AnimationTarget::Color => 4,
+        }
+    }
+}
+
+/// A value produced by sampling an [`Animation`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AnimatedValue {
+    Scalar(f32),
+    Color(Color),
+}
+
+/// A single value in an animation's timeline.
+#[derive(Clone, Copy, Debug)]
+pub struct Keyframe {
+    /// How far through a single iteration this keyframe is, from 0.0 to 1.0.
+    pub fraction: f32,
+    /// The value at this point, in straight-alpha RGBA for colours.
+    ///
+    /// Only the first [`AnimationTarget::components`] values are meaningful.
+    pub value: [f32; 4],
+}
+
+/// The curve used to map linear time to progress through an iteration.
+#[derive(Clone, Copy, Debug)]
+pub enum Easing {
+    Linear,
+    /// A CSS-style cubic Bézier curve, with implicit end points at (0, 0) and (1, 1).
+    ///
+    /// This matches the `CubicBezierEasing` type in Compose.
+    CubicBezier {
+        x1: f32,
+        y1: f32,
+        x2: f32,
+        y2: f32,
+    },
+}
+
+impl Easing {
+    /// Map the linear progress `t` (from 0.0 to 1.0) to eased progress.
+    pub fn transform(self, t: f32) -> f32 {
+        match self {
+            Easing::Linear => t,
+            Easing::CubicBezier { x1, y1, x2, y2 } => {
+                if t <= 0.0 || t >= 1.0 {
+                    return t.clamp(0.0, 1.0);
+                }
+                fn bezier(a: f32, b: f32, s: f32) -> f32 {
+                    let inv = 1.0 - s;
+                    3.0 * a * inv * inv * s + 3.0 * b * inv * s * s + s * s * s
+                }
+                // Find the parameter `s` of the curve with x-coordinate `t` by bisection.
+                // The x-coordinate is monotonic for any valid easing curve (i.e. with `x1` and
+                // `x2` in `0..=1`), so this always converges.
+                let (mut low, mut high) = (0.0_f32, 1.0_f32);
+                let mut s = t;
+                for _ in 0..24 {
+                    let x = bezier(x1, x2, s);
+                    if (x - t).abs() < 1e-5 {
+                        break;
+                    }
+                    if x < t {
+                        low = s;
+                    } else {
+                        high = s;
+                    }
+                    s = (low + high) / 2.0;
+                }
+                bezier(y1, y2, s)
+            }
+        }
+    }
+}
+
+/// What happens when an animation reaches the end of an iteration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RepeatMode {
+    /// Jump back to the first keyframe.
+    Restart,
+    /// Play the next iteration backwards.
+    Reverse,
+}
+
+/// A keyframe animation of a single property of a surface.
+#[derive(Clone, Debug)]
+pub struct Animation {
+    pub target: AnimationTarget,
+    /// The keyframes, sorted by [`Keyframe::fraction`].
+    pub keyframes: Vec<Keyframe>,
+    /// The duration of a single iteration.
+    pub duration: Duration,
+    pub easing: Easing,
+    pub repeat_mode: RepeatMode,
+    /// The number of iterations to play, or `None` to repeat forever.
+    pub iterations: Option<u32>,
+}
+
+impl Animation {
+    /// Build an animation from the flattened form used over JNI.
+    ///
+    /// `values` contains [`AnimationTarget::components`] entries for each of the `fractions`.
+    /// Returns `None` if the arrays are inconsistent.
+    pub fn from_flattened(
+        target: AnimationTarget,
+        fractions: &[f32],
+        values: &[f32],
+        duration: Duration,
+        easing: Easing,
+        repeat_mode: RepeatMode,
+        iterations: Option<u32>,
+    ) -> Option<Self> {
+        let components = target.components();
+        if fractions.is_empty() || fractions.len() * components != values.len() {
+            return None;
+        }
+        let mut keyframes = fractions
+            .iter()
+            .zip(values.chunks_exact(components))
+            .map(|(fraction, value)| {
+                let mut keyframe = Keyframe {
+                    fraction: fraction.clamp(0.0, 1.0),
+                    value: [0.0; 4],
+                };
+                keyframe.value[..components].copy_from_slice(value);
+                keyframe
+            })
+            .collect::<Vec<_>>();
+        keyframes.sort_by(|a, b| a.fraction.total_cmp(&b.fraction));
+        Some(Self {
+            target,
+            keyframes,
+            duration,
+            easing,
+            repeat_mode,
+            iterations,
+        })
+    }
+
+    /// Sample this animation `elapsed` after it started.
+    ///
+    /// Also returns whether the animation has finished, in which case the value is the final value.
+    pub fn sample(&self, elapsed: Duration) -> (AnimatedValue, bool) {
+        let (progress, finished) = self.progress(elapsed);
+        let eased = self.easing.transform(progress);
+        (self.interpolate(eased), finished)
+    }
+
+    /// The linear progress through the current iteration, accounting for the repeat mode.
+    fn progress(&self, elapsed: Duration) -> (f32, bool) {
+        let duration = self.duration.as_secs_f64();
+        if duration <= 0.0 {
+            return (self.final_progress(), true);
+        }
+        let iterations_elapsed = elapsed.as_secs_f64() / duration;
+        if let Some(iterations) = self.iterations {
+            if iterations_elapsed >= f64::from(iterations) {
+                return (self.final_progress(), true);
+            }
+        }
+        let iteration = iterations_elapsed.floor();
+        let fraction = (iterations_elapsed - iteration) as f32;
+        let reversed = self.repeat_mode == RepeatMode::Reverse && iteration % 2.0 == 1.0;
+        (if reversed { 1.0 - fraction } else { fraction }, false)
+    }
+
+    /// The progress at which this animation rests once all of its iterations have played.
+    fn final_progress(&self) -> f32 {
+        match (self.repeat_mode, self.iterations) {
+            (RepeatMode::Reverse, Some(iterations)) if iterations % 2 == 0 => 0.0,
+            _ => 1.0,
+        }
+    }
+
+    fn interpolate(&self, progress: f32) -> AnimatedValue {
+        let value = match self
+            .keyframes
+            .iter()
+            .position(|keyframe| keyframe.fraction >= progress)
+        {
+            None => self.keyframes.last().unwrap().value,
+            Some(0) => self.keyframes[0].value,
+            Some(index) => {
+                let from = &self.keyframes[index - 1];
+                let to = &self.keyframes[index];
+                let span = to.fraction - from.fraction;
+                let t = if span > 0.0 {
+                    (progress - from.fraction) / span
+                } else {
+                    1.0
+                };
+                std::array::from_fn(|i| from.value[i] + (to.value[i] - from.value[i]) * t)
+            }
+        };
+        match self.target {
+            AnimationTarget::FontSize | AnimationTarget::Axis(_) => AnimatedValue::Scalar(value[0]),
+            AnimationTarget::Color => {
+                let [r, g, b, a] = value.map(f64::from);
+                AnimatedValue::Color(Color::rgba(r, g, b, a))
+            }
+        }
+    }
+}
+
+/// An animation which has been attached to a surface on the render thread.
+pub(crate) struct RunningAnimation {
+    pub(crate) id: AnimationId,
+    pub(crate) animation: Animation,
+    /// The time of the first frame this animation was sampled for.
+    ///
+    /// We start the clock at the first frame rather than when the animation was requested,
+    /// so that the start of the animation is never skipped.
+    start: Option<Instant>,
+    /// The value most recently applied to the surface, if the animation has been sampled.
+    value: Option<AnimatedValue>,
+}
+
+impl RunningAnimation {
+    pub(crate) fn new(id: AnimationId, animation: Animation) -> Self {
+        Self {
+            id,
+            animation,
+            start: None,
+            value: None,
+        }
+    }
+
+    pub(crate) fn sample(&mut self, now: Instant) -> (AnimatedValue, bool) {
+        let start = *self.start.get_or_insert(now);
+        let (value, finished) = self.animation.sample(now.saturating_duration_since(start));
+        self.value = Some(value);
+        (value, finished)
+    }
+
+    /// The notification that this animation on `surface_id` ended, with the value it left its
+    /// property at.
+    pub(crate) fn ended(&self, surface_id: SurfaceId, reason: EndReason) -> AnimationEnded {
+        AnimationEnded {
+            surface_id,
+            animation_id: self.id,
+            reason,
+            value: self.value,
+        }
+    }
+}
+
+/// Why an animation is no longer running.
+///
+/// The discriminants are used in the FFI, so must match `AnimationEndReason` in Kotlin.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum EndReason {
+    /// The animation played all of its iterations.
+    Completed = 0,
+    /// The animation was replaced by another animation of the same target, or its surface was
+    /// destroyed.
+    Replaced = 1,
+    /// Kotlin cancelled the animation.
+    Cancelled = 2,
+}
+
+/// A notification that an animation is no longer running.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct AnimationEnded {
+    pub(crate) surface_id: SurfaceId,
+    pub(crate) animation_id: AnimationId,
+    pub(crate) reason: EndReason,
+    /// The value the animation left its property at, or `None` if it never ran.
+    ///
+    /// The property keeps this value until it is next changed, so Kotlin's copy of it must be
+    /// updated to match.
+    pub(crate) value: Option<AnimatedValue>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use vello::peniko::Color;
+
+    use super::{
+        AnimatedValue, Animation, AnimationTarget, Easing, EndReason, RepeatMode, RunningAnimation,
+    };
+
+    /// A linear animation of the font size from `from` to `to` over a second.
+    fn font_size(
+        from: f32,
+        to: f32,
+        repeat_mode: RepeatMode,
+        iterations: Option<u32>,
+    ) -> Animation {
+        Animation::from_flattened(
+            AnimationTarget::FontSize,
+            &[0., 1.],
+            &[from, to],
+            Duration::from_secs(1),
+            Easing::Linear,
+            repeat_mode,
+            iterations,
+        )
+        .unwrap()
+    }
+
+    /// The value of `animation` `seconds` after it started, and whether it has finished.
+    fn sample(animation: &Animation, seconds: f32) -> (f32, bool) {
+        match animation.sample(Duration::from_secs_f32(seconds)) {
+            (AnimatedValue::Scalar(value), finished) => (value, finished),
+            (value, _) => panic!("Expected a scalar, got {value:?}"),
+        }
+    }
+
+    fn assert_near(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-3, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn cubic_bezier_easing() {
+        // This curve is symmetric about its midpoint.
+        let ease_in_out = Easing::CubicBezier {
+            x1: 0.42,
+            y1: 0.,
+            x2: 0.58,
+            y2: 1.,
+        };
+        assert_near(ease_in_out.transform(0.5), 0.5);
+        assert_near(
+            ease_in_out.transform(0.25) + ease_in_out.transform(0.75),
+            1.,
+        );
+        assert!(ease_in_out.transform(0.25) < 0.25);
+        // Control points on the diagonal make a linear curve.
+        let linear = Easing::CubicBezier {
+            x1: 1. / 3.,
+            y1: 1. / 3.,
+            x2: 2. / 3.,
+            y2: 2. / 3.,
+        };
+        for t in [0.1, 0.3, 0.6, 0.9] {
+            assert_near(linear.transform(t), t);
+        }
+        // Values outside of the curve are clamped to its ends.
+        assert_eq!(ease_in_out.transform(-0.5), 0.);
+        assert_eq!(ease_in_out.transform(1.5), 1.);
+    }
+
+    #[test]
+    fn repeat_modes() {
+        let restart = font_size(10., 20., RepeatMode::Restart, None);
+        assert_near(sample(&restart, 0.25).0, 12.5);
+        assert_near(sample(&restart, 1.25).0, 12.5);
+        assert_near(sample(&restart, 2.0).0, 10.);
+
+        let reverse = font_size(10., 20., RepeatMode::Reverse, None);
+        assert_near(sample(&reverse, 0.25).0, 12.5);
+        // The second iteration plays backwards, and the third forwards again.
+        assert_near(sample(&reverse, 1.25).0, 17.5);
+        assert_near(sample(&reverse, 1.5).0, 15.);
+        assert_near(sample(&reverse, 2.0).0, 10.);
+        assert_near(sample(&reverse, 2.25).0, 12.5);
+        assert!(!sample(&reverse, 1000.).1);
+    }
+
+    #[test]
+    fn finite_animations_finish_at_their_final_value() {
+        for (repeat_mode, iterations, last) in [
+            (RepeatMode::Restart, 1, 20.),
+            (RepeatMode::Restart, 2, 20.),
+            (RepeatMode::Reverse, 2, 10.),
+            (RepeatMode::Reverse, 3, 20.),
+        ] {
+            let animation = font_size(10., 20., repeat_mode, Some(iterations));
+            let before_end = iterations as f32 - 0.5;
+            assert!(!sample(&animation, before_end).1);
+            let after_end = sample(&animation, iterations as f32 + 0.5);
+            assert_eq!(after_end, (last, true), "{repeat_mode:?} {iterations}");
+        }
+        // An animation without a duration finishes immediately.
+        let mut instant = font_size(10., 20., RepeatMode::Reverse, Some(2));
+        instant.duration = Duration::ZERO;
+        assert_eq!(sample(&instant, 0.), (10., true));
+    }
+
+    #[test]
+    fn flattened_animations() {
+        let from_flattened = |target, fractions: &[f32], values: &[f32]| {
+            Animation::from_flattened(
+                target,
+                fractions,
+                values,
+                Duration::from_secs(1),
+                Easing::Linear,
+                RepeatMode::Restart,
+                None,
+            )
+        };
+        assert!(from_flattened(AnimationTarget::FontSize, &[0., 1.], &[10.]).is_none());
+        assert!(from_flattened(AnimationTarget::FontSize, &[], &[]).is_none());
+        assert!(from_flattened(AnimationTarget::Color, &[0., 1.], &[0., 0., 0., 1.]).is_none());
+
+        // Keyframes are sorted, and their fractions clamped.
+        let animation =
+            from_flattened(AnimationTarget::FontSize, &[1.5, 0., 0.5], &[30., 10., 20.]).unwrap();
+        let keyframes = animation
+            .keyframes
+            .iter()
+            .map(|keyframe| (keyframe.fraction, keyframe.value[0]));
+        assert_eq!(
+            keyframes.collect::<Vec<_>>(),
+            [(0., 10.), (0.5, 20.), (1., 30.)]
+        );
+        assert_near(sample(&animation, 0.75).0, 25.);
+
+        let color = from_flattened(
+            AnimationTarget::Color,
+            &[0., 1.],
+            &[0., 0., 0., 1., 1., 1., 1., 1.],
+        )
+        .unwrap();
+        let (value, _) = color.sample(Duration::from_millis(500));
+        assert_eq!(value, AnimatedValue::Color(Color::rgba(0.5, 0.5, 0.5, 1.)));
+    }
+
+    #[test]
+    fn ended_animations_report_their_last_value() {
+        let mut running =
+            RunningAnimation::new(7, font_size(10., 20., RepeatMode::Restart, Some(1)));
+        assert_eq!(running.ended(1, EndReason::Replaced).value, None);
+        let start = Instant::now();
+        running.sample(start);
+        running.sample(start + Duration::from_millis(500));
+        let end = running.ended(1, EndReason::Cancelled);
+        assert_eq!((end.animation_id, end.reason), (7, EndReason::Cancelled));
+        assert_eq!(end.value, Some(AnimatedValue::Scalar(15.)));
+    }
+}
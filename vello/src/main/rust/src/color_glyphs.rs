@@ -0,0 +1,494 @@
+//! Colour glyphs, such as emoji, drawn from the `COLR` table with a chosen `CPAL` palette.
+//!
+//! Vello can draw `COLR` glyphs itself, but always uses the first palette of the font. Text
+//! surfaces instead paint them here, so that Kotlin can pick another palette and replace some
+//! of its colours. Bitmap glyphs (from `CBDT` or `sbix`) have their colours built in, so are
+//! still drawn by Vello.
+
+use vello::{
+    kurbo::{Affine, BezPath, Point, Rect},
+    peniko::{Brush, Color, ColorStop, Compose, Extend, Fill, Gradient, Mix},
+    skrifa::{
+        color::{self, ColorPainter, CompositeMode},
+        instance::{LocationRef, Size},
+        outline::{DrawSettings, OutlinePen},
+        raw::{
+            tables::cpal::Cpal,
+            types::{BoundingBox, Point as FontPoint},
+            TableProvider,
+        },
+        FontRef, GlyphId, MetadataProvider, OutlineGlyphCollection,
+    },
+    Scene,
+};
+
+/// The palette index which `COLR` uses for the colour of the text.
+const FOREGROUND_INDEX: u16 = 0xFFFF;
+
+/// Paints are clipped to this box, in font units, if the glyph doesn't have a clip box.
+const DEFAULT_CLIP_BOX: Rect = Rect::new(-100_000., -100_000., 100_000., 100_000.);
+
+/// Which colours of the font's `CPAL` table colour glyphs are drawn with.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ColorPalette {
+    /// The index of the palette in the font. Fonts without this palette use their first one.
+    pub index: u16,
+    /// Colours which replace the entry of the palette at their index.
+    pub overrides: Vec<(u16, Color)>,
+}
+
+impl ColorPalette {
+    /// The colours of this palette in `cpal`, with the overrides applied.
+    pub(crate) fn colors(&self, cpal: &Cpal<'_>) -> Vec<Color> {
+        let index = if self.index < cpal.num_palettes() {
+            self.index
+        } else {
+            0
+        };
+        let first = cpal
+            .color_record_indices()
+            .get(usize::from(index))
+            .map_or(0, |first| usize::from(first.get()));
+        let records = match cpal.color_records_array() {
+            Some(Ok(records)) => records,
+            _ => &[],
+        };
+        let mut colors = (first..first + usize::from(cpal.num_palette_entries()))
+            .map(|index| {
+                records.get(index).map_or(Color::TRANSPARENT, |record| {
+                    Color::rgba8(record.red, record.green, record.blue, record.alpha)
+                })
+            })
+            .collect::<Vec<_>>();
+        for &(index, color) in &self.overrides {
+            if let Some(entry) = colors.get_mut(usize::from(index)) {
+                *entry = color;
+            }
+        }
+        colors
+    }
+}
+
+/// How the colours of a colour glyph are chosen.
+pub(crate) enum GlyphColors<'a> {
+    /// Use the colours of the palette, with `foreground` for the colour of the text.
+    Palette {
+        palette: &'a [Color],
+        foreground: &'a Brush,
+    },
+    /// Draw every part of the glyph in `color`, such as for its shadow.
+    Tint(Color),
+}
+
+impl GlyphColors<'_> {
+    /// The colour of the palette entry `index`, multiplied by `alpha`.
+    fn color(&self, index: u16, alpha: f32) -> Brush {
+        match self {
+            GlyphColors::Tint(color) => Brush::Solid(color.multiply_alpha(alpha)),
+            GlyphColors::Palette { foreground, .. } if index == FOREGROUND_INDEX => {
+                (*foreground).clone().multiply_alpha(alpha)
+            }
+            GlyphColors::Palette { palette, .. } => Brush::Solid(
+                palette
+                    .get(usize::from(index))
+                    .copied()
+                    .unwrap_or(Color::TRANSPARENT)
+                    .multiply_alpha(alpha),
+            ),
+        }
+    }
+
+    /// The colour of a gradient stop, which can't be a gradient itself.
+    fn stop_color(&self, index: u16, alpha: f32) -> Color {
+        match self.color(index, alpha) {
+            Brush::Solid(color) => color,
+            // A gradient foreground has no single colour, so use the colour it starts with.
+            Brush::Gradient(gradient) => gradient
+                .stops
+                .first()
+                .map_or(Color::BLACK, |stop| stop.color.multiply_alpha(alpha)),
+            Brush::Image(_) => Color::BLACK,
+        }
+    }
+
+    /// Convert a `COLR` brush, which is in font units.
+    pub(crate) fn brush(&self, brush: color::Brush<'_>) -> Brush {
+        let stops = |stops: &[color::ColorStop]| {
+            stops
+                .iter()
+                .map(|stop| ColorStop {
+                    offset: stop.offset,
+                    color: self.stop_color(stop.palette_index, stop.alpha),
+                })
+                .collect::<Vec<_>>()
+        };
+        match brush {
+            color::Brush::Solid {
+                palette_index,
+                alpha,
+            } => self.color(palette_index, alpha),
+            color::Brush::LinearGradient {
+                p0,
+                p1,
+                color_stops,
+                extend,
+            } => Brush::Gradient(
+                Gradient::new_linear(point(p0), point(p1))
+                    .with_extend(convert_extend(extend))
+                    .with_stops(stops(color_stops).as_slice()),
+            ),
+            color::Brush::RadialGradient {
+                c0,
+                r0,
+                c1,
+                r1,
+                color_stops,
+                extend,
+            } => Brush::Gradient(
+                Gradient::new_two_point_radial(point(c0), r0, point(c1), r1)
+                    .with_extend(convert_extend(extend))
+                    .with_stops(stops(color_stops).as_slice()),
+            ),
+            color::Brush::SweepGradient {
+                c0,
+                start_angle,
+                end_angle,
+                color_stops,
+                extend,
+            } => Brush::Gradient(
+                Gradient::new_sweep(point(c0), start_angle, end_angle)
+                    .with_extend(convert_extend(extend))
+                    .with_stops(stops(color_stops).as_slice()),
+            ),
+        }
+    }
+}
+
+/// Draw the `COLR` glyph `glyph_id` of `font`, whose origin is moved to the surface by
+/// `transform`, at `size` pixels per em.
+///
+/// Nothing is drawn if the font has no colour glyph for `glyph_id`.
+pub(crate) fn draw_color_glyph(
+    scene: &mut Scene,
+    font: &FontRef<'_>,
+    glyph_id: GlyphId,
+    location: LocationRef<'_>,
+    size: f32,
+    transform: Affine,
+    colors: &GlyphColors<'_>,
+) {
+    let Some(glyph) = font.color_glyphs().get(glyph_id) else {
+        return;
+    };
+    let upem = font.head().map_or(1000, |head| head.units_per_em());
+    let scale = f64::from(size) / f64::from(upem);
+    // Font units are y-up, but the surface is y-down.
+    let transform = transform * Affine::scale_non_uniform(scale, -scale);
+    let mut painter = ScenePainter {
+        scene,
+        outlines: font.outline_glyphs(),
+        location,
+        colors,
+        transforms: vec![transform],
+        clip_box: DEFAULT_CLIP_BOX,
+        clip_depth: 0,
+    };
+    if let Err(e) = glyph.paint(location, &mut painter) {
+        log::warn!("Failed to paint colour glyph {glyph_id}: {e}");
+    }
+}
+
+/// Whether `font` has a colour glyph for `glyph_id`.
+pub(crate) fn is_color_glyph(font: &FontRef<'_>, glyph_id: GlyphId) -> bool {
+    font.color_glyphs().get(glyph_id).is_some()
+}
+
+/// Paints a colour glyph into a [`Scene`].
+struct ScenePainter<'a> {
+    scene: &'a mut Scene,
+    outlines: OutlineGlyphCollection<'a>,
+    location: LocationRef<'a>,
+    colors: &'a GlyphColors<'a>,
+    /// The transform from the current paint's coordinates to the surface.
+    transforms: Vec<Affine>,
+    /// The outermost clip box, which bounds fills of the whole clip.
+    clip_box: Rect,
+    clip_depth: u32,
+}
+
+impl ScenePainter<'_> {
+    fn transform(&self) -> Affine {
+        self.transforms.last().copied().unwrap_or_default()
+    }
+
+    /// The outline of `glyph_id` in font units, if it has one.
+    fn outline(&self, glyph_id: GlyphId) -> Option<BezPath> {
+        let outline = self.outlines.get(glyph_id)?;
+        let mut path = PathPen(BezPath::new());
+        let settings = DrawSettings::unhinted(Size::unscaled(), self.location);
+        outline.draw(settings, &mut path).ok()?;
+        Some(path.0)
+    }
+}
+
+impl ColorPainter for ScenePainter<'_> {
+    fn push_transform(&mut self, transform: color::Transform) {
+        let transform = self.transform() * convert_transform(transform);
+        self.transforms.push(transform);
+    }
+
+    fn pop_transform(&mut self) {
+        self.transforms.pop();
+    }
+
+    fn push_clip_glyph(&mut self, glyph_id: GlyphId) {
+        let path = self.outline(glyph_id).unwrap_or_default();
+        self.clip_depth += 1;
+        self.scene
+            .push_layer(Mix::Clip, 1.0, self.transform(), &path);
+    }
+
+    fn push_clip_box(&mut self, clip_box: BoundingBox<f32>) {
+        let clip_box = Rect::new(
+            clip_box.x_min.into(),
+            clip_box.y_min.into(),
+            clip_box.x_max.into(),
+            clip_box.y_max.into(),
+        );
+        if self.clip_depth == 0 {
+            self.clip_box = clip_box;
+        }
+        self.clip_depth += 1;
+        self.scene
+            .push_layer(Mix::Clip, 1.0, self.transform(), &clip_box);
+    }
+
+    fn pop_clip(&mut self) {
+        self.scene.pop_layer();
+        self.clip_depth -= 1;
+        if self.clip_depth == 0 {
+            self.clip_box = DEFAULT_CLIP_BOX;
+        }
+    }
+
+    fn fill(&mut self, brush: color::Brush<'_>) {
+        let brush = self.colors.brush(brush);
+        self.scene.fill(
+            Fill::NonZero,
+            self.transform(),
+            &brush,
+            None,
+            &self.clip_box,
+        );
+    }
+
+    fn fill_glyph(
+        &mut self,
+        glyph_id: GlyphId,
+        brush_transform: Option<color::Transform>,
+        brush: color::Brush<'_>,
+    ) {
+        let Some(path) = self.outline(glyph_id) else {
+            return;
+        };
+        self.scene.fill(
+            Fill::NonZero,
+            self.transform(),
+            &self.colors.brush(brush),
+            brush_transform.map(convert_transform),
+            &path,
+        );
+    }
+
+    fn push_layer(&mut self, composite_mode: CompositeMode) {
+        let compose = match composite_mode {
+            CompositeMode::Clear => Compose::Clear,
+            CompositeMode::Src => Compose::Copy,
+            CompositeMode::Dest => Compose::Dest,
+            CompositeMode::SrcOver => Compose::SrcOver,
+            CompositeMode::DestOver => Compose::DestOver,
+            CompositeMode::SrcIn => Compose::SrcIn,
+            CompositeMode::DestIn => Compose::DestIn,
+            CompositeMode::SrcOut => Compose::SrcOut,
+            CompositeMode::DestOut => Compose::DestOut,
+            CompositeMode::SrcAtop => Compose::SrcAtop,
+            CompositeMode::DestAtop => Compose::DestAtop,
+            CompositeMode::Xor => Compose::Xor,
+            CompositeMode::Plus => Compose::Plus,
+            // Vello doesn't support the blend modes of COLRv1 yet.
+            _ => Compose::SrcOver,
+        };
+        self.scene
+            .push_layer(compose, 1.0, self.transform(), &self.clip_box);
+    }
+
+    fn pop_layer(&mut self) {
+        self.scene.pop_layer();
+    }
+}
+
+/// Collects an outline into a [`BezPath`].
+struct PathPen(BezPath);
+
+impl OutlinePen for PathPen {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.0.move_to((x, y));
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        self.0.line_to((x, y));
+    }
+
+    fn quad_to(&mut self, cx0: f32, cy0: f32, x: f32, y: f32) {
+        self.0.quad_to((cx0, cy0), (x, y));
+    }
+
+    fn curve_to(&mut self, cx0: f32, cy0: f32, cx1: f32, cy1: f32, x: f32, y: f32) {
+        self.0.curve_to((cx0, cy0), (cx1, cy1), (x, y));
+    }
+
+    fn close(&mut self) {
+        self.0.close_path();
+    }
+}
+
+fn convert_transform(transform: color::Transform) -> Affine {
+    Affine::new(
+        [
+            transform.xx,
+            transform.yx,
+            transform.xy,
+            transform.yy,
+            transform.dx,
+            transform.dy,
+        ]
+        .map(f64::from),
+    )
+}
+
+fn point(point: FontPoint<f32>) -> Point {
+    Point::new(point.x.into(), point.y.into())
+}
+
+fn convert_extend(extend: color::Extend) -> Extend {
+    match extend {
+        color::Extend::Repeat => Extend::Repeat,
+        color::Extend::Reflect => Extend::Reflect,
+        _ => Extend::Pad,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vello::{
+        peniko::{Brush, Color},
+        skrifa::{
+            color::{self, ColorStop},
+            raw::{tables::cpal::Cpal, types::Point, FontData, FontRead},
+        },
+    };
+
+    use super::{ColorPalette, GlyphColors, FOREGROUND_INDEX};
+
+    const RED: Color = Color::rgba8(255, 0, 0, 255);
+    const GREEN: Color = Color::rgba8(0, 255, 0, 255);
+    const BLUE: Color = Color::rgba8(0, 0, 255, 255);
+    const WHITE: Color = Color::rgba8(255, 255, 255, 128);
+
+    /// A version 0 `CPAL` table with the palettes `[RED, GREEN]` and `[BLUE, WHITE]`.
+    fn cpal_data() -> Vec<u8> {
+        let mut data = Vec::new();
+        // Version, entries per palette, palettes and colour records.
+        for value in [0_u16, 2, 2, 4] {
+            data.extend(value.to_be_bytes());
+        }
+        // The offset of the colour records, after the index of each palette's first record.
+        data.extend(16_u32.to_be_bytes());
+        for first in [0_u16, 2] {
+            data.extend(first.to_be_bytes());
+        }
+        for color in [RED, GREEN, BLUE, WHITE] {
+            data.extend([color.b, color.g, color.r, color.a]);
+        }
+        data
+    }
+
+    #[test]
+    fn palettes_can_be_chosen_and_overridden() {
+        let data = cpal_data();
+        let cpal = Cpal::read(FontData::new(&data)).unwrap();
+        assert_eq!(ColorPalette::default().colors(&cpal), [RED, GREEN]);
+        let second = ColorPalette {
+            index: 1,
+            overrides: Vec::new(),
+        };
+        assert_eq!(second.colors(&cpal), [BLUE, WHITE]);
+        let overridden = ColorPalette {
+            index: 1,
+            // Entries past the end of the palette are ignored.
+            overrides: vec![(1, RED), (7, GREEN)],
+        };
+        assert_eq!(overridden.colors(&cpal), [BLUE, RED]);
+        let missing = ColorPalette {
+            index: 2,
+            overrides: Vec::new(),
+        };
+        assert_eq!(
+            missing.colors(&cpal),
+            [RED, GREEN],
+            "Uses the first palette"
+        );
+    }
+
+    #[test]
+    fn brushes_use_the_palette() {
+        let foreground = Brush::Solid(Color::BLACK);
+        let colors = GlyphColors::Palette {
+            palette: &[RED, GREEN],
+            foreground: &foreground,
+        };
+        let solid = |palette_index| color::Brush::Solid {
+            palette_index,
+            alpha: 0.5,
+        };
+        assert_eq!(
+            colors.brush(solid(1)),
+            Brush::Solid(GREEN.multiply_alpha(0.5))
+        );
+        assert_eq!(
+            colors.brush(solid(FOREGROUND_INDEX)),
+            Brush::Solid(Color::BLACK.multiply_alpha(0.5))
+        );
+        assert_eq!(
+            GlyphColors::Tint(BLUE).brush(solid(0)),
+            Brush::Solid(BLUE.multiply_alpha(0.5))
+        );
+        let stops = [
+            ColorStop {
+                offset: 0.,
+                palette_index: 0,
+                alpha: 1.,
+            },
+            ColorStop {
+                offset: 1.,
+                palette_index: 1,
+                alpha: 1.,
+            },
+        ];
+        let gradient = colors.brush(color::Brush::LinearGradient {
+            p0: Point::new(0., 0.),
+            p1: Point::new(100., 0.),
+            color_stops: &stops,
+            extend: color::Extend::Pad,
+        });
+        let Brush::Gradient(gradient) = gradient else {
+            panic!("Expected a gradient, got {gradient:?}");
+        };
+        let stops = gradient
+            .stops
+            .iter()
+            .map(|stop| stop.color)
+            .collect::<Vec<_>>();
+        assert_eq!(stops, [RED, GREEN]);
+    }
+}
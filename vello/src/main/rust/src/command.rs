@@ -0,0 +1,948 @@
+//! The commands sent from Kotlin to the render thread.
+//!
+//! All state which Kotlin controls is changed by sending a [`Command`], and the render thread
+//! applies the commands in the order they were sent. Several commands are generally received
+//! between frames; these are applied as a batch, and the surfaces requested by each
+//! [`Command::RenderFrame`] in that batch are merged into a single frame.
+
+use std::{ops::Range, sync::mpsc::SyncSender, time::Instant};
+
+use parley::FontFeature;
+
+#[cfg(target_os = "android")]
+use ndk::native_window::NativeWindow;
+
+#[cfg(target_os = "android")]
+use crate::context::CreateSurfaceError;
+use crate::{
+    accessibility::AccessNode,
+    advances::AdvanceMode,
+    animation::{Animation, AnimationEnded, AnimationId},
+    color_glyphs::ColorPalette,
+    debug::DebugMode,
+    direction::TextDirection,
+    editor::{EditOp, EditorState, TextEditor},
+    effects::TextEffects,
+    features::{self, FeatureSpan},
+    hinting::TextRendering,
+    input::{HitId, PointerEvent, PointerEventKind, PointerHit},
+    params::ParameterBlock,
+    scale::TextScale,
+    selection::{utf16_to_byte, SelectedText, SelectionChange},
+    SurfaceId, SurfaceKind,
+};
+
+/// The id of a [`Command::RenderFrame`], assigned by Kotlin.
+pub(crate) type RequestId = i64;
+
+pub(crate) enum Command {
+    /// Start rendering to a new window.
+    ///
+    /// The result is sent to `reply`, so that creation failures can be reported to Kotlin.
+    #[cfg(target_os = "android")]
+    CreateSurface {
+        surface: NewSurface,
+        reply: SyncSender<Result<(), CreateSurfaceError>>,
+    },
+    /// Change the content of a surface.
+    UpdateSurface {
+        surface_id: SurfaceId,
+        update: SurfaceUpdate,
+    },
+    /// The window of a surface has changed size.
+    ResizeSurface {
+        surface_id: SurfaceId,
+        width: u32,
+        height: u32,
+    },
+    /// Stop rendering to a surface, and release its resources.
+    DestroySurface {
+        surface_id: SurfaceId,
+    },
+    /// Render a frame which includes `surfaces`.
+    RenderFrame {
+        surfaces: Vec<SurfaceId>,
+        /// The id Kotlin uses to match this request to the frame which presented it.
+        request_id: RequestId,
+        /// Where to send the outcome of the frame, once the GPU has finished it.
+        reply: Option<SyncSender<RenderStatus>>,
+    },
+    /// Change the selected text (or the position of the caret) of a surface.
+    SetSelection {
+        surface_id: SurfaceId,
+        change: SelectionChange,
+    },
+    /// Read the selected text of a surface, which is sent to `reply`.
+    GetSelection {
+        surface_id: SurfaceId,
+        reply: SyncSender<Option<SelectedText>>,
+    },
+    /// Read the accessibility tree of a surface, which is sent to `reply`.
+    GetAccessibilityTree {
+        surface_id: SurfaceId,
+        reply: SyncSender<Option<AccessNode>>,
+    },
+    /// Edit the text of a [`SurfaceKind::TextEditor`].
+    Edit {
+        surface_id: SurfaceId,
+        op: EditOp,
+    },
+    /// A pointer has changed over a surface, so should be hit tested against its content.
+    PointerEvent {
+        surface_id: SurfaceId,
+        event: PointerEvent,
+    },
+    StartAnimation {
+        surface_id: SurfaceId,
+        animation_id: AnimationId,
+        animation: Box<Animation>,
+    },
+    CancelAnimation {
+        surface_id: SurfaceId,
+        animation_id: AnimationId,
+    },
+    /// Read the values of a surface from `block`, which Kotlin writes to directly.
+    SetParameterBlock {
+        surface_id: SurfaceId,
+        block: ParameterBlock,
+    },
+    SetDebugOverlay(DebugMode),
+    /// Destroy every device, to test recovery from device loss.
+    SimulateDeviceLoss,
+    /// Stop the render thread. Any commands after this are ignored.
+    #[allow(
+        dead_code,
+        reason = "TODO: Send this when the Kotlin `Vello` is cleaned up"
+    )]
+    Finish,
+}
+
+/// The outcome of a [`Command::RenderFrame`] which Kotlin waits for.
+///
+/// The discriminants are used in the FFI, so must match `RenderStatus` in Kotlin.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderStatus {
+    /// Every requested surface was presented, and the GPU has finished rendering them.
+    Success = 0,
+    /// The frame wasn't finished within the timeout.
+    Timeout = 1,
+    /// At least one requested surface wasn't presented, such as if it had been destroyed.
+    SurfaceLost = 2,
+    /// A device was lost, so the frame may not have been rendered.
+    DeviceLost = 3,
+    /// The render thread stopped before finishing the frame, so it won't be rendered.
+    Stopped = 4,
+}
+
+impl RenderStatus {
+    /// The status of a frame which presented `presented`, for a request of `requested`.
+    pub fn for_frame(requested: &[SurfaceId], presented: &[SurfaceId], device_lost: bool) -> Self {
+        if device_lost {
+            Self::DeviceLost
+        } else if requested.iter().all(|id| presented.contains(id)) {
+            Self::Success
+        } else {
+            Self::SurfaceLost
+        }
+    }
+}
+
+/// A render request which Kotlin is waiting for.
+#[derive(Debug)]
+pub(crate) struct FrameWaiter {
+    pub(crate) surfaces: Vec<SurfaceId>,
+    pub(crate) reply: SyncSender<RenderStatus>,
+}
+
+/// The parameters of [`Command::CreateSurface`].
+#[cfg(target_os = "android")]
+pub(crate) struct NewSurface {
+    pub(crate) surface_id: SurfaceId,
+    pub(crate) window: NativeWindow,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    /// Overrides the present modes of the `VelloConfig` for this surface.
+    pub(crate) present_modes: Option<Vec<wgpu::PresentMode>>,
+}
+
+/// A change to the [`SurfaceKind`] of a surface.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum SurfaceUpdate {
+    /// Make the surface a [`SurfaceKind::VariableFont`], replacing its previous content.
+    SetVariableFont {
+        text: String,
+        size: f32,
+        weight: f32,
+    },
+    /// Change the text of a variable font surface.
+    VariableFontText(String),
+    /// Set the OpenType features of a variable font surface.
+    ///
+    /// If `range` is set, the features only apply to that UTF-16 range of the current text, and
+    /// take precedence over the features of the whole surface. Otherwise, they replace the
+    /// features of the whole surface.
+    SetFontFeatures {
+        features: Vec<FontFeature>,
+        range: Option<Range<usize>>,
+    },
+    /// Set how the glyphs of a variable font surface are spaced.
+    SetAdvanceMode(AdvanceMode),
+    /// Set the base direction of the paragraphs of a variable font surface.
+    SetTextDirection(TextDirection),
+    /// Set the BCP 47 language tag used to shape the text of a variable font surface, or use
+    /// the default shaping of each script if `None`.
+    SetLocale(Option<Box<str>>),
+    /// Set whether the glyphs of a variable font surface are hinted.
+    SetTextRendering(TextRendering),
+    /// Set how the font size of a variable font surface or text editor is converted to pixels.
+    SetTextScale(TextScale),
+    /// Set the outline and shadows of a variable font surface.
+    SetTextEffects(TextEffects),
+    /// Set the palette of the colour glyphs of a variable font surface.
+    SetColorPalette(ColorPalette),
+    /// Make the surface a [`SurfaceKind::TextEditor`], with the caret at the end of `text`.
+    SetTextEditor {
+        text: String,
+        multiline: bool,
+        size: f32,
+    },
+}
+
+impl SurfaceUpdate {
+    /// Apply this update to `kind`.
+    ///
+    /// Updates which don't apply to this kind of surface are ignored.
+    pub(crate) fn apply(self, kind: &mut SurfaceKind) {
+        match (self, kind) {
+            (Self::SetVariableFont { text, size, weight }, kind) => {
+                *kind = SurfaceKind::VariableFont {
+                    text,
+                    size,
+                    scale: TextScale::default(),
+                    weight,
+                    variations: Vec::new(),
+                    features: Vec::new(),
+                    feature_spans: Vec::new(),
+                    advance_mode: Box::default(),
+                    direction: TextDirection::Auto,
+                    locale: None,
+                    rendering: TextRendering::Smooth,
+                    color: vello::peniko::Color::BLACK,
+                    effects: Box::default(),
+                    palette: Box::default(),
+                    selection: None,
+                };
+            }
+            (
+                Self::VariableFontText(new_text),
+                SurfaceKind::VariableFont {
+                    text,
+                    selection,
+                    feature_spans,
+                    ..
+                },
+            ) => {
+                *text = new_text;
+                *selection = selection.map(|selection| selection.clamped(text));
+                *feature_spans = std::mem::take(feature_spans)
+                    .into_iter()
+                    .filter_map(|span| span.clamped(text))
+                    .collect();
+            }
+            (
+                Self::SetFontFeatures {
+                    features: new_features,
+                    range: None,
+                },
+                SurfaceKind::VariableFont { features, .. },
+            ) => *features = new_features,
+            (
+                Self::SetFontFeatures {
+                    features,
+                    range: Some(range),
+                },
+                SurfaceKind::VariableFont {
+                    text,
+                    feature_spans,
+                    ..
+                },
+            ) => {
+                let range = utf16_to_byte(text, range.start)..utf16_to_byte(text, range.end);
+                if !range.is_empty() {
+                    features::add_span(feature_spans, FeatureSpan { range, features });
+                }
+            }
+            (Self::SetAdvanceMode(mode), SurfaceKind::VariableFont { advance_mode, .. }) => {
+                **advance_mode = mode;
+            }
+            (
+                Self::SetTextDirection(new_direction),
+                SurfaceKind::VariableFont { direction, .. },
+            ) => {
+                *direction = new_direction;
+            }
+            (Self::SetLocale(new_locale), SurfaceKind::VariableFont { locale, .. }) => {
+                *locale = new_locale;
+            }
+            (
+                Self::SetTextRendering(new_rendering),
+                SurfaceKind::VariableFont { rendering, .. },
+            ) => {
+                *rendering = new_rendering;
+            }
+            (Self::SetTextScale(new_scale), SurfaceKind::VariableFont { scale, .. }) => {
+                *scale = new_scale;
+            }
+            (Self::SetTextScale(scale), SurfaceKind::TextEditor(editor)) => {
+                editor.set_scale(scale);
+            }
+            (Self::SetTextEffects(new_effects), SurfaceKind::VariableFont { effects, .. }) => {
+                **effects = new_effects;
+            }
+            (Self::SetColorPalette(new_palette), SurfaceKind::VariableFont { palette, .. }) => {
+                **palette = new_palette;
+            }
+            (
+                Self::SetTextEditor {
+                    text,
+                    multiline,
+                    size,
+                },
+                kind,
+            ) => {
+                let editor = TextEditor::new(text, multiline, size, Instant::now());
+                *kind = SurfaceKind::TextEditor(Box::new(editor));
+            }
+            (_, SurfaceKind::Unset | SurfaceKind::TextEditor(_)) => {}
+        }
+    }
+}
+
+/// The operations of the render thread's state which are controlled by [`Command`]s.
+pub(crate) trait CommandHandler {
+    #[cfg(target_os = "android")]
+    fn create_surface(&mut self, surface: NewSurface) -> Result<(), CreateSurfaceError>;
+    fn has_surface(&self, surface_id: SurfaceId) -> bool;
+    fn update_surface(&mut self, surface_id: SurfaceId, update: SurfaceUpdate);
+    fn resize_surface(&mut self, surface_id: SurfaceId, width: u32, height: u32);
+    /// Remove a surface, returning the end notifications of its running animations.
+    fn destroy_surface(&mut self, surface_id: SurfaceId) -> Vec<AnimationEnded>;
+    /// Find the content of a surface at `(x, y)`, in pixels from its top left.
+    fn hit_test(&mut self, surface_id: SurfaceId, x: f32, y: f32) -> Option<HitId>;
+    /// Change the selection of a surface, returning its new state if it is an editor.
+    fn set_selection(
+        &mut self,
+        surface_id: SurfaceId,
+        change: SelectionChange,
+    ) -> Option<EditorState>;
+    fn selected_text(&self, surface_id: SurfaceId) -> Option<SelectedText>;
+    /// Describe the content of a surface for accessibility services.
+    fn accessibility_tree(&mut self, surface_id: SurfaceId) -> Option<AccessNode>;
+    /// Edit a text editor surface, returning its new state.
+    fn edit(&mut self, surface_id: SurfaceId, op: EditOp) -> Option<EditorState>;
+    /// Start an animation, returning the end notification of any animation which it replaced.
+    fn start_animation(
+        &mut self,
+        surface_id: SurfaceId,
+        animation_id: AnimationId,
+        animation: Animation,
+    ) -> Option<AnimationEnded>;
+    /// Stop an animation, returning its end notification if it was still running.
+    fn cancel_animation(
+        &mut self,
+        surface_id: SurfaceId,
+        animation_id: AnimationId,
+    ) -> Option<AnimationEnded>;
+    fn set_parameter_block(&mut self, surface_id: SurfaceId, block: ParameterBlock);
+    fn set_debug_overlay(&mut self, mode: DebugMode);
+    fn simulate_device_loss(&mut self);
+}
+
+/// The outcome of applying a batch of commands with [`apply_commands`].
+#[derive(Debug, Default)]
+pub(crate) struct AppliedCommands {
+    /// The surfaces to render, in the order in which they were first requested.
+    pub(crate) frame: Vec<SurfaceId>,
+    /// The ids of the [`Command::RenderFrame`]s which were merged into `frame`.
+    pub(crate) requests: Vec<RequestId>,
+    /// The requests in `requests` which Kotlin is waiting for.
+    pub(crate) waiters: Vec<FrameWaiter>,
+    /// Animations which ended because they were replaced or cancelled, or their surface was
+    /// destroyed.
+    pub(crate) ended: Vec<AnimationEnded>,
+    /// The results of each [`Command::PointerEvent`] on a surface which exists.
+    pub(crate) hits: Vec<PointerHit>,
+    /// The latest state of each editor which was edited, or had its selection changed.
+    pub(crate) editors: Vec<(SurfaceId, EditorState)>,
+    /// Whether [`Command::Finish`] was received.
+    pub(crate) finished: bool,
+}
+
+/// Apply `commands` to `handler` in order.
+///
+/// Surfaces which are destroyed or unknown are left out of the frame, even if they were
+/// requested before being destroyed. Resized surfaces are always rendered, as the previous
+/// contents of their windows are no longer valid.
+pub(crate) fn apply_commands(
+    handler: &mut impl CommandHandler,
+    commands: impl IntoIterator<Item = Command>,
+) -> AppliedCommands {
+    let mut applied = AppliedCommands::default();
+    let frame = &mut applied.frame;
+    for command in commands {
+        match command {
+            #[cfg(target_os = "android")]
+            Command::CreateSurface { surface, reply } => {
+                // If Kotlin has stopped waiting, there's nobody to report to.
+                let _ = reply.send(handler.create_surface(surface));
+            }
+            Command::UpdateSurface { surface_id, update } => {
+                handler.update_surface(surface_id, update);
+            }
+            Command::ResizeSurface {
+                surface_id,
+                width,
+                height,
+            } => {
+                handler.resize_surface(surface_id, width, height);
+                add_to_frame(handler, frame, surface_id);
+            }
+            Command::DestroySurface { surface_id } => {
+                applied.ended.extend(handler.destroy_surface(surface_id));
+                frame.retain(|id| *id != surface_id);
+            }
+            Command::RenderFrame {
+                surfaces,
+                request_id,
+                reply,
+            } => {
+                applied.requests.push(request_id);
+                for surface_id in &surfaces {
+                    add_to_frame(handler, frame, *surface_id);
+                }
+                if let Some(reply) = reply {
+                    applied.waiters.push(FrameWaiter { surfaces, reply });
+                }
+            }
+            Command::SetSelection { surface_id, change } => {
+                if let Some(state) = handler.set_selection(surface_id, change) {
+                    set_editor_state(&mut applied.editors, surface_id, state);
+                }
+            }
+            Command::GetAccessibilityTree { surface_id, reply } => {
+                // If Kotlin has stopped waiting, there's nobody to report to.
+                let _ = reply.send(handler.accessibility_tree(surface_id));
+            }
+            Command::Edit { surface_id, op } => {
+                if let Some(state) = handler.edit(surface_id, op) {
+                    set_editor_state(&mut applied.editors, surface_id, state);
+                }
+            }
+            Command::GetSelection { surface_id, reply } => {
+                // If Kotlin has stopped waiting, there's nobody to report to.
+                let _ = reply.send(handler.selected_text(surface_id));
+            }
+            Command::PointerEvent { surface_id, event } => {
+                if !handler.has_surface(surface_id) {
+                    log::debug!(
+                        "Ignoring pointer event on surface {surface_id}, which doesn't exist"
+                    );
+                    continue;
+                }
+                let hit = match event.kind {
+                    PointerEventKind::Cancel => None,
+                    _ => handler.hit_test(surface_id, event.x, event.y),
+                };
+                applied.hits.push(PointerHit {
+                    surface_id,
+                    event,
+                    hit,
+                });
+            }
+            Command::StartAnimation {
+                surface_id,
+                animation_id,
+                animation,
+            } => {
+                applied
+                    .ended
+                    .extend(handler.start_animation(surface_id, animation_id, *animation));
+            }
+            Command::CancelAnimation {
+                surface_id,
+                animation_id,
+            } => {
+                applied
+                    .ended
+                    .extend(handler.cancel_animation(surface_id, animation_id));
+            }
+            Command::SetParameterBlock { surface_id, block } => {
+                handler.set_parameter_block(surface_id, block);
+            }
+            Command::SetDebugOverlay(mode) => handler.set_debug_overlay(mode),
+            Command::SimulateDeviceLoss => handler.simulate_device_loss(),
+            Command::Finish => {
+                applied.finished = true;
+                break;
+            }
+        }
+    }
+    applied
+}
+
+/// Record the state of an editor, replacing any earlier state from the same batch.
+fn set_editor_state(
+    editors: &mut Vec<(SurfaceId, EditorState)>,
+    surface_id: SurfaceId,
+    state: EditorState,
+) {
+    match editors.iter_mut().find(|(id, _)| *id == surface_id) {
+        Some((_, existing)) => *existing = state,
+        None => editors.push((surface_id, state)),
+    }
+}
+
+fn add_to_frame(handler: &impl CommandHandler, frame: &mut Vec<SurfaceId>, surface_id: SurfaceId) {
+    if !handler.has_surface(surface_id) {
+        log::debug!("Not rendering surface {surface_id}, which doesn't exist");
+    } else if !frame.contains(&surface_id) {
+        frame.push(surface_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, time::Instant};
+
+    use crate::{
+        accessibility::AccessNode,
+        animation::{Animation, AnimationEnded, AnimationId},
+        debug::DebugMode,
+        editor::{EditOp, EditorState},
+        input::{HitId, PointerEvent, PointerEventKind},
+        params::ParameterBlock,
+        selection::{SelectedText, SelectionChange, TextSelection},
+        test_contexts, SurfaceId, SurfaceKind,
+    };
+
+    use super::{apply_commands, Command, CommandHandler, RenderStatus, SurfaceUpdate};
+
+    /// A [`CommandHandler`] which records the operations applied to it.
+    #[derive(Default)]
+    struct Recorder {
+        surfaces: HashMap<SurfaceId, SurfaceKind>,
+        operations: Vec<String>,
+    }
+
+    impl Recorder {
+        fn with_surfaces(ids: &[SurfaceId]) -> Self {
+            Self {
+                surfaces: ids.iter().map(|id| (*id, SurfaceKind::Unset)).collect(),
+                operations: Vec::new(),
+            }
+        }
+
+        fn text(&self, surface_id: SurfaceId) -> &str {
+            match &self.surfaces[&surface_id] {
+                SurfaceKind::VariableFont { text, .. } => text,
+                SurfaceKind::TextEditor(editor) => editor.text(),
+                SurfaceKind::Unset => panic!("Surface {surface_id} is unset"),
+            }
+        }
+    }
+
+    impl CommandHandler for Recorder {
+        fn has_surface(&self, surface_id: SurfaceId) -> bool {
+            self.surfaces.contains_key(&surface_id)
+        }
+
+        fn update_surface(&mut self, surface_id: SurfaceId, update: SurfaceUpdate) {
+            self.operations.push(format!("update {surface_id}"));
+            if let Some(kind) = self.surfaces.get_mut(&surface_id) {
+                update.apply(kind);
+            }
+        }
+
+        fn resize_surface(&mut self, surface_id: SurfaceId, width: u32, height: u32) {
+            self.operations
+                .push(format!("resize {surface_id} {width}x{height}"));
+        }
+
+        fn destroy_surface(&mut self, surface_id: SurfaceId) -> Vec<AnimationEnded> {
+            self.operations.push(format!("destroy {surface_id}"));
+            self.surfaces.remove(&surface_id);
+            Vec::new()
+        }
+
+        fn hit_test(&mut self, surface_id: SurfaceId, x: f32, y: f32) -> Option<HitId> {
+            self.operations.push(format!("hit {surface_id} {x}x{y}"));
+            // Pretend that each surface's text is a row of 10 pixel wide characters.
+            let index = (x / 10.) as usize;
+            (index < self.text(surface_id).len()).then_some(index as HitId)
+        }
+
+        fn set_selection(
+            &mut self,
+            surface_id: SurfaceId,
+            change: SelectionChange,
+        ) -> Option<EditorState> {
+            self.operations.push(format!("select {surface_id}"));
+            if let Some(SurfaceKind::VariableFont {
+                text, selection, ..
+            }) = self.surfaces.get_mut(&surface_id)
+            {
+                let layout = || unreachable!("The tests only select by offsets");
+                *selection = TextSelection::apply(*selection, change, text, layout, Instant::now());
+            }
+            None
+        }
+
+        fn selected_text(&self, surface_id: SurfaceId) -> Option<SelectedText> {
+            match self.surfaces.get(&surface_id)? {
+                SurfaceKind::VariableFont {
+                    text, selection, ..
+                } => selection.map(|selection| selection.selected_text(text)),
+                SurfaceKind::TextEditor(editor) => {
+                    Some(editor.selection().selected_text(editor.text()))
+                }
+                SurfaceKind::Unset => None,
+            }
+        }
+
+        fn accessibility_tree(&mut self, surface_id: SurfaceId) -> Option<AccessNode> {
+            self.operations.push(format!("accessibility {surface_id}"));
+            None
+        }
+
+        fn edit(&mut self, surface_id: SurfaceId, op: EditOp) -> Option<EditorState> {
+            self.operations.push(format!("edit {surface_id}"));
+            let Some(SurfaceKind::TextEditor(editor)) = self.surfaces.get_mut(&surface_id) else {
+                return None;
+            };
+            let (mut font_ctx, mut layout_ctx) = test_contexts();
+            editor.apply(op, Instant::now(), &mut font_ctx, &mut layout_ctx);
+            Some(editor.state())
+        }
+
+        fn start_animation(
+            &mut self,
+            surface_id: SurfaceId,
+            animation_id: AnimationId,
+            _: Animation,
+        ) -> Option<AnimationEnded> {
+            self.operations
+                .push(format!("animate {surface_id} {animation_id}"));
+            None
+        }
+
+        fn cancel_animation(
+            &mut self,
+            surface_id: SurfaceId,
+            animation_id: AnimationId,
+        ) -> Option<AnimationEnded> {
+            self.operations
+                .push(format!("cancel {surface_id} {animation_id}"));
+            None
+        }
+
+        fn set_parameter_block(&mut self, surface_id: SurfaceId, _: ParameterBlock) {
+            self.operations.push(format!("parameters {surface_id}"));
+        }
+
+        fn set_debug_overlay(&mut self, mode: DebugMode) {
+            self.operations.push(format!("debug {mode:?}"));
+        }
+
+        fn simulate_device_loss(&mut self) {
+            self.operations.push("lose devices".to_string());
+        }
+    }
+
+    fn set_text(surface_id: SurfaceId, text: &str) -> Command {
+        Command::UpdateSurface {
+            surface_id,
+            update: SurfaceUpdate::SetVariableFont {
+                text: text.to_string(),
+                size: 12.,
+                weight: 400.,
+            },
+        }
+    }
+
+    fn pointer(surface_id: SurfaceId, kind: PointerEventKind, x: f32) -> Command {
+        Command::PointerEvent {
+            surface_id,
+            event: PointerEvent {
+                kind,
+                pointer_id: 1,
+                x,
+                y: 5.,
+                pressure: 1.,
+            },
+        }
+    }
+
+    fn render(surfaces: &[SurfaceId]) -> Command {
+        Command::RenderFrame {
+            surfaces: surfaces.to_vec(),
+            request_id: surfaces.len().try_into().unwrap(),
+            reply: None,
+        }
+    }
+
+    #[test]
+    fn coalesced_frames_merge_surfaces() {
+        let mut recorder = Recorder::with_surfaces(&[1, 2, 3]);
+        let applied = apply_commands(
+            &mut recorder,
+            [render(&[2, 1]), render(&[]), render(&[3, 2])],
+        );
+        assert_eq!(applied.frame, [2, 1, 3]);
+        assert_eq!(applied.requests, [2, 0, 2]);
+        assert!(!applied.finished);
+    }
+
+    #[test]
+    fn updates_apply_in_order() {
+        let mut recorder = Recorder::with_surfaces(&[1]);
+        let applied = apply_commands(
+            &mut recorder,
+            [
+                set_text(1, "first"),
+                render(&[1]),
+                set_text(1, "second"),
+                Command::UpdateSurface {
+                    surface_id: 1,
+                    update: SurfaceUpdate::VariableFontText("third".to_string()),
+                },
+                render(&[1]),
+            ],
+        );
+        // Only the latest content is rendered, as both frames are coalesced.
+        assert_eq!(recorder.text(1), "third");
+        assert_eq!(applied.frame, [1]);
+        assert_eq!(recorder.operations, ["update 1", "update 1", "update 1"]);
+    }
+
+    #[test]
+    fn destroyed_surfaces_are_not_rendered() {
+        let mut recorder = Recorder::with_surfaces(&[1, 2]);
+        let applied = apply_commands(
+            &mut recorder,
+            [
+                render(&[1, 2]),
+                Command::DestroySurface { surface_id: 1 },
+                // Kotlin can request a render before it learns that the surface is gone.
+                render(&[1]),
+                set_text(1, "ignored"),
+            ],
+        );
+        assert_eq!(applied.frame, [2]);
+        assert!(!recorder.has_surface(1));
+    }
+
+    #[test]
+    fn resized_surfaces_are_rendered() {
+        let mut recorder = Recorder::with_surfaces(&[1, 2]);
+        let applied = apply_commands(
+            &mut recorder,
+            [
+                render(&[2]),
+                Command::ResizeSurface {
+                    surface_id: 1,
+                    width: 100,
+                    height: 50,
+                },
+            ],
+        );
+        assert_eq!(applied.frame, [2, 1]);
+        assert_eq!(recorder.operations, ["resize 1 100x50"]);
+    }
+
+    #[test]
+    fn commands_after_finish_are_ignored() {
+        let mut recorder = Recorder::with_surfaces(&[1]);
+        let applied = apply_commands(
+            &mut recorder,
+            [
+                Command::SetDebugOverlay(DebugMode::Hud),
+                Command::Finish,
+                Command::SimulateDeviceLoss,
+                render(&[1]),
+            ],
+        );
+        assert!(applied.finished);
+        assert!(applied.frame.is_empty());
+        assert_eq!(recorder.operations, ["debug Hud"]);
+    }
+
+    #[test]
+    fn waiters_after_finish_are_dropped() {
+        let mut recorder = Recorder::with_surfaces(&[1]);
+        let (reply, result) = std::sync::mpsc::sync_channel(1);
+        let applied = apply_commands(
+            &mut recorder,
+            [
+                Command::Finish,
+                Command::RenderFrame {
+                    surfaces: vec![1],
+                    request_id: 7,
+                    reply: Some(reply),
+                },
+            ],
+        );
+        assert!(applied.finished);
+        // `renderAndWait` reports this as `RenderStatus::Stopped`, rather than waiting forever.
+        assert!(result.recv().is_err());
+    }
+
+    #[test]
+    fn waiters_keep_their_surfaces() {
+        let mut recorder = Recorder::with_surfaces(&[1, 2]);
+        let (reply, result) = std::sync::mpsc::sync_channel(1);
+        let applied = apply_commands(
+            &mut recorder,
+            [
+                render(&[1]),
+                Command::RenderFrame {
+                    surfaces: vec![2],
+                    request_id: 7,
+                    reply: Some(reply),
+                },
+                Command::DestroySurface { surface_id: 2 },
+            ],
+        );
+        assert_eq!(applied.frame, [1]);
+        assert_eq!(applied.requests, [1, 7]);
+        let [waiter] = &applied.waiters[..] else {
+            panic!("Expected one waiter, got {:?}", applied.waiters);
+        };
+        assert_eq!(waiter.surfaces, [2]);
+        let status = RenderStatus::for_frame(&waiter.surfaces, &applied.frame, false);
+        assert_eq!(status, RenderStatus::SurfaceLost);
+        waiter.reply.send(status).unwrap();
+        assert_eq!(result.recv().unwrap(), RenderStatus::SurfaceLost);
+    }
+
+    #[test]
+    fn render_status() {
+        assert_eq!(
+            RenderStatus::for_frame(&[1, 2], &[2, 3, 1], false),
+            RenderStatus::Success
+        );
+        assert_eq!(
+            RenderStatus::for_frame(&[1, 2], &[2], false),
+            RenderStatus::SurfaceLost
+        );
+        assert_eq!(
+            RenderStatus::for_frame(&[1], &[1], true),
+            RenderStatus::DeviceLost
+        );
+    }
+
+    #[test]
+    fn pointer_events_hit_the_latest_content() {
+        let mut recorder = Recorder::with_surfaces(&[1]);
+        let applied = apply_commands(
+            &mut recorder,
+            [
+                set_text(1, "12"),
+                pointer(1, PointerEventKind::Down, 25.),
+                set_text(1, "12:34"),
+                pointer(1, PointerEventKind::Move, 25.),
+                pointer(1, PointerEventKind::Cancel, 25.),
+                pointer(2, PointerEventKind::Down, 5.),
+            ],
+        );
+        let hits = applied
+            .hits
+            .iter()
+            .map(|hit| (hit.surface_id, hit.event.kind, hit.hit))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            hits,
+            [
+                (1, PointerEventKind::Down, None),
+                (1, PointerEventKind::Move, Some(2)),
+                (1, PointerEventKind::Cancel, None),
+            ]
+        );
+        // Pointer events don't need the surface to be rendered again.
+        assert!(applied.frame.is_empty());
+        assert_eq!(
+            recorder.operations,
+            ["update 1", "hit 1 25x5", "update 1", "hit 1 25x5"]
+        );
+    }
+
+    #[test]
+    fn selections_follow_text_changes() {
+        let mut recorder = Recorder::with_surfaces(&[1]);
+        let (reply, result) = std::sync::mpsc::sync_channel(2);
+        let get_selection = || Command::GetSelection {
+            surface_id: 1,
+            reply: reply.clone(),
+        };
+        apply_commands(
+            &mut recorder,
+            [
+                set_text(1, "12:34"),
+                Command::SetSelection {
+                    surface_id: 1,
+                    change: SelectionChange::Utf16 {
+                        anchor: 1,
+                        focus: 4,
+                    },
+                },
+                get_selection(),
+                Command::UpdateSurface {
+                    surface_id: 1,
+                    update: SurfaceUpdate::VariableFontText("12:".to_string()),
+                },
+                get_selection(),
+            ],
+        );
+        let selected = |text: &str, anchor, focus| SelectedText {
+            anchor,
+            focus,
+            text: text.to_string(),
+        };
+        assert_eq!(result.recv().unwrap(), Some(selected("2:3", 1, 4)));
+        assert_eq!(result.recv().unwrap(), Some(selected("2:", 1, 3)));
+    }
+
+    #[test]
+    fn editors_report_their_latest_state() {
+        let mut recorder = Recorder::with_surfaces(&[1, 2]);
+        let commit = |surface_id, text: &str| Command::Edit {
+            surface_id,
+            op: EditOp::CommitText {
+                text: text.to_string(),
+                new_cursor: 1,
+            },
+        };
+        let applied = apply_commands(
+            &mut recorder,
+            [
+                Command::UpdateSurface {
+                    surface_id: 1,
+                    update: SurfaceUpdate::SetTextEditor {
+                        text: "12".to_string(),
+                        multiline: false,
+                        size: 12.,
+                    },
+                },
+                commit(1, ":3"),
+                // Surfaces which aren't editors can't be edited.
+                commit(2, "ignored"),
+                commit(1, "4"),
+            ],
+        );
+        assert_eq!(recorder.text(1), "12:34");
+        let [(1, state)] = &applied.editors[..] else {
+            panic!("Expected one editor state, got {:?}", applied.editors);
+        };
+        assert_eq!((state.anchor, state.focus), (5, 5));
+        assert_eq!(state.text, "12:34");
+    }
+}
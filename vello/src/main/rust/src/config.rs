@@ -0,0 +1,176 @@
+//! Configuration of the renderer, chosen when Vello is initialised.
+
+use std::num::NonZeroUsize;
+
+use vello::{AaConfig, AaSupport};
+use wgpu::{Backends, InstanceFlags, PowerPreference, PresentMode};
+
+/// The antialiasing method used to render the atlas.
+///
+/// The discriminants are used in the FFI, so must match `AntialiasingMethod` in Kotlin.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AntialiasingMethod {
+    /// Analytic area antialiasing, which is the highest quality for most content.
+    #[default]
+    Area = 0,
+    Msaa8 = 1,
+    Msaa16 = 2,
+}
+
+impl AntialiasingMethod {
+    pub fn from_index(index: i32) -> Option<Self> {
+        [Self::Area, Self::Msaa8, Self::Msaa16]
+            .into_iter()
+            .find(|method| *method as i32 == index)
+    }
+
+    pub fn aa_config(self) -> AaConfig {
+        match self {
+            Self::Area => AaConfig::Area,
+            Self::Msaa8 => AaConfig::Msaa8,
+            Self::Msaa16 => AaConfig::Msaa16,
+        }
+    }
+
+    /// The support needed in the renderer's shaders for only this method.
+    pub fn aa_support(self) -> AaSupport {
+        AaSupport {
+            area: self == Self::Area,
+            msaa8: self == Self::Msaa8,
+            msaa16: self == Self::Msaa16,
+        }
+    }
+}
+
+/// The graphics API used for rendering.
+///
+/// The discriminants are used in the FFI, so must match `GraphicsBackend` in Kotlin.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GraphicsBackend {
+    /// The best supported API of the platform, which is Vulkan on Android.
+    #[default]
+    Primary = 0,
+    Vulkan = 1,
+    Gles = 2,
+}
+
+impl GraphicsBackend {
+    pub fn from_index(index: i32) -> Option<Self> {
+        [Self::Primary, Self::Vulkan, Self::Gles]
+            .into_iter()
+            .find(|backend| *backend as i32 == index)
+    }
+
+    pub fn backends(self) -> Backends {
+        match self {
+            Self::Primary => Backends::PRIMARY,
+            Self::Vulkan => Backends::VULKAN,
+            Self::Gles => Backends::GL,
+        }
+    }
+}
+
+/// The power preferences which can be passed over the FFI, in the order of `PowerPreference`
+/// in Kotlin.
+pub const POWER_PREFERENCES: [PowerPreference; 3] = [
+    PowerPreference::None,
+    PowerPreference::LowPower,
+    PowerPreference::HighPerformance,
+];
+
+/// The present modes which can be passed over the FFI, in the order of `PresentMode` in Kotlin.
+pub const PRESENT_MODES: [PresentMode; 4] = [
+    PresentMode::Fifo,
+    PresentMode::FifoRelaxed,
+    PresentMode::Immediate,
+    PresentMode::Mailbox,
+];
+
+/// The configuration of a Vello instance.
+///
+/// Environment variables supported by wgpu (such as `WGPU_BACKEND`) take priority over these
+/// values, to allow overriding them whilst debugging.
+#[derive(Clone, Debug)]
+pub struct VelloConfig {
+    pub antialiasing: AntialiasingMethod,
+    pub backend: GraphicsBackend,
+    pub power_preference: PowerPreference,
+    /// The present modes to use for surfaces, in order of preference.
+    ///
+    /// The first which is supported by a surface is used, and [`PresentMode::Fifo`] is used if
+    /// none are supported. Individual surfaces can override this.
+    pub present_modes: Vec<PresentMode>,
+    /// Enable wgpu's debug information and API validation, which has a large runtime cost.
+    pub validation: bool,
+    /// The number of threads used to compile the renderer's shaders.
+    ///
+    /// `None` uses many (but not all) available threads.
+    pub init_threads: Option<NonZeroUsize>,
+}
+
+impl Default for VelloConfig {
+    fn default() -> Self {
+        Self {
+            antialiasing: AntialiasingMethod::default(),
+            backend: GraphicsBackend::default(),
+            power_preference: PowerPreference::default(),
+            present_modes: vec![PresentMode::Mailbox, PresentMode::Fifo],
+            validation: cfg!(debug_assertions),
+            init_threads: None,
+        }
+    }
+}
+
+impl VelloConfig {
+    pub fn instance_flags(&self) -> InstanceFlags {
+        let flags = if self.validation {
+            InstanceFlags::DEBUG | InstanceFlags::VALIDATION
+        } else {
+            InstanceFlags::empty()
+        };
+        flags.with_env()
+    }
+}
+
+/// Choose the first of `preferred` which is `supported`, falling back to [`PresentMode::Fifo`],
+/// which all surfaces support.
+pub fn choose_present_mode(preferred: &[PresentMode], supported: &[PresentMode]) -> PresentMode {
+    preferred
+        .iter()
+        .copied()
+        .find(|mode| supported.contains(mode))
+        .unwrap_or(PresentMode::Fifo)
+}
+
+#[cfg(test)]
+mod tests {
+    use wgpu::PresentMode;
+
+    use super::{choose_present_mode, AntialiasingMethod};
+
+    #[test]
+    fn present_mode_fallback() {
+        let supported = [PresentMode::Fifo, PresentMode::Immediate];
+        assert_eq!(
+            choose_present_mode(&[PresentMode::Mailbox, PresentMode::Immediate], &supported),
+            PresentMode::Immediate
+        );
+        assert_eq!(
+            choose_present_mode(&[PresentMode::Mailbox], &supported),
+            PresentMode::Fifo
+        );
+        assert_eq!(choose_present_mode(&[], &supported), PresentMode::Fifo);
+    }
+
+    #[test]
+    fn antialiasing_support_matches_method() {
+        for index in 0..3 {
+            let method = AntialiasingMethod::from_index(index).unwrap();
+            let support = method.aa_support();
+            let supported = [support.area, support.msaa8, support.msaa16];
+            assert_eq!(supported.iter().filter(|it| **it).count(), 1);
+            assert!(supported[method as usize]);
+        }
+        assert_eq!(AntialiasingMethod::from_index(3), None);
+    }
+}
@@ -0,0 +1,368 @@
+//! Ownership of the wgpu instance and devices.
+//!
+//! This is adapted from [`vello::util::RenderContext`], but also requests the optional device
+//! features which we make use of, such as timestamp queries.
+
+use std::fmt;
+
+use vello::util::RenderSurface;
+use wgpu::{
+    Adapter, CompositeAlphaMode, Device, Features, Instance, Limits, PowerPreference, PresentMode,
+    Queue, RequestAdapterOptions, Surface, SurfaceCapabilities, SurfaceTarget, TextureFormat,
+};
+
+use crate::{config::choose_present_mode, recovery::DeviceLostFlag};
+
+/// The features which we use if they are available, but which aren't required.
+const OPTIONAL_FEATURES: Features = Features::CLEAR_TEXTURE
+    .union(Features::TIMESTAMP_QUERY)
+    .union(Features::TIMESTAMP_QUERY_INSIDE_ENCODERS);
+
+/// The surface formats which our blit pipeline can write to, in order of preference.
+///
+/// Vello's output is already sRGB encoded, so the `Srgb` variants are not suitable.
+const SURFACE_FORMATS: [TextureFormat; 2] = [TextureFormat::Rgba8Unorm, TextureFormat::Bgra8Unorm];
+
+/// The alpha modes we can use, in order of preference.
+///
+/// Our output is opaque, so any alpha mode gives the same result; these are the cheapest.
+const ALPHA_MODES: [CompositeAlphaMode; 4] = [
+    CompositeAlphaMode::Opaque,
+    CompositeAlphaMode::Inherit,
+    CompositeAlphaMode::PreMultiplied,
+    CompositeAlphaMode::PostMultiplied,
+];
+
+/// Why a surface couldn't be created.
+///
+/// The discriminants are used in the FFI, so must match `SurfaceCreationError` in Kotlin.
+#[derive(Debug)]
+pub enum CreateSurfaceError {
+    /// The platform refused to create a surface for the window.
+    CreateSurface(wgpu::CreateSurfaceError),
+    /// There was no adapter which could present to the surface.
+    NoCompatibleDevice,
+    /// The surface doesn't support any of the formats we can render to.
+    UnsupportedFormat(Vec<TextureFormat>),
+    /// The surface doesn't support any alpha modes which we know how to use.
+    UnsupportedAlphaMode(Vec<CompositeAlphaMode>),
+}
+
+impl CreateSurfaceError {
+    /// The index of this error's variant, for the FFI.
+    pub fn index(&self) -> i32 {
+        match self {
+            Self::CreateSurface(_) => 0,
+            Self::NoCompatibleDevice => 1,
+            Self::UnsupportedFormat(_) => 2,
+            Self::UnsupportedAlphaMode(_) => 3,
+        }
+    }
+}
+
+impl fmt::Display for CreateSurfaceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CreateSurface(e) => write!(f, "Couldn't create surface: {e}"),
+            Self::NoCompatibleDevice => f.write_str("No device can present to the surface"),
+            Self::UnsupportedFormat(supported) => {
+                write!(f, "No usable surface format, from {supported:?}")
+            }
+            Self::UnsupportedAlphaMode(supported) => {
+                write!(f, "No usable alpha mode, from {supported:?}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CreateSurfaceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::CreateSurface(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// The parts of a surface's configuration which depend on its capabilities.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SurfaceChoice {
+    pub format: TextureFormat,
+    pub present_mode: PresentMode,
+    pub alpha_mode: CompositeAlphaMode,
+}
+
+/// Choose the best supported configuration from `capabilities`, using the first of
+/// `present_modes` which is supported (or [`PresentMode::Fifo`]).
+pub fn choose_surface_config(
+    capabilities: &SurfaceCapabilities,
+    present_modes: &[PresentMode],
+) -> Result<SurfaceChoice, CreateSurfaceError> {
+    let format = SURFACE_FORMATS
+        .into_iter()
+        .find(|format| capabilities.formats.contains(format))
+        .ok_or_else(|| CreateSurfaceError::UnsupportedFormat(capabilities.formats.clone()))?;
+    let alpha_mode = ALPHA_MODES
+        .into_iter()
+        .find(|mode| capabilities.alpha_modes.contains(mode))
+        .ok_or_else(|| {
+            CreateSurfaceError::UnsupportedAlphaMode(capabilities.alpha_modes.clone())
+        })?;
+    Ok(SurfaceChoice {
+        format,
+        present_mode: choose_present_mode(present_modes, &capabilities.present_modes),
+        alpha_mode,
+    })
+}
+
+pub struct DeviceHandle {
+    pub adapter: Adapter,
+    pub device: Device,
+    pub queue: Queue,
+    /// Set when the device is lost, after which it needs to be recreated.
+    pub lost: DeviceLostFlag,
+}
+
+impl DeviceHandle {
+    /// Whether this device can measure GPU time using timestamp queries.
+    pub fn supports_timestamps(&self) -> bool {
+        self.device
+            .features()
+            .contains(Features::TIMESTAMP_QUERY | Features::TIMESTAMP_QUERY_INSIDE_ENCODERS)
+    }
+}
+
+pub struct GpuContext {
+    pub instance: Instance,
+    pub devices: Vec<DeviceHandle>,
+    /// The preference used when choosing an adapter, unless overridden by `WGPU_POWER_PREF`.
+    power_preference: PowerPreference,
+}
+
+impl GpuContext {
+    pub fn new(instance: Instance, power_preference: PowerPreference) -> Self {
+        Self {
+            instance,
+            devices: Vec::new(),
+            power_preference,
+        }
+    }
+
+    /// Creates a new surface for the specified window and dimensions.
+    ///
+    /// The format, present mode and alpha mode are chosen from those supported by the surface;
+    /// see [`choose_surface_config`].
+    pub async fn create_surface<'w>(
+        &mut self,
+        window: impl Into<SurfaceTarget<'w>>,
+        width: u32,
+        height: u32,
+        present_modes: &[PresentMode],
+    ) -> Result<RenderSurface<'w>, CreateSurfaceError> {
+        let surface = self
+            .instance
+            .create_surface(window.into())
+            .map_err(CreateSurfaceError::CreateSurface)?;
+        let dev_id = self
+            .device(Some(&surface))
+            .await
+            .ok_or(CreateSurfaceError::NoCompatibleDevice)?;
+
+        let mut render_surface = RenderSurface {
+            surface,
+            config: wgpu::SurfaceConfiguration {
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                format: TextureFormat::Rgba8Unorm,
+                width,
+                height,
+                present_mode: PresentMode::Fifo,
+                desired_maximum_frame_latency: 2,
+                alpha_mode: CompositeAlphaMode::Opaque,
+                view_formats: vec![],
+            },
+            dev_id,
+            format: TextureFormat::Rgba8Unorm,
+        };
+        self.configure_surface(&mut render_surface, present_modes)?;
+        Ok(render_surface)
+    }
+
+    /// Configure `render_surface` for its device, choosing the best of its capabilities.
+    ///
+    /// This is also used to move a surface to a recreated device, which may have different
+    /// capabilities.
+    pub fn configure_surface(
+        &self,
+        render_surface: &mut RenderSurface<'_>,
+        present_modes: &[PresentMode],
+    ) -> Result<(), CreateSurfaceError> {
+        let device_handle = &self.devices[render_surface.dev_id];
+        let capabilities = render_surface
+            .surface
+            .get_capabilities(&device_handle.adapter);
+        let choice = choose_surface_config(&capabilities, present_modes)?;
+        log::info!("Chose {choice:?} for surface with capabilities {capabilities:?}");
+        let config = &mut render_surface.config;
+        config.format = choice.format;
+        config.present_mode = choice.present_mode;
+        config.alpha_mode = choice.alpha_mode;
+        render_surface.format = choice.format;
+        render_surface
+            .surface
+            .configure(&device_handle.device, config);
+        Ok(())
+    }
+
+    /// Resize `render_surface`, keeping the rest of its configuration.
+    pub fn resize_surface(&self, render_surface: &mut RenderSurface<'_>, width: u32, height: u32) {
+        render_surface.config.width = width;
+        render_surface.config.height = height;
+        render_surface.surface.configure(
+            &self.devices[render_surface.dev_id].device,
+            &render_surface.config,
+        );
+    }
+
+    /// Replace the lost device `dev_id` with a new device, which is compatible with
+    /// `compatible_surface` if provided.
+    ///
+    /// Returns false if no new device could be created.
+    pub async fn recreate_device(
+        &mut self,
+        dev_id: usize,
+        compatible_surface: Option<&Surface<'_>>,
+    ) -> bool {
+        let Some(device_handle) = self.create_device(compatible_surface, false).await else {
+            return false;
+        };
+        // The old device is dropped here, which calls its lost callback with `Dropped`.
+        self.devices[dev_id] = device_handle;
+        true
+    }
+
+    /// Finds or creates a compatible device handle id.
+    async fn device(&mut self, compatible_surface: Option<&Surface<'_>>) -> Option<usize> {
+        let compatible = match compatible_surface {
+            Some(s) => self
+                .devices
+                .iter()
+                .position(|d| !d.lost.is_set() && d.adapter.is_surface_supported(s)),
+            None => self.devices.iter().position(|d| !d.lost.is_set()),
+        };
+        match compatible {
+            Some(compatible) => Some(compatible),
+            None => self.new_device(compatible_surface).await,
+        }
+    }
+
+    /// Creates a compatible device handle id.
+    async fn new_device(&mut self, compatible_surface: Option<&Surface<'_>>) -> Option<usize> {
+        let device_handle = self.create_device(compatible_surface, false).await?;
+        self.devices.push(device_handle);
+        Some(self.devices.len() - 1)
+    }
+
+    /// Creates a device which isn't for any surface, returning its handle id.
+    ///
+    /// If `force_fallback_adapter` is set, the device is on the software fallback adapter,
+    /// which lets tests use more than one device on hosts with a single GPU.
+    #[cfg(test)]
+    pub async fn new_test_device(&mut self, force_fallback_adapter: bool) -> Option<usize> {
+        let device_handle = self.create_device(None, force_fallback_adapter).await?;
+        self.devices.push(device_handle);
+        Some(self.devices.len() - 1)
+    }
+
+    async fn create_device(
+        &self,
+        compatible_surface: Option<&Surface<'_>>,
+        force_fallback_adapter: bool,
+    ) -> Option<DeviceHandle> {
+        let adapter =
+            match wgpu::util::initialize_adapter_from_env(&self.instance, compatible_surface) {
+                Some(adapter) => adapter,
+                None => {
+                    self.instance
+                        .request_adapter(&RequestAdapterOptions {
+                            power_preference: wgpu::util::power_preference_from_env()
+                                .unwrap_or(self.power_preference),
+                            force_fallback_adapter,
+                            compatible_surface,
+                        })
+                        .await?
+                }
+            };
+        let features = adapter.features();
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: None,
+                    required_features: features & OPTIONAL_FEATURES,
+                    required_limits: Limits::default(),
+                    memory_hints: Default::default(),
+                },
+                None,
+            )
+            .await
+            .ok()?;
+        log::info!(
+            "Created device on {:?}, with features {:?}",
+            adapter.get_info(),
+            device.features()
+        );
+        let lost = DeviceLostFlag::default();
+        device.set_device_lost_callback(lost.callback());
+        // Errors are expected whilst a device is lost (until we next check for loss), so
+        // we log them rather than using the default handler, which panics.
+        device.on_uncaptured_error(Box::new(|error| {
+            log::error!("Uncaptured wgpu error: {error}")
+        }));
+        Some(DeviceHandle {
+            adapter,
+            device,
+            queue,
+            lost,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wgpu::{CompositeAlphaMode, PresentMode, SurfaceCapabilities, TextureFormat};
+
+    use super::{choose_surface_config, CreateSurfaceError, SurfaceChoice};
+
+    fn capabilities() -> SurfaceCapabilities {
+        SurfaceCapabilities {
+            formats: vec![TextureFormat::Rgba8UnormSrgb, TextureFormat::Bgra8Unorm],
+            present_modes: vec![PresentMode::Fifo],
+            alpha_modes: vec![CompositeAlphaMode::Inherit],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn chooses_supported_config() {
+        let choice = choose_surface_config(&capabilities(), &[PresentMode::Mailbox]).unwrap();
+        assert_eq!(
+            choice,
+            SurfaceChoice {
+                format: TextureFormat::Bgra8Unorm,
+                present_mode: PresentMode::Fifo,
+                alpha_mode: CompositeAlphaMode::Inherit,
+            }
+        );
+    }
+
+    #[test]
+    fn unsupported_format_is_an_error() {
+        let capabilities = SurfaceCapabilities {
+            formats: vec![TextureFormat::Rgba16Float],
+            ..capabilities()
+        };
+        let error = choose_surface_config(&capabilities, &[]).unwrap_err();
+        assert!(
+            matches!(error, CreateSurfaceError::UnsupportedFormat(ref formats) if formats == &[TextureFormat::Rgba16Float])
+        );
+    }
+}
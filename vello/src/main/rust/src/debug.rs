@@ -0,0 +1,429 @@
+//! A debug overlay which Vello draws on top of each surface.
+//!
+//! This is useful for diagnosing problems with the atlas and frame pacing, without needing
+//! external tools.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use vello::{
+    kurbo::{Affine, BezPath, Point, Rect, Shape, Stroke},
+    peniko::{Color, Fill},
+    Scene,
+};
+
+use crate::SurfaceId;
+
+/// What the debug overlay shows.
+///
+/// The discriminants are used in the FFI, so must match `DebugOverlay` in Kotlin.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DebugMode {
+    #[default]
+    Off = 0,
+    /// A heads-up display with frame timings and details of the surface's atlas slot.
+    Hud = 1,
+    /// A scaled down view of the whole atlas, with each allocation outlined.
+    AtlasLayout = 2,
+}
+
+impl DebugMode {
+    pub fn from_index(index: i32) -> Option<Self> {
+        [Self::Off, Self::Hud, Self::AtlasLayout]
+            .into_iter()
+            .find(|mode| *mode as i32 == index)
+    }
+}
+
+/// The number of recent frames shown in the frame time graph.
+const HISTORY_LENGTH: usize = 120;
+
+pub(crate) struct DebugOverlay {
+    pub(crate) mode: DebugMode,
+    /// The start time and CPU duration of recent frames.
+    history: VecDeque<(Instant, Duration)>,
+}
+
+/// The details of the current frame's atlas, needed to draw the overlay.
+pub(crate) struct AtlasInfo<'a> {
+    pub(crate) size: (u32, u32),
+    pub(crate) allocations: &'a HashMap<SurfaceId, guillotiere::Rectangle>,
+}
+
+impl AtlasInfo<'_> {
+    /// The fraction of the atlas which is allocated.
+    fn occupancy(&self) -> f64 {
+        let allocated: i64 = self
+            .allocations
+            .values()
+            .map(|rect| i64::from(rect.width()) * i64::from(rect.height()))
+            .sum();
+        allocated as f64 / (f64::from(self.size.0) * f64::from(self.size.1))
+    }
+}
+
+impl DebugOverlay {
+    pub(crate) fn new() -> Self {
+        Self {
+            mode: DebugMode::Off,
+            history: VecDeque::with_capacity(HISTORY_LENGTH),
+        }
+    }
+
+    pub(crate) fn record_frame(&mut self, start: Instant, duration: Duration) {
+        if self.history.len() == HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+        self.history.push_back((start, duration));
+    }
+
+    /// The rate at which recent frames were started, or `None` if there aren't enough frames.
+    fn fps(&self) -> Option<f64> {
+        let (first, _) = self.history.front()?;
+        let (last, _) = self.history.back()?;
+        let elapsed = last.duration_since(*first).as_secs_f64();
+        (elapsed > 0.0).then(|| (self.history.len() - 1) as f64 / elapsed)
+    }
+
+    /// Build the overlay for `surface_id`, in the coordinate space of its atlas slot.
+    pub(crate) fn scene(&self, surface_id: SurfaceId, atlas: &AtlasInfo<'_>) -> Option<Scene> {
+        let slot = atlas.allocations.get(&surface_id)?;
+        let mut scene = Scene::new();
+        match self.mode {
+            DebugMode::Off => return None,
+            DebugMode::Hud => self.draw_hud(&mut scene, surface_id, *slot, atlas),
+            DebugMode::AtlasLayout => draw_atlas_layout(&mut scene, surface_id, *slot, atlas),
+        }
+        Some(scene)
+    }
+
+    fn draw_hud(
+        &self,
+        scene: &mut Scene,
+        surface_id: SurfaceId,
+        slot: guillotiere::Rectangle,
+        atlas: &AtlasInfo<'_>,
+    ) {
+        let fps = match self.fps() {
+            Some(fps) => format!("FPS {fps:.1}"),
+            None => "FPS -".to_string(),
+        };
+        let last_frame = match self.history.back() {
+            Some((_, duration)) => format!("CPU {:.2}MS", duration.as_secs_f64() * 1000.),
+            None => "CPU -".to_string(),
+        };
+        let lines = [
+            format!("ID {surface_id} {}X{}", slot.width(), slot.height()),
+            format!(
+                "SLOT {},{} - {},{}",
+                slot.min.x, slot.min.y, slot.max.x, slot.max.y
+            ),
+            format!("{fps} {last_frame}"),
+            format!(
+                "ATLAS {:.1}% ({} SURFACES)",
+                atlas.occupancy() * 100.,
+                atlas.allocations.len()
+            ),
+        ];
+        let text_width = lines
+            .iter()
+            .map(|line| text_width(line, PIXEL))
+            .fold(0., f64::max);
+        let width = text_width.max(GRAPH_WIDTH) + 2. * PADDING;
+        let height = lines.len() as f64 * LINE_HEIGHT + GRAPH_HEIGHT + 3. * PADDING;
+        scene.fill(
+            Fill::NonZero,
+            Affine::IDENTITY,
+            Color::rgba8(0, 0, 0, 180),
+            None,
+            &Rect::new(0., 0., width, height),
+        );
+        for (i, line) in lines.iter().enumerate() {
+            draw_text(
+                scene,
+                line,
+                Point::new(PADDING, PADDING + i as f64 * LINE_HEIGHT),
+                PIXEL,
+                Color::WHITE,
+            );
+        }
+        let graph_top = PADDING * 2. + lines.len() as f64 * LINE_HEIGHT;
+        self.draw_graph(scene, Point::new(PADDING, graph_top));
+    }
+
+    /// Draw a bar graph of the CPU time of recent frames.
+    fn draw_graph(&self, scene: &mut Scene, origin: Point) {
+        // The full height of the graph is two frames at 60fps.
+        const GRAPH_SCALE: Duration = Duration::from_nanos(2 * 1_000_000_000 / 60);
+        let graph = Rect::from_origin_size(origin, (GRAPH_WIDTH, GRAPH_HEIGHT));
+        scene.fill(
+            Fill::NonZero,
+            Affine::IDENTITY,
+            Color::rgba8(255, 255, 255, 40),
+            None,
+            &graph,
+        );
+        let bar_width = GRAPH_WIDTH / HISTORY_LENGTH as f64;
+        for (i, (_, duration)) in self.history.iter().enumerate() {
+            let fraction = (duration.as_secs_f64() / GRAPH_SCALE.as_secs_f64()).min(1.0);
+            let color = if *duration > Duration::from_nanos(1_000_000_000 / 60) {
+                Color::RED
+            } else if *duration > Duration::from_nanos(1_000_000_000 / 90) {
+                Color::YELLOW
+            } else {
+                Color::LIME
+            };
+            let x = graph.x0 + i as f64 * bar_width;
+            scene.fill(
+                Fill::NonZero,
+                Affine::IDENTITY,
+                color,
+                None,
+                &Rect::new(
+                    x,
+                    graph.y1 - fraction * GRAPH_HEIGHT,
+                    x + bar_width,
+                    graph.y1,
+                ),
+            );
+        }
+        // Mark the budgets for 60fps and 90fps.
+        for fps in [60., 90.] {
+            let y = graph.y1 - GRAPH_HEIGHT / GRAPH_SCALE.as_secs_f64() / fps;
+            scene.stroke(
+                &Stroke::new(1.),
+                Affine::IDENTITY,
+                Color::WHITE,
+                None,
+                &vello::kurbo::Line::new((graph.x0, y), (graph.x1, y)),
+            );
+        }
+    }
+}
+
+/// Draw the layout of the whole atlas, scaled to fit in `slot`.
+fn draw_atlas_layout(
+    scene: &mut Scene,
+    surface_id: SurfaceId,
+    slot: guillotiere::Rectangle,
+    atlas: &AtlasInfo<'_>,
+) {
+    let (atlas_width, atlas_height) = (f64::from(atlas.size.0), f64::from(atlas.size.1));
+    let scale =
+        (f64::from(slot.width()) / atlas_width).min(f64::from(slot.height()) / atlas_height);
+    let transform = Affine::scale(scale);
+    scene.fill(
+        Fill::NonZero,
+        Affine::IDENTITY,
+        Color::rgba8(0, 0, 0, 200),
+        None,
+        &Rect::new(0., 0., slot.width().into(), slot.height().into()),
+    );
+    // Strokes are specified in atlas pixels, so make them a consistent width on screen.
+    let stroke = Stroke::new(1. / scale);
+    scene.stroke(
+        &stroke,
+        transform,
+        Color::GRAY,
+        None,
+        &Rect::new(0., 0., atlas_width, atlas_height),
+    );
+    for (id, rect) in atlas.allocations {
+        let rect = Rect::new(
+            rect.min.x.into(),
+            rect.min.y.into(),
+            rect.max.x.into(),
+            rect.max.y.into(),
+        );
+        if *id == surface_id {
+            scene.fill(
+                Fill::NonZero,
+                transform,
+                Color::rgba8(0, 255, 0, 100),
+                None,
+                &rect,
+            );
+        }
+        scene.stroke(&stroke, transform, Color::LIME, None, &rect);
+    }
+    let occupancy = format!("{:.1}%", atlas.occupancy() * 100.);
+    let origin = Point::new(atlas_width * scale + PADDING, PADDING);
+    draw_text(scene, &occupancy, origin, PIXEL, Color::WHITE);
+}
+
+/// The size of a pixel of the debug font.
+const PIXEL: f64 = 2.;
+const LINE_HEIGHT: f64 = (GLYPH_HEIGHT + 2) as f64 * PIXEL;
+const PADDING: f64 = 4.;
+const GRAPH_WIDTH: f64 = 240.;
+const GRAPH_HEIGHT: f64 = 30.;
+
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+
+/// Get the bitmap of `c` in the debug font, where each row's bits are ordered with the
+/// leftmost column as the most significant bit.
+///
+/// We don't use Parley for the overlay, because the bundled font only has digits.
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '%' => [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '(' => [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+        ')' => [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+        _ => [0; GLYPH_HEIGHT],
+    }
+}
+
+fn text_width(text: &str, pixel: f64) -> f64 {
+    text.chars().count() as f64 * (GLYPH_WIDTH + 1) as f64 * pixel
+}
+
+/// Draw `text` in the debug font, with its top left corner at `origin`.
+fn draw_text(scene: &mut Scene, text: &str, origin: Point, pixel: f64, color: Color) {
+    let mut path = BezPath::new();
+    for (i, c) in text.chars().enumerate() {
+        let glyph_x = origin.x + (i * (GLYPH_WIDTH + 1)) as f64 * pixel;
+        for (row, bits) in glyph(c).into_iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - column)) == 0 {
+                    continue;
+                }
+                let pixel_origin = Point::new(
+                    glyph_x + column as f64 * pixel,
+                    origin.y + row as f64 * pixel,
+                );
+                path.extend(
+                    Rect::from_origin_size(pixel_origin, (pixel, pixel)).path_elements(0.1),
+                );
+            }
+        }
+    }
+    scene.fill(Fill::NonZero, Affine::IDENTITY, color, None, &path);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        time::{Duration, Instant},
+    };
+
+    use guillotiere::{point2, Rectangle};
+
+    use super::{AtlasInfo, DebugMode, DebugOverlay, HISTORY_LENGTH};
+
+    #[test]
+    fn occupancy_is_the_allocated_fraction() {
+        let allocations = HashMap::from([
+            (1, Rectangle::new(point2(0, 0), point2(50, 20))),
+            (2, Rectangle::new(point2(50, 0), point2(100, 30))),
+        ]);
+        let atlas = AtlasInfo {
+            size: (100, 100),
+            allocations: &allocations,
+        };
+        assert_eq!(atlas.occupancy(), 0.25);
+        let empty = HashMap::new();
+        let atlas = AtlasInfo {
+            size: (100, 100),
+            allocations: &empty,
+        };
+        assert_eq!(atlas.occupancy(), 0.);
+    }
+
+    #[test]
+    fn fps_needs_two_frames() {
+        let mut overlay = DebugOverlay::new();
+        assert_eq!(overlay.fps(), None);
+        let start = Instant::now();
+        overlay.record_frame(start, Duration::from_millis(2));
+        assert_eq!(overlay.fps(), None);
+        for frame in 1..=30 {
+            overlay.record_frame(start + Duration::from_millis(frame * 20), Duration::ZERO);
+        }
+        let fps = overlay.fps().unwrap();
+        assert!((fps - 50.).abs() < 1e-6, "{fps}");
+    }
+
+    #[test]
+    fn history_keeps_recent_frames() {
+        let mut overlay = DebugOverlay::new();
+        let start = Instant::now();
+        let frames = HISTORY_LENGTH as u64 + 80;
+        for frame in 0..frames {
+            overlay.record_frame(
+                start + Duration::from_millis(frame * 10),
+                Duration::from_millis(frame),
+            );
+        }
+        assert_eq!(overlay.history.len(), HISTORY_LENGTH);
+        assert_eq!(
+            overlay.history.front().unwrap().1,
+            Duration::from_millis(80)
+        );
+        assert_eq!(
+            overlay.history.back().unwrap().1,
+            Duration::from_millis(frames - 1)
+        );
+        let fps = overlay.fps().unwrap();
+        assert!((fps - 100.).abs() < 1e-6, "{fps}");
+    }
+
+    #[test]
+    fn overlay_is_only_drawn_when_enabled() {
+        let allocations = HashMap::from([(1, Rectangle::new(point2(0, 0), point2(300, 200)))]);
+        let atlas = AtlasInfo {
+            size: (512, 512),
+            allocations: &allocations,
+        };
+        let mut overlay = DebugOverlay::new();
+        assert!(overlay.scene(1, &atlas).is_none());
+        overlay.mode = DebugMode::Hud;
+        assert!(overlay.scene(1, &atlas).is_some());
+        // Surfaces without a slot have nothing to draw on.
+        assert!(overlay.scene(2, &atlas).is_none());
+    }
+}
@@ -0,0 +1,381 @@
+//! The base direction of the paragraphs of text surfaces.
+//!
+//! Parley always lays out paragraphs with a left-to-right base direction, which puts the runs
+//! of a mixed-direction paragraph in the wrong order if it should be read right to left. To lay
+//! out those paragraphs, a right-to-left embedding mark is inserted at their start, which makes
+//! the whole paragraph right to left. The mark is a default ignorable character, so has no
+//! glyphs, but it does move the text after it, so offsets into the layout need to be mapped
+//! back to the surface's text with a [`DirectedText`].
+
+use std::borrow::Cow;
+
+use parley::swash::text::{BidiClass, Codepoint};
+
+/// The mark inserted at the start of right-to-left paragraphs.
+///
+/// This is RIGHT-TO-LEFT EMBEDDING, which lasts until the end of the paragraph.
+const RTL_MARK: char = '\u{202B}';
+
+/// The base direction of the paragraphs of a text surface.
+///
+/// The discriminants are used in the FFI, so must match `TextDirection` in Kotlin.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TextDirection {
+    /// Use the direction of the first strong character of each paragraph, as in the Unicode
+    /// Bidirectional Algorithm. Paragraphs without any are left to right.
+    #[default]
+    Auto = 0,
+    Ltr = 1,
+    Rtl = 2,
+}
+
+impl TextDirection {
+    pub fn from_index(index: i32) -> Option<Self> {
+        [Self::Auto, Self::Ltr, Self::Rtl]
+            .into_iter()
+            .find(|direction| *direction as i32 == index)
+    }
+}
+
+/// The text which is laid out for a surface's text, with the marks needed for its direction.
+pub(crate) struct DirectedText<'a> {
+    /// The surface's text.
+    source: &'a str,
+    /// The text to lay out, which is `source` with the marks inserted.
+    text: Cow<'a, str>,
+    /// The byte offsets into the surface's text at which a mark was inserted, in order.
+    marks: Vec<usize>,
+}
+
+impl<'a> DirectedText<'a> {
+    pub(crate) fn new(text: &'a str, direction: TextDirection) -> Self {
+        let marks = paragraph_starts(text)
+            .filter(|&start| match direction {
+                TextDirection::Auto => first_strong_is_rtl(&text[start..]),
+                TextDirection::Ltr => false,
+                TextDirection::Rtl => true,
+            })
+            .collect::<Vec<_>>();
+        if marks.is_empty() {
+            return Self {
+                source: text,
+                text: Cow::Borrowed(text),
+                marks,
+            };
+        }
+        let mut directed = String::with_capacity(text.len() + marks.len() * RTL_MARK.len_utf8());
+        let mut copied = 0;
+        for &mark in &marks {
+            directed.push_str(&text[copied..mark]);
+            directed.push(RTL_MARK);
+            copied = mark;
+        }
+        directed.push_str(&text[copied..]);
+        Self {
+            source: text,
+            text: Cow::Owned(directed),
+            marks,
+        }
+    }
+
+    /// The surface's text, which offsets are mapped to by [`to_text`](Self::to_text).
+    pub(crate) fn source(&self) -> &'a str {
+        self.source
+    }
+
+    /// The text to lay out.
+    pub(crate) fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /// Whether the first paragraph is right to left, so the lines should be aligned to the
+    /// right.
+    ///
+    /// Parley aligns every line of a layout in the same way, so later paragraphs are aligned in
+    /// the same way as the first.
+    pub(crate) fn starts_rtl(&self) -> bool {
+        self.marks.first() == Some(&0)
+    }
+
+    /// The offset into the laid out text of the byte offset `index` of the surface's text.
+    ///
+    /// Offsets at the start of a right-to-left paragraph are after its mark.
+    pub(crate) fn to_layout(&self, index: usize) -> usize {
+        index + RTL_MARK.len_utf8() * self.marks.partition_point(|&mark| mark <= index)
+    }
+
+    /// The byte offset into the surface's text of the offset `index` into the laid out text.
+    ///
+    /// Offsets within or after a mark are at the start of its paragraph.
+    pub(crate) fn to_text(&self, index: usize) -> usize {
+        let mut inserted = 0;
+        for &mark in &self.marks {
+            let mark_start = mark + inserted;
+            if index <= mark_start {
+                break;
+            }
+            if index < mark_start + RTL_MARK.len_utf8() {
+                return mark;
+            }
+            inserted += RTL_MARK.len_utf8();
+        }
+        index - inserted
+    }
+}
+
+/// The byte offset of the start of each paragraph of `text`.
+fn paragraph_starts(text: &str) -> impl Iterator<Item = usize> + '_ {
+    let mut chars = text.char_indices().peekable();
+    let separators = std::iter::from_fn(move || {
+        while let Some((index, c)) = chars.next() {
+            // "\r\n" separates paragraphs once, after the "\n".
+            let crlf = c == '\r' && chars.peek().is_some_and(|&(_, next)| next == '\n');
+            if c.bidi_class() == BidiClass::B && !crlf {
+                return Some(index + c.len_utf8());
+            }
+        }
+        None
+    });
+    std::iter::once(0).chain(separators)
+}
+
+/// Whether the first strong character of the paragraph at the start of `text` is right to left.
+///
+/// Characters between an isolate initiator and its matching PDI are skipped, as they don't
+/// affect the direction of the paragraph.
+fn first_strong_is_rtl(text: &str) -> bool {
+    let mut isolates = 0_usize;
+    for c in text.chars() {
+        match c.bidi_class() {
+            BidiClass::B => break,
+            BidiClass::LRI | BidiClass::RLI | BidiClass::FSI => isolates += 1,
+            BidiClass::PDI => isolates = isolates.saturating_sub(1),
+            _ if isolates > 0 => {}
+            BidiClass::L => return false,
+            BidiClass::R | BidiClass::AL => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use parley::{fontique::FallbackKey, FontContext};
+    use vello::Glyph;
+
+    use crate::{
+        command::SurfaceUpdate, selection, selection::SelectionChange, test_contexts,
+        test_variable_font, LayoutContext, SurfaceKind,
+    };
+
+    use super::{DirectedText, TextDirection};
+
+    /// DejaVu Sans, which covers Arabic and Hebrew (see `test_fonts/dejavu/README.md`).
+    const DEJAVU_SANS: &[u8] = include_bytes!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test_fonts/dejavu/DejaVuSans.ttf"
+    ));
+
+    /// A generated font for Devanagari and Thai (see `test_fonts/complex_scripts/README.md`).
+    const COMPLEX_SCRIPTS: &[u8] = include_bytes!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test_fonts/complex_scripts/ComplexScripts.ttf"
+    ));
+
+    /// Contexts where text which Roboto Flex doesn't cover falls back to DejaVu Sans, or to
+    /// the complex scripts font for Devanagari and Thai.
+    fn contexts() -> (FontContext, LayoutContext) {
+        let (mut font_ctx, layout_ctx) = test_contexts();
+        let (dejavu, _) = font_ctx.collection.register_fonts(DEJAVU_SANS.into())[0];
+        let (complex, _) = font_ctx.collection.register_fonts(COMPLEX_SCRIPTS.into())[0];
+        for (script, family) in [
+            ("Arab", dejavu),
+            ("Hebr", dejavu),
+            ("Latn", dejavu),
+            ("Cyrl", dejavu),
+            ("Deva", complex),
+            ("Thai", complex),
+        ] {
+            font_ctx
+                .collection
+                .append_fallbacks(FallbackKey::new(script, None), [family].into_iter());
+        }
+        (font_ctx, layout_ctx)
+    }
+
+    fn surface(text: &str, direction: TextDirection, locale: Option<&str>) -> SurfaceKind {
+        let mut kind = test_variable_font(text, 20.);
+        SurfaceUpdate::SetTextDirection(direction).apply(&mut kind);
+        SurfaceUpdate::SetLocale(locale.map(Box::from)).apply(&mut kind);
+        kind
+    }
+
+    /// The glyphs drawn for `text`, from left to right.
+    fn drawn(text: &str, direction: TextDirection, locale: Option<&str>) -> Vec<Glyph> {
+        let (mut font_ctx, mut layout_ctx) = contexts();
+        let kind = surface(text, direction, locale);
+        let scene = kind.scene(200, 100, false, &mut font_ctx, &mut layout_ctx);
+        let mut glyphs = scene.encoding().resources.glyphs.clone();
+        glyphs.sort_by(|a, b| a.x.total_cmp(&b.x));
+        glyphs
+    }
+
+    /// The id of each glyph drawn for `text`, from left to right.
+    ///
+    /// The ids are those of DejaVu Sans 2.37, the complex scripts font for Devanagari and Thai,
+    /// or Roboto Flex for digits.
+    fn drawn_glyphs(text: &str, direction: TextDirection, locale: Option<&str>) -> Vec<u32> {
+        drawn(text, direction, locale)
+            .into_iter()
+            .map(|glyph| glyph.id)
+            .collect()
+    }
+
+    #[test]
+    fn marks_start_rtl_paragraphs() {
+        let text = "\u{5d0}b\nab\r\n\u{5d1}";
+        let directed = DirectedText::new(text, TextDirection::Auto);
+        assert_eq!(directed.marks, [0, 8]);
+        assert_eq!(directed.as_str(), "\u{202b}\u{5d0}b\nab\r\n\u{202b}\u{5d1}");
+        assert!(directed.starts_rtl());
+        for index in 0..=text.len() {
+            assert_eq!(directed.to_text(directed.to_layout(index)), index);
+        }
+        // Offsets within a mark are at the start of its paragraph.
+        assert_eq!(directed.to_text(1), 0);
+        assert_eq!(directed.to_text(13), 8);
+
+        let ltr = DirectedText::new(text, TextDirection::Ltr);
+        assert_eq!(ltr.as_str(), text);
+        assert!(!ltr.starts_rtl());
+        let rtl = DirectedText::new("12 ab", TextDirection::Rtl);
+        assert_eq!(rtl.marks, [0]);
+        // Digits aren't strong, and isolated text is skipped.
+        for (text, rtl) in [
+            ("12 \u{5d0}", true),
+            ("(a) \u{5d0}", false),
+            ("\u{2067}a\u{2069} \u{627}", true),
+        ] {
+            assert_eq!(
+                DirectedText::new(text, TextDirection::Auto).starts_rtl(),
+                rtl,
+                "{text:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn hebrew_is_drawn_right_to_left() {
+        // "שלום" is drawn as final mem, vav, lamed then shin.
+        let golden = [1332, 1324, 1331, 1344];
+        assert_eq!(drawn_glyphs("שלום", TextDirection::Auto, None), golden);
+        assert_eq!(drawn_glyphs("שלום", TextDirection::Rtl, None), golden);
+    }
+
+    #[test]
+    fn arabic_is_joined() {
+        // The four letters of "سلام" are drawn as three glyphs, as lam and alef form a ligature,
+        // which is followed by an unjoined meem on the left.
+        assert_eq!(
+            drawn_glyphs("سلام", TextDirection::Auto, None),
+            [1390, 5366, 5293]
+        );
+    }
+
+    #[test]
+    fn base_direction_orders_mixed_runs() {
+        let latin = [68, 69, 70];
+        let hebrew = [1332, 1324, 1331, 1344];
+        let space = 3;
+        let ltr = drawn_glyphs("abc שלום", TextDirection::Auto, None);
+        assert_eq!(ltr, [&latin[..], &[space], &hebrew].concat());
+        let rtl = drawn_glyphs("abc שלום", TextDirection::Rtl, None);
+        assert_eq!(rtl, [&hebrew[..], &[space], &latin].concat());
+    }
+
+    #[test]
+    fn locale_selects_local_forms() {
+        // Serbian uses a different form of "б".
+        assert_eq!(drawn_glyphs("б", TextDirection::Auto, None), [966]);
+        assert_eq!(drawn_glyphs("б", TextDirection::Auto, Some("sr")), [5040]);
+    }
+
+    #[test]
+    fn devanagari_vowel_signs_are_reordered() {
+        // "ि" is drawn before "क", which it follows in the text, and "ा" after "त".
+        assert_eq!(
+            drawn_glyphs("किताब", TextDirection::Auto, None),
+            [8, 2, 6, 9, 12]
+        );
+        // "स्त" is drawn as a conjunct, with "े" above it.
+        assert_eq!(
+            drawn_glyphs("नमस्ते", TextDirection::Auto, None),
+            [3, 4, 11, 10]
+        );
+    }
+
+    #[test]
+    fn thai_marks_are_stacked() {
+        let [consonant, vowel, tone] = drawn("ที่", TextDirection::Auto, None)[..] else {
+            panic!("\"ที่\" should be drawn as three glyphs");
+        };
+        assert_eq!([consonant.id, vowel.id, tone.id], [14, 16, 17]);
+        // The vowel sign is centred above the consonant (whose advance is 11px), and the tone
+        // mark is drawn 4px above the vowel sign, rather than on top of it.
+        assert_eq!((vowel.x - consonant.x, vowel.y), (5.5, consonant.y));
+        assert_eq!((tone.x, tone.y), (vowel.x, vowel.y - 4.));
+        // "ำ" is a spacing vowel, so follows the consonant.
+        assert_eq!(drawn_glyphs("กำ", TextDirection::Auto, None), [13, 18]);
+    }
+
+    #[test]
+    fn complex_script_clusters_stay_whole() {
+        // Carets and selections don't split a consonant from its vowel signs or marks.
+        let (mut font_ctx, mut layout_ctx) = contexts();
+        for (text, golden) in [
+            ("नमस्ते", &["न", "म", "स्ते"][..]),
+            ("किताब", &["कि", "ता", "ब"]),
+            ("ที่นี่", &["ที่", "นี่"]),
+            ("กำ", &["กำ"]),
+        ] {
+            let kind = surface(text, TextDirection::Auto, None);
+            let layout = kind.layout(&mut font_ctx, &mut layout_ctx).unwrap();
+            let mut clusters = Vec::new();
+            let mut index = 0;
+            while index < text.len() {
+                let range = selection::cluster_range(&layout, index).unwrap();
+                clusters.push(&text[range.clone()]);
+                index = range.end;
+            }
+            assert_eq!(clusters, golden);
+        }
+    }
+
+    #[test]
+    fn offsets_skip_the_marks() {
+        let (mut font_ctx, mut layout_ctx) = contexts();
+        let text = "abc שלום";
+        let mut kind = surface(text, TextDirection::Rtl, None);
+        // The last letter of the Hebrew word is drawn at the left.
+        let hit = kind.hit_test(1., 5., &mut font_ctx, &mut layout_ctx);
+        assert_eq!(hit, Some(7));
+
+        let change = SelectionChange::Utf16 {
+            anchor: 4,
+            focus: 8,
+        };
+        kind.set_selection(change, Instant::now(), &mut font_ctx, &mut layout_ctx);
+        let selected = kind.selected_text().unwrap();
+        assert_eq!((selected.anchor, selected.focus), (4, 8));
+        assert_eq!(selected.text, "שלום");
+
+        let tree = kind
+            .accessibility_tree(&mut font_ctx, &mut layout_ctx)
+            .unwrap();
+        assert_eq!(tree.children[0].text, text);
+        assert_eq!(tree.children[0].text_range, 0..8);
+    }
+}
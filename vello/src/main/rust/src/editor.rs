@@ -0,0 +1,836 @@
+//! Editable text surfaces, whose text, selection and undo history are owned by the render thread.
+//!
+//! Kotlin forwards the edits of an Android `InputConnection` (and key presses) as [`EditOp`]s,
+//! and is told the resulting [`EditorState`] after each batch of commands, so that it can answer
+//! the input method's queries. Offsets from Kotlin are in UTF-16 code units, and are converted
+//! to byte offsets here.
+//!
+//! Parley's `PlainEditor` doesn't expose the selection offsets which input methods need, so this
+//! keeps its own buffer, and uses Parley's [`Selection`] to move the caret through the layout.
+
+use std::{borrow::Cow, ops::Range, time::Instant};
+
+use parley::{Alignment, FontContext, Layout, Selection, StyleProperty, VisualMode};
+use vello::{
+    kurbo::{Affine, Rect},
+    peniko::{Brush, Color, Fill},
+    Scene,
+};
+
+use crate::{
+    scale::TextScale,
+    selection::{self, byte_to_utf16, utf16_to_byte, SelectionChange, TextSelection},
+    LayoutContext,
+};
+
+/// The families used for editable text, in order of preference.
+///
+/// Roboto Flex is always available, but only supports digits and `:`.
+const EDITOR_FONTS: &str = "sans-serif, Roboto Flex";
+
+/// The maximum number of edits which can be undone.
+const MAX_UNDO: usize = 100;
+
+/// The height of the underline of the composing region, in pixels.
+const COMPOSING_UNDERLINE: f64 = 2.;
+
+/// How far the caret is kept from the edges of the surface whilst scrolling, in pixels.
+const SCROLL_MARGIN: f32 = 8.;
+
+/// A key which edits the text or moves the caret.
+///
+/// The discriminants are used in the FFI, so must match `EditKey` in Kotlin.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EditKey {
+    /// Delete the selection, or the grapheme before the caret.
+    Backspace = 0,
+    /// Delete the selection, or the grapheme after the caret.
+    Delete = 1,
+    Left = 2,
+    Right = 3,
+    Up = 4,
+    Down = 5,
+    LineStart = 6,
+    LineEnd = 7,
+    TextStart = 8,
+    TextEnd = 9,
+    WordLeft = 10,
+    WordRight = 11,
+    /// Delete the selection, or the word before the caret.
+    BackspaceWord = 12,
+    /// Delete the selection, or the word after the caret.
+    DeleteWord = 13,
+    /// Insert a line break, if the editor is multi-line.
+    Enter = 14,
+    SelectAll = 15,
+    Undo = 16,
+    Redo = 17,
+}
+
+impl EditKey {
+    pub fn from_index(index: i32) -> Option<Self> {
+        [
+            Self::Backspace,
+            Self::Delete,
+            Self::Left,
+            Self::Right,
+            Self::Up,
+            Self::Down,
+            Self::LineStart,
+            Self::LineEnd,
+            Self::TextStart,
+            Self::TextEnd,
+            Self::WordLeft,
+            Self::WordRight,
+            Self::BackspaceWord,
+            Self::DeleteWord,
+            Self::Enter,
+            Self::SelectAll,
+            Self::Undo,
+            Self::Redo,
+        ]
+        .into_iter()
+        .find(|key| *key as i32 == index)
+    }
+}
+
+/// An edit sent from Kotlin, mostly mirroring the methods of Android's `InputConnection`.
+///
+/// All offsets and lengths are in UTF-16 code units.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EditOp {
+    /// Replace the composing region (or the selection) with `text`.
+    ///
+    /// `new_cursor` is where the caret goes, as in `InputConnection.commitText`: if positive,
+    /// it is relative to the end of `text` minus one, otherwise it is relative to its start.
+    CommitText {
+        text: String,
+        new_cursor: i32,
+    },
+    /// Replace the composing region (or the selection) with `text`, which becomes the composing
+    /// region.
+    SetComposingText {
+        text: String,
+        new_cursor: i32,
+    },
+    /// Mark existing text as the composing region.
+    SetComposingRegion {
+        start: usize,
+        end: usize,
+    },
+    /// Keep the composing text, but stop treating it as composing.
+    FinishComposingText,
+    /// Delete `before` code units before the selection, and `after` after it.
+    DeleteSurroundingText {
+        before: usize,
+        after: usize,
+    },
+    SetSelection {
+        anchor: usize,
+        focus: usize,
+    },
+    /// A key press, which extends the selection rather than moving the caret if `extend` is set
+    /// (for the keys which move the caret).
+    Key {
+        key: EditKey,
+        extend: bool,
+    },
+}
+
+/// The state of an editor which Kotlin needs to answer an input method, in UTF-16 code units.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EditorState {
+    pub text: String,
+    pub anchor: usize,
+    pub focus: usize,
+    pub composing: Option<Range<usize>>,
+}
+
+/// The text and selection of an editor before an edit, which the edit can be undone to.
+#[derive(Clone, Debug)]
+struct Snapshot {
+    text: String,
+    anchor: usize,
+    focus: usize,
+}
+
+/// A text editor, which is the content of a [`SurfaceKind::TextEditor`](crate::SurfaceKind).
+#[derive(Clone)]
+pub struct TextEditor {
+    text: String,
+    selection: TextSelection,
+    /// The byte range of the text which the input method is still composing.
+    composing: Option<Range<usize>>,
+    /// Whether lines are wrapped to the width of the surface, and line breaks can be entered.
+    multiline: bool,
+    /// The font size, in sp.
+    size: f32,
+    scale: TextScale,
+    color: Color,
+    /// The width and height of the surface, in pixels.
+    viewport: (f32, f32),
+    /// How far the content is scrolled, to keep the caret visible.
+    scroll: (f32, f32),
+    /// The layout of `text`, which is dropped whenever it would change.
+    layout: Option<Layout<Brush>>,
+    undo: Vec<Snapshot>,
+    redo: Vec<Snapshot>,
+}
+
+impl TextEditor {
+    pub fn new(text: String, multiline: bool, size: f32, now: Instant) -> Self {
+        let end = text.len();
+        Self {
+            selection: TextSelection::new(&text, end, end, now),
+            text,
+            composing: None,
+            multiline,
+            size,
+            scale: TextScale::default(),
+            color: Color::BLACK,
+            viewport: (0., 0.),
+            scroll: (0., 0.),
+            layout: None,
+            undo: Vec::new(),
+            redo: Vec::new(),
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn selection(&self) -> &TextSelection {
+        &self.selection
+    }
+
+    pub fn selection_mut(&mut self) -> &mut TextSelection {
+        &mut self.selection
+    }
+
+    pub fn set_color(&mut self, color: Color) {
+        if self.color != color {
+            self.color = color;
+            self.layout = None;
+        }
+    }
+
+    /// Set how the font size is converted to pixels.
+    pub fn set_scale(&mut self, scale: TextScale) {
+        if self.scale != scale {
+            self.scale = scale;
+            self.layout = None;
+        }
+    }
+
+    /// Set the size of the surface, which multi-line editors wrap their text to.
+    pub fn set_viewport(&mut self, width: f32, height: f32) {
+        if self.viewport != (width, height) {
+            self.viewport = (width, height);
+            self.layout = None;
+        }
+    }
+
+    /// The state of this editor, as Kotlin sees it.
+    pub fn state(&self) -> EditorState {
+        let utf16 = |index| byte_to_utf16(&self.text, index);
+        EditorState {
+            text: self.text.clone(),
+            anchor: utf16(self.selection.anchor),
+            focus: utf16(self.selection.focus),
+            composing: self
+                .composing
+                .as_ref()
+                .map(|range| utf16(range.start)..utf16(range.end)),
+        }
+    }
+
+    /// The layout of the text, if it is up to date, or a new layout otherwise.
+    pub fn layout(
+        &self,
+        font_ctx: &mut FontContext,
+        layout_ctx: &mut LayoutContext,
+    ) -> Cow<'_, Layout<Brush>> {
+        match &self.layout {
+            Some(layout) => Cow::Borrowed(layout),
+            None => Cow::Owned(self.build_layout(font_ctx, layout_ctx)),
+        }
+    }
+
+    fn build_layout(
+        &self,
+        font_ctx: &mut FontContext,
+        layout_ctx: &mut LayoutContext,
+    ) -> Layout<Brush> {
+        let mut builder =
+            layout_ctx.ranged_builder(font_ctx, &self.text, self.scale.pixels_per_sp());
+        builder.push_default(StyleProperty::FontStack(EDITOR_FONTS.into()));
+        builder.push_default(StyleProperty::FontSize(self.size));
+        builder.push_default(StyleProperty::Brush(Brush::Solid(self.color)));
+        builder.push_default(StyleProperty::LineHeight(1.3));
+        let mut layout = builder.build(&self.text);
+        let width = self.multiline.then_some(self.viewport.0);
+        layout.break_all_lines(width);
+        layout.align(width, Alignment::Start);
+        layout
+    }
+
+    fn update_layout(&mut self, font_ctx: &mut FontContext, layout_ctx: &mut LayoutContext) {
+        if self.layout.is_none() {
+            self.layout = Some(self.build_layout(font_ctx, layout_ctx));
+        }
+    }
+
+    /// Convert the content coordinates of a point to those of the surface.
+    pub fn transform(&self) -> Affine {
+        Affine::translate((-f64::from(self.scroll.0), -f64::from(self.scroll.1)))
+    }
+
+    /// Apply `op`, then scroll to keep the caret visible.
+    pub fn apply(
+        &mut self,
+        op: EditOp,
+        now: Instant,
+        font_ctx: &mut FontContext,
+        layout_ctx: &mut LayoutContext,
+    ) {
+        match op {
+            EditOp::CommitText { text, new_cursor } => {
+                let range = self.composing_or_selection();
+                self.replace(range, &text, new_cursor, false, now);
+            }
+            EditOp::SetComposingText { text, new_cursor } => {
+                let range = self.composing_or_selection();
+                self.replace(range, &text, new_cursor, true, now);
+            }
+            EditOp::SetComposingRegion { start, end } => {
+                let start = utf16_to_byte(&self.text, start);
+                let end = utf16_to_byte(&self.text, end);
+                let range = start.min(end)..start.max(end);
+                self.composing = (!range.is_empty()).then_some(range);
+            }
+            EditOp::FinishComposingText => self.composing = None,
+            EditOp::DeleteSurroundingText { before, after } => {
+                let selected = self.selection.range();
+                let start = byte_to_utf16(&self.text, selected.start).saturating_sub(before);
+                let end = byte_to_utf16(&self.text, selected.end) + after;
+                let start = utf16_to_byte(&self.text, start);
+                let end = utf16_to_byte(&self.text, end);
+                // Both sides are undone at once.
+                if self.composing.is_none() && (start < selected.start || selected.end < end) {
+                    self.push_undo();
+                }
+                // Delete after the selection first, so that `start` stays valid.
+                self.remove(selected.end..end, now);
+                self.remove(start..selected.start, now);
+            }
+            EditOp::SetSelection { anchor, focus } => {
+                self.selection = TextSelection::apply(
+                    Some(self.selection),
+                    SelectionChange::Utf16 { anchor, focus },
+                    &self.text,
+                    || unreachable!("Offsets don't need a layout"),
+                    now,
+                )
+                .unwrap();
+            }
+            EditOp::Key { key, extend } => {
+                self.update_layout(font_ctx, layout_ctx);
+                self.key(key, extend, now);
+            }
+        }
+        self.update_layout(font_ctx, layout_ctx);
+        self.scroll_to_caret();
+    }
+
+    /// Apply a change from the selection API of text surfaces.
+    pub fn set_selection(
+        &mut self,
+        change: SelectionChange,
+        now: Instant,
+        font_ctx: &mut FontContext,
+        layout_ctx: &mut LayoutContext,
+    ) {
+        self.update_layout(font_ctx, layout_ctx);
+        let layout = self.layout.as_ref().unwrap();
+        let change = match change {
+            // The point is relative to the surface, which may be scrolled.
+            SelectionChange::Point { x, y, extend } => SelectionChange::Point {
+                x: x + self.scroll.0,
+                y: y + self.scroll.1,
+                extend,
+            },
+            // Editors always show the caret.
+            SelectionChange::Clear => SelectionChange::Bytes {
+                anchor: self.selection.focus,
+                focus: self.selection.focus,
+            },
+            change => change,
+        };
+        self.selection = TextSelection::apply(
+            Some(self.selection),
+            change,
+            &self.text,
+            || layout.clone(),
+            now,
+        )
+        .unwrap();
+        self.scroll_to_caret();
+    }
+
+    fn composing_or_selection(&self) -> Range<usize> {
+        self.composing
+            .clone()
+            .unwrap_or_else(|| self.selection.range())
+    }
+
+    /// Replace `range` with `text`, placing the caret as described by [`EditOp::CommitText`].
+    fn replace(
+        &mut self,
+        range: Range<usize>,
+        text: &str,
+        new_cursor: i32,
+        composing: bool,
+        now: Instant,
+    ) {
+        // A composition is undone all at once.
+        if self.composing.is_none() {
+            self.push_undo();
+        }
+        self.text.replace_range(range.clone(), text);
+        self.layout = None;
+        let inserted = range.start..range.start + text.len();
+        self.composing = (composing && !inserted.is_empty()).then_some(inserted.clone());
+        let start = byte_to_utf16(&self.text, inserted.start);
+        let end = byte_to_utf16(&self.text, inserted.end);
+        let caret = if new_cursor > 0 {
+            end.saturating_add_signed(new_cursor as isize - 1)
+        } else {
+            start.saturating_add_signed(new_cursor as isize)
+        };
+        let caret = utf16_to_byte(&self.text, caret);
+        self.selection = TextSelection::new(&self.text, caret, caret, now);
+    }
+
+    /// Delete `range`, keeping the selection and composing region on the same text.
+    fn delete(&mut self, range: Range<usize>, now: Instant) {
+        if !range.is_empty() && self.composing.is_none() {
+            self.push_undo();
+        }
+        self.remove(range, now);
+    }
+
+    /// Delete `range` like [`Self::delete`], without recording it to be undone.
+    fn remove(&mut self, range: Range<usize>, now: Instant) {
+        if range.is_empty() {
+            return;
+        }
+        self.text.replace_range(range.clone(), "");
+        self.layout = None;
+        let shift = |index: usize| {
+            if index >= range.end {
+                index - range.len()
+            } else {
+                index.min(range.start)
+            }
+        };
+        self.composing = self.composing.take().and_then(|composing| {
+            let shifted = shift(composing.start)..shift(composing.end);
+            (!shifted.is_empty()).then_some(shifted)
+        });
+        let anchor = shift(self.selection.anchor);
+        let focus = shift(self.selection.focus);
+        self.selection = TextSelection::new(&self.text, anchor, focus, now);
+    }
+
+    fn key(&mut self, key: EditKey, extend: bool, now: Instant) {
+        let layout = self.layout.as_ref().unwrap();
+        let focus = self.selection.focus;
+        // The caret, as a Parley selection, so that it can be moved through the layout.
+        let caret = Selection::from(selection::caret_cursor(layout, &self.text, focus));
+        let moved = |selection: Selection| selection.focus().text_range().start;
+        let selected = self.selection.range();
+        // Parley doesn't move past the start of the last word, so move to the end of the text.
+        let next_word = || {
+            let next = moved(caret.next_word(layout, false));
+            if next > focus {
+                next
+            } else {
+                self.text.len()
+            }
+        };
+        let target = match key {
+            EditKey::Left | EditKey::Right if !extend && !self.selection.is_collapsed() => {
+                // Moving without extending collapses the selection to the side moved towards.
+                if key == EditKey::Left {
+                    selected.start
+                } else {
+                    selected.end
+                }
+            }
+            EditKey::Left => moved(caret.previous_visual(layout, VisualMode::default(), false)),
+            EditKey::Right => moved(caret.next_visual(layout, VisualMode::default(), false)),
+            EditKey::Up if self.multiline => moved(caret.previous_line(layout, false)),
+            EditKey::Down if self.multiline => moved(caret.next_line(layout, false)),
+            EditKey::Up | EditKey::TextStart => 0,
+            EditKey::Down | EditKey::TextEnd => self.text.len(),
+            EditKey::LineStart => moved(caret.line_start(layout, false)),
+            EditKey::LineEnd => moved(caret.line_end(layout, false)),
+            EditKey::WordLeft => moved(caret.previous_word(layout, false)),
+            EditKey::WordRight => next_word(),
+            EditKey::Backspace | EditKey::Delete | EditKey::BackspaceWord | EditKey::DeleteWord
+                if !selected.is_empty() =>
+            {
+                self.delete(selected, now);
+                return;
+            }
+            EditKey::Backspace => {
+                let start = previous_cluster_start(layout, &self.text, focus);
+                self.delete(start..focus, now);
+                return;
+            }
+            EditKey::Delete => {
+                let end = next_cluster_end(layout, &self.text, focus);
+                self.delete(focus..end, now);
+                return;
+            }
+            EditKey::BackspaceWord => {
+                let start = moved(caret.previous_word(layout, false)).min(focus);
+                self.delete(start..focus, now);
+                return;
+            }
+            EditKey::DeleteWord => {
+                let end = next_word();
+                self.delete(focus..end, now);
+                return;
+            }
+            EditKey::Enter => {
+                if self.multiline {
+                    let range = self.composing_or_selection();
+                    self.replace(range, "\n", 1, false, now);
+                }
+                return;
+            }
+            EditKey::SelectAll => {
+                self.selection = TextSelection::new(&self.text, 0, self.text.len(), now);
+                return;
+            }
+            EditKey::Undo => {
+                self.restore(true, now);
+                return;
+            }
+            EditKey::Redo => {
+                self.restore(false, now);
+                return;
+            }
+        };
+        let anchor = if extend {
+            self.selection.anchor
+        } else {
+            target
+        };
+        self.selection = TextSelection::new(&self.text, anchor, target, now);
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            text: self.text.clone(),
+            anchor: self.selection.anchor,
+            focus: self.selection.focus,
+        }
+    }
+
+    /// Record the current state, before it is edited.
+    fn push_undo(&mut self) {
+        if self.undo.len() == MAX_UNDO {
+            self.undo.remove(0);
+        }
+        self.undo.push(self.snapshot());
+        self.redo.clear();
+    }
+
+    /// Undo (or redo, if `undo` is false) the most recent edit.
+    fn restore(&mut self, undo: bool, now: Instant) {
+        let (from, to) = if undo {
+            (&mut self.undo, &mut self.redo)
+        } else {
+            (&mut self.redo, &mut self.undo)
+        };
+        let Some(snapshot) = from.pop() else {
+            return;
+        };
+        to.push(Snapshot {
+            text: std::mem::replace(&mut self.text, snapshot.text),
+            anchor: self.selection.anchor,
+            focus: self.selection.focus,
+        });
+        self.layout = None;
+        self.composing = None;
+        self.selection = TextSelection::new(&self.text, snapshot.anchor, snapshot.focus, now);
+    }
+
+    /// The position of the caret in content coordinates.
+    fn caret_rect(&self, layout: &Layout<Brush>) -> Rect {
+        selection::caret_rect(layout, &self.text, self.selection.focus).unwrap_or_else(|| {
+            // Empty text has no lines, so put the caret where the first line would be.
+            let line_height = self.size * self.scale.pixels_per_sp() * 1.3;
+            Rect::new(0., 0., 2., f64::from(line_height))
+        })
+    }
+
+    /// Scroll so that the caret is within the surface.
+    fn scroll_to_caret(&mut self) {
+        let Some(layout) = &self.layout else {
+            return;
+        };
+        let caret = self.caret_rect(layout);
+        let (scroll, start, end, viewport) = if self.multiline {
+            (&mut self.scroll.1, caret.y0, caret.y1, self.viewport.1)
+        } else {
+            (&mut self.scroll.0, caret.x0, caret.x1, self.viewport.0)
+        };
+        let (start, end) = (start as f32, end as f32);
+        if start < *scroll {
+            *scroll = (start - SCROLL_MARGIN).max(0.);
+        } else if end > *scroll + viewport {
+            *scroll = end - viewport + SCROLL_MARGIN;
+        }
+    }
+
+    /// Draw the selection, caret and composing underline, over text drawn with [`Self::transform`].
+    pub fn draw_decorations(&self, scene: &mut Scene, layout: &Layout<Brush>) {
+        let transform = self.transform();
+        self.selection
+            .draw(scene, transform, layout, &self.text, self.color);
+        let placed = selection::caret_rect(layout, &self.text, self.selection.focus).is_some();
+        if self.selection.caret_visible() && !placed {
+            // `TextSelection` can't find the caret in a layout without lines, such as of empty
+            // text, so draw it where the first line would be.
+            scene.fill(
+                Fill::NonZero,
+                transform,
+                self.color,
+                None,
+                &self.caret_rect(layout),
+            );
+        }
+        if let Some(composing) = &self.composing {
+            selection::highlight_rects(layout, composing, |rect| {
+                let underline = Rect::new(rect.x0, rect.y1 - COMPOSING_UNDERLINE, rect.x1, rect.y1);
+                scene.fill(Fill::NonZero, transform, self.color, None, &underline);
+            });
+        }
+    }
+}
+
+/// The start of the grapheme cluster before the byte offset `index` of `text`.
+fn previous_cluster_start(layout: &Layout<Brush>, text: &str, index: usize) -> usize {
+    let Some((before, _)) = text[..index].char_indices().next_back() else {
+        return 0;
+    };
+    selection::cluster_range(layout, before).map_or(before, |range| range.start.min(before))
+}
+
+/// The end of the grapheme cluster at the byte offset `index` of `text`.
+fn next_cluster_end(layout: &Layout<Brush>, text: &str, index: usize) -> usize {
+    let Some(c) = text[index..].chars().next() else {
+        return index;
+    };
+    let after = index + c.len_utf8();
+    selection::cluster_range(layout, index).map_or(after, |range| range.end.max(after))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use parley::FontContext;
+
+    use crate::{test_contexts, LayoutContext};
+
+    use super::{EditKey, EditOp, EditorState, TextEditor};
+
+    struct Harness {
+        editor: TextEditor,
+        font_ctx: FontContext,
+        layout_ctx: LayoutContext,
+    }
+
+    impl Harness {
+        fn new(text: &str, multiline: bool) -> Self {
+            let (font_ctx, layout_ctx) = test_contexts();
+            let mut editor = TextEditor::new(text.to_string(), multiline, 40., Instant::now());
+            editor.set_viewport(1000., 1000.);
+            Self {
+                editor,
+                font_ctx,
+                layout_ctx,
+            }
+        }
+
+        fn apply(&mut self, op: EditOp) -> EditorState {
+            self.editor
+                .apply(op, Instant::now(), &mut self.font_ctx, &mut self.layout_ctx);
+            self.editor.state()
+        }
+
+        fn key(&mut self, key: EditKey, extend: bool) -> EditorState {
+            self.apply(EditOp::Key { key, extend })
+        }
+    }
+
+    fn state(text: &str, anchor: usize, focus: usize) -> EditorState {
+        EditorState {
+            text: text.to_string(),
+            anchor,
+            focus,
+            composing: None,
+        }
+    }
+
+    #[test]
+    fn composes_and_commits() {
+        let mut harness = Harness::new("12", false);
+        let composing = harness.apply(EditOp::SetComposingText {
+            text: "3".to_string(),
+            new_cursor: 1,
+        });
+        assert_eq!(composing.text, "123");
+        assert_eq!(composing.composing, Some(2..3));
+        let composing = harness.apply(EditOp::SetComposingText {
+            text: "34".to_string(),
+            new_cursor: 1,
+        });
+        assert_eq!(composing.composing, Some(2..4));
+        let committed = harness.apply(EditOp::CommitText {
+            text: ":45".to_string(),
+            new_cursor: 1,
+        });
+        assert_eq!(committed, state("12:45", 5, 5));
+        // The whole composition is undone at once.
+        assert_eq!(harness.key(EditKey::Undo, false), state("12", 2, 2));
+        assert_eq!(harness.key(EditKey::Redo, false), state("12:45", 5, 5));
+    }
+
+    #[test]
+    fn places_the_caret_relative_to_committed_text() {
+        let mut harness = Harness::new("1234", false);
+        harness.apply(EditOp::SetSelection {
+            anchor: 2,
+            focus: 2,
+        });
+        let before = harness.apply(EditOp::CommitText {
+            text: "::".to_string(),
+            new_cursor: 0,
+        });
+        assert_eq!(before, state("12::34", 2, 2));
+        let after = harness.apply(EditOp::CommitText {
+            text: "0".to_string(),
+            new_cursor: 2,
+        });
+        assert_eq!(after, state("120::34", 4, 4));
+    }
+
+    #[test]
+    fn deletes_surrounding_text() {
+        let mut harness = Harness::new("12:34", false);
+        harness.apply(EditOp::SetSelection {
+            anchor: 2,
+            focus: 3,
+        });
+        let deleted = harness.apply(EditOp::DeleteSurroundingText {
+            before: 1,
+            after: 1,
+        });
+        assert_eq!(deleted, state("1:4", 1, 2));
+        // Both sides are restored by a single undo.
+        assert_eq!(harness.key(EditKey::Undo, false), state("12:34", 2, 3));
+        assert_eq!(harness.key(EditKey::Undo, false), state("12:34", 2, 3));
+    }
+
+    #[test]
+    fn moves_and_deletes_by_cluster() {
+        let mut harness = Harness::new("12:34", false);
+        assert_eq!(harness.key(EditKey::Left, false), state("12:34", 4, 4));
+        assert_eq!(harness.key(EditKey::Left, true), state("12:34", 4, 3));
+        assert_eq!(harness.key(EditKey::Right, false), state("12:34", 4, 4));
+        assert_eq!(harness.key(EditKey::Backspace, false), state("12:4", 3, 3));
+        assert_eq!(harness.key(EditKey::Delete, false), state("12:", 3, 3));
+        assert_eq!(harness.key(EditKey::TextStart, true), state("12:", 3, 0));
+        assert_eq!(harness.key(EditKey::Delete, false), state("", 0, 0));
+        assert_eq!(harness.key(EditKey::Undo, false), state("12:", 3, 0));
+    }
+
+    #[test]
+    fn moves_and_deletes_by_word() {
+        let mut harness = Harness::new("one two three", false);
+        assert_eq!(
+            harness.key(EditKey::WordLeft, false),
+            state("one two three", 8, 8)
+        );
+        assert_eq!(
+            harness.key(EditKey::WordLeft, true),
+            state("one two three", 8, 4)
+        );
+        assert_eq!(
+            harness.key(EditKey::WordRight, false),
+            state("one two three", 8, 8)
+        );
+        // The last word is moved over to the end of the text.
+        assert_eq!(
+            harness.key(EditKey::WordRight, false),
+            state("one two three", 13, 13)
+        );
+        assert_eq!(
+            harness.key(EditKey::BackspaceWord, false),
+            state("one two ", 8, 8)
+        );
+        assert_eq!(
+            harness.key(EditKey::BackspaceWord, false),
+            state("one ", 4, 4)
+        );
+        assert_eq!(harness.key(EditKey::Undo, false), state("one two ", 8, 8));
+        harness.apply(EditOp::SetSelection {
+            anchor: 4,
+            focus: 4,
+        });
+        assert_eq!(
+            harness.key(EditKey::DeleteWord, false),
+            state("one  ", 4, 4)
+        );
+    }
+
+    #[test]
+    fn moves_between_lines() {
+        let mut harness = Harness::new("12\n345\n6", true);
+        harness.apply(EditOp::SetSelection {
+            anchor: 4,
+            focus: 4,
+        });
+        assert_eq!(harness.key(EditKey::LineStart, false).focus, 3);
+        assert_eq!(
+            harness.key(EditKey::LineEnd, true),
+            state("12\n345\n6", 3, 6)
+        );
+        assert_eq!(harness.key(EditKey::Up, false).focus, 2);
+        assert_eq!(harness.key(EditKey::Down, false).focus, 5);
+        assert_eq!(harness.key(EditKey::Down, false).focus, 8);
+        // Single line editors move to the ends of the text instead.
+        let mut single = Harness::new("12:34", false);
+        single.apply(EditOp::SetSelection {
+            anchor: 2,
+            focus: 2,
+        });
+        assert_eq!(single.key(EditKey::Up, false), state("12:34", 0, 0));
+        assert_eq!(single.key(EditKey::Down, true), state("12:34", 0, 5));
+    }
+
+    #[test]
+    fn only_multiline_editors_break_lines() {
+        let mut single = Harness::new("12", false);
+        assert_eq!(single.key(EditKey::Enter, false), state("12", 2, 2));
+        let mut multi = Harness::new("12", true);
+        assert_eq!(multi.key(EditKey::Enter, false), state("12\n", 3, 3));
+        assert_eq!(multi.key(EditKey::Up, false).focus, 0);
+    }
+}
@@ -0,0 +1,155 @@
+//! Outlines, shadows and glows drawn with the glyphs of text surfaces.
+//!
+//! Vello can't blur glyphs, so blurred shadows are drawn as several copies of the glyphs spread
+//! over the blur radius. The copies are translucent, and overlap most in the middle of the
+//! shadow, so it fades out towards its edges.
+
+use vello::{
+    kurbo::{Join, Vec2},
+    peniko::Color,
+};
+
+/// The joins which Kotlin can ask for.
+///
+/// The index of each join is used in the FFI, so must match `StrokeJoin` in Kotlin.
+pub const JOINS: [Join; 3] = [Join::Bevel, Join::Miter, Join::Round];
+
+/// How many rings of copies are drawn around the middle of a blurred shadow.
+const BLUR_RINGS: usize = 2;
+/// How many copies are drawn in each ring of a blurred shadow.
+const BLUR_RING_SAMPLES: usize = 8;
+
+/// An outline drawn over the edges of the glyphs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextStroke {
+    /// The width of the outline in pixels, centred on the edges of the glyphs.
+    pub width: f32,
+    pub join: Join,
+    pub color: Color,
+}
+
+/// A copy of the glyphs drawn under them, such as a drop shadow or a glow.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextShadow {
+    /// How far the shadow is moved from the glyphs, in pixels.
+    pub offset: Vec2,
+    /// How far the shadow is spread out, in pixels.
+    pub blur_radius: f32,
+    pub color: Color,
+}
+
+impl TextShadow {
+    /// A glow, which is a shadow directly under the glyphs.
+    pub fn glow(blur_radius: f32, color: Color) -> Self {
+        Self {
+            offset: Vec2::ZERO,
+            blur_radius,
+            color,
+        }
+    }
+
+    /// The offset and colour of each copy of the glyphs drawn for this shadow.
+    ///
+    /// Where every copy overlaps, they add up to the colour of the shadow.
+    pub fn passes(&self) -> Vec<(Vec2, Color)> {
+        if self.blur_radius <= 0. {
+            return vec![(self.offset, self.color)];
+        }
+        let mut offsets = vec![self.offset];
+        for ring in 1..=BLUR_RINGS {
+            let radius = f64::from(self.blur_radius) * ring as f64 / BLUR_RINGS as f64;
+            for sample in 0..BLUR_RING_SAMPLES {
+                let angle = std::f64::consts::TAU * sample as f64 / BLUR_RING_SAMPLES as f64;
+                offsets.push(self.offset + Vec2::from_angle(angle) * radius);
+            }
+        }
+        // Drawing `n` copies with alpha `a` over each other covers `1 - (1 - a)^n`.
+        let alpha = f32::from(self.color.a) / 255.;
+        let pass_alpha = 1. - (1. - alpha).powf(1. / offsets.len() as f32);
+        let color = Color {
+            a: (pass_alpha * 255.).round() as u8,
+            ..self.color
+        };
+        offsets.into_iter().map(|offset| (offset, color)).collect()
+    }
+}
+
+/// The effects drawn with the glyphs of a text surface.
+///
+/// Shadows are drawn in order under all of the glyphs, and the stroke is drawn over them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TextEffects {
+    pub stroke: Option<TextStroke>,
+    pub shadows: Vec<TextShadow>,
+}
+
+#[cfg(test)]
+mod tests {
+
+    use vello::{
+        kurbo::{Join, Vec2},
+        peniko::{Color, Style},
+    };
+
+    use crate::{command::SurfaceUpdate, test_contexts, test_variable_font};
+
+    use super::{TextEffects, TextShadow, TextStroke};
+
+    #[test]
+    fn sharp_shadows_are_one_copy() {
+        let shadow = TextShadow {
+            offset: Vec2::new(2., 3.),
+            blur_radius: 0.,
+            color: Color::BLACK,
+        };
+        assert_eq!(shadow.passes(), [(Vec2::new(2., 3.), Color::BLACK)]);
+    }
+
+    #[test]
+    fn blurred_copies_add_up_to_the_colour() {
+        let color = Color::rgba8(255, 200, 0, 160);
+        let passes = TextShadow::glow(6., color).passes();
+        assert_eq!(passes.len(), 17);
+        let covered = passes.iter().fold(0., |covered, (_, color)| {
+            let alpha = f32::from(color.a) / 255.;
+            covered + alpha * (1. - covered)
+        });
+        assert!((covered - 160. / 255.).abs() < 0.05, "{covered}");
+        for (offset, pass) in &passes {
+            assert!(offset.hypot() <= 6. + 1e-9);
+            assert_eq!((pass.r, pass.g, pass.b), (255, 200, 0));
+        }
+    }
+
+    #[test]
+    fn shadows_are_under_the_glyphs() {
+        let (mut font_ctx, mut layout_ctx) = test_contexts();
+        let mut kind = test_variable_font("12:34", 40.);
+        SurfaceUpdate::SetTextEffects(TextEffects {
+            stroke: Some(TextStroke {
+                width: 2.,
+                join: Join::Round,
+                color: Color::WHITE,
+            }),
+            shadows: vec![TextShadow {
+                offset: Vec2::new(3., 4.),
+                blur_radius: 0.,
+                color: Color::BLACK,
+            }],
+        })
+        .apply(&mut kind);
+        let scene = kind.scene(100, 100, false, &mut font_ctx, &mut layout_ctx);
+        let runs = &scene.encoding().resources.glyph_runs;
+        let layers = runs
+            .iter()
+            .map(|run| {
+                let stroke = matches!(run.style, Style::Stroke(_));
+                (run.transform.translation, stroke)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            layers,
+            [([3., 4.], false), ([0., 0.], false), ([0., 0.], true)]
+        );
+    }
+}
@@ -0,0 +1,177 @@
+//! OpenType feature settings of text surfaces, such as tabular figures for clocks.
+//!
+//! Features are applied through Parley's [`StyleProperty::FontFeatures`](parley::StyleProperty),
+//! either to all of the text of a surface or to spans of it. As the font of text surfaces is
+//! built in, features are checked against the features its `GSUB` and `GPOS` tables support
+//! before they are sent to the render thread.
+
+use std::{fmt, ops::Range, sync::OnceLock};
+
+use parley::{swash::Tag, FontFeature};
+use vello::skrifa::{raw::TableProvider, FontRef};
+
+use crate::ROBOTO_FLEX;
+
+/// Features which are applied to a byte range of the text of a surface.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FeatureSpan {
+    pub range: Range<usize>,
+    pub features: Vec<FontFeature>,
+}
+
+impl FeatureSpan {
+    /// Keep this span within `text`, after the text has changed.
+    ///
+    /// Returns `None` if none of the span is left.
+    pub fn clamped(self, text: &str) -> Option<Self> {
+        let end = self.range.end.min(text.len());
+        let range = self.range.start.min(end)..end;
+        (!range.is_empty()).then_some(Self { range, ..self })
+    }
+}
+
+/// Add `span` to `spans`, replacing the spans which it overlaps.
+///
+/// Later spans take precedence where they overlap, so removing the overlapped spans only avoids
+/// keeping spans which can't have an effect.
+pub fn add_span(spans: &mut Vec<FeatureSpan>, span: FeatureSpan) {
+    spans.retain(|existing| {
+        existing.range.start < span.range.start || existing.range.end > span.range.end
+    });
+    spans.push(span);
+}
+
+/// A feature which the font doesn't support.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnsupportedFeature(pub Tag);
+
+impl fmt::Display for UnsupportedFeature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The font doesn't support the OpenType feature '{}'",
+            tag_to_string(self.0)
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedFeature {}
+
+/// The features in the `GSUB` and `GPOS` tables of `font`, sorted and without duplicates.
+///
+/// Fonts which can't be read support no features.
+pub fn supported_features(font: &[u8]) -> Vec<Tag> {
+    let Ok(font) = FontRef::new(font) else {
+        return Vec::new();
+    };
+    let mut tags = Vec::new();
+    if let Ok(list) = font.gsub().and_then(|gsub| gsub.feature_list()) {
+        tags.extend(list.feature_records().iter().map(|r| r.feature_tag()));
+    }
+    if let Ok(list) = font.gpos().and_then(|gpos| gpos.feature_list()) {
+        tags.extend(list.feature_records().iter().map(|r| r.feature_tag()));
+    }
+    let mut tags = tags
+        .into_iter()
+        .map(|tag| u32::from_be_bytes(tag.to_be_bytes()))
+        .collect::<Vec<_>>();
+    tags.sort_unstable();
+    tags.dedup();
+    tags
+}
+
+/// The features supported by the font of text surfaces.
+pub fn text_surface_features() -> &'static [Tag] {
+    static FEATURES: OnceLock<Vec<Tag>> = OnceLock::new();
+    FEATURES.get_or_init(|| supported_features(ROBOTO_FLEX))
+}
+
+/// Check that every feature of `features` is in `supported`.
+pub fn validate(features: &[FontFeature], supported: &[Tag]) -> Result<(), UnsupportedFeature> {
+    match features
+        .iter()
+        .find(|feature| supported.binary_search(&feature.tag).is_err())
+    {
+        Some(feature) => Err(UnsupportedFeature(feature.tag)),
+        None => Ok(()),
+    }
+}
+
+/// The four characters of `tag`, for messages.
+pub fn tag_to_string(tag: Tag) -> String {
+    String::from_utf8_lossy(&tag.to_be_bytes()).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use parley::{swash::tag_from_bytes, FontFeature};
+
+    use crate::{
+        command::SurfaceUpdate, test_contexts, test_variable_font, SurfaceKind, ROBOTO_FLEX,
+    };
+
+    use super::{add_span, supported_features, validate, FeatureSpan, UnsupportedFeature};
+
+    fn feature(tag: &[u8; 4]) -> FontFeature {
+        FontFeature {
+            tag: tag_from_bytes(tag),
+            value: 1,
+        }
+    }
+
+    #[test]
+    fn reads_supported_features() {
+        let supported = supported_features(ROBOTO_FLEX);
+        assert!(supported.is_sorted());
+        // The subset's digits are tabular by default, so it only has proportional figures.
+        assert_eq!(
+            supported,
+            [tag_from_bytes(b"kern"), tag_from_bytes(b"pnum")]
+        );
+        assert_eq!(validate(&[feature(b"pnum")], &supported), Ok(()));
+        assert_eq!(
+            validate(&[feature(b"kern"), feature(b"tnum")], &supported),
+            Err(UnsupportedFeature(tag_from_bytes(b"tnum")))
+        );
+        assert_eq!(supported_features(b"not a font"), []);
+    }
+
+    #[test]
+    fn spans_follow_text_changes() {
+        let span = |range| FeatureSpan {
+            range,
+            features: vec![feature(b"tnum")],
+        };
+        assert_eq!(span(2..5).clamped("1234"), Some(span(2..4)));
+        assert_eq!(span(4..5).clamped("1234"), None);
+        let mut spans = vec![span(0..2), span(2..4), span(3..6)];
+        add_span(&mut spans, span(1..4));
+        assert_eq!(spans, [span(0..2), span(3..6), span(1..4)]);
+    }
+
+    #[test]
+    fn proportional_figures_change_advances() {
+        let (mut font_ctx, mut layout_ctx) = test_contexts();
+        let mut kind = test_variable_font("1111", 40.);
+        let mut width = |kind: &SurfaceKind| {
+            let layout = kind.layout(&mut font_ctx, &mut layout_ctx).unwrap();
+            layout.width()
+        };
+        let tabular = width(&kind);
+        SurfaceUpdate::SetFontFeatures {
+            features: vec![feature(b"pnum")],
+            range: Some(0..2),
+        }
+        .apply(&mut kind);
+        let half = width(&kind);
+        SurfaceUpdate::SetFontFeatures {
+            features: vec![feature(b"pnum")],
+            range: None,
+        }
+        .apply(&mut kind);
+        let proportional = width(&kind);
+        // "1" is narrower than the other digits, so is narrower still when proportional.
+        assert!(proportional < half, "{proportional} < {half}");
+        assert!(half < tabular, "{half} < {tabular}");
+    }
+}
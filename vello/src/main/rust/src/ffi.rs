@@ -4,87 +4,242 @@
 )]
 
 use std::{
-    collections::HashMap,
-    sync::{Arc, Mutex},
+    num::NonZeroUsize,
+    ptr::NonNull,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
 use jni::{
-    objects::{JClass, JLongArray, JObject, JString},
-    sys::{jfloat, jint, jlong},
+    objects::{
+        GlobalRef, JByteBuffer, JClass, JFloatArray, JIntArray, JLongArray, JObject, JObjectArray,
+        JString, JValue,
+    },
+    sys::{jboolean, jfloat, jint, jintArray, jlong, jobject, jstring},
     JNIEnv,
 };
+#[cfg(target_os = "android")]
 use ndk::native_window::NativeWindow;
+use parley::{FontFeature, FontVariation};
+use vello::{kurbo::Vec2, peniko::Color};
 
+#[cfg(target_os = "android")]
+use crate::command::NewSurface;
 use crate::{
-    util::{abort_on_panic, INIT},
-    SurfaceId, SurfaceKind, VelloJni,
+    accessibility::{AccessAction, AccessNode},
+    advances::AdvanceMode,
+    animation::{AnimatedValue, Animation, AnimationEnded, AnimationTarget, Easing, RepeatMode},
+    color_glyphs::ColorPalette,
+    command::{apply_commands, Command, RenderStatus, RequestId, SurfaceUpdate},
+    config::{AntialiasingMethod, GraphicsBackend, VelloConfig, POWER_PREFERENCES, PRESENT_MODES},
+    debug::DebugMode,
+    direction::TextDirection,
+    editor::{EditKey, EditOp, EditorState},
+    effects::{self, TextEffects, TextShadow, TextStroke},
+    features,
+    hinting::TextRendering,
+    input::{PointerEvent, PointerEventKind, PointerHit},
+    logging::{self, LogConfig},
+    params::ParameterBlock,
+    recovery::GpuEvent,
+    scale::TextScale,
+    selection::SelectionChange,
+    stats::{FrameStatistics, FrameTimings, Phase},
+    trace::{self, TraceConfig},
+    util::abort_on_panic,
+    vsync::{platform_vsync_source, VsyncSource},
+    SurfaceId, VelloJni,
 };
 
-enum Command {
-    Render,
-    Finish,
-}
+/// How often the render thread produces frames whilst an animation is running, if there
+/// are no requests from Kotlin and it isn't pacing itself from vsync.
+const ANIMATION_FRAME_INTERVAL: Duration = Duration::from_nanos(1_000_000_000 / 60);
 
 struct FfiState {
-    vello: Mutex<VelloJni>,
-    updated_surfaces_scratch: Mutex<Vec<jlong>>,
-    surface_kinds: Mutex<HashMap<SurfaceId, SurfaceKind>>,
+    /// The queue of commands to the render thread, which owns the [`VelloJni`].
     control_thread: std::sync::mpsc::Sender<Command>,
+    /// Timings of recent frames, kept separately from `vello` so that querying them doesn't
+    /// wait for a frame to finish rendering.
+    frame_statistics: Mutex<FrameStatistics>,
+    /// Whether to report the timings of each frame to Kotlin.
+    frame_timing_callback: AtomicBool,
+    /// Whether to report every frame to `onFramePresented`, rather than only those which
+    /// Kotlin requested.
+    frame_presented_callback: AtomicBool,
+}
+
+impl FfiState {
+    fn send(&self, command: Command) {
+        self.control_thread
+            .send(command)
+            .expect("Render thread still running");
+    }
 }
 
 /// Trick the linker into keeping this library around
 #[unsafe(no_mangle)]
 pub extern "C" fn linker_trick_rust() {}
 
+/// Configure how logs are written to logcat, and whether stdout and stderr are forwarded there.
+///
+/// Levels are the indices of [`log::LevelFilter`]. Each entry of `targets` is logged at the
+/// level at the same index in `target_levels`. If `tag` is null, the module path of each
+/// record is used as its tag.
+///
+/// # Aborts
+///
+/// If any level is invalid, or if `targets` and `target_levels` have different lengths.
+#[tracing::instrument(name = "configureLogging", skip_all)]
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_org_linebender_vello_Vello_configureLogging<'local>(
+    mut env: JNIEnv<'local>,
+    _: JClass<'local>,
+    default_level: jint,
+    targets: JObjectArray<'local>,
+    target_levels: JIntArray<'local>,
+    tag: JString<'local>,
+    forward_stdio: jboolean,
+) {
+    abort_on_panic(|| {
+        let level = |level: jint| {
+            usize::try_from(level)
+                .ok()
+                .and_then(|level| log::LevelFilter::iter().nth(level))
+                .expect("Unknown log level")
+        };
+        let len = env.get_array_length(&targets).unwrap();
+        assert_eq!(
+            len,
+            env.get_array_length(&target_levels).unwrap(),
+            "Each log target needs a level"
+        );
+        let mut levels = vec![0; len.try_into().unwrap()];
+        env.get_int_array_region(&target_levels, 0, &mut levels)
+            .unwrap();
+        let target_levels = levels
+            .into_iter()
+            .enumerate()
+            .map(|(i, target_level)| {
+                let target = JString::from(
+                    env.get_object_array_element(&targets, i.try_into().unwrap())
+                        .unwrap(),
+                );
+                let target = env.get_string(&target).unwrap().into();
+                (target, level(target_level))
+            })
+            .collect();
+        let tag = (!tag.is_null()).then(|| env.get_string(&tag).unwrap().into());
+        logging::configure(&LogConfig {
+            default_level: level(default_level),
+            target_levels,
+            tag,
+            forward_stdio: forward_stdio != 0,
+        });
+    });
+}
+
+/// Set up which subscribers receive the tracing spans of the render pipeline.
+///
+/// If `chrome_trace_path` is not null, a Chrome trace is written to that file until
+/// [`Java_org_linebender_vello_Vello_finishChromeTrace`] is called.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_org_linebender_vello_Vello_configureTracing<'local>(
+    mut env: JNIEnv<'local>,
+    _: JClass<'local>,
+    logcat: jboolean,
+    system_trace: jboolean,
+    chrome_trace_path: JString<'local>,
+) {
+    abort_on_panic(|| {
+        let chrome_trace_path = (!chrome_trace_path.is_null())
+            .then(|| env.get_string(&chrome_trace_path).unwrap().into());
+        trace::init(&TraceConfig {
+            logcat: logcat != 0,
+            system_trace: system_trace != 0,
+            chrome_trace_path,
+        });
+    });
+}
+
+/// Complete and flush the Chrome trace file, if one is being written.
 #[unsafe(no_mangle)]
-pub extern "system" fn Java_org_linebender_vello_Vello_initRust<'local>(
+pub extern "system" fn Java_org_linebender_vello_Vello_finishChromeTrace<'local>(
     _: JNIEnv<'local>,
     _: JClass<'local>,
 ) {
-    let _ = &*INIT;
+    abort_on_panic(trace::finish_chrome_trace);
 }
 
+/// Create the Rust state for a `Vello` instance, and start its render thread.
+///
+/// If `self_paced` is true, the render thread produces frames paced by the display's vsync,
+/// rather than only when requested by `doRender`.
+///
+/// The remaining parameters are the fields of [`VelloConfig`], using the indices of their enums.
+/// `present_modes` are indices into [`PRESENT_MODES`], and `init_threads` is 0 to let Vello choose.
+///
+/// # Aborts
+///
+/// If any of the enum indices are invalid.
+#[tracing::instrument(name = "initialise", skip_all)]
 #[unsafe(no_mangle)]
 pub extern "system" fn Java_org_linebender_vello_Vello_initialise<'local>(
-    _: JNIEnv<'local>,
-    _: JObject<'local>,
+    env: JNIEnv<'local>,
+    this: JObject<'local>,
+    self_paced: jboolean,
+    antialiasing: jint,
+    backend: jint,
+    power_preference: jint,
+    present_modes: JIntArray<'local>,
+    validation: jboolean,
+    init_threads: jint,
 ) -> jlong {
     abort_on_panic(|| {
+        let config = VelloConfig {
+            antialiasing: AntialiasingMethod::from_index(antialiasing)
+                .expect("Unknown antialiasing method"),
+            backend: GraphicsBackend::from_index(backend).expect("Unknown graphics backend"),
+            power_preference: *usize::try_from(power_preference)
+                .ok()
+                .and_then(|index| POWER_PREFERENCES.get(index))
+                .expect("Unknown power preference"),
+            present_modes: read_present_modes(&env, &present_modes),
+            validation: validation != 0,
+            init_threads: usize::try_from(init_threads)
+                .ok()
+                .and_then(NonZeroUsize::new),
+        };
+        let vm = env.get_java_vm().unwrap();
+        // The `Vello` object receives callbacks from the render thread.
+        let listener = env.new_global_ref(this).unwrap();
         let (tx, rx) = std::sync::mpsc::channel();
-        let vello = VelloJni::new();
+        let vello = VelloJni::new(config);
         let state = FfiState {
-            vello: Mutex::new(vello),
-            updated_surfaces_scratch: Mutex::new(Vec::with_capacity(20)),
-            surface_kinds: Default::default(),
             control_thread: tx,
+            frame_statistics: Default::default(),
+            frame_timing_callback: AtomicBool::new(false),
+            frame_presented_callback: AtomicBool::new(false),
         };
         let state = Arc::new(state);
         {
             let state = state.clone();
-            std::thread::spawn(move || {
-                abort_on_panic(|| loop {
-                    let mut command = rx
-                        .recv()
-                        .expect("We have access to the sending side, so this cannot be closed.");
-                    while let Ok(new_command) = rx.try_recv() {
-                        command = new_command;
-                    }
-                    match command {
-                        Command::Render => {
-                            let mut vello = state.vello.lock().unwrap();
-                            let surfaces = state.surface_kinds.lock().unwrap();
-                            for (id, kind) in &*surfaces {
-                                vello.surfaces.get_mut(id).unwrap().kind = kind.clone();
-                            }
-                            drop(surfaces);
-                            let updated_surfaces =
-                                state.updated_surfaces_scratch.lock().unwrap().clone();
-                            vello.perform_render(&updated_surfaces);
-                        }
-                        Command::Finish => break,
-                    }
+            std::thread::Builder::new()
+                .name("vello-render".to_string())
+                .spawn(move || {
+                    abort_on_panic(|| {
+                        let env = vm
+                            .attach_current_thread_permanently()
+                            .expect("Could attach render thread to the JVM");
+                        // The vsync source must be created on the thread which uses it.
+                        let vsync = (self_paced != 0).then(platform_vsync_source);
+                        render_thread(&state, vello, &rx, env, &listener, vsync);
+                    });
                 })
-            });
+                .expect("Failed to start render thread");
         }
 
         let state = Arc::<FfiState>::into_raw(state) as usize;
@@ -92,41 +247,197 @@ pub extern "system" fn Java_org_linebender_vello_Vello_initialise<'local>(
     })
 }
 
+/// The main loop of the render thread, which runs until [`Command::Finish`] is received.
+///
+/// If `vsync` is provided, frames are produced at each vsync whilst there is work to do.
+/// Otherwise, frames are produced when Kotlin requests them, or on a timer whilst animating.
+fn render_thread(
+    state: &FfiState,
+    mut vello: VelloJni,
+    rx: &Receiver<Command>,
+    mut env: JNIEnv<'_>,
+    listener: &GlobalRef,
+    mut vsync: Option<Box<dyn VsyncSource>>,
+) {
+    let mut commands = Vec::new();
+    loop {
+        // Surfaces which failed to render are retried on the same schedule as animations.
+        let animating = vello.has_running_animations() || vello.has_pending_renders();
+        // Carets only need a frame when they appear or disappear.
+        let next_blink = vello.next_caret_blink(Instant::now());
+        let frame_time = if let Some(vsync) = &mut vsync {
+            if !animating {
+                // There's nothing to render until we get a command (or a caret blinks), so don't
+                // wake for each vsync.
+                wait_for_command(rx, &mut commands, next_blink);
+            }
+            vsync.wait_for_vsync()
+        } else {
+            // Whilst animating, we produce frames even without a request from Kotlin.
+            let deadline = if animating {
+                Some(Instant::now() + ANIMATION_FRAME_INTERVAL)
+            } else {
+                next_blink
+            };
+            wait_for_command(rx, &mut commands, deadline);
+            Instant::now()
+        };
+        commands.extend(rx.try_iter());
+
+        let commands_span = tracing::info_span!("commands", count = commands.len()).entered();
+        let mut applied = apply_commands(&mut vello, commands.drain(..));
+        if applied.finished {
+            return;
+        }
+        let mut surfaces_to_render = std::mem::take(&mut applied.frame);
+        let mut ended = std::mem::take(&mut applied.ended);
+        let hits = std::mem::take(&mut applied.hits);
+        let editors = std::mem::take(&mut applied.editors);
+        commands_span.exit();
+        tracing::info_span!("read_parameters")
+            .in_scope(|| vello.read_parameter_blocks(&surfaces_to_render));
+        let completed = tracing::info_span!("advance_animations").in_scope(|| {
+            vello.blink_carets(frame_time, &mut surfaces_to_render);
+            vello.advance_animations(frame_time, &mut surfaces_to_render)
+        });
+        ended.extend(completed);
+        let timings = vello.perform_render(&surfaces_to_render);
+        let presented = vello.take_presented();
+        if !applied.waiters.is_empty() {
+            tracing::info_span!("wait_for_gpu").in_scope(|| vello.wait_for_gpu());
+            for waiter in applied.waiters.drain(..) {
+                let status = RenderStatus::for_frame(
+                    &waiter.surfaces,
+                    &presented,
+                    vello.any_device_lost(&waiter.surfaces),
+                );
+                // If Kotlin has stopped waiting, there's nobody to report to.
+                let _ = waiter.reply.send(status);
+            }
+        }
+        let gpu_events = vello.take_gpu_events();
+        let _notify_span = tracing::info_span!("notify").entered();
+        if let Some(timings) = timings {
+            state.frame_statistics.lock().unwrap().record(&timings);
+            if state.frame_timing_callback.load(Ordering::Relaxed) {
+                notify_frame_timings(&mut env, listener, &timings);
+            }
+        }
+        // Kotlin always needs to know when its requests are done, even if nothing was rendered.
+        if !applied.requests.is_empty()
+            || (timings.is_some() && state.frame_presented_callback.load(Ordering::Relaxed))
+        {
+            notify_frame_presented(
+                &mut env,
+                listener,
+                timings.as_ref(),
+                &applied.requests,
+                &presented,
+            );
+        }
+        for hit in hits {
+            notify_pointer_hit(&mut env, listener, &hit);
+        }
+        for (surface_id, editor) in editors {
+            notify_editor_changed(&mut env, listener, surface_id, &editor);
+        }
+        for end in ended {
+            notify_animation_ended(&mut env, listener, end);
+        }
+        for event in gpu_events {
+            notify_gpu_event(&mut env, listener, &event);
+        }
+    }
+}
+
+/// Wait for the next command from Kotlin, adding it to `commands`.
+///
+/// If `deadline` is set, this gives up waiting at that time.
+fn wait_for_command(
+    rx: &Receiver<Command>,
+    commands: &mut Vec<Command>,
+    deadline: Option<Instant>,
+) {
+    let command = match deadline {
+        Some(deadline) => match rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        {
+            Ok(command) => command,
+            Err(RecvTimeoutError::Timeout) => return,
+            Err(RecvTimeoutError::Disconnected) => {
+                unreachable!("We have access to the sending side, so this cannot be closed.")
+            }
+        },
+        None => rx
+            .recv()
+            .expect("We have access to the sending side, so this cannot be closed."),
+    };
+    commands.push(command);
+}
+
 /// # Safety
 ///
 /// - `env` must be a valid JNI environment
 /// - `surface` must be a `Surface` associated with `env`
 /// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
-///    and which has not been freed.
+///   and which has not been freed.
+/// - `present_modes` must be null, or an Int Array of indices into [`PRESENT_MODES`], which
+///   overrides the present modes of the `VelloConfig` for this surface.
+///
+/// If the surface can't be created, this throws a `SurfaceCreationException`.
+#[cfg(target_os = "android")]
+#[tracing::instrument(
+    name = "newSurface",
+    skip_all,
+    fields(surface_id = surface_id, width = width, height = height)
+)]
 #[unsafe(no_mangle)]
 pub unsafe extern "system" fn Java_org_linebender_vello_Vello_newSurface<'local>(
-    env: JNIEnv<'local>,
+    mut env: JNIEnv<'local>,
     _: JObject<'local>,
     state: jlong,
     surface: JObject<'local>,
     surface_id: jlong,
     width: jint,
     height: jint,
+    present_modes: JIntArray<'local>,
 ) {
     abort_on_panic(|| {
         // Safety: Precondition of this function that state is correct
         let state = unsafe { access_stored_state(state) };
-        let mut vello = state.vello.lock().unwrap();
+        let present_modes =
+            (!present_modes.is_null()).then(|| read_present_modes(&env, &present_modes));
         assert!(!surface.is_null());
-        state
-            .surface_kinds
-            .lock()
-            .unwrap()
-            .insert(surface_id, SurfaceKind::Unset);
         // Safety: This is probably a valid surface.
         let window =
             unsafe { NativeWindow::from_surface(env.get_native_interface(), *surface).unwrap() };
-        vello.new_window(
-            window,
-            surface_id,
-            width.try_into().unwrap(),
-            height.try_into().unwrap(),
-        );
+        let (reply, result) = std::sync::mpsc::sync_channel(1);
+        state.send(Command::CreateSurface {
+            surface: NewSurface {
+                surface_id,
+                window,
+                width: width.try_into().unwrap(),
+                height: height.try_into().unwrap(),
+                present_modes,
+            },
+            reply,
+        });
+        // Creation happens on the render thread, so that it is ordered with other commands.
+        match result.recv().expect("Render thread still running") {
+            Ok(()) => {}
+            Err(e) => {
+                log::error!("Failed to create surface {surface_id}: {e}");
+                let message = env.new_string(e.to_string()).unwrap();
+                let exception = env
+                    .new_object(
+                        "org/linebender/vello/SurfaceCreationException",
+                        "(ILjava/lang/String;)V",
+                        &[JValue::Int(e.index()), JValue::Object(&message)],
+                    )
+                    .unwrap();
+                env.throw(jni::objects::JThrowable::from(exception))
+                    .unwrap();
+            }
+        }
     })
 }
 
@@ -134,12 +445,19 @@ pub unsafe extern "system" fn Java_org_linebender_vello_Vello_newSurface<'local>
 ///
 /// - `env` must be a valid JNI environment
 /// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
-///    and which has not been freed.
+///   and which has not been freed.
 /// - `updated_surfaces` must be a valid Long Array from Java.
 ///
+/// The frame which renders this request reports `request_id` to `onFramePresented`.
+///
 /// # Aborts
 ///
 /// If `updated_surfaces` does not contain at least `n_updated_surfaces`.
+#[tracing::instrument(
+    name = "doRender",
+    skip_all,
+    fields(n_updated_surfaces = n_updated_surfaces, request_id = request_id)
+)]
 #[unsafe(no_mangle)]
 pub unsafe extern "system" fn Java_org_linebender_vello_Vello_doRender<'local>(
     env: JNIEnv<'local>,
@@ -147,28 +465,144 @@ pub unsafe extern "system" fn Java_org_linebender_vello_Vello_doRender<'local>(
     state: jlong,
     updated_surfaces: JLongArray<'local>,
     n_updated_surfaces: jint,
+    request_id: jlong,
 ) {
     abort_on_panic(|| {
         // Safety: Precondition of this function that state is correct
         let state = unsafe { access_stored_state(state) };
-        let mut scratch = state.updated_surfaces_scratch.lock().unwrap();
-        let len = n_updated_surfaces.try_into().unwrap();
-        scratch.resize(len, 0);
-        env.get_long_array_region(&updated_surfaces, 0, &mut scratch)
+        let mut surfaces = vec![0; n_updated_surfaces.try_into().unwrap()];
+        env.get_long_array_region(&updated_surfaces, 0, &mut surfaces)
             .unwrap();
-        state
-            .control_thread
-            .send(Command::Render)
-            .expect("Render thread still running");
+        state.send(Command::RenderFrame {
+            surfaces,
+            request_id,
+            reply: None,
+        });
     });
 }
 
+/// Render a frame including `surfaces`, and wait until the GPU has finished it.
+///
+/// This is intended for tests and screenshots, which need to know that pixels exist. It returns
+/// the index of a [`RenderStatus`], which is [`RenderStatus::Timeout`] if the frame isn't finished
+/// within `timeout_millis`, or [`RenderStatus::Stopped`] if the render thread stops first. The
+/// request is reported to `onFramePresented` like `doRender`, unless the render thread stops.
+///
+/// # Safety
+///
+/// - `env` must be a valid JNI environment
+/// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
+///   and which has not been freed.
+/// - `surfaces` must be a valid Long Array from Java.
+///
+/// # Aborts
+///
+/// If `surfaces` does not contain at least `n_surfaces`, or `timeout_millis` is negative.
+#[tracing::instrument(
+    name = "renderAndWait",
+    skip_all,
+    fields(n_surfaces = n_surfaces, request_id = request_id)
+)]
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn Java_org_linebender_vello_Vello_renderAndWait<'local>(
+    env: JNIEnv<'local>,
+    _: JObject<'local>,
+    state: jlong,
+    surfaces: JLongArray<'local>,
+    n_surfaces: jint,
+    request_id: jlong,
+    timeout_millis: jlong,
+) -> jint {
+    abort_on_panic(|| {
+        // Safety: Precondition of this function that state is correct
+        let state = unsafe { access_stored_state(state) };
+        let mut surface_ids = vec![0; n_surfaces.try_into().unwrap()];
+        env.get_long_array_region(&surfaces, 0, &mut surface_ids)
+            .unwrap();
+        let (reply, result) = std::sync::mpsc::sync_channel(1);
+        state.send(Command::RenderFrame {
+            surfaces: surface_ids,
+            request_id,
+            reply: Some(reply),
+        });
+        let timeout = Duration::from_millis(timeout_millis.try_into().unwrap());
+        let status = match result.recv_timeout(timeout) {
+            Ok(status) => status,
+            Err(RecvTimeoutError::Timeout) => RenderStatus::Timeout,
+            // The render thread drops the waiters of any commands left when it stops.
+            Err(RecvTimeoutError::Disconnected) => RenderStatus::Stopped,
+        };
+        status as jint
+    })
+}
+
+/// Tell the render thread that the window of `surface_id` has changed size.
+///
+/// The surface is rendered again in the next frame.
+///
+/// # Safety
+///
+/// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
+///   and which has not been freed.
+///
+/// # Aborts
+///
+/// If `width` or `height` are negative.
+#[tracing::instrument(
+    name = "resizeSurface",
+    skip_all,
+    fields(surface_id = surface_id, width = width, height = height)
+)]
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn Java_org_linebender_vello_Vello_resizeSurface<'local>(
+    _: JNIEnv<'local>,
+    _: JObject<'local>,
+    state: jlong,
+    surface_id: jlong,
+    width: jint,
+    height: jint,
+) {
+    abort_on_panic(|| {
+        // Safety: Precondition of this function that state is correct
+        let state = unsafe { access_stored_state(state) };
+        state.send(Command::ResizeSurface {
+            surface_id,
+            width: width.try_into().unwrap(),
+            height: height.try_into().unwrap(),
+        });
+    })
+}
+
+/// Stop rendering to `surface_id`, and release its resources on the render thread.
+///
+/// Any animations running on the surface end without completing.
+///
+/// # Safety
+///
+/// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
+///   and which has not been freed.
+#[tracing::instrument(name = "destroySurface", skip_all, fields(surface_id = surface_id))]
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn Java_org_linebender_vello_Vello_destroySurface<'local>(
+    _: JNIEnv<'local>,
+    _: JObject<'local>,
+    state: jlong,
+    surface_id: jlong,
+) {
+    abort_on_panic(|| {
+        // Safety: Precondition of this function that state is correct
+        let state = unsafe { access_stored_state(state) };
+        state.send(Command::DestroySurface { surface_id });
+    })
+}
+
 /// # Safety
 ///
 /// - `env` must be a valid JNI environment
 /// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
-///    and which has not been freed.
+///   and which has not been freed.
 /// - `text` must be a valid `String` from Java.
+#[tracing::instrument(name = "makeVariableFontSurface", skip_all, fields(surface_id = surface_id))]
 #[unsafe(no_mangle)]
 pub unsafe extern "system" fn Java_org_linebender_vello_Vello_makeVariableFontSurface<'local>(
     mut env: JNIEnv<'local>,
@@ -182,85 +616,1656 @@ pub unsafe extern "system" fn Java_org_linebender_vello_Vello_makeVariableFontSu
     abort_on_panic(|| {
         // Safety: Precondition of this function that state is correct
         let state = unsafe { access_stored_state(state) };
-        let mut surface_kinds = state.surface_kinds.lock().unwrap();
         let text = env.get_string(&text).unwrap().into();
-        let surface = surface_kinds
-            .get_mut(&surface_id)
-            .expect("Tried to make a variable font surface for an invalid surface");
-        *surface = SurfaceKind::VariableFont {
-            text,
-            size: font_size,
-            weight: font_weight,
-        }
+        state.send(Command::UpdateSurface {
+            surface_id,
+            update: SurfaceUpdate::SetVariableFont {
+                text,
+                size: font_size,
+                weight: font_weight,
+            },
+        });
     })
 }
 
 /// # Safety
 ///
+/// - `env` must be a valid JNI environment
 /// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
-///    and which has not been freed.
+///   and which has not been freed.
+/// - `new_text` must be a valid `String` from Java.
+#[tracing::instrument(name = "updateVariableFontText", skip_all, fields(surface_id = surface_id))]
 #[unsafe(no_mangle)]
-pub unsafe extern "system" fn Java_org_linebender_vello_Vello_updateVariableFontParameters<
-    'local,
->(
-    _: JNIEnv<'local>,
+pub unsafe extern "system" fn Java_org_linebender_vello_Vello_updateVariableFontText<'local>(
+    mut env: JNIEnv<'local>,
+    _: JObject<'local>,
+    state: jlong,
+    surface_id: jlong,
+    new_text: JString<'local>,
+) {
+    abort_on_panic(|| {
+        // Safety: Precondition of this function that state is correct
+        let state = unsafe { access_stored_state(state) };
+        let new_text = env.get_string(&new_text).unwrap().into();
+        state.send(Command::UpdateSurface {
+            surface_id,
+            update: SurfaceUpdate::VariableFontText(new_text),
+        });
+    })
+}
+
+/// Make `surface_id` an editor of `text`, with the caret at its end.
+///
+/// Multi-line editors wrap their text to the width of the surface, and scroll vertically.
+/// Single-line editors scroll horizontally instead.
+///
+/// # Safety
+///
+/// - `env` must be a valid JNI environment
+/// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
+///   and which has not been freed.
+/// - `text` must be a valid `String` from Java.
+#[tracing::instrument(name = "makeTextEditor", skip_all, fields(surface_id = surface_id))]
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn Java_org_linebender_vello_Vello_makeTextEditor<'local>(
+    mut env: JNIEnv<'local>,
     _: JObject<'local>,
     state: jlong,
     surface_id: jlong,
+    text: JString<'local>,
+    multiline: jboolean,
     font_size: jfloat,
-    font_weight: jfloat,
 ) {
     abort_on_panic(|| {
         // Safety: Precondition of this function that state is correct
         let state = unsafe { access_stored_state(state) };
-        let mut surface_kinds = state.surface_kinds.lock().unwrap();
-        let surface = surface_kinds
-            .get_mut(&surface_id)
-            .expect("Tried to make a variable font surface for an invalid surface");
-        if let SurfaceKind::VariableFont { size, weight, .. } = surface {
-            *size = font_size;
-            *weight = font_weight;
-        }
+        let text = env.get_string(&text).unwrap().into();
+        state.send(Command::UpdateSurface {
+            surface_id,
+            update: SurfaceUpdate::SetTextEditor {
+                text,
+                multiline: multiline != 0,
+                size: font_size,
+            },
+        });
     })
 }
 
+/// Set the OpenType features of the text of `surface_id`.
+///
+/// Each feature has the tag at the same index in `tags`, packed as an OpenType `Tag`, and the
+/// value at that index in `values`. If `start` is negative, the features replace those of the
+/// whole surface. Otherwise, they apply to the UTF-16 range `start..end` of the current text.
+///
+/// Throws an `IllegalArgumentException` if the font doesn't support any of the features, in
+/// which case none of them are set.
+///
 /// # Safety
 ///
 /// - `env` must be a valid JNI environment
 /// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
-///    and which has not been freed.
-/// - `new_text` must be a valid `String` from Java.
+///   and which has not been freed.
+/// - `tags` and `values` must be valid Int Arrays from Java.
+///
+/// # Aborts
+///
+/// If `tags` and `values` have different lengths, a value is negative, or `end` is before
+/// `start`.
+#[tracing::instrument(name = "setFontFeatures", skip_all, fields(surface_id = surface_id))]
 #[unsafe(no_mangle)]
-pub unsafe extern "system" fn Java_org_linebender_vello_Vello_updateVariableFontText<'local>(
+pub unsafe extern "system" fn Java_org_linebender_vello_Vello_setFontFeatures<'local>(
     mut env: JNIEnv<'local>,
     _: JObject<'local>,
     state: jlong,
     surface_id: jlong,
-    new_text: JString<'local>,
+    tags: JIntArray<'local>,
+    values: JIntArray<'local>,
+    start: jint,
+    end: jint,
 ) {
     abort_on_panic(|| {
         // Safety: Precondition of this function that state is correct
         let state = unsafe { access_stored_state(state) };
-        let mut surface_kinds = state.surface_kinds.lock().unwrap();
-        let new_text = env.get_string(&new_text).unwrap().into();
-        let surface = surface_kinds
-            .get_mut(&surface_id)
-            .expect("Tried to make a variable font surface for an invalid surface");
-        if let SurfaceKind::VariableFont { text, .. } = surface {
-            *text = new_text;
+        let tags = read_int_array(&env, &tags);
+        let values = read_int_array(&env, &values);
+        assert_eq!(tags.len(), values.len(), "Every feature needs a value");
+        let features = tags
+            .into_iter()
+            .zip(values)
+            .map(|(tag, value)| FontFeature {
+                tag: u32::from_ne_bytes(tag.to_ne_bytes()),
+                value: value.try_into().unwrap(),
+            })
+            .collect::<Vec<_>>();
+        if let Err(e) = features::validate(&features, features::text_surface_features()) {
+            env.throw_new("java/lang/IllegalArgumentException", e.to_string())
+                .unwrap();
+            return;
         }
+        let range = (start >= 0).then(|| {
+            let start: usize = start.try_into().unwrap();
+            let end: usize = end.try_into().unwrap();
+            assert!(start <= end, "Feature span {start}..{end} is backwards");
+            start..end
+        });
+        state.send(Command::UpdateSurface {
+            surface_id,
+            update: SurfaceUpdate::SetFontFeatures { features, range },
+        });
     })
 }
 
-/// Access a stored
+/// The OpenType features which can be set with
+/// [`Java_org_linebender_vello_Vello_setFontFeatures`], each packed as an OpenType `Tag`.
+#[tracing::instrument(name = "getSupportedFontFeatures", skip_all)]
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_org_linebender_vello_Vello_getSupportedFontFeatures<'local>(
+    env: JNIEnv<'local>,
+    _: JClass<'local>,
+) -> jintArray {
+    abort_on_panic(|| {
+        let tags = features::text_surface_features()
+            .iter()
+            .map(|tag| i32::from_ne_bytes(tag.to_ne_bytes()))
+            .collect::<Vec<_>>();
+        let array = env.new_int_array(tags.len().try_into().unwrap()).unwrap();
+        env.set_int_array_region(&array, 0, &tags).unwrap();
+        array.into_raw()
+    })
+}
+
+/// The values of `mode` passed to [`Java_org_linebender_vello_Vello_setAdvanceMode`].
+const ADVANCE_NATURAL: jint = 0;
+const ADVANCE_REFERENCE: jint = 1;
+const ADVANCE_MONOSPACE: jint = 2;
+
+/// Set how the glyphs of the variable font surface `surface_id` are spaced.
+///
+/// For `ADVANCE_REFERENCE`, each axis tag in `tags` is paired with the value at that index in
+/// `values`, which are the axis values whose advances are used. Otherwise, both are ignored.
+///
+/// # Safety
+///
+/// - `env` must be a valid JNI environment
 /// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
-///    and which has not been freed.
-unsafe fn access_stored_state(state: jlong) -> Arc<FfiState> {
-    let value: usize = bytemuck::cast(state);
-    let ptr = value as *const FfiState;
-    assert!(!ptr.is_null());
-    unsafe {
-        Arc::increment_strong_count(ptr);
-        Arc::from_raw(ptr)
+///   and which has not been freed.
+/// - `tags` must be a valid Int Array and `values` a valid Float Array from Java.
+///
+/// # Aborts
+///
+/// If `mode` is unknown, or `tags` and `values` have different lengths.
+#[tracing::instrument(name = "setAdvanceMode", skip_all, fields(surface_id = surface_id, mode = mode))]
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn Java_org_linebender_vello_Vello_setAdvanceMode<'local>(
+    env: JNIEnv<'local>,
+    _: JObject<'local>,
+    state: jlong,
+    surface_id: jlong,
+    mode: jint,
+    tags: JIntArray<'local>,
+    values: JFloatArray<'local>,
+) {
+    abort_on_panic(|| {
+        // Safety: Precondition of this function that state is correct
+        let state = unsafe { access_stored_state(state) };
+        let mode = match mode {
+            ADVANCE_NATURAL => AdvanceMode::Natural,
+            ADVANCE_REFERENCE => {
+                let tags = read_int_array(&env, &tags);
+                let values = read_float_array(&env, &values);
+                assert_eq!(tags.len(), values.len(), "Every axis needs a value");
+                let axes = tags
+                    .into_iter()
+                    .zip(values)
+                    .map(|(tag, value)| FontVariation {
+                        tag: u32::from_ne_bytes(tag.to_ne_bytes()),
+                        value,
+                    })
+                    .collect();
+                AdvanceMode::Reference(axes)
+            }
+            ADVANCE_MONOSPACE => AdvanceMode::Monospace,
+            _ => panic!("Unknown advance mode {mode}"),
+        };
+        state.send(Command::UpdateSurface {
+            surface_id,
+            update: SurfaceUpdate::SetAdvanceMode(mode),
+        });
+    })
+}
+
+/// Set the base direction of the paragraphs of the variable font surface `surface_id`.
+///
+/// `direction` is the index of a [`TextDirection`].
+///
+/// # Safety
+///
+/// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
+///   and which has not been freed.
+///
+/// # Aborts
+///
+/// If `direction` is unknown.
+#[tracing::instrument(name = "setTextDirection", skip_all, fields(surface_id = surface_id, direction = direction))]
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn Java_org_linebender_vello_Vello_setTextDirection<'local>(
+    _: JNIEnv<'local>,
+    _: JObject<'local>,
+    state: jlong,
+    surface_id: jlong,
+    direction: jint,
+) {
+    abort_on_panic(|| {
+        // Safety: Precondition of this function that state is correct
+        let state = unsafe { access_stored_state(state) };
+        let direction = TextDirection::from_index(direction).expect("Unknown text direction");
+        state.send(Command::UpdateSurface {
+            surface_id,
+            update: SurfaceUpdate::SetTextDirection(direction),
+        });
+    })
+}
+
+/// Set the BCP 47 language tag used to shape the text of the variable font surface
+/// `surface_id`, or use the default shaping of each script if `locale` is null.
+///
+/// # Safety
+///
+/// - `env` must be a valid JNI environment
+/// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
+///   and which has not been freed.
+/// - `locale` must be a valid `String` from Java, or null.
+#[tracing::instrument(name = "setLocale", skip_all, fields(surface_id = surface_id))]
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn Java_org_linebender_vello_Vello_setLocale<'local>(
+    mut env: JNIEnv<'local>,
+    _: JObject<'local>,
+    state: jlong,
+    surface_id: jlong,
+    locale: JString<'local>,
+) {
+    abort_on_panic(|| {
+        // Safety: Precondition of this function that state is correct
+        let state = unsafe { access_stored_state(state) };
+        let locale =
+            (!locale.is_null()).then(|| String::from(env.get_string(&locale).unwrap()).into());
+        state.send(Command::UpdateSurface {
+            surface_id,
+            update: SurfaceUpdate::SetLocale(locale),
+        });
+    })
+}
+
+/// Set whether the glyphs of the variable font surface `surface_id` are hinted and snapped to
+/// pixels whilst its size and axes aren't moving.
+///
+/// `rendering` is the index of a [`TextRendering`].
+///
+/// # Safety
+///
+/// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
+///   and which has not been freed.
+///
+/// # Aborts
+///
+/// If `rendering` is unknown.
+#[tracing::instrument(name = "setTextRendering", skip_all, fields(surface_id = surface_id, rendering = rendering))]
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn Java_org_linebender_vello_Vello_setTextRendering<'local>(
+    _: JNIEnv<'local>,
+    _: JObject<'local>,
+    state: jlong,
+    surface_id: jlong,
+    rendering: jint,
+) {
+    abort_on_panic(|| {
+        // Safety: Precondition of this function that state is correct
+        let state = unsafe { access_stored_state(state) };
+        let rendering = TextRendering::from_index(rendering).expect("Unknown text rendering");
+        state.send(Command::UpdateSurface {
+            surface_id,
+            update: SurfaceUpdate::SetTextRendering(rendering),
+        });
+    })
+}
+
+/// Set the density of the display showing `surface_id` and the user's font size setting, which
+/// convert its font size in sp to pixels.
+///
+/// These are the `density` and `fontScale` of Compose's `Density`.
+///
+/// # Safety
+///
+/// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
+///   and which has not been freed.
+#[tracing::instrument(name = "setTextScale", skip_all, fields(surface_id = surface_id, density = density, font_scale = font_scale))]
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn Java_org_linebender_vello_Vello_setTextScale<'local>(
+    _: JNIEnv<'local>,
+    _: JObject<'local>,
+    state: jlong,
+    surface_id: jlong,
+    density: jfloat,
+    font_scale: jfloat,
+) {
+    abort_on_panic(|| {
+        // Safety: Precondition of this function that state is correct
+        let state = unsafe { access_stored_state(state) };
+        state.send(Command::UpdateSurface {
+            surface_id,
+            update: SurfaceUpdate::SetTextScale(TextScale {
+                density,
+                font_scale,
+            }),
+        });
+    })
+}
+
+/// Set the outline and shadows drawn with the glyphs of the variable font surface `surface_id`.
+///
+/// The outline is only drawn if `stroke_width` is positive, with the join at index `stroke_join`
+/// of [`effects::JOINS`]. Each shadow is three values of `shadow_geometry`, which are its offset
+/// in `x` and `y` then its blur radius, and has the colour at the same index of `shadow_colors`.
+/// Colours are ARGB ints, as used by Android.
+///
+/// # Safety
+///
+/// - `env` must be a valid JNI environment
+/// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
+///   and which has not been freed.
+/// - `shadow_geometry` must be a valid Float Array and `shadow_colors` a valid Int Array from
+///   Java.
+///
+/// # Aborts
+///
+/// If `stroke_join` is unknown, or there isn't a colour for each shadow.
+#[tracing::instrument(name = "setTextEffects", skip_all, fields(surface_id = surface_id))]
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn Java_org_linebender_vello_Vello_setTextEffects<'local>(
+    env: JNIEnv<'local>,
+    _: JObject<'local>,
+    state: jlong,
+    surface_id: jlong,
+    stroke_width: jfloat,
+    stroke_join: jint,
+    stroke_color: jint,
+    shadow_geometry: JFloatArray<'local>,
+    shadow_colors: JIntArray<'local>,
+) {
+    abort_on_panic(|| {
+        // Safety: Precondition of this function that state is correct
+        let state = unsafe { access_stored_state(state) };
+        let stroke = (stroke_width > 0.).then(|| TextStroke {
+            width: stroke_width,
+            join: usize::try_from(stroke_join)
+                .ok()
+                .and_then(|join| effects::JOINS.get(join).copied())
+                .unwrap_or_else(|| panic!("Unknown stroke join {stroke_join}")),
+            color: argb_color(stroke_color),
+        });
+        let geometry = read_float_array(&env, &shadow_geometry);
+        let colors = read_int_array(&env, &shadow_colors);
+        assert_eq!(
+            geometry.len(),
+            colors.len() * 3,
+            "Every shadow needs an offset, blur radius and colour"
+        );
+        let shadows = geometry
+            .chunks_exact(3)
+            .zip(colors)
+            .map(|(geometry, color)| TextShadow {
+                offset: Vec2::new(geometry[0].into(), geometry[1].into()),
+                blur_radius: geometry[2],
+                color: argb_color(color),
+            })
+            .collect();
+        state.send(Command::UpdateSurface {
+            surface_id,
+            update: SurfaceUpdate::SetTextEffects(TextEffects { stroke, shadows }),
+        });
+    })
+}
+
+/// Set the palette which the colour glyphs of the variable font surface `surface_id`, such as
+/// emoji, are drawn with.
+///
+/// `index` is the index of the palette in the `CPAL` table of the font. Each palette entry in
+/// `entries` is replaced by the ARGB colour at the same index of `colors`.
+///
+/// # Safety
+///
+/// - `env` must be a valid JNI environment
+/// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
+///   and which has not been freed.
+/// - `entries` and `colors` must be valid Int Arrays from Java.
+///
+/// # Aborts
+///
+/// If `index` or an entry isn't a valid `u16`, or `entries` and `colors` have different
+/// lengths.
+#[tracing::instrument(name = "setColorPalette", skip_all, fields(surface_id = surface_id, index = index))]
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn Java_org_linebender_vello_Vello_setColorPalette<'local>(
+    env: JNIEnv<'local>,
+    _: JObject<'local>,
+    state: jlong,
+    surface_id: jlong,
+    index: jint,
+    entries: JIntArray<'local>,
+    colors: JIntArray<'local>,
+) {
+    abort_on_panic(|| {
+        // Safety: Precondition of this function that state is correct
+        let state = unsafe { access_stored_state(state) };
+        let entries = read_int_array(&env, &entries);
+        let colors = read_int_array(&env, &colors);
+        assert_eq!(entries.len(), colors.len(), "Every entry needs a colour");
+        let overrides = entries
+            .into_iter()
+            .zip(colors)
+            .map(|(entry, color)| (entry.try_into().unwrap(), argb_color(color)))
+            .collect();
+        state.send(Command::UpdateSurface {
+            surface_id,
+            update: SurfaceUpdate::SetColorPalette(ColorPalette {
+                index: index.try_into().unwrap(),
+                overrides,
+            }),
+        });
+    })
+}
+
+/// Convert an Android ARGB colour int to a [`Color`].
+fn argb_color(argb: jint) -> Color {
+    let [a, r, g, b] = argb.to_be_bytes();
+    Color::rgba8(r, g, b, a)
+}
+
+/// Select the text of `surface_id` between `anchor` and `focus`, which are UTF-16 offsets if
+/// `utf16` is set, or byte offsets otherwise.
+///
+/// The caret is shown at `focus` if they are equal.
+///
+/// # Safety
+///
+/// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
+///   and which has not been freed.
+///
+/// # Aborts
+///
+/// If `anchor` or `focus` is negative.
+#[tracing::instrument(name = "setSelection", skip_all, fields(surface_id = surface_id))]
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn Java_org_linebender_vello_Vello_setSelection<'local>(
+    _: JNIEnv<'local>,
+    _: JObject<'local>,
+    state: jlong,
+    surface_id: jlong,
+    anchor: jint,
+    focus: jint,
+    utf16: jboolean,
+) {
+    abort_on_panic(|| {
+        // Safety: Precondition of this function that state is correct
+        let state = unsafe { access_stored_state(state) };
+        let anchor = anchor.try_into().unwrap();
+        let focus = focus.try_into().unwrap();
+        let change = if utf16 != 0 {
+            SelectionChange::Utf16 { anchor, focus }
+        } else {
+            SelectionChange::Bytes { anchor, focus }
+        };
+        state.send(Command::SetSelection { surface_id, change });
+    })
+}
+
+/// Move the selection focus of `surface_id` to the text closest to `(x, y)`, keeping its anchor
+/// if `extend` is set.
+///
+/// # Safety
+///
+/// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
+///   and which has not been freed.
+#[tracing::instrument(name = "selectAtPoint", skip_all, fields(surface_id = surface_id))]
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn Java_org_linebender_vello_Vello_selectAtPoint<'local>(
+    _: JNIEnv<'local>,
+    _: JObject<'local>,
+    state: jlong,
+    surface_id: jlong,
+    x: jfloat,
+    y: jfloat,
+    extend: jboolean,
+) {
+    abort_on_panic(|| {
+        // Safety: Precondition of this function that state is correct
+        let state = unsafe { access_stored_state(state) };
+        state.send(Command::SetSelection {
+            surface_id,
+            change: SelectionChange::Point {
+                x,
+                y,
+                extend: extend != 0,
+            },
+        });
+    })
+}
+
+/// Stop showing the selection and caret of `surface_id`.
+///
+/// # Safety
+///
+/// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
+///   and which has not been freed.
+#[tracing::instrument(name = "clearSelection", skip_all, fields(surface_id = surface_id))]
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn Java_org_linebender_vello_Vello_clearSelection<'local>(
+    _: JNIEnv<'local>,
+    _: JObject<'local>,
+    state: jlong,
+    surface_id: jlong,
+) {
+    abort_on_panic(|| {
+        // Safety: Precondition of this function that state is correct
+        let state = unsafe { access_stored_state(state) };
+        state.send(Command::SetSelection {
+            surface_id,
+            change: SelectionChange::Clear,
+        });
+    })
+}
+
+/// Read the selection of `surface_id`, after all previous commands have been applied.
+///
+/// Returns the selected text, or null if there is no selection. The UTF-16 offsets of the anchor
+/// and focus are written to `offsets`.
+///
+/// # Safety
+///
+/// - `env` must be a valid JNI environment
+/// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
+///   and which has not been freed.
+/// - `offsets` must be a valid Int Array from Java.
+///
+/// # Aborts
+///
+/// If `offsets` has fewer than 2 elements.
+#[tracing::instrument(name = "getSelection", skip_all, fields(surface_id = surface_id))]
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn Java_org_linebender_vello_Vello_getSelection<'local>(
+    env: JNIEnv<'local>,
+    _: JObject<'local>,
+    state: jlong,
+    surface_id: jlong,
+    offsets: JIntArray<'local>,
+) -> jstring {
+    abort_on_panic(|| {
+        // Safety: Precondition of this function that state is correct
+        let state = unsafe { access_stored_state(state) };
+        let (reply, result) = std::sync::mpsc::sync_channel(1);
+        state.send(Command::GetSelection { surface_id, reply });
+        let Some(selected) = result
+            .recv()
+            .expect("The render thread always replies to selection reads")
+        else {
+            return JObject::null().into_raw();
+        };
+        let anchor = selected.anchor.try_into().unwrap();
+        let focus = selected.focus.try_into().unwrap();
+        env.set_int_array_region(&offsets, 0, &[anchor, focus])
+            .unwrap();
+        env.new_string(selected.text).unwrap().into_raw()
+    })
+}
+
+/// Read the accessibility tree of `surface_id`, after all previous commands have been applied.
+///
+/// Returns the root `AccessibilityNode`, or null if the surface has no accessible content.
+///
+/// # Safety
+///
+/// - `env` must be a valid JNI environment
+/// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
+///   and which has not been freed.
+#[tracing::instrument(name = "getAccessibilityTree", skip_all, fields(surface_id = surface_id))]
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn Java_org_linebender_vello_Vello_getAccessibilityTree<'local>(
+    mut env: JNIEnv<'local>,
+    _: JObject<'local>,
+    state: jlong,
+    surface_id: jlong,
+) -> jobject {
+    abort_on_panic(|| {
+        // Safety: Precondition of this function that state is correct
+        let state = unsafe { access_stored_state(state) };
+        let (reply, result) = std::sync::mpsc::sync_channel(1);
+        state.send(Command::GetAccessibilityTree { surface_id, reply });
+        match result
+            .recv()
+            .expect("The render thread always replies to accessibility tree reads")
+        {
+            Some(tree) => new_accessibility_node(&mut env, &tree).into_raw(),
+            None => JObject::null().into_raw(),
+        }
+    })
+}
+
+/// The JNI name of the Kotlin class which [`AccessNode`]s are converted to.
+const ACCESSIBILITY_NODE_CLASS: &str = "org/linebender/vello/AccessibilityNode";
+
+/// Convert `node` and its children into a Kotlin `AccessibilityNode`.
+fn new_accessibility_node<'local>(env: &mut JNIEnv<'local>, node: &AccessNode) -> JObject<'local> {
+    let children = env
+        .new_object_array(
+            node.children.len().try_into().unwrap(),
+            ACCESSIBILITY_NODE_CLASS,
+            JObject::null(),
+        )
+        .unwrap();
+    for (index, child) in node.children.iter().enumerate() {
+        let child = new_accessibility_node(env, child);
+        env.set_object_array_element(&children, index.try_into().unwrap(), &child)
+            .unwrap();
+        // Trees can be large, so don't keep a local reference for every node.
+        env.delete_local_ref(child).unwrap();
+    }
+    let text = JObject::from(env.new_string(&node.text).unwrap());
+    let index = |value: usize| -> jint { value.try_into().unwrap() };
+    let (anchor, focus) = node
+        .selection
+        .map_or((-1, -1), |(anchor, focus)| (index(anchor), index(focus)));
+    env.new_object(
+        ACCESSIBILITY_NODE_CLASS,
+        "(ILjava/lang/String;FFFFIIIII[Lorg/linebender/vello/AccessibilityNode;)V",
+        &[
+            JValue::Int(node.role as i32),
+            JValue::Object(&text),
+            JValue::Float(node.bounds.x0 as f32),
+            JValue::Float(node.bounds.y0 as f32),
+            JValue::Float(node.bounds.x1 as f32),
+            JValue::Float(node.bounds.y1 as f32),
+            JValue::Int(index(node.text_range.start)),
+            JValue::Int(index(node.text_range.end)),
+            JValue::Int(anchor),
+            JValue::Int(focus),
+            JValue::Int(AccessAction::bits(&node.actions)),
+            JValue::Object(&children),
+        ],
+    )
+    .unwrap()
+}
+
+/// The values of `op` passed to [`Java_org_linebender_vello_Vello_editText`].
+const EDIT_COMMIT: jint = 0;
+const EDIT_COMPOSE: jint = 1;
+const EDIT_COMPOSING_REGION: jint = 2;
+const EDIT_FINISH_COMPOSING: jint = 3;
+const EDIT_DELETE_SURROUNDING: jint = 4;
+const EDIT_SET_SELECTION: jint = 5;
+const EDIT_KEY: jint = 6;
+
+/// Decode the arguments of [`Java_org_linebender_vello_Vello_editText`].
+fn read_edit(op: jint, text: Option<String>, first: jint, second: jint) -> EditOp {
+    let offset = |value: jint| -> usize { value.try_into().unwrap() };
+    match op {
+        EDIT_COMMIT => EditOp::CommitText {
+            text: text.expect("Committing needs text"),
+            new_cursor: first,
+        },
+        EDIT_COMPOSE => EditOp::SetComposingText {
+            text: text.expect("Composing needs text"),
+            new_cursor: first,
+        },
+        EDIT_COMPOSING_REGION => EditOp::SetComposingRegion {
+            start: offset(first),
+            end: offset(second),
+        },
+        EDIT_FINISH_COMPOSING => EditOp::FinishComposingText,
+        EDIT_DELETE_SURROUNDING => EditOp::DeleteSurroundingText {
+            before: offset(first),
+            after: offset(second),
+        },
+        EDIT_SET_SELECTION => EditOp::SetSelection {
+            anchor: offset(first),
+            focus: offset(second),
+        },
+        EDIT_KEY => EditOp::Key {
+            key: EditKey::from_index(first).unwrap_or_else(|| panic!("Unknown edit key {first}")),
+            extend: second != 0,
+        },
+        _ => panic!("Unknown edit {op}"),
+    }
+}
+
+/// Edit the text of the editor `surface_id`, as described by `op`.
+///
+/// The meaning of `text`, `first` and `second` depends on `op`:
+/// - `EDIT_COMMIT` and `EDIT_COMPOSE` insert `text`, with `first` as the new cursor position in
+///   the form used by `InputConnection.commitText`.
+/// - `EDIT_COMPOSING_REGION`, `EDIT_DELETE_SURROUNDING` and `EDIT_SET_SELECTION` use `first` and
+///   `second` as the two UTF-16 offsets or lengths of the `InputConnection` method.
+/// - `EDIT_KEY` presses the [`EditKey`] with index `first`, extending the selection if `second`
+///   is non-zero.
+///
+/// The new state of the editor is reported to `onEditorChanged`.
+///
+/// # Safety
+///
+/// - `env` must be a valid JNI environment
+/// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
+///   and which has not been freed.
+/// - `text` must be null or a valid `String` from Java.
+///
+/// # Aborts
+///
+/// If `op` or the key is unknown, `text` is null when it is needed, or an offset is negative.
+#[tracing::instrument(name = "editText", skip_all, fields(surface_id = surface_id, op = op))]
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn Java_org_linebender_vello_Vello_editText<'local>(
+    mut env: JNIEnv<'local>,
+    _: JObject<'local>,
+    state: jlong,
+    surface_id: jlong,
+    op: jint,
+    text: JString<'local>,
+    first: jint,
+    second: jint,
+) {
+    abort_on_panic(|| {
+        // Safety: Precondition of this function that state is correct
+        let state = unsafe { access_stored_state(state) };
+        let text = (!text.is_null()).then(|| env.get_string(&text).unwrap().into());
+        let op = read_edit(op, text, first, second);
+        state.send(Command::Edit { surface_id, op });
+    })
+}
+
+/// The values of `target` passed to [`Java_org_linebender_vello_Vello_startAnimation`].
+const TARGET_FONT_SIZE: jint = 0;
+const TARGET_AXIS: jint = 1;
+const TARGET_COLOR: jint = 2;
+
+/// The values of `easing` passed to [`Java_org_linebender_vello_Vello_startAnimation`].
+const EASING_LINEAR: jint = 0;
+const EASING_CUBIC_BEZIER: jint = 1;
+
+/// The values of `repeat_mode` passed to [`Java_org_linebender_vello_Vello_startAnimation`].
+const REPEAT_RESTART: jint = 0;
+const REPEAT_REVERSE: jint = 1;
+
+fn read_target(target: jint, axis_tag: jint) -> AnimationTarget {
+    match target {
+        TARGET_FONT_SIZE => AnimationTarget::FontSize,
+        TARGET_AXIS => AnimationTarget::Axis(u32::from_ne_bytes(axis_tag.to_ne_bytes())),
+        TARGET_COLOR => AnimationTarget::Color,
+        _ => panic!("Unknown animation target {target}"),
+    }
+}
+
+/// Let Kotlin control values of `surface_id` by writing to `buffer`, rather than through JNI.
+///
+/// Each slot of the buffer controls the target at the same index in `targets`, with the tags
+/// of axis targets in `axis_tags`. See [`crate::params`] for the layout of the buffer.
+///
+/// # Safety
+///
+/// - `env` must be a valid JNI environment
+/// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
+///   and which has not been freed.
+/// - `buffer` must be a direct `ByteBuffer` in native byte order, which is only written to using
+///   the protocol described in [`crate::params`].
+/// - `targets` and `axis_tags` must be valid Int Arrays from Java.
+///
+/// # Aborts
+///
+/// If `targets` and `axis_tags` have different lengths, any target isn't a scalar target, or
+/// `buffer` is too small.
+#[tracing::instrument(name = "registerParameterBlock", skip_all, fields(surface_id = surface_id))]
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn Java_org_linebender_vello_Vello_registerParameterBlock<'local>(
+    env: JNIEnv<'local>,
+    _: JObject<'local>,
+    state: jlong,
+    surface_id: jlong,
+    buffer: JByteBuffer<'local>,
+    targets: JIntArray<'local>,
+    axis_tags: JIntArray<'local>,
+) {
+    abort_on_panic(|| {
+        // Safety: Precondition of this function that state is correct
+        let state = unsafe { access_stored_state(state) };
+        let targets = read_int_array(&env, &targets);
+        let axis_tags = read_int_array(&env, &axis_tags);
+        assert_eq!(
+            targets.len(),
+            axis_tags.len(),
+            "Each target needs an axis tag"
+        );
+        let targets = targets
+            .into_iter()
+            .zip(axis_tags)
+            .map(|(target, axis_tag)| {
+                let target = read_target(target, axis_tag);
+                assert!(
+                    target.components() == 1,
+                    "Parameter blocks only support scalar targets"
+                );
+                target
+            })
+            .collect();
+        let ptr = env
+            .get_direct_buffer_address(&buffer)
+            .expect("Parameter blocks must be direct buffers");
+        let len = env.get_direct_buffer_capacity(&buffer).unwrap();
+        // The buffer's memory is owned by the buffer object, so keep that alive.
+        let owner = env.new_global_ref(&buffer).unwrap();
+        // Safety: `ptr` is the start of the `len` bytes of `buffer`, which `owner` keeps alive,
+        // and it is a precondition of this function that it is written using the protocol.
+        let block = unsafe {
+            ParameterBlock::from_raw(NonNull::new(ptr).unwrap(), len, targets, Box::new(owner))
+        }
+        .expect("Invalid parameter block");
+        state.send(Command::SetParameterBlock { surface_id, block });
+    })
+}
+
+/// # Safety
+///
+/// - `env` must be a valid JNI environment
+/// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
+///   and which has not been freed.
+/// - `fractions`, `values` and `easing_parameters` must be valid Float Arrays from Java.
+///
+/// # Aborts
+///
+/// If `values` does not contain one value (or four for colours) for each of `fractions`,
+/// or if `target`, `easing` or `repeat_mode` are unknown.
+#[tracing::instrument(
+    name = "startAnimation",
+    skip_all,
+    fields(surface_id = surface_id, animation_id = animation_id)
+)]
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn Java_org_linebender_vello_Vello_startAnimation<'local>(
+    env: JNIEnv<'local>,
+    _: JObject<'local>,
+    state: jlong,
+    surface_id: jlong,
+    animation_id: jlong,
+    target: jint,
+    axis_tag: jint,
+    fractions: JFloatArray<'local>,
+    values: JFloatArray<'local>,
+    duration_nanos: jlong,
+    easing: jint,
+    easing_parameters: JFloatArray<'local>,
+    repeat_mode: jint,
+    iterations: jint,
+) {
+    abort_on_panic(|| {
+        // Safety: Precondition of this function that state is correct
+        let state = unsafe { access_stored_state(state) };
+        let target = read_target(target, axis_tag);
+        let easing = match easing {
+            EASING_LINEAR => Easing::Linear,
+            EASING_CUBIC_BEZIER => {
+                let [x1, y1, x2, y2] = read_float_array(&env, &easing_parameters)
+                    .try_into()
+                    .expect("Cubic Bézier easing needs four parameters");
+                Easing::CubicBezier { x1, y1, x2, y2 }
+            }
+            _ => panic!("Unknown easing {easing}"),
+        };
+        let repeat_mode = match repeat_mode {
+            REPEAT_RESTART => RepeatMode::Restart,
+            REPEAT_REVERSE => RepeatMode::Reverse,
+            _ => panic!("Unknown repeat mode {repeat_mode}"),
+        };
+        let animation = Animation::from_flattened(
+            target,
+            &read_float_array(&env, &fractions),
+            &read_float_array(&env, &values),
+            Duration::from_nanos(duration_nanos.try_into().unwrap()),
+            easing,
+            repeat_mode,
+            // Non-positive iteration counts mean repeat forever
+            iterations
+                .try_into()
+                .ok()
+                .filter(|iterations| *iterations > 0),
+        )
+        .expect("Animation keyframes should have a value for each fraction");
+        state.send(Command::StartAnimation {
+            surface_id,
+            animation_id,
+            animation: Box::new(animation),
+        });
+    })
+}
+
+/// Stop the animation `animation_id` of `surface_id`, leaving its property at the last rendered
+/// value.
+///
+/// If the animation was still running, that value is reported to `onAnimationEnded`.
+///
+/// # Safety
+///
+/// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
+///   and which has not been freed.
+#[tracing::instrument(
+    name = "cancelAnimation",
+    skip_all,
+    fields(surface_id = surface_id, animation_id = animation_id)
+)]
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn Java_org_linebender_vello_Vello_cancelAnimation<'local>(
+    _: JNIEnv<'local>,
+    _: JObject<'local>,
+    state: jlong,
+    surface_id: jlong,
+    animation_id: jlong,
+) {
+    abort_on_panic(|| {
+        // Safety: Precondition of this function that state is correct
+        let state = unsafe { access_stored_state(state) };
+        state.send(Command::CancelAnimation {
+            surface_id,
+            animation_id,
+        });
+    })
+}
+
+/// Read an array of indices into [`PRESENT_MODES`].
+fn read_present_modes(env: &JNIEnv<'_>, array: &JIntArray<'_>) -> Vec<wgpu::PresentMode> {
+    read_int_array(env, array)
+        .into_iter()
+        .map(|index| {
+            *usize::try_from(index)
+                .ok()
+                .and_then(|index| PRESENT_MODES.get(index))
+                .expect("Unknown present mode")
+        })
+        .collect()
+}
+
+fn read_int_array(env: &JNIEnv<'_>, array: &JIntArray<'_>) -> Vec<jint> {
+    let len = env.get_array_length(array).unwrap();
+    let mut result = vec![0; len.try_into().unwrap()];
+    env.get_int_array_region(array, 0, &mut result).unwrap();
+    result
+}
+
+fn read_float_array(env: &JNIEnv<'_>, array: &JFloatArray<'_>) -> Vec<f32> {
+    let len = env.get_array_length(array).unwrap();
+    let mut result = vec![0.; len.try_into().unwrap()];
+    env.get_float_array_region(array, 0, &mut result).unwrap();
+    result
+}
+
+/// Tell the Kotlin `Vello` object that an animation has ended.
+///
+/// The value the animation left its property at is passed as an array of its components, in
+/// straight-alpha RGBA for colours, or as `null` if the animation never ran.
+///
+/// This is called on the render thread.
+fn notify_animation_ended(env: &mut JNIEnv<'_>, listener: &GlobalRef, end: AnimationEnded) {
+    with_notify_frame(env, |env| {
+        let value = match end.value {
+            Some(value) => {
+                let components = match value {
+                    AnimatedValue::Scalar(value) => vec![value],
+                    AnimatedValue::Color(color) => [color.r, color.g, color.b, color.a]
+                        .map(|component| f32::from(component) / 255.)
+                        .to_vec(),
+                };
+                let array = env
+                    .new_float_array(components.len().try_into().unwrap())
+                    .unwrap();
+                env.set_float_array_region(&array, 0, &components).unwrap();
+                JObject::from(array)
+            }
+            None => JObject::null(),
+        };
+        call_listener(
+            env,
+            listener,
+            "onAnimationEnded",
+            "(JJI[F)V",
+            &[
+                JValue::Long(end.surface_id),
+                JValue::Long(end.animation_id),
+                JValue::Int(end.reason as jint),
+                JValue::Object(&value),
+            ],
+        );
+    });
+}
+
+/// Tell the Kotlin `Vello` object what was under a pointer, with `-1` for nothing.
+///
+/// This is called on the render thread.
+fn notify_pointer_hit(env: &mut JNIEnv<'_>, listener: &GlobalRef, hit: &PointerHit) {
+    with_notify_frame(env, |env| {
+        call_listener(
+            env,
+            listener,
+            "onPointerHit",
+            "(JJIFFJ)V",
+            &[
+                JValue::Long(hit.surface_id),
+                JValue::Long(hit.event.pointer_id),
+                JValue::Int(hit.event.kind as i32),
+                JValue::Float(hit.event.x),
+                JValue::Float(hit.event.y),
+                JValue::Long(hit.hit.unwrap_or(-1)),
+            ],
+        );
+    });
+}
+
+/// Tell the Kotlin `Vello` object the new state of an editor, with `-1` for no composing region.
+///
+/// This is called on the render thread.
+fn notify_editor_changed(
+    env: &mut JNIEnv<'_>,
+    listener: &GlobalRef,
+    surface_id: SurfaceId,
+    editor: &EditorState,
+) {
+    with_notify_frame(env, |env| {
+        let text = JObject::from(env.new_string(&editor.text).unwrap());
+        let utf16 = |offset: usize| -> jint { offset.try_into().unwrap() };
+        let (composing_start, composing_end) = editor
+            .composing
+            .as_ref()
+            .map_or((-1, -1), |range| (utf16(range.start), utf16(range.end)));
+        call_listener(
+            env,
+            listener,
+            "onEditorChanged",
+            "(JLjava/lang/String;IIII)V",
+            &[
+                JValue::Long(surface_id),
+                JValue::Object(&text),
+                JValue::Int(utf16(editor.anchor)),
+                JValue::Int(utf16(editor.focus)),
+                JValue::Int(composing_start),
+                JValue::Int(composing_end),
+            ],
+        );
+    });
+}
+
+/// Tell the Kotlin `Vello` object about an event in recovering from a lost surface or device.
+///
+/// This is called on the render thread.
+fn notify_gpu_event(env: &mut JNIEnv<'_>, listener: &GlobalRef, event: &GpuEvent) {
+    with_notify_frame(env, |env| {
+        let message = match event.message() {
+            Some(message) => JObject::from(env.new_string(message).unwrap()),
+            None => JObject::null(),
+        };
+        call_listener(
+            env,
+            listener,
+            "onGpuEvent",
+            "(IJLjava/lang/String;)V",
+            &[
+                JValue::Int(event.kind()),
+                JValue::Long(event.id()),
+                JValue::Object(&message),
+            ],
+        );
+    });
+}
+
+/// The local references a notification may create: its arguments, and those made by
+/// [`call_listener`].
+const NOTIFY_LOCAL_REFS: i32 = 8;
+
+/// Run `notify` in a new frame of local references, which are freed when it returns.
+///
+/// The render thread is attached to the JVM permanently, so never returns to it to free the local
+/// references it creates. Without a frame, each notification would leak its arguments.
+fn with_notify_frame(env: &mut JNIEnv<'_>, notify: impl FnOnce(&mut JNIEnv<'_>)) {
+    let result = env.with_local_frame(NOTIFY_LOCAL_REFS, |env| {
+        notify(env);
+        Ok::<_, jni::errors::Error>(())
+    });
+    if let Err(e) = result {
+        log::error!("Failed to push a local reference frame: {e:?}");
+    }
+}
+
+/// Call the method `name` on the Kotlin `Vello` object, logging (and clearing) any exception.
+///
+/// This must be called within [`with_notify_frame`], as looking up the method creates local
+/// references.
+fn call_listener(
+    env: &mut JNIEnv<'_>,
+    listener: &GlobalRef,
+    name: &str,
+    signature: &str,
+    args: &[JValue<'_, '_>],
+) {
+    if let Err(e) = env.call_method(listener, name, signature, args) {
+        log::error!("Failed to call `Vello.{name}`: {e:?}");
+        if env.exception_check().unwrap_or(false) {
+            let _ = env.exception_describe();
+            let _ = env.exception_clear();
+        }
+    }
+}
+
+/// The number of values per phase written by [`Java_org_linebender_vello_Vello_getFrameStatistics`].
+const VALUES_PER_PHASE: usize = 6;
+
+/// Write a summary of the recent frame timings into `out`.
+///
+/// For each [`Phase`] in order, `out` receives the number of samples, then the mean, 50th, 90th
+/// and 99th percentile, and maximum durations in nanoseconds.
+///
+/// # Safety
+///
+/// - `env` must be a valid JNI environment
+/// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
+///   and which has not been freed.
+/// - `out` must be a valid Long Array from Java.
+///
+/// # Aborts
+///
+/// If `out` is shorter than 6 values for each phase.
+#[tracing::instrument(name = "getFrameStatistics", skip_all)]
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn Java_org_linebender_vello_Vello_getFrameStatistics<'local>(
+    env: JNIEnv<'local>,
+    _: JObject<'local>,
+    state: jlong,
+    out: JLongArray<'local>,
+) {
+    abort_on_panic(|| {
+        // Safety: Precondition of this function that state is correct
+        let state = unsafe { access_stored_state(state) };
+        let statistics = state.frame_statistics.lock().unwrap();
+        let mut values = Vec::with_capacity(Phase::ALL.len() * VALUES_PER_PHASE);
+        for phase in Phase::ALL {
+            let summary = statistics.summary(phase);
+            values.push(summary.count.try_into().unwrap());
+            values.extend(
+                [
+                    summary.mean,
+                    summary.p50,
+                    summary.p90,
+                    summary.p99,
+                    summary.max,
+                ]
+                .map(duration_to_nanos),
+            );
+        }
+        drop(statistics);
+        env.set_long_array_region(&out, 0, &values).unwrap();
+    })
+}
+
+/// Fill `out` with a histogram of the recent durations of the phase with index `phase`.
+///
+/// # Safety
+///
+/// - `env` must be a valid JNI environment
+/// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
+///   and which has not been freed.
+/// - `out` must be a valid Int Array from Java.
+///
+/// # Aborts
+///
+/// If `phase` isn't a valid phase, or `bucket_width_nanos` is negative.
+#[tracing::instrument(name = "getFrameTimeHistogram", skip_all, fields(phase = phase))]
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn Java_org_linebender_vello_Vello_getFrameTimeHistogram<'local>(
+    env: JNIEnv<'local>,
+    _: JObject<'local>,
+    state: jlong,
+    phase: jint,
+    bucket_width_nanos: jlong,
+    out: JIntArray<'local>,
+) {
+    abort_on_panic(|| {
+        // Safety: Precondition of this function that state is correct
+        let state = unsafe { access_stored_state(state) };
+        let phase = usize::try_from(phase)
+            .ok()
+            .and_then(Phase::from_index)
+            .expect("Unknown frame phase");
+        let len = env.get_array_length(&out).unwrap();
+        let mut buckets = vec![0_u32; len.try_into().unwrap()];
+        state.frame_statistics.lock().unwrap().histogram(
+            phase,
+            Duration::from_nanos(bucket_width_nanos.try_into().unwrap()),
+            &mut buckets,
+        );
+        let buckets = buckets
+            .into_iter()
+            .map(|count| count.try_into().unwrap_or(jint::MAX))
+            .collect::<Vec<_>>();
+        env.set_int_array_region(&out, 0, &buckets).unwrap();
+    })
+}
+
+/// # Safety
+///
+/// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
+///   and which has not been freed.
+#[tracing::instrument(name = "resetFrameStatistics", skip_all)]
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn Java_org_linebender_vello_Vello_resetFrameStatistics<'local>(
+    _: JNIEnv<'local>,
+    _: JObject<'local>,
+    state: jlong,
+) {
+    abort_on_panic(|| {
+        // Safety: Precondition of this function that state is correct
+        let state = unsafe { access_stored_state(state) };
+        state.frame_statistics.lock().unwrap().clear();
+    })
+}
+
+/// Enable or disable calls to `onFramePresented` for frames which Kotlin didn't request, such
+/// as those of animations.
+///
+/// # Safety
+///
+/// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
+///   and which has not been freed.
+#[tracing::instrument(name = "setFramePresentedCallbackEnabled", skip_all)]
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn Java_org_linebender_vello_Vello_setFramePresentedCallbackEnabled<
+    'local,
+>(
+    _: JNIEnv<'local>,
+    _: JObject<'local>,
+    state: jlong,
+    enabled: jboolean,
+) {
+    abort_on_panic(|| {
+        // Safety: Precondition of this function that state is correct
+        let state = unsafe { access_stored_state(state) };
+        state
+            .frame_presented_callback
+            .store(enabled != 0, Ordering::Relaxed);
+    })
+}
+
+/// Enable or disable calls to `onFrameTimings` on the `Vello` object after each frame.
+///
+/// # Safety
+///
+/// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
+///   and which has not been freed.
+#[tracing::instrument(name = "setFrameTimingCallbackEnabled", skip_all)]
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn Java_org_linebender_vello_Vello_setFrameTimingCallbackEnabled<
+    'local,
+>(
+    _: JNIEnv<'local>,
+    _: JObject<'local>,
+    state: jlong,
+    enabled: jboolean,
+) {
+    abort_on_panic(|| {
+        // Safety: Precondition of this function that state is correct
+        let state = unsafe { access_stored_state(state) };
+        state
+            .frame_timing_callback
+            .store(enabled != 0, Ordering::Relaxed);
+    })
+}
+
+/// Forward a pointer event over `surface_id` to the render thread, to be hit tested.
+///
+/// The result is reported to `onPointerHit`, after any earlier changes to the surface's content
+/// have been applied.
+///
+/// # Safety
+///
+/// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
+///   and which has not been freed.
+///
+/// # Aborts
+///
+/// If `kind` isn't a valid [`PointerEventKind`].
+#[tracing::instrument(
+    name = "pointerEvent",
+    skip_all,
+    fields(surface_id = surface_id, kind = kind, pointer_id = pointer_id)
+)]
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn Java_org_linebender_vello_Vello_pointerEvent<'local>(
+    _: JNIEnv<'local>,
+    _: JObject<'local>,
+    state: jlong,
+    surface_id: jlong,
+    kind: jint,
+    pointer_id: jlong,
+    x: jfloat,
+    y: jfloat,
+    pressure: jfloat,
+) {
+    abort_on_panic(|| {
+        // Safety: Precondition of this function that state is correct
+        let state = unsafe { access_stored_state(state) };
+        let kind = PointerEventKind::from_index(kind).expect("Unknown pointer event kind");
+        state.send(Command::PointerEvent {
+            surface_id,
+            event: PointerEvent {
+                kind,
+                pointer_id,
+                x,
+                y,
+                pressure,
+            },
+        });
+    })
+}
+
+/// Set what the debug overlay drawn on each surface shows, from the index of a [`DebugMode`].
+///
+/// This takes effect from the next frame which each surface is rendered in.
+///
+/// # Safety
+///
+/// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
+///   and which has not been freed.
+///
+/// # Aborts
+///
+/// If `mode` isn't a valid mode.
+#[tracing::instrument(name = "setDebugOverlay", skip_all, fields(mode = mode))]
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn Java_org_linebender_vello_Vello_setDebugOverlay<'local>(
+    _: JNIEnv<'local>,
+    _: JObject<'local>,
+    state: jlong,
+    mode: jint,
+) {
+    abort_on_panic(|| {
+        // Safety: Precondition of this function that state is correct
+        let state = unsafe { access_stored_state(state) };
+        let mode = DebugMode::from_index(mode).expect("Unknown debug overlay mode");
+        state.send(Command::SetDebugOverlay(mode));
+    })
+}
+
+/// Destroy every device used by `state`, as if they had been lost, to test recovery.
+///
+/// The devices are recreated before the next frame.
+///
+/// # Safety
+///
+/// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
+///   and which has not been freed.
+#[tracing::instrument(name = "simulateDeviceLoss", skip_all)]
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn Java_org_linebender_vello_Vello_simulateDeviceLoss<'local>(
+    _: JNIEnv<'local>,
+    _: JObject<'local>,
+    state: jlong,
+) {
+    abort_on_panic(|| {
+        // Safety: Precondition of this function that state is correct
+        let state = unsafe { access_stored_state(state) };
+        state.send(Command::SimulateDeviceLoss);
+    })
+}
+
+fn duration_to_nanos(duration: Duration) -> jlong {
+    duration.as_nanos().try_into().unwrap_or(jlong::MAX)
+}
+
+/// Tell the Kotlin `Vello` object that a frame has been presented.
+///
+/// `timings` is `None` if nothing was rendered, such as when every requested surface had been
+/// destroyed. The frame is coalesced if it rendered more than one request from Kotlin.
+///
+/// This is called on the render thread.
+fn notify_frame_presented(
+    env: &mut JNIEnv<'_>,
+    listener: &GlobalRef,
+    timings: Option<&FrameTimings>,
+    requests: &[RequestId],
+    presented: &[SurfaceId],
+) {
+    with_notify_frame(env, |env| {
+        let request_ids = env
+            .new_long_array(requests.len().try_into().unwrap())
+            .unwrap();
+        env.set_long_array_region(&request_ids, 0, requests)
+            .unwrap();
+        let surface_ids = env
+            .new_long_array(presented.len().try_into().unwrap())
+            .unwrap();
+        env.set_long_array_region(&surface_ids, 0, presented)
+            .unwrap();
+        let (frame_id, present_nanos, total_nanos) = match timings {
+            Some(timings) => (
+                timings.frame_id.try_into().unwrap(),
+                duration_to_nanos(timings.present),
+                duration_to_nanos(timings.total),
+            ),
+            None => (-1, -1, -1),
+        };
+        call_listener(
+            env,
+            listener,
+            "onFramePresented",
+            "(J[J[JZJJ)V",
+            &[
+                JValue::Long(frame_id),
+                JValue::Object(&request_ids),
+                JValue::Object(&surface_ids),
+                JValue::Bool(jboolean::from(requests.len() > 1)),
+                JValue::Long(present_nanos),
+                JValue::Long(total_nanos),
+            ],
+        );
+    });
+}
+
+/// Tell the Kotlin `Vello` object how long a frame took.
+///
+/// This is called on the render thread.
+fn notify_frame_timings(env: &mut JNIEnv<'_>, listener: &GlobalRef, timings: &FrameTimings) {
+    with_notify_frame(env, |env| {
+        let (gpu_frame_id, gpu_nanos) = match timings.gpu {
+            Some(gpu) => (
+                gpu.frame_id.try_into().unwrap(),
+                duration_to_nanos(gpu.duration),
+            ),
+            None => (-1, -1),
+        };
+        call_listener(
+            env,
+            listener,
+            "onFrameTimings",
+            "(JJJJJJJJJ)V",
+            &[
+                JValue::Long(timings.frame_id.try_into().unwrap()),
+                JValue::Long(duration_to_nanos(timings.scene_build)),
+                JValue::Long(duration_to_nanos(timings.render)),
+                JValue::Long(duration_to_nanos(timings.blit)),
+                JValue::Long(duration_to_nanos(timings.submit)),
+                JValue::Long(duration_to_nanos(timings.present)),
+                JValue::Long(duration_to_nanos(timings.total)),
+                JValue::Long(gpu_frame_id),
+                JValue::Long(gpu_nanos),
+            ],
+        );
+    });
+}
+
+/// Access a stored
+/// - `state` must be a value which was returned from [`Java_org_linebender_vello_Vello_initialise`]
+///   and which has not been freed.
+unsafe fn access_stored_state(state: jlong) -> Arc<FfiState> {
+    let value: usize = bytemuck::cast(state);
+    let ptr = value as *const FfiState;
+    assert!(!ptr.is_null());
+    unsafe {
+        Arc::increment_strong_count(ptr);
+        Arc::from_raw(ptr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ffi::{c_void, CString},
+        os::unix::ffi::OsStringExt,
+        path::PathBuf,
+        ptr::null_mut,
+        sync::OnceLock,
+    };
+
+    use jni::{sys, JavaVM};
+
+    use crate::{
+        animation::{AnimatedValue, AnimationEnded, EndReason},
+        editor::EditorState,
+        input::{PointerEvent, PointerEventKind, PointerHit},
+        recovery::GpuEvent,
+        stats::FrameTimings,
+    };
+
+    use super::{
+        notify_animation_ended, notify_editor_changed, notify_frame_presented,
+        notify_frame_timings, notify_gpu_event, notify_pointer_hit,
+    };
+
+    /// A stand-in for the Kotlin `Vello` object (see `test_jvm/README.md`).
+    const RECORDING_LISTENER: &[u8] = include_bytes!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test_jvm/RecordingListener.class"
+    ));
+
+    /// The number of local references older versions of Android allow a thread to hold.
+    const LOCAL_REF_CAPACITY: usize = 512;
+
+    /// The `libjvm.so` of the JDK at `JAVA_HOME`, or of the `java` on the path.
+    fn libjvm_path() -> Option<PathBuf> {
+        let java_home = match std::env::var_os("JAVA_HOME") {
+            Some(java_home) => PathBuf::from(java_home),
+            None => {
+                let path = std::env::var_os("PATH")?;
+                let java = std::env::split_paths(&path)
+                    .map(|dir| dir.join("java"))
+                    .find(|java| java.is_file())?;
+                // The `java` on the path is generally a link to `bin/java` in the JDK.
+                java.canonicalize().ok()?.parent()?.parent()?.to_path_buf()
+            }
+        };
+        let libjvm = java_home.join("lib/server/libjvm.so");
+        libjvm.is_file().then_some(libjvm)
+    }
+
+    /// A JVM with a 64 MiB heap, or `None` if there's no JDK to load it from.
+    ///
+    /// A process can only create one JVM, so it is shared by the tests.
+    fn test_vm() -> Option<&'static JavaVM> {
+        static VM: OnceLock<Option<JavaVM>> = OnceLock::new();
+        VM.get_or_init(|| {
+            let libjvm = CString::new(libjvm_path()?.into_os_string().into_vec()).unwrap();
+            // The library is never unloaded, as the JVM is never destroyed.
+            let library = unsafe { libc::dlopen(libjvm.as_ptr(), libc::RTLD_NOW) };
+            assert!(!library.is_null(), "Failed to load {libjvm:?}");
+            let create = unsafe { libc::dlsym(library, c"JNI_CreateJavaVM".as_ptr()) };
+            assert!(!create.is_null(), "{libjvm:?} has no `JNI_CreateJavaVM`");
+            let create: unsafe extern "system" fn(
+                *mut *mut sys::JavaVM,
+                *mut *mut c_void,
+                *mut c_void,
+            ) -> sys::jint = unsafe { std::mem::transmute(create) };
+            let mut options = [sys::JavaVMOption {
+                optionString: c"-Xmx64m".as_ptr().cast_mut(),
+                extraInfo: null_mut(),
+            }];
+            let mut args = sys::JavaVMInitArgs {
+                version: sys::JNI_VERSION_1_8,
+                nOptions: options.len().try_into().unwrap(),
+                options: options.as_mut_ptr(),
+                ignoreUnrecognized: sys::JNI_FALSE,
+            };
+            let mut vm = null_mut();
+            let mut env = null_mut();
+            let status = unsafe { create(&mut vm, &mut env, (&raw mut args).cast()) };
+            assert_eq!(status, sys::JNI_OK, "Failed to create a JVM");
+            Some(unsafe { JavaVM::from_raw(vm) }.unwrap())
+        })
+        .as_ref()
+    }
+
+    #[test]
+    fn notifications_free_their_local_references() {
+        let Some(vm) = test_vm() else {
+            eprintln!("Skipping test, as there is no JDK");
+            return;
+        };
+        // Like the render thread, this thread never returns to the JVM.
+        let mut env = vm.attach_current_thread_permanently().unwrap();
+        let loader = env
+            .call_static_method(
+                "java/lang/ClassLoader",
+                "getSystemClassLoader",
+                "()Ljava/lang/ClassLoader;",
+                &[],
+            )
+            .and_then(|loader| loader.l())
+            .unwrap();
+        let class = env
+            .define_class("RecordingListener", &loader, RECORDING_LISTENER)
+            .unwrap();
+        let listener = env.new_object(&class, "()V", &[]).unwrap();
+        let listener = env.new_global_ref(listener).unwrap();
+
+        // Each frame creates two 4 MiB arrays, so if they weren't freed, the heap would run out
+        // after a few frames.
+        let ids = vec![0; 1 << 19];
+        let timings = FrameTimings::default();
+        let hit = PointerHit {
+            surface_id: 1,
+            event: PointerEvent {
+                kind: PointerEventKind::Down,
+                pointer_id: 0,
+                x: 0.,
+                y: 0.,
+                pressure: 1.,
+            },
+            hit: None,
+        };
+        let editor = EditorState {
+            text: "12:34".to_string(),
+            anchor: 0,
+            focus: 5,
+            composing: None,
+        };
+        let event = GpuEvent::DeviceLost {
+            dev_id: 0,
+            reason: "Lost".to_string(),
+        };
+        let end = AnimationEnded {
+            surface_id: 1,
+            animation_id: 2,
+            reason: EndReason::Completed,
+            value: Some(AnimatedValue::Scalar(20.)),
+        };
+        let frames = 2 * LOCAL_REF_CAPACITY;
+        for _ in 0..frames {
+            notify_frame_presented(&mut env, &listener, Some(&timings), &ids, &ids);
+            notify_frame_timings(&mut env, &listener, &timings);
+            notify_pointer_hit(&mut env, &listener, &hit);
+            notify_editor_changed(&mut env, &listener, 1, &editor);
+            notify_gpu_event(&mut env, &listener, &event);
+            notify_animation_ended(&mut env, &listener, end);
+        }
+        let calls = env.get_field(&listener, "calls", "I").unwrap().i().unwrap();
+        assert_eq!(calls, 6 * i32::try_from(frames).unwrap());
     }
 }
@@ -0,0 +1,115 @@
+//! Hinting of the glyphs of text surfaces, and snapping them to device pixels.
+//!
+//! Hinting fits the outlines of glyphs to the pixel grid, which makes small static text sharper
+//! than the smooth outlines Vello draws by default. However, hinted outlines jump between
+//! shapes as the font size or variation axes change, and glyphs snapped to pixels jump between
+//! them, so text whose size or axes are moving is always drawn smoothly.
+
+/// How the glyphs of a variable font surface are drawn.
+///
+/// The discriminants are used in the FFI, so must match `TextRendering` in Kotlin.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TextRendering {
+    /// Draw the outlines unhinted, at their exact positions.
+    #[default]
+    Smooth = 0,
+    /// Draw hinted outlines, with baselines and glyph origins snapped to device pixels, whilst
+    /// the size and axes aren't moving.
+    Hinted = 1,
+}
+
+impl TextRendering {
+    pub fn from_index(index: i32) -> Option<Self> {
+        [Self::Smooth, Self::Hinted]
+            .into_iter()
+            .find(|rendering| *rendering as i32 == index)
+    }
+
+    /// Whether glyphs are hinted and snapped in this mode, if the text is `moving`.
+    pub(crate) fn hints(self, moving: bool) -> bool {
+        self == Self::Hinted && !moving
+    }
+}
+
+/// Tracks whether the size and axes of a surface's text are changing between frames.
+///
+/// Kotlin can change them every frame through commands or a parameter block, as well as with
+/// animations on the render thread, so the values drawn in each frame are compared.
+#[derive(Debug, Default)]
+pub(crate) struct TextMotion {
+    /// The size and axis values of the text in the last frame drawn, if it had any.
+    drawn: Vec<f32>,
+}
+
+impl TextMotion {
+    /// Record that a frame is being drawn with the size and axis values `values`, returning
+    /// whether they are moving.
+    ///
+    /// `animating` is whether an animation of the size or an axis is running.
+    pub(crate) fn update(&mut self, values: Vec<f32>, animating: bool) -> bool {
+        let changed = !self.drawn.is_empty() && self.drawn != values;
+        self.drawn = values;
+        animating || changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{command::SurfaceUpdate, test_contexts, test_variable_font};
+
+    use super::{TextMotion, TextRendering};
+
+    /// The glyph runs drawn for a clock with `rendering`, as whether each is hinted, and the
+    /// position of each glyph.
+    fn drawn(rendering: TextRendering, moving: bool) -> Vec<(bool, Vec<[f32; 2]>)> {
+        let (mut font_ctx, mut layout_ctx) = test_contexts();
+        let mut kind = test_variable_font("10:27", 13.3);
+        SurfaceUpdate::SetTextRendering(rendering).apply(&mut kind);
+        let scene = kind.scene(100, 100, moving, &mut font_ctx, &mut layout_ctx);
+        let resources = &scene.encoding().resources;
+        resources
+            .glyph_runs
+            .iter()
+            .map(|run| {
+                let glyphs = resources.glyphs[run.glyphs.clone()]
+                    .iter()
+                    .map(|glyph| [glyph.x, glyph.y])
+                    .collect();
+                (run.hint, glyphs)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn hinted_glyphs_are_snapped_whilst_still() {
+        let is_whole = |value: f32| value == value.round();
+        let runs = drawn(TextRendering::Hinted, false);
+        assert!(!runs.is_empty());
+        for (hint, glyphs) in runs {
+            assert!(hint);
+            assert!(glyphs.iter().flatten().all(|&value| is_whole(value)));
+        }
+
+        for (rendering, moving) in [
+            (TextRendering::Hinted, true),
+            (TextRendering::Smooth, false),
+        ] {
+            let runs = drawn(rendering, moving);
+            assert!(runs.iter().all(|(hint, _)| !hint));
+            let glyphs = runs.iter().flat_map(|(_, glyphs)| glyphs).flatten();
+            assert!(!glyphs.copied().all(is_whole), "{rendering:?}");
+        }
+    }
+
+    #[test]
+    fn text_moves_whilst_its_values_change() {
+        let mut motion = TextMotion::default();
+        assert!(!motion.update(vec![40., 400.], false));
+        assert!(!motion.update(vec![40., 400.], false));
+        assert!(motion.update(vec![40., 500.], false));
+        // Once the values settle, the next frame is still.
+        assert!(!motion.update(vec![40., 500.], false));
+        assert!(motion.update(vec![40., 500.], true));
+    }
+}
@@ -0,0 +1,123 @@
+//! Pointer input forwarded from Compose, and hit testing of the content of surfaces.
+//!
+//! Kotlin sends each pointer event to the render thread, which owns the content of every
+//! surface. The render thread finds what is under the pointer, and reports it back to Kotlin
+//! as a [`HitId`].
+
+use parley::{Cursor, Layout};
+use vello::peniko::Brush;
+
+use crate::{direction::DirectedText, SurfaceId};
+
+/// What was under the pointer, in a form specific to the kind of surface.
+///
+/// For text, this is the UTF-16 index of the start of the glyph cluster under the pointer, so
+/// that Kotlin can use it to index into its `String`.
+pub type HitId = i64;
+
+/// The kind of a [`PointerEvent`].
+///
+/// The discriminants are used in the FFI, so must match `PointerEventKind` in Kotlin.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PointerEventKind {
+    Down = 0,
+    Move = 1,
+    Up = 2,
+    /// The gesture was cancelled (such as by a parent scrolling), so the pointer is no longer
+    /// interacting with the surface.
+    Cancel = 3,
+}
+
+impl PointerEventKind {
+    pub fn from_index(index: i32) -> Option<Self> {
+        [Self::Down, Self::Move, Self::Up, Self::Cancel]
+            .into_iter()
+            .find(|kind| *kind as i32 == index)
+    }
+}
+
+/// A change to one pointer over a surface.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PointerEvent {
+    pub kind: PointerEventKind,
+    /// The id Compose uses for this pointer, which is the same for every event of a gesture.
+    pub pointer_id: i64,
+    /// The position of the pointer, in pixels from the top left of the surface.
+    pub x: f32,
+    pub y: f32,
+    /// The pressure of the pointer, from 0.0 to 1.0.
+    pub pressure: f32,
+}
+
+/// The result of hit testing a [`PointerEvent`], which is reported to Kotlin.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PointerHit {
+    pub surface_id: SurfaceId,
+    pub event: PointerEvent,
+    /// `None` if there was nothing under the pointer, or the event was a cancellation.
+    pub hit: Option<HitId>,
+}
+
+/// Find the glyph cluster of `layout` at `(x, y)`.
+///
+/// `layout` must have been built from `text`.
+pub(crate) fn hit_test_text(
+    layout: &Layout<Brush>,
+    text: &DirectedText<'_>,
+    x: f32,
+    y: f32,
+) -> Option<HitId> {
+    // `Cursor::from_point` snaps to the nearest cluster, but points outside of the text
+    // aren't over any of it.
+    if x < 0. || y < 0. || x >= layout.width() || y >= layout.height() {
+        return None;
+    }
+    let index = text.to_text(Cursor::from_point(layout, x, y).index());
+    Some(
+        text.source()[..index]
+            .encode_utf16()
+            .count()
+            .try_into()
+            .unwrap(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{test_contexts, test_variable_font, SurfaceKind};
+
+    #[test]
+    fn hits_text_clusters() {
+        let (mut font_ctx, mut layout_ctx) = test_contexts();
+        let kind = test_variable_font("12:34", 40.);
+        let layout = kind.layout(&mut font_ctx, &mut layout_ctx).unwrap();
+        let y = layout.height() / 2.;
+        let mut hit = |x| kind.hit_test(x, y, &mut font_ctx, &mut layout_ctx);
+        assert_eq!(hit(1.), Some(0));
+        assert_eq!(hit(layout.width() - 1.), Some(4));
+        // Every character is a cluster, and they are hit from left to right.
+        let hits = (0..layout.width() as u32)
+            .filter_map(|x| hit(x as f32))
+            .collect::<Vec<_>>();
+        assert!(hits.is_sorted());
+        assert_eq!(hits.first(), Some(&0));
+        assert_eq!(hits.last(), Some(&4));
+        assert!((0..5).all(|index| hits.contains(&index)));
+    }
+
+    #[test]
+    fn misses_outside_of_text() {
+        let (mut font_ctx, mut layout_ctx) = test_contexts();
+        let kind = test_variable_font("12:34", 40.);
+        let layout = kind.layout(&mut font_ctx, &mut layout_ctx).unwrap();
+        let mut hit = |x, y| kind.hit_test(x, y, &mut font_ctx, &mut layout_ctx);
+        assert_eq!(hit(-1., 1.), None);
+        assert_eq!(hit(1., -1.), None);
+        assert_eq!(hit(layout.width() + 1., 1.), None);
+        assert_eq!(hit(1., layout.height() + 1.), None);
+        assert_eq!(
+            SurfaceKind::Unset.hit_test(1., 1., &mut font_ctx, &mut layout_ctx),
+            None
+        );
+    }
+}
@@ -2,44 +2,120 @@
 // Don't allow unsafe code in the main module. Note that it is allowed in the other modules
 #![deny(unsafe_code)]
 
+pub mod accessibility;
+pub mod advances;
+pub mod animation;
+pub mod color_glyphs;
+pub mod command;
+pub mod config;
+pub mod context;
+pub mod debug;
+pub mod direction;
+pub mod editor;
+pub mod effects;
+pub mod features;
 pub mod ffi;
+pub mod hinting;
+pub mod input;
+pub mod logging;
+pub mod params;
+pub mod recovery;
+pub mod scale;
+pub mod selection;
+pub mod stats;
+pub mod trace;
 pub mod util;
+pub mod vsync;
 
-use std::collections::HashMap;
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    time::Instant,
+};
 
 use guillotiere::{euclid::Size2D, SimpleAtlasAllocator};
+#[cfg(target_os = "android")]
 use ndk::native_window::NativeWindow;
-use parley::{Alignment, FontContext, FontWeight, PositionedLayoutItem, StyleProperty};
+use parley::{
+    swash::{tag_from_bytes, Tag},
+    Alignment, FontContext, FontFeature, FontSettings, FontVariation, FontWeight, Layout,
+    PositionedLayoutItem, StyleProperty,
+};
 use vello::{
-    kurbo::{Affine, Rect, Vec2},
-    peniko::{Color, Fill, Mix},
-    skrifa::raw::tables::glyf::PointCoord,
-    util::{RenderContext, RenderSurface},
-    AaSupport, RenderParams, Renderer, RendererOptions, Scene,
+    kurbo::{Affine, Rect, Stroke, Vec2},
+    peniko::{Brush, Color, Fill, Font, Mix, StyleRef},
+    skrifa::{
+        instance::{LocationRef, NormalizedCoord},
+        raw::{tables::glyf::PointCoord, TableProvider},
+        FontRef, GlyphId,
+    },
+    util::RenderSurface,
+    AaConfig, RenderParams, Renderer, RendererOptions, Scene,
 };
+#[cfg(target_os = "android")]
+use wgpu::rwh::{DisplayHandle, HasDisplayHandle, HasWindowHandle};
 use wgpu::{
-    rwh::{DisplayHandle, HasDisplayHandle, HasWindowHandle},
     BindGroupDescriptor, BindGroupEntry, BindGroupLayout, Buffer, BufferDescriptor, BufferUsages,
-    CommandEncoder, CommandEncoderDescriptor, Device, Instance, InstanceFlags, MultisampleState,
+    CommandEncoder, CommandEncoderDescriptor, Device, Instance, MultisampleState,
     PipelineCompilationOptions, PrimitiveState, RenderPassColorAttachment, RenderPassDescriptor,
     RenderPipelineDescriptor, SurfaceTexture, TextureDescriptor, TextureFormat,
     TextureViewDescriptor,
 };
 
+use accessibility::{AccessNode, AccessRole};
+use advances::AdvanceMode;
+use animation::{
+    AnimatedValue, AnimationEnded, AnimationId, AnimationTarget, EndReason, RunningAnimation,
+};
+use color_glyphs::{ColorPalette, GlyphColors};
+#[cfg(target_os = "android")]
+use command::NewSurface;
+use command::{CommandHandler, SurfaceUpdate};
+use config::VelloConfig;
+#[cfg(target_os = "android")]
+use context::CreateSurfaceError;
+use context::{DeviceHandle, GpuContext};
+use debug::{AtlasInfo, DebugMode, DebugOverlay};
+use direction::{DirectedText, TextDirection};
+use editor::{EditOp, EditorState, TextEditor};
+use effects::TextEffects;
+use features::FeatureSpan;
+use hinting::{TextMotion, TextRendering};
+use input::HitId;
+use params::ParameterBlock;
+use recovery::{DeviceRecovery, GpuEvent, SurfaceAction};
+use scale::TextScale;
+use selection::{utf16_to_byte, SelectedText, SelectionChange, TextSelection};
+use stats::{FrameTimings, GpuTimer};
+
 // TODO: Bytemuck? a struct?
 type SurfaceId = i64;
 
-type LayoutContext = parley::LayoutContext<vello::peniko::Brush>;
+type LayoutContext = parley::LayoutContext<Brush>;
 
 pub struct VelloJni {
-    cx: vello::util::RenderContext,
-    renderer: Option<RendererResources>,
+    cx: GpuContext,
+    /// The renderer of each device which has been rendered to, by `dev_id`.
+    renderers: HashMap<usize, RendererResources>,
     surfaces: HashMap<SurfaceId, TargetSurface>,
 
     font_ctx: FontContext,
     layout_ctx: LayoutContext,
 
     scene: Scene,
+    /// The id of the next frame to be rendered.
+    next_frame_id: u64,
+    debug: DebugOverlay,
+    config: VelloConfig,
+    /// Events in the recovery from lost surfaces and devices, which haven't been reported yet.
+    gpu_events: Vec<GpuEvent>,
+    /// Surfaces which need to be rendered again, because they weren't updated in the last frame
+    /// they were rendered in, or their text was drawn unhinted whilst it was moving.
+    pending_renders: Vec<SurfaceId>,
+    /// The devices which we have reported as lost, but haven't yet recovered.
+    recovery: DeviceRecovery,
+    /// The surfaces which were presented in the last frame.
+    presented: Vec<SurfaceId>,
 }
 
 struct RendererResources {
@@ -47,6 +123,94 @@ struct RendererResources {
     target_texture: wgpu::Texture,
     texture_view: wgpu::TextureView,
     blit_pipelines: HashMap<TextureFormat, BlitPipeline>,
+    /// The GPU timer, if the device supports timestamp queries.
+    gpu_timer: Option<GpuTimer>,
+}
+
+impl RendererResources {
+    /// The size of the texture which every surface on this renderer's device is drawn into.
+    fn atlas_size(&self) -> (u32, u32) {
+        (self.target_texture.width(), self.target_texture.height())
+    }
+
+    /// Render `scene`, which was drawn with an [`AtlasBuilder`], into the atlas texture.
+    fn render_atlas(
+        &mut self,
+        device_handle: &DeviceHandle,
+        scene: &Scene,
+        antialiasing: AaConfig,
+    ) {
+        let (width, height) = self.atlas_size();
+        self.renderer
+            .render_to_texture(
+                &device_handle.device,
+                &device_handle.queue,
+                scene,
+                &self.texture_view,
+                &RenderParams {
+                    antialiasing_method: antialiasing,
+                    base_color: Color::WHITE,
+                    height,
+                    width,
+                },
+            )
+            .unwrap();
+    }
+}
+
+/// Draws the scenes of a device's surfaces into a scene of its atlas, each in its own zone.
+struct AtlasBuilder<'a> {
+    scene: &'a mut Scene,
+    allocator: SimpleAtlasAllocator,
+    /// The zone of the atlas which each surface was drawn into.
+    allocations: HashMap<SurfaceId, guillotiere::Rectangle>,
+}
+
+impl<'a> AtlasBuilder<'a> {
+    /// Start drawing into `scene`, which is cleared, for an atlas of `size`.
+    fn new(scene: &'a mut Scene, size: (u32, u32)) -> Self {
+        scene.reset();
+        Self {
+            scene,
+            allocator: SimpleAtlasAllocator::new(Size2D::new(
+                size.0.try_into().unwrap(),
+                size.1.try_into().unwrap(),
+            )),
+            allocations: HashMap::new(),
+        }
+    }
+
+    /// Draw `scene`, of a surface of `width` by `height`, into a new zone of the atlas.
+    fn add(&mut self, surface_id: SurfaceId, width: u32, height: u32, scene: &Scene) {
+        let zone = tracing::info_span!("atlas_allocation").in_scope(|| {
+            self.allocator
+                .allocate(Size2D::new(
+                    width.try_into().unwrap(),
+                    height.try_into().unwrap(),
+                ))
+                .expect("Should have room for surface")
+        });
+        self.allocations.insert(surface_id, zone);
+        self.scene.push_layer(
+            Mix::Clip,
+            1.0,
+            Affine::IDENTITY,
+            &Rect {
+                x0: zone.min.x.into(),
+                y0: zone.min.y.into(),
+                x1: zone.max.x.into(),
+                y1: zone.max.y.into(),
+            },
+        );
+        self.scene.append(
+            scene,
+            Some(Affine::translate(Vec2::new(
+                zone.min.x.into(),
+                zone.min.y.into(),
+            ))),
+        );
+        self.scene.pop_layer();
+    }
 }
 
 struct BlitPipeline {
@@ -134,7 +298,7 @@ impl BlitPipeline {
 
     fn blit(
         &self,
-        device_handle: &vello::util::DeviceHandle,
+        device_handle: &DeviceHandle,
         from_texture: &wgpu::TextureView,
         to_texture: &wgpu::TextureView,
         x: i32,
@@ -193,10 +357,38 @@ impl BlitPipeline {
 pub enum SurfaceKind {
     VariableFont {
         text: String,
+        /// The font size, in sp.
         size: f32,
+        /// How `size` is converted to pixels.
+        scale: TextScale,
         weight: f32,
+        /// Settings for variation axes other than `wght`, which is controlled by `weight`.
+        variations: Vec<FontVariation>,
+        /// OpenType features applied to all of the text.
+        features: Vec<FontFeature>,
+        /// OpenType features applied to parts of the text, which take precedence over `features`.
+        feature_spans: Vec<FeatureSpan>,
+        /// How the glyphs are spaced, which can keep them still whilst the axes are animated,
+        /// boxed as it is rarely set.
+        advance_mode: Box<AdvanceMode>,
+        /// The base direction of each paragraph.
+        direction: TextDirection,
+        /// The BCP 47 language tag used to shape the text, such as to use the Serbian forms of
+        /// Cyrillic letters.
+        locale: Option<Box<str>>,
+        /// Whether the glyphs are hinted and snapped to pixels when they aren't moving.
+        rendering: TextRendering,
+        color: Color,
+        /// The outline and shadows drawn with the glyphs, boxed as they are rarely set.
+        effects: Box<TextEffects>,
+        /// The palette of colour glyphs, such as emoji, boxed as it is rarely set.
+        palette: Box<ColorPalette>,
+        /// The selected text, or the position of the caret, if either is shown.
+        selection: Option<TextSelection>,
         // We don't store the Parley layout here, because if we are using this, we are re-rendering anyway.
     },
+    /// Editable text, which Kotlin forwards input method edits to.
+    TextEditor(Box<TextEditor>),
     Unset,
 }
 
@@ -216,81 +408,531 @@ const ROBOTO_FLEX: &[u8] = include_bytes!(concat!(
     "RobotoFlex-Subset.ttf"
 ));
 
+/// The tag of the weight variation axis, which is controlled using [`FontWeight`].
+const WGHT: Tag = tag_from_bytes(b"wght");
+
 impl SurfaceKind {
-    fn scene(
+    /// Set the property controlled by `target` to `value`.
+    ///
+    /// Targets which don't apply to this kind of surface are ignored.
+    fn apply_animated(&mut self, target: AnimationTarget, value: AnimatedValue) {
+        match (self, target, value) {
+            (
+                SurfaceKind::VariableFont { size, .. },
+                AnimationTarget::FontSize,
+                AnimatedValue::Scalar(value),
+            ) => {
+                *size = value;
+            }
+            (
+                SurfaceKind::VariableFont { weight, .. },
+                AnimationTarget::Axis(WGHT),
+                AnimatedValue::Scalar(value),
+            ) => {
+                *weight = value;
+            }
+            (
+                SurfaceKind::VariableFont { variations, .. },
+                AnimationTarget::Axis(tag),
+                AnimatedValue::Scalar(value),
+            ) => match variations.iter_mut().find(|variation| variation.tag == tag) {
+                Some(variation) => variation.value = value,
+                None => variations.push(FontVariation { tag, value }),
+            },
+            (
+                SurfaceKind::VariableFont { color, .. },
+                AnimationTarget::Color,
+                AnimatedValue::Color(value),
+            ) => {
+                *color = value;
+            }
+            (
+                SurfaceKind::TextEditor(editor),
+                AnimationTarget::Color,
+                AnimatedValue::Color(value),
+            ) => editor.set_color(value),
+            _ => {}
+        }
+    }
+
+    /// Apply the values of `block`, if Kotlin has written to it since it was last read.
+    fn apply_parameters(&mut self, block: &mut ParameterBlock) {
+        if let Some(values) = block.read_if_changed() {
+            for (target, value) in block.targets().iter().zip(values) {
+                self.apply_animated(*target, AnimatedValue::Scalar(value));
+            }
+        }
+    }
+
+    /// Lay out the text of this surface, if it has any.
+    ///
+    /// Editors keep their layout between frames, so this borrows it if it is up to date.
+    fn layout(
         &self,
-        _width: u32,
-        _height: u32,
         font_ctx: &mut FontContext,
         layout_ctx: &mut LayoutContext,
-    ) -> Scene {
-        let mut scene = Scene::new();
+    ) -> Option<Cow<'_, Layout<Brush>>> {
         match self {
-            SurfaceKind::Unset => {}
-            SurfaceKind::VariableFont { text, size, weight } => {
-                let mut builder = layout_ctx.ranged_builder(font_ctx, text, 1.0);
+            SurfaceKind::Unset => None,
+            SurfaceKind::TextEditor(editor) => Some(editor.layout(font_ctx, layout_ctx)),
+            SurfaceKind::VariableFont {
+                text,
+                size,
+                scale,
+                weight,
+                variations,
+                features,
+                feature_spans,
+                direction,
+                locale,
+                color,
+                ..
+            } => {
+                let directed = DirectedText::new(text, *direction);
+                let mut builder =
+                    layout_ctx.ranged_builder(font_ctx, directed.as_str(), scale.pixels_per_sp());
                 builder.push_default(StyleProperty::FontStack(parley::FontStack::Single(
                     parley::FontFamily::Named("Roboto Flex".into()),
                 )));
                 builder.push_default(StyleProperty::FontSize(*size));
                 builder.push_default(StyleProperty::FontWeight(FontWeight::new(*weight)));
-                builder.push_default(StyleProperty::Brush(vello::peniko::Brush::Solid(
-                    Color::BLACK,
-                )));
+                if !variations.is_empty() {
+                    builder.push_default(StyleProperty::FontVariations(FontSettings::List(
+                        Cow::Borrowed(variations),
+                    )));
+                }
+                if !features.is_empty() {
+                    builder.push_default(StyleProperty::FontFeatures(FontSettings::List(
+                        Cow::Borrowed(features),
+                    )));
+                }
+                for span in feature_spans {
+                    builder.push(
+                        StyleProperty::FontFeatures(FontSettings::List(Cow::Borrowed(
+                            &span.features,
+                        ))),
+                        directed.to_layout(span.range.start)..directed.to_layout(span.range.end),
+                    );
+                }
+                if let Some(locale) = locale {
+                    builder.push_default(StyleProperty::Locale(Some(locale)));
+                }
+                builder.push_default(StyleProperty::Brush(Brush::Solid(*color)));
                 builder.push_default(StyleProperty::LineHeight(1.3));
-                let mut layout = builder.build(text);
+                let mut layout = builder.build(directed.as_str());
                 layout.break_all_lines(Some(500.));
-                layout.align(Some(500.), Alignment::Start);
-                for line in layout.lines() {
-                    for item in line.items() {
-                        let PositionedLayoutItem::GlyphRun(glyph_run) = item else {
-                            continue;
-                        };
-                        let mut x = glyph_run.offset();
-                        let y = glyph_run.baseline();
-                        let run = glyph_run.run();
-                        let font = run.font();
-                        let font_size = run.font_size();
-                        let synthesis = run.synthesis();
-                        let glyph_xform = synthesis
-                            .skew()
-                            .map(|angle| Affine::skew(angle.to_radians().tan() as f64, 0.0));
-                        let coords = run
-                            .normalized_coords()
-                            .iter()
-                            .map(|coord| {
-                                vello::skrifa::instance::NormalizedCoord::from_bits(*coord)
-                            })
-                            .collect::<Vec<_>>();
-                        scene
-                            .draw_glyphs(font)
-                            .brush(&glyph_run.style().brush)
-                            // We think this might be animated, so don't enable hinting
-                            .hint(false)
-                            .glyph_transform(glyph_xform)
-                            .font_size(font_size)
-                            .normalized_coords(&coords)
-                            .draw(
-                                Fill::NonZero,
-                                glyph_run.glyphs().map(|glyph| {
-                                    let gx = x + glyph.x;
-                                    let gy = y - glyph.y;
-                                    x += glyph.advance;
-                                    vello::Glyph {
-                                        id: glyph.id as _,
-                                        x: gx,
-                                        y: gy,
-                                    }
-                                }),
-                            );
-                    }
+                if directed.starts_rtl() {
+                    // Right-to-left lines end at the right of the widest line, rather than of the
+                    // width they were broken at, so that the text starts at the surface's edge.
+                    layout.align(None, Alignment::End);
+                } else {
+                    layout.align(Some(500.), Alignment::Start);
                 }
+                Some(Cow::Owned(layout))
+            }
+        }
+    }
+
+    /// Find the content of this surface at `(x, y)`, in pixels from its top left.
+    ///
+    /// For text, this is the glyph cluster under the point (see [`HitId`]).
+    fn hit_test(
+        &self,
+        x: f32,
+        y: f32,
+        font_ctx: &mut FontContext,
+        layout_ctx: &mut LayoutContext,
+    ) -> Option<HitId> {
+        match self {
+            SurfaceKind::Unset => None,
+            SurfaceKind::VariableFont {
+                text, direction, ..
+            } => {
+                let layout = self.layout(font_ctx, layout_ctx)?;
+                input::hit_test_text(&layout, &DirectedText::new(text, *direction), x, y)
+            }
+            SurfaceKind::TextEditor(editor) => {
+                let layout = editor.layout(font_ctx, layout_ctx);
+                // Hit test in the coordinates of the text, which may be scrolled.
+                let point =
+                    editor.transform().inverse() * vello::kurbo::Point::new(x.into(), y.into());
+                // The editor lays out its text without any marks.
+                let text = DirectedText::new(editor.text(), TextDirection::Ltr);
+                input::hit_test_text(&layout, &text, point.x as f32, point.y as f32)
+            }
+        }
+    }
+
+    /// Apply `change` to the selection of this surface, if it has text.
+    ///
+    /// Returns the new state of the surface if it is an editor.
+    fn set_selection(
+        &mut self,
+        change: SelectionChange,
+        now: Instant,
+        font_ctx: &mut FontContext,
+        layout_ctx: &mut LayoutContext,
+    ) -> Option<EditorState> {
+        if let SurfaceKind::TextEditor(editor) = self {
+            editor.set_selection(change, now, font_ctx, layout_ctx);
+            return Some(editor.state());
+        }
+        let this = &*self;
+        let layout = || this.layout(font_ctx, layout_ctx).unwrap().into_owned();
+        let new_selection = match this {
+            SurfaceKind::Unset | SurfaceKind::TextEditor(_) => return None,
+            SurfaceKind::VariableFont {
+                text,
+                direction,
+                selection,
+                ..
+            } => {
+                // The selection is changed in the layout's offsets, as points are found in it.
+                let directed = DirectedText::new(text, *direction);
+                let change = match change {
+                    SelectionChange::Bytes { anchor, focus } => SelectionChange::Bytes {
+                        anchor: directed.to_layout(anchor),
+                        focus: directed.to_layout(focus),
+                    },
+                    SelectionChange::Utf16 { anchor, focus } => SelectionChange::Bytes {
+                        anchor: directed.to_layout(utf16_to_byte(text, anchor)),
+                        focus: directed.to_layout(utf16_to_byte(text, focus)),
+                    },
+                    SelectionChange::Point { .. } | SelectionChange::Clear => change,
+                };
+                let selection = selection.map(|selection| selection.map(|i| directed.to_layout(i)));
+                TextSelection::apply(selection, change, directed.as_str(), layout, now)
+                    .map(|selection| selection.map(|i| directed.to_text(i)))
             }
+        };
+        if let SurfaceKind::VariableFont { selection, .. } = self {
+            *selection = new_selection;
+        }
+        None
+    }
+
+    fn selected_text(&self) -> Option<SelectedText> {
+        match self {
+            SurfaceKind::Unset => None,
+            SurfaceKind::VariableFont {
+                text, selection, ..
+            } => selection.map(|selection| selection.selected_text(text)),
+            SurfaceKind::TextEditor(editor) => {
+                Some(editor.selection().selected_text(editor.text()))
+            }
+        }
+    }
+
+    /// Describe the content of this surface for accessibility services, if it has any.
+    fn accessibility_tree(
+        &self,
+        font_ctx: &mut FontContext,
+        layout_ctx: &mut LayoutContext,
+    ) -> Option<AccessNode> {
+        let layout = self.layout(font_ctx, layout_ctx)?;
+        match self {
+            SurfaceKind::Unset => None,
+            SurfaceKind::VariableFont {
+                text,
+                direction,
+                selection,
+                ..
+            } => Some(accessibility::text_tree(
+                &layout,
+                &DirectedText::new(text, *direction),
+                Affine::IDENTITY,
+                AccessRole::Label,
+                selection.as_ref(),
+            )),
+            SurfaceKind::TextEditor(editor) => Some(accessibility::text_tree(
+                &layout,
+                &DirectedText::new(editor.text(), TextDirection::Ltr),
+                editor.transform(),
+                AccessRole::TextInput,
+                Some(editor.selection()),
+            )),
+        }
+    }
+
+    fn selection_mut(&mut self) -> Option<&mut TextSelection> {
+        match self {
+            SurfaceKind::Unset => None,
+            SurfaceKind::VariableFont { selection, .. } => selection.as_mut(),
+            SurfaceKind::TextEditor(editor) => Some(editor.selection_mut()),
+        }
+    }
+
+    /// Whether this surface's text is hinted when its size and axes aren't moving.
+    fn hints_when_still(&self) -> bool {
+        matches!(
+            self,
+            SurfaceKind::VariableFont {
+                rendering: TextRendering::Hinted,
+                ..
+            }
+        )
+    }
+
+    /// The size and axis values of this surface's text, which change as it is animated.
+    fn font_values(&self) -> Vec<f32> {
+        match self {
+            SurfaceKind::VariableFont {
+                size,
+                weight,
+                variations,
+                ..
+            } => [*size, *weight]
+                .into_iter()
+                .chain(variations.iter().map(|variation| variation.value))
+                .collect(),
+            SurfaceKind::TextEditor(_) | SurfaceKind::Unset => Vec::new(),
+        }
+    }
+
+    /// Draw this surface.
+    ///
+    /// `moving` is whether the size or axes of its text are changing, in which case it isn't
+    /// hinted (see [`TextRendering`]).
+    fn scene(
+        &self,
+        _width: u32,
+        _height: u32,
+        moving: bool,
+        font_ctx: &mut FontContext,
+        layout_ctx: &mut LayoutContext,
+    ) -> Scene {
+        let mut scene = Scene::new();
+        let Some(layout) = self.layout(font_ctx, layout_ctx) else {
+            return scene;
+        };
+        match self {
+            SurfaceKind::VariableFont {
+                text,
+                weight,
+                variations,
+                advance_mode,
+                direction,
+                rendering,
+                color,
+                effects,
+                palette,
+                selection,
+                ..
+            } => {
+                let mut axes = vec![FontVariation {
+                    tag: WGHT,
+                    value: *weight,
+                }];
+                axes.extend_from_slice(variations);
+                let positions = advances::glyph_positions(&layout, advance_mode, &axes);
+                draw_layout(
+                    &mut scene,
+                    &layout,
+                    Affine::IDENTITY,
+                    positions.as_deref(),
+                    rendering.hints(moving),
+                    effects,
+                    palette,
+                );
+                if let Some(selection) = selection {
+                    let directed = DirectedText::new(text, *direction);
+                    selection.map(|i| directed.to_layout(i)).draw(
+                        &mut scene,
+                        Affine::IDENTITY,
+                        &layout,
+                        directed.as_str(),
+                        *color,
+                    );
+                }
+            }
+            SurfaceKind::TextEditor(editor) => {
+                draw_layout(
+                    &mut scene,
+                    &layout,
+                    editor.transform(),
+                    None,
+                    // The editor's text scrolls, so is never hinted.
+                    false,
+                    &TextEffects::default(),
+                    &ColorPalette::default(),
+                );
+                editor.draw_decorations(&mut scene, &layout);
+            }
+            SurfaceKind::Unset => {}
         }
         scene
     }
 }
 
+/// Draw the glyphs of `layout`, moved by `transform`, with `effects`.
+///
+/// If `positions` is set, it replaces the `x` position of each glyph, in drawing order. If `hint`
+/// is set, the glyphs are hinted and snapped to whole pixels of the layout, so `transform` should
+/// be a translation by whole pixels. Colour glyphs use the colours of `palette`, but aren't
+/// outlined by the stroke of `effects`.
+fn draw_layout(
+    scene: &mut Scene,
+    layout: &Layout<Brush>,
+    transform: Affine,
+    positions: Option<&[f32]>,
+    hint: bool,
+    effects: &TextEffects,
+    palette: &ColorPalette,
+) {
+    let runs = glyph_runs(layout, positions, hint);
+    // Each layer is drawn for every run before the next, so that shadows are under all of the
+    // glyphs, and not just those of their own run.
+    for shadow in &effects.shadows {
+        for (offset, color) in shadow.passes() {
+            let brush = Brush::Solid(color);
+            for run in &runs {
+                let transform = Affine::translate(offset) * transform;
+                run.draw(scene, transform, &brush, Fill::NonZero.into());
+                run.draw_colors(scene, transform, &GlyphColors::Tint(color));
+            }
+        }
+    }
+    for run in &runs {
+        run.draw(scene, transform, &run.brush, Fill::NonZero.into());
+        if !run.color_glyphs.is_empty() {
+            let colors = GlyphColors::Palette {
+                palette: &run.palette_colors(palette),
+                foreground: &run.brush,
+            };
+            run.draw_colors(scene, transform, &colors);
+        }
+    }
+    if let Some(stroke) = &effects.stroke {
+        let style = Stroke::new(stroke.width.into()).with_join(stroke.join);
+        let brush = Brush::Solid(stroke.color);
+        for run in &runs {
+            run.draw(scene, transform, &brush, (&style).into());
+        }
+    }
+}
+
+/// A glyph run of a layout, positioned for drawing.
+struct RunGlyphs {
+    font: Font,
+    font_size: f32,
+    glyph_transform: Option<Affine>,
+    coords: Vec<NormalizedCoord>,
+    brush: Brush,
+    /// Whether Vello hints the outlines of `glyphs`.
+    hint: bool,
+    /// The glyphs drawn as outlines or bitmaps by Vello.
+    glyphs: Vec<vello::Glyph>,
+    /// The glyphs drawn from the `COLR` table of the font, with a chosen palette.
+    color_glyphs: Vec<vello::Glyph>,
+}
+
+impl RunGlyphs {
+    fn draw(&self, scene: &mut Scene, transform: Affine, brush: &Brush, style: StyleRef<'_>) {
+        scene
+            .draw_glyphs(&self.font)
+            .brush(brush)
+            .transform(transform)
+            .hint(self.hint)
+            .glyph_transform(self.glyph_transform)
+            .font_size(self.font_size)
+            .normalized_coords(&self.coords)
+            .draw(style, self.glyphs.iter().copied());
+    }
+
+    /// Draw the colour glyphs of this run, moved by `transform`.
+    fn draw_colors(&self, scene: &mut Scene, transform: Affine, colors: &GlyphColors<'_>) {
+        let Ok(font) = FontRef::from_index(self.font.data.as_ref(), self.font.index) else {
+            return;
+        };
+        for glyph in &self.color_glyphs {
+            let transform = transform
+                * Affine::translate((glyph.x.into(), glyph.y.into()))
+                * self.glyph_transform.unwrap_or_default();
+            color_glyphs::draw_color_glyph(
+                scene,
+                &font,
+                GlyphId::new(glyph.id),
+                LocationRef::new(&self.coords),
+                self.font_size,
+                transform,
+                colors,
+            );
+        }
+    }
+
+    /// The colours of the palette of this run's font, if it has colour glyphs.
+    fn palette_colors(&self, palette: &ColorPalette) -> Vec<Color> {
+        FontRef::from_index(self.font.data.as_ref(), self.font.index)
+            .and_then(|font| font.cpal())
+            .map(|cpal| palette.colors(&cpal))
+            .unwrap_or_default()
+    }
+}
+
+/// The glyph runs of `layout`, with the `x` positions of the glyphs replaced by `positions` if
+/// it is set.
+///
+/// If `hint` is set, the glyphs are hinted, and their positions are rounded to whole pixels.
+fn glyph_runs(layout: &Layout<Brush>, positions: Option<&[f32]>, hint: bool) -> Vec<RunGlyphs> {
+    let snap = |value: f32| if hint { value.round() } else { value };
+    let mut positions = positions.map(|positions| positions.iter());
+    let mut runs = Vec::new();
+    for line in layout.lines() {
+        for item in line.items() {
+            let PositionedLayoutItem::GlyphRun(glyph_run) = item else {
+                continue;
+            };
+            let mut x = glyph_run.offset();
+            let y = glyph_run.baseline();
+            let run = glyph_run.run();
+            let synthesis = run.synthesis();
+            let glyph_transform = synthesis
+                .skew()
+                .map(|angle| Affine::skew(angle.to_radians().tan() as f64, 0.0));
+            let coords = run
+                .normalized_coords()
+                .iter()
+                .map(|coord| NormalizedCoord::from_bits(*coord))
+                .collect::<Vec<_>>();
+            let glyphs = glyph_run
+                .glyphs()
+                .map(|glyph| {
+                    let natural = x + glyph.x;
+                    let gx = positions
+                        .as_mut()
+                        .and_then(|positions| positions.next().copied())
+                        .unwrap_or(natural);
+                    let gy = y - glyph.y;
+                    x += glyph.advance;
+                    vello::Glyph {
+                        id: glyph.id as _,
+                        x: snap(gx),
+                        y: snap(gy),
+                    }
+                })
+                .collect::<Vec<_>>();
+            let font = run.font();
+            let (color_glyphs, glyphs) = match FontRef::from_index(font.data.as_ref(), font.index) {
+                Ok(font) if font.colr().is_ok() => glyphs
+                    .into_iter()
+                    .partition(|glyph| color_glyphs::is_color_glyph(&font, GlyphId::new(glyph.id))),
+                _ => (Vec::new(), glyphs),
+            };
+            runs.push(RunGlyphs {
+                font: font.clone(),
+                font_size: run.font_size(),
+                glyph_transform,
+                coords,
+                brush: glyph_run.style().brush.clone(),
+                hint,
+                glyphs,
+                color_glyphs,
+            });
+        }
+    }
+    runs
+}
+
 struct TargetSurface {
     /// The Vello rendering surface for this texture.
     ///
@@ -299,147 +941,538 @@ struct TargetSurface {
     /// The Android window underlying this target.
     ///
     /// TODO: Why is this here - it is currently unused?
+    #[cfg(target_os = "android")]
     window: NativeWindow,
     /// The style of rendering used for this `Surface`
     kind: SurfaceKind,
+    /// The animations of `kind` which are evaluated on the render thread.
+    animations: Vec<RunningAnimation>,
+    /// The present modes this surface was created with, in order of preference.
+    ///
+    /// These are needed to configure the surface again if its device is recreated.
+    present_modes: Vec<wgpu::PresentMode>,
+    /// Values of `kind` which Kotlin writes directly, if it has registered a parameter block.
+    parameters: Option<ParameterBlock>,
+    /// Whether the size and axes of the text of `kind` are changing, so it shouldn't be hinted.
+    motion: TextMotion,
+}
+
+impl TargetSurface {
+    /// Tell an editor the size of its surface, which it wraps and scrolls its text within.
+    fn update_viewport(&mut self) {
+        if let SurfaceKind::TextEditor(editor) = &mut self.kind {
+            let config = &self.render_surface.config;
+            editor.set_viewport(config.width as f32, config.height as f32);
+        }
+    }
+
+    /// Whether the size or axes of this surface's text are moving in the frame being drawn.
+    fn text_is_moving(&mut self) -> bool {
+        let animating = self.animations.iter().any(|running| {
+            matches!(
+                running.animation.target,
+                AnimationTarget::FontSize | AnimationTarget::Axis(_)
+            )
+        });
+        self.motion.update(self.kind.font_values(), animating)
+    }
 }
 
 impl VelloJni {
-    fn new() -> Self {
+    fn new(config: VelloConfig) -> Self {
         let instance = Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::util::backend_bits_from_env().unwrap_or(wgpu::Backends::PRIMARY),
+            backends: wgpu::util::backend_bits_from_env().unwrap_or(config.backend.backends()),
             dx12_shader_compiler: wgpu::Dx12Compiler::Fxc,
-            flags: InstanceFlags::DEBUG,
+            flags: config.instance_flags(),
             ..Default::default()
         });
-        let cx = RenderContext {
-            instance,
-            devices: Vec::new(),
-        };
+        let cx = GpuContext::new(instance, config.power_preference);
         let mut font_ctx = FontContext::new();
         font_ctx.collection.register_fonts(ROBOTO_FLEX.into());
 
         VelloJni {
             cx,
-            renderer: Default::default(),
+            renderers: Default::default(),
             surfaces: Default::default(),
             // TODO: Install the default font here
             font_ctx,
             layout_ctx: LayoutContext::new(),
             scene: Default::default(),
+            next_frame_id: 0,
+            debug: DebugOverlay::new(),
+            config,
+            gpu_events: Vec::new(),
+            pending_renders: Vec::new(),
+            recovery: DeviceRecovery::default(),
+            presented: Vec::new(),
         }
     }
 
-    fn new_window(&mut self, window: NativeWindow, surface_id: SurfaceId, width: u32, height: u32) {
+    #[cfg(target_os = "android")]
+    /// Create a surface for `window`.
+    ///
+    /// If `present_modes` is `None`, the present modes from the [`VelloConfig`] are used.
+    fn new_window(
+        &mut self,
+        window: NativeWindow,
+        surface_id: SurfaceId,
+        width: u32,
+        height: u32,
+        present_modes: Option<&[wgpu::PresentMode]>,
+    ) -> Result<(), CreateSurfaceError> {
         log::info!("Window Size: {width}x{height}");
         assert!(
             !self.surfaces.contains_key(&surface_id),
             "Tried to use duplicate surface id."
         );
+        let present_modes = present_modes.unwrap_or(&self.config.present_modes);
         let render_surface = pollster::block_on(self.cx.create_surface(
             AndroidWindowHandle {
                 window: window.clone(),
             },
             width,
             height,
-            wgpu::PresentMode::Mailbox,
-        ))
-        .expect("Could create surface");
-        assert_eq!(
-            render_surface.dev_id, 0,
-            "Cannot handle more than one device at a time for MVP."
-        );
+            present_modes,
+        ))?;
         let target_surface = TargetSurface {
             render_surface,
             window,
             kind: SurfaceKind::Unset,
+            animations: Vec::new(),
+            present_modes: present_modes.to_vec(),
+            parameters: None,
+            motion: TextMotion::default(),
         };
         self.surfaces.insert(surface_id, target_surface);
+        Ok(())
     }
 
-    fn perform_render(&mut self, surfaces: &[SurfaceId]) {
-        if surfaces.is_empty() {
+    /// Resize the surface `surface_id`, which must then be rendered before it is shown.
+    fn resize_surface(&mut self, surface_id: SurfaceId, width: u32, height: u32) {
+        let Some(surface) = self.surfaces.get_mut(&surface_id) else {
+            log::warn!("Tried to resize surface {surface_id}, which doesn't exist");
+            return;
+        };
+        if width == 0 || height == 0 {
+            // wgpu can't configure empty surfaces, and there is nothing to show anyway.
             return;
         }
-        self.hydrate_renderer();
-        let renderer = self.renderer.as_mut().unwrap();
-        let mut allocator = SimpleAtlasAllocator::new(Size2D {
-            width: renderer.target_texture.width().try_into().unwrap(),
-            height: renderer.target_texture.height().try_into().unwrap(),
-            ..Default::default()
+        if self.recovery.is_unconfigured(surface_id) {
+            // The surface is configured at its new size once its device supports it.
+            let config = &mut surface.render_surface.config;
+            (config.width, config.height) = (width, height);
+        } else {
+            self.cx
+                .resize_surface(&mut surface.render_surface, width, height);
+        }
+        surface.update_viewport();
+    }
+
+    /// Stop rendering to the surface `surface_id`.
+    ///
+    /// Returns the end notifications of the animations which were running on that surface.
+    fn destroy_surface(&mut self, surface_id: SurfaceId) -> Vec<AnimationEnded> {
+        let Some(surface) = self.surfaces.remove(&surface_id) else {
+            log::warn!("Tried to destroy surface {surface_id}, which doesn't exist");
+            return Vec::new();
+        };
+        self.pending_renders.retain(|id| *id != surface_id);
+        self.recovery.remove_surface(surface_id);
+        surface
+            .animations
+            .iter()
+            .map(|running| running.ended(surface_id, EndReason::Replaced))
+            .collect()
+    }
+
+    /// Attach `animation` to the surface `surface_id`.
+    ///
+    /// Any existing animation of the same target on that surface is replaced, in which case
+    /// this returns the end notification for the replaced animation. If the surface doesn't
+    /// exist, this returns the end notification for `animation_id` itself.
+    fn start_animation(
+        &mut self,
+        surface_id: SurfaceId,
+        animation_id: AnimationId,
+        animation: animation::Animation,
+    ) -> Option<AnimationEnded> {
+        let target = animation.target;
+        let Some(surface) = self.surfaces.get_mut(&surface_id) else {
+            // The surface was destroyed before the animation could start.
+            log::warn!("Tried to animate surface {surface_id}, which doesn't exist");
+            return Some(AnimationEnded {
+                surface_id,
+                animation_id,
+                reason: EndReason::Replaced,
+                value: None,
+            });
+        };
+        let replaced = surface
+            .animations
+            .iter()
+            .position(|running| running.animation.target == target)
+            .map(|index| surface.animations.swap_remove(index));
+        surface
+            .animations
+            .push(RunningAnimation::new(animation_id, animation));
+        replaced.map(|replaced| replaced.ended(surface_id, EndReason::Replaced))
+    }
+
+    /// Stop the animation `animation_id`, leaving its property at the last rendered value.
+    ///
+    /// Returns the end notification for the animation, which holds that value, if it was still
+    /// running.
+    fn cancel_animation(
+        &mut self,
+        surface_id: SurfaceId,
+        animation_id: AnimationId,
+    ) -> Option<AnimationEnded> {
+        let surface = self.surfaces.get_mut(&surface_id)?;
+        let index = surface
+            .animations
+            .iter()
+            .position(|running| running.id == animation_id)?;
+        let cancelled = surface.animations.swap_remove(index);
+        Some(cancelled.ended(surface_id, EndReason::Cancelled))
+    }
+
+    /// Whether any surface has an animation which needs the render thread to produce new frames.
+    fn has_running_animations(&self) -> bool {
+        self.surfaces
+            .values()
+            .any(|surface| !surface.animations.is_empty())
+    }
+
+    /// Apply the values of all running animations at `now` to their surfaces.
+    ///
+    /// The ids of the animated surfaces are added to `to_render` (if not already present),
+    /// and the animations which completed are returned.
+    fn advance_animations(
+        &mut self,
+        now: Instant,
+        to_render: &mut Vec<SurfaceId>,
+    ) -> Vec<AnimationEnded> {
+        let mut ended = Vec::new();
+        for (surface_id, surface) in &mut self.surfaces {
+            if surface.animations.is_empty() {
+                continue;
+            }
+            if !to_render.contains(surface_id) {
+                to_render.push(*surface_id);
+            }
+            let kind = &mut surface.kind;
+            surface.animations.retain_mut(|running| {
+                let (value, finished) = running.sample(now);
+                let target = running.animation.target;
+                kind.apply_animated(target, value);
+                if finished {
+                    ended.push(running.ended(*surface_id, EndReason::Completed));
+                }
+                !finished
+            });
+        }
+        ended
+    }
+
+    /// Apply the values which Kotlin has written to the parameter blocks of `surfaces` since
+    /// they were last read.
+    ///
+    /// This should be called before [`Self::advance_animations`], so that running animations
+    /// take priority over values from Kotlin.
+    fn read_parameter_blocks(&mut self, surfaces: &[SurfaceId]) {
+        for surface_id in surfaces {
+            let Some(surface) = self.surfaces.get_mut(surface_id) else {
+                continue;
+            };
+            if let Some(block) = &mut surface.parameters {
+                surface.kind.apply_parameters(block);
+            }
+        }
+    }
+
+    /// Update the blinking of every caret to `now`.
+    ///
+    /// The ids of the surfaces whose caret appeared or disappeared are added to `to_render`
+    /// (if not already present).
+    fn blink_carets(&mut self, now: Instant, to_render: &mut Vec<SurfaceId>) {
+        for (surface_id, surface) in &mut self.surfaces {
+            let Some(selection) = surface.kind.selection_mut() else {
+                continue;
+            };
+            if selection.blink(now) && !to_render.contains(surface_id) {
+                to_render.push(*surface_id);
+            }
+        }
+    }
+
+    /// The next time after `now` at which a caret appears or disappears, if any are shown.
+    fn next_caret_blink(&mut self, now: Instant) -> Option<Instant> {
+        self.surfaces
+            .values_mut()
+            .filter_map(|surface| surface.kind.selection_mut()?.next_blink(now))
+            .min()
+    }
+
+    /// Whether there are surfaces which need to be rendered again, even without a new request.
+    fn has_pending_renders(&self) -> bool {
+        !self.pending_renders.is_empty()
+    }
+
+    /// Destroy every device, as if they had been lost, so that they are recreated in the next frame.
+    ///
+    /// This is used to test recovery from device loss.
+    fn simulate_device_loss(&mut self) {
+        for device_handle in &self.cx.devices {
+            device_handle.device.destroy();
+            // wgpu may only call the device lost callback once the device is next polled.
+            device_handle.lost.set("Simulated device loss".to_string());
+        }
+        self.pending_renders.extend(self.surfaces.keys());
+    }
+
+    /// Block until every device has finished its submitted work.
+    fn wait_for_gpu(&self) {
+        for device_handle in &self.cx.devices {
+            device_handle.device.poll(wgpu::Maintain::Wait);
+        }
+    }
+
+    /// Whether the device of any of `surfaces` is lost (or hasn't yet been recovered).
+    fn any_device_lost(&self, surfaces: &[SurfaceId]) -> bool {
+        surfaces.iter().any(|id| {
+            self.surfaces.get(id).is_some_and(|surface| {
+                let dev_id = surface.render_surface.dev_id;
+                self.recovery.is_lost(dev_id, &self.cx.devices[dev_id].lost)
+            })
+        })
+    }
+
+    /// Take the ids of the surfaces which were presented in the last frame.
+    fn take_presented(&mut self) -> Vec<SurfaceId> {
+        std::mem::take(&mut self.presented)
+    }
+
+    /// Take the recovery events which have happened since this was last called.
+    fn take_gpu_events(&mut self) -> Vec<GpuEvent> {
+        std::mem::take(&mut self.gpu_events)
+    }
+
+    /// Recreate any devices which have been lost, and their surfaces' configurations.
+    ///
+    /// The surfaces of recovered devices are added to `pending_renders`, as their previous
+    /// contents may have been lost.
+    fn recover_lost_devices(&mut self) {
+        let lost = self
+            .cx
+            .devices
+            .iter()
+            .map(|device_handle| device_handle.lost.clone())
+            .collect::<Vec<_>>();
+        let recreate = |dev_id| {
+            self.renderers.remove(&dev_id);
+            let surface_ids = self
+                .surfaces
+                .iter()
+                .filter(|(_, surface)| surface.render_surface.dev_id == dev_id)
+                .map(|(id, _)| *id)
+                .collect::<Vec<_>>();
+            let compatible_surface = surface_ids
+                .first()
+                .map(|id| &self.surfaces[id].render_surface.surface);
+            if !pollster::block_on(self.cx.recreate_device(dev_id, compatible_surface)) {
+                return None;
+            }
+            let configured = surface_ids.into_iter().map(|surface_id| {
+                let surface = self.surfaces.get_mut(&surface_id).unwrap();
+                (surface_id, configure(&self.cx, surface_id, surface))
+            });
+            Some(configured.collect())
+        };
+        self.recovery.recover(
+            &lost,
+            recreate,
+            &mut self.gpu_events,
+            &mut self.pending_renders,
+        );
+    }
+
+    /// Render `surfaces`, returning how long each phase of rendering took.
+    ///
+    /// Surfaces which need to be rendered again after a failure (see [`Self::has_pending_renders`])
+    /// are also rendered. Returns `None` if there was nothing to render.
+    fn perform_render(&mut self, surfaces: &[SurfaceId]) -> Option<FrameTimings> {
+        self.recover_lost_devices();
+        let mut pending = std::mem::take(&mut self.pending_renders);
+        // Surfaces on devices which are still lost wait until their device is recovered.
+        pending.retain(|id| {
+            self.surfaces.get(id).is_some_and(|surface| {
+                let lost = self.cx.devices[surface.render_surface.dev_id].lost.is_set();
+                if lost {
+                    self.pending_renders.push(*id);
+                }
+                !lost
+            })
+        });
+        let mut surfaces = surfaces.to_vec();
+        for id in pending {
+            if !surfaces.contains(&id) {
+                surfaces.push(id);
+            }
+        }
+        surfaces.retain(|id| {
+            let dev_id = self.surfaces[id].render_surface.dev_id;
+            !self.cx.devices[dev_id].lost.is_set()
         });
-        let final_scene: &mut Scene = &mut self.scene;
-        final_scene.reset();
-        let mut allocations: HashMap<SurfaceId, guillotiere::Rectangle> = HashMap::new();
+        // Surfaces which weren't configured for their recreated device are retried first.
+        self.recovery.configure_pending(
+            &mut surfaces,
+            |surface_id| {
+                let surface = self.surfaces.get_mut(&surface_id).unwrap();
+                configure(&self.cx, surface_id, surface)
+            },
+            &mut self.gpu_events,
+            &mut self.pending_renders,
+        );
+        if surfaces.is_empty() {
+            return None;
+        }
+        let frame_start = Instant::now();
+        let frame_id = self.next_frame_id;
+        self.next_frame_id += 1;
+        let _frame_span =
+            tracing::info_span!("frame", frame_id, surfaces = surfaces.len()).entered();
+        // Each device renders its own atlas, so group the surfaces by device.
+        let mut by_device: BTreeMap<usize, Vec<SurfaceId>> = BTreeMap::new();
+        for surface_id in &surfaces {
+            let dev_id = self.surfaces[surface_id].render_surface.dev_id;
+            by_device.entry(dev_id).or_default().push(*surface_id);
+        }
+        let mut timings = FrameTimings {
+            frame_id,
+            ..Default::default()
+        };
+        for (dev_id, surfaces) in by_device {
+            let _device_span = tracing::info_span!("device", dev_id).entered();
+            self.render_device(dev_id, &surfaces, frame_id, &mut timings);
+        }
+        timings.total = frame_start.elapsed();
+        self.debug.record_frame(frame_start, timings.total);
+        Some(timings)
+    }
+
+    /// Render the atlas of `surfaces`, which are all on the device `dev_id`, and present them.
+    ///
+    /// The time taken by each phase is added to `timings`.
+    fn render_device(
+        &mut self,
+        dev_id: usize,
+        surfaces: &[SurfaceId],
+        frame_id: u64,
+        timings: &mut FrameTimings,
+    ) {
+        let mut phase_start = Instant::now();
+        let mut end_phase = || {
+            let now = Instant::now();
+            let duration = now - phase_start;
+            phase_start = now;
+            duration
+        };
+        self.hydrate_renderer(dev_id);
+        let renderer = self.renderers.get_mut(&dev_id).unwrap();
+        let scene_build_span = tracing::info_span!("scene_build").entered();
         // Render into one big scene atlas.
+        let mut atlas = AtlasBuilder::new(&mut self.scene, renderer.atlas_size());
         for surface_id in surfaces {
-            let surface = self.surfaces.get(surface_id).unwrap();
+            let surface = self.surfaces.get_mut(surface_id).unwrap();
+            let moving = surface.text_is_moving();
             let width = surface.render_surface.config.width;
             let height = surface.render_surface.config.height;
-            let zone = allocator
-                .allocate(Size2D {
-                    width: width.try_into().unwrap(),
-                    height: height.try_into().unwrap(),
-                    ..Default::default()
-                })
-                .expect("Should have room for surface");
-            allocations.insert(*surface_id, zone);
-            final_scene.push_layer(
-                Mix::Clip,
-                1.0,
-                Affine::IDENTITY,
-                &Rect {
-                    x0: zone.min.x.into(),
-                    y0: zone.min.y.into(),
-                    x1: zone.max.x.into(),
-                    y1: zone.max.y.into(),
-                },
+            let _surface_span =
+                tracing::info_span!("surface_scene", surface_id, width, height).entered();
+            let scene = surface.kind.scene(
+                width,
+                height,
+                moving,
+                &mut self.font_ctx,
+                &mut self.layout_ctx,
             );
-            let scene = surface
-                .kind
-                .scene(width, height, &mut self.font_ctx, &mut self.layout_ctx);
-            final_scene.append(
-                &scene,
-                Some(Affine::translate(Vec2::new(
+            if moving && surface.kind.hints_when_still() {
+                // Draw the text hinted once it stops moving, even if nothing asks for a frame.
+                self.pending_renders.push(*surface_id);
+            }
+            atlas.add(*surface_id, width, height, &scene);
+        }
+        let allocations = atlas.allocations;
+        if self.debug.mode != DebugMode::Off {
+            let atlas = AtlasInfo {
+                size: renderer.atlas_size(),
+                allocations: &allocations,
+            };
+            for (surface_id, zone) in &allocations {
+                let Some(overlay) = self.debug.scene(*surface_id, &atlas) else {
+                    continue;
+                };
+                let slot = Rect::new(
                     zone.min.x.into(),
                     zone.min.y.into(),
-                ))),
-            );
-            final_scene.pop_layer();
+                    zone.max.x.into(),
+                    zone.max.y.into(),
+                );
+                self.scene
+                    .push_layer(Mix::Clip, 1.0, Affine::IDENTITY, &slot);
+                self.scene
+                    .append(&overlay, Some(Affine::translate(slot.origin().to_vec2())));
+                self.scene.pop_layer();
+            }
         }
-        let device_handle = &self.cx.devices[0];
-        renderer
-            .renderer
-            .render_to_texture(
-                &device_handle.device,
-                &device_handle.queue,
-                final_scene,
-                &renderer.texture_view,
-                &RenderParams {
-                    antialiasing_method: vello::AaConfig::Area,
-                    base_color: Color::WHITE,
-                    height: renderer.target_texture.height(),
-                    width: renderer.target_texture.width(),
-                },
-            )
-            .unwrap();
+        scene_build_span.exit();
+        timings.scene_build += end_phase();
+        let device_handle = &self.cx.devices[dev_id];
+        let render_span = tracing::info_span!(
+            "vello_render",
+            width = renderer.target_texture.width(),
+            height = renderer.target_texture.height()
+        )
+        .entered();
+        if let Some(timer) = &mut renderer.gpu_timer {
+            timer.begin(&device_handle.device, &device_handle.queue);
+        }
+        renderer.render_atlas(
+            device_handle,
+            &self.scene,
+            self.config.antialiasing.aa_config(),
+        );
+        render_span.exit();
+        timings.render += end_phase();
+        let blit_span = tracing::info_span!("blit").entered();
         let mut encoder = device_handle
             .device
             .create_command_encoder(&CommandEncoderDescriptor { label: None });
-        let mut targets: Vec<SurfaceTexture> = Vec::new();
+        let mut targets: Vec<(SurfaceId, SurfaceTexture)> = Vec::new();
         for (surface_id, range) in allocations {
             let surface = self.surfaces.get(&surface_id).unwrap();
+            let _surface_span = tracing::info_span!(
+                "blit_surface",
+                surface_id,
+                width = range.width(),
+                height = range.height()
+            )
+            .entered();
             let blit = renderer
                 .blit_pipelines
                 .entry(surface.render_surface.format)
                 .or_insert_with(|| {
                     BlitPipeline::new(&device_handle.device, surface.render_surface.format)
                 });
-            let current_texture = surface
-                .render_surface
-                .surface
-                .get_current_texture()
-                .unwrap();
+            let Some(current_texture) = acquire_texture(
+                surface_id,
+                &surface.render_surface,
+                device_handle,
+                &mut self.gpu_events,
+            ) else {
+                self.pending_renders.push(surface_id);
+                continue;
+            };
             blit.blit(
                 device_handle,
                 &renderer.texture_view,
@@ -450,31 +1483,61 @@ impl VelloJni {
                 range.min.y,
                 &mut encoder,
             );
-            targets.push(current_texture);
+            targets.push((surface_id, current_texture));
         }
-        device_handle.queue.submit([encoder.finish()]);
-        for target in targets {
-            target.present();
+        if let Some(timer) = &mut renderer.gpu_timer {
+            timer.end(&mut encoder);
         }
+        blit_span.exit();
+        timings.blit += end_phase();
+        tracing::info_span!("submit").in_scope(|| {
+            device_handle.queue.submit([encoder.finish()]);
+            if let Some(timer) = &mut renderer.gpu_timer {
+                timer.after_submit(frame_id);
+            }
+        });
+        timings.submit += end_phase();
+        tracing::info_span!("present", surfaces = targets.len()).in_scope(|| {
+            for (surface_id, target) in targets {
+                target.present();
+                self.presented.push(surface_id);
+            }
+        });
+        timings.present += end_phase();
         device_handle.device.poll(wgpu::MaintainBase::Poll);
+        if let Some(gpu) = renderer.gpu_timer.as_mut().and_then(GpuTimer::collect) {
+            // Devices run in parallel, so the slowest device determines the GPU time.
+            if timings
+                .gpu
+                .is_none_or(|other| other.duration < gpu.duration)
+            {
+                timings.gpu = Some(gpu);
+            }
+        }
     }
 
-    fn hydrate_renderer(&mut self) {
-        if self.renderer.is_some() {
+    /// Create the renderer for `dev_id`, if it hasn't already been created.
+    fn hydrate_renderer(&mut self, dev_id: usize) {
+        if self.renderers.contains_key(&dev_id) {
             return;
         }
-        let device = &self.cx.devices[0].device;
+        let device_handle = &self.cx.devices[dev_id];
+        let device = &device_handle.device;
         let renderer = Renderer::new(
             device,
             RendererOptions {
                 // We don't use the built-in blit pipeline.
                 surface_format: None,
                 use_cpu: false,
-                antialiasing_support: AaSupport::area_only(),
-                num_init_threads: None,
+                antialiasing_support: self.config.antialiasing.aa_support(),
+                num_init_threads: self.config.init_threads,
             },
         )
         .unwrap();
+        let usage = wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING;
+        // Tests read the atlas back, to check what was drawn.
+        #[cfg(test)]
+        let usage = usage | wgpu::TextureUsages::COPY_SRC;
         let target_texture = device.create_texture(&TextureDescriptor {
             label: Some("VelloJNI Target Texture"),
             size: wgpu::Extent3d {
@@ -486,31 +1549,368 @@ impl VelloJni {
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format: TextureFormat::Rgba8Unorm,
-            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            usage,
             view_formats: &[],
         });
         let texture_view = target_texture.create_view(&wgpu::TextureViewDescriptor::default());
-        self.renderer = Some(RendererResources {
-            renderer,
-            target_texture,
-            blit_pipelines: HashMap::new(),
-            texture_view,
-        })
+        self.renderers.insert(
+            dev_id,
+            RendererResources {
+                renderer,
+                target_texture,
+                blit_pipelines: HashMap::new(),
+                texture_view,
+                gpu_timer: device_handle
+                    .supports_timestamps()
+                    .then(|| GpuTimer::new(device, &device_handle.queue)),
+            },
+        );
+    }
+}
+
+impl CommandHandler for VelloJni {
+    #[cfg(target_os = "android")]
+    fn create_surface(&mut self, surface: NewSurface) -> Result<(), CreateSurfaceError> {
+        self.new_window(
+            surface.window,
+            surface.surface_id,
+            surface.width,
+            surface.height,
+            surface.present_modes.as_deref(),
+        )
+    }
+
+    fn has_surface(&self, surface_id: SurfaceId) -> bool {
+        self.surfaces.contains_key(&surface_id)
+    }
+
+    fn update_surface(&mut self, surface_id: SurfaceId, update: SurfaceUpdate) {
+        match self.surfaces.get_mut(&surface_id) {
+            Some(surface) => {
+                update.apply(&mut surface.kind);
+                surface.update_viewport();
+            }
+            None => log::warn!("Tried to update surface {surface_id}, which doesn't exist"),
+        }
+    }
+
+    fn resize_surface(&mut self, surface_id: SurfaceId, width: u32, height: u32) {
+        Self::resize_surface(self, surface_id, width, height);
+    }
+
+    fn destroy_surface(&mut self, surface_id: SurfaceId) -> Vec<AnimationEnded> {
+        Self::destroy_surface(self, surface_id)
+    }
+
+    fn hit_test(&mut self, surface_id: SurfaceId, x: f32, y: f32) -> Option<HitId> {
+        let surface = self.surfaces.get(&surface_id)?;
+        surface
+            .kind
+            .hit_test(x, y, &mut self.font_ctx, &mut self.layout_ctx)
+    }
+
+    fn set_selection(
+        &mut self,
+        surface_id: SurfaceId,
+        change: SelectionChange,
+    ) -> Option<EditorState> {
+        let Some(surface) = self.surfaces.get_mut(&surface_id) else {
+            log::warn!("Tried to set the selection of surface {surface_id}, which doesn't exist");
+            return None;
+        };
+        surface.kind.set_selection(
+            change,
+            Instant::now(),
+            &mut self.font_ctx,
+            &mut self.layout_ctx,
+        )
+    }
+
+    fn accessibility_tree(&mut self, surface_id: SurfaceId) -> Option<AccessNode> {
+        let surface = self.surfaces.get(&surface_id)?;
+        surface
+            .kind
+            .accessibility_tree(&mut self.font_ctx, &mut self.layout_ctx)
+    }
+
+    fn edit(&mut self, surface_id: SurfaceId, op: EditOp) -> Option<EditorState> {
+        let Some(surface) = self.surfaces.get_mut(&surface_id) else {
+            log::warn!("Tried to edit surface {surface_id}, which doesn't exist");
+            return None;
+        };
+        let SurfaceKind::TextEditor(editor) = &mut surface.kind else {
+            log::warn!("Tried to edit surface {surface_id}, which isn't a text editor");
+            return None;
+        };
+        editor.apply(op, Instant::now(), &mut self.font_ctx, &mut self.layout_ctx);
+        Some(editor.state())
+    }
+
+    fn selected_text(&self, surface_id: SurfaceId) -> Option<SelectedText> {
+        self.surfaces.get(&surface_id)?.kind.selected_text()
+    }
+
+    fn start_animation(
+        &mut self,
+        surface_id: SurfaceId,
+        animation_id: AnimationId,
+        animation: animation::Animation,
+    ) -> Option<AnimationEnded> {
+        Self::start_animation(self, surface_id, animation_id, animation)
     }
+
+    fn cancel_animation(
+        &mut self,
+        surface_id: SurfaceId,
+        animation_id: AnimationId,
+    ) -> Option<AnimationEnded> {
+        Self::cancel_animation(self, surface_id, animation_id)
+    }
+
+    fn set_parameter_block(&mut self, surface_id: SurfaceId, block: ParameterBlock) {
+        match self.surfaces.get_mut(&surface_id) {
+            Some(surface) => surface.parameters = Some(block),
+            None => log::warn!(
+                "Tried to set the parameters of surface {surface_id}, which doesn't exist"
+            ),
+        }
+    }
+
+    fn set_debug_overlay(&mut self, mode: DebugMode) {
+        self.debug.mode = mode;
+    }
+
+    fn simulate_device_loss(&mut self) {
+        Self::simulate_device_loss(self);
+    }
+}
+
+/// Configure `surface` for its device, such as after the device was recreated.
+///
+/// Returns whether it succeeded, which it may not if the device doesn't support the surface.
+fn configure(cx: &GpuContext, surface_id: SurfaceId, surface: &mut TargetSurface) -> bool {
+    cx.configure_surface(&mut surface.render_surface, &surface.present_modes)
+        .inspect_err(|e| log::error!("Couldn't configure surface {surface_id} for its device: {e}"))
+        .is_ok()
 }
 
+/// Get the next texture of `render_surface`, recovering from errors where possible.
+///
+/// Returns `None` if the surface can't be updated in this frame.
+fn acquire_texture(
+    surface_id: SurfaceId,
+    render_surface: &RenderSurface<'_>,
+    device_handle: &DeviceHandle,
+    events: &mut Vec<GpuEvent>,
+) -> Option<SurfaceTexture> {
+    let error = match render_surface.surface.get_current_texture() {
+        Ok(texture) => return Some(texture),
+        Err(error) => error,
+    };
+    match SurfaceAction::for_error(&error) {
+        SurfaceAction::Reconfigure => {
+            log::warn!("Surface {surface_id} failed ({error}), so reconfiguring it");
+            render_surface
+                .surface
+                .configure(&device_handle.device, &render_surface.config);
+            events.push(GpuEvent::SurfaceReconfigured { surface_id });
+            match render_surface.surface.get_current_texture() {
+                Ok(texture) => Some(texture),
+                Err(error) => {
+                    log::warn!(
+                        "Skipping frame of surface {surface_id} after reconfiguring: {error}"
+                    );
+                    events.push(GpuEvent::FrameSkipped { surface_id });
+                    None
+                }
+            }
+        }
+        SurfaceAction::SkipFrame => {
+            log::warn!("Skipping frame of surface {surface_id}: {error}");
+            events.push(GpuEvent::FrameSkipped { surface_id });
+            None
+        }
+        SurfaceAction::RecreateDevice => {
+            device_handle
+                .lost
+                .set(format!("Surface {surface_id} failed: {error}"));
+            events.push(GpuEvent::FrameSkipped { surface_id });
+            None
+        }
+    }
+}
+
+/// Font and layout contexts with Roboto Flex registered, like those of [`VelloJni`].
+#[cfg(test)]
+pub(crate) fn test_contexts() -> (FontContext, LayoutContext) {
+    let mut font_ctx = FontContext::new();
+    font_ctx.collection.register_fonts(ROBOTO_FLEX.into());
+    (font_ctx, LayoutContext::new())
+}
+
+/// A variable font surface showing `text` at `size`, with a weight of 400.
+#[cfg(test)]
+pub(crate) fn test_variable_font(text: &str, size: f32) -> SurfaceKind {
+    let mut kind = SurfaceKind::Unset;
+    SurfaceUpdate::SetVariableFont {
+        text: text.to_string(),
+        size,
+        weight: 400.,
+    }
+    .apply(&mut kind);
+    kind
+}
+
+#[cfg(target_os = "android")]
 pub struct AndroidWindowHandle {
     window: NativeWindow,
 }
 
+#[cfg(target_os = "android")]
 impl HasDisplayHandle for AndroidWindowHandle {
     fn display_handle(&self) -> Result<DisplayHandle<'_>, wgpu::rwh::HandleError> {
         Ok(DisplayHandle::android())
     }
 }
 
+#[cfg(target_os = "android")]
 impl HasWindowHandle for AndroidWindowHandle {
     fn window_handle(&self) -> Result<wgpu::rwh::WindowHandle<'_>, wgpu::rwh::HandleError> {
         self.window.window_handle()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use vello::Scene;
+    use wgpu::{
+        BufferDescriptor, BufferUsages, CommandEncoderDescriptor, ErrorFilter, Extent3d,
+        ImageCopyBuffer, ImageCopyTexture, ImageDataLayout, Maintain, MapMode, Origin3d,
+        TextureAspect,
+    };
+
+    use crate::{
+        config::{GraphicsBackend, VelloConfig},
+        test_contexts, test_variable_font, AtlasBuilder, VelloJni,
+    };
+
+    /// The RGBA pixels of `zone` of the atlas of `dev_id`, row by row.
+    fn read_atlas(vello: &VelloJni, dev_id: usize, zone: guillotiere::Rectangle) -> Vec<u8> {
+        let device_handle = &vello.cx.devices[dev_id];
+        let (width, height) = (zone.width() as u32, zone.height() as u32);
+        let row_len = 4 * width as usize;
+        let padded_row_len = (4 * width).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let buffer = device_handle.device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: u64::from(padded_row_len * height),
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device_handle
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor { label: None });
+        encoder.copy_texture_to_buffer(
+            ImageCopyTexture {
+                texture: &vello.renderers[&dev_id].target_texture,
+                mip_level: 0,
+                origin: Origin3d {
+                    x: zone.min.x as u32,
+                    y: zone.min.y as u32,
+                    z: 0,
+                },
+                aspect: TextureAspect::All,
+            },
+            ImageCopyBuffer {
+                buffer: &buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_len),
+                    rows_per_image: None,
+                },
+            },
+            Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        device_handle.queue.submit([encoder.finish()]);
+        let slice = buffer.slice(..);
+        slice.map_async(MapMode::Read, |result| result.unwrap());
+        device_handle.device.poll(Maintain::Wait);
+        let pixels = slice
+            .get_mapped_range()
+            .chunks(padded_row_len as usize)
+            .flat_map(|row| &row[..row_len])
+            .copied()
+            .collect();
+        pixels
+    }
+
+    /// Whether any pixel in `pixels` is dark, as the text is black on a white background.
+    fn has_text(pixels: &[u8]) -> bool {
+        pixels.chunks(4).any(|pixel| pixel[0] < 128)
+    }
+
+    #[test]
+    fn each_device_renders_its_own_atlas() {
+        // Hosts without a GPU may still have a software GL adapter.
+        let backends = [GraphicsBackend::Primary, GraphicsBackend::Gles];
+        let Some((mut vello, first, second)) = backends.into_iter().find_map(|backend| {
+            let mut vello = VelloJni::new(VelloConfig {
+                backend,
+                ..Default::default()
+            });
+            // The fallback adapter may be the same as the first device's, but the devices are
+            // still separate.
+            let [first, second] = [false, true]
+                .map(|fallback| pollster::block_on(vello.cx.new_test_device(fallback)));
+            Some((vello, first?, second?))
+        }) else {
+            eprintln!("Skipping test, as there are no adapters to create two devices on");
+            return;
+        };
+        let (mut font_ctx, mut layout_ctx) = test_contexts();
+        let surfaces = [
+            (first, vec![(1, "12:34")]),
+            (second, vec![(2, "56"), (3, "78")]),
+        ];
+        let mut scene = Scene::new();
+        let mut allocations = HashMap::new();
+        for (dev_id, surfaces) in &surfaces {
+            vello.hydrate_renderer(*dev_id);
+            let renderer = vello.renderers.get_mut(dev_id).unwrap();
+            let mut atlas = AtlasBuilder::new(&mut scene, renderer.atlas_size());
+            for (surface_id, text) in surfaces {
+                let kind = test_variable_font(text, 40.);
+                let surface = kind.scene(200, 100, false, &mut font_ctx, &mut layout_ctx);
+                atlas.add(*surface_id, 200, 100, &surface);
+            }
+            allocations.extend(atlas.allocations);
+            let device_handle = &vello.cx.devices[*dev_id];
+            device_handle
+                .device
+                .push_error_scope(ErrorFilter::Validation);
+            let antialiasing = vello.config.antialiasing.aa_config();
+            renderer.render_atlas(device_handle, &scene, antialiasing);
+            let error = pollster::block_on(device_handle.device.pop_error_scope());
+            assert!(
+                error.is_none(),
+                "Rendering on device {dev_id} failed: {error:?}"
+            );
+        }
+        assert_eq!(vello.renderers.len(), 2);
+        for (dev_id, surfaces) in &surfaces {
+            for (surface_id, _) in surfaces {
+                let pixels = read_atlas(&vello, *dev_id, allocations[surface_id]);
+                assert!(has_text(&pixels), "Surface {surface_id} wasn't drawn");
+            }
+        }
+        // The first device's atlas only has its own surface, although the second surface of the
+        // second device was drawn at a different place in that device's atlas.
+        let pixels = read_atlas(&vello, first, allocations[&3]);
+        assert!(!has_text(&pixels));
+    }
+}
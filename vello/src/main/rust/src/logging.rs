@@ -0,0 +1,132 @@
+//! Configuration of where our logs go, and of forwarding stdout and stderr into those logs.
+
+use std::sync::{Mutex, RwLock};
+
+use android_logger::{AndroidLogger, Config};
+use log::{LevelFilter, Log, Metadata, Record};
+
+use crate::util::StdioForwarder;
+
+/// How logs from Rust are written to logcat.
+#[derive(Clone, Debug)]
+pub struct LogConfig {
+    /// The level of records whose target doesn't match any of `target_levels`.
+    pub default_level: LevelFilter,
+    /// Levels for specific targets (generally module paths).
+    ///
+    /// These also apply to all targets within that module, with the most specific taking priority.
+    pub target_levels: Vec<(String, LevelFilter)>,
+    /// The logcat tag to use, or `None` to use the module path of each record.
+    pub tag: Option<String>,
+    /// Whether to forward everything written to stdout and stderr into logcat.
+    ///
+    /// This redirects the file descriptors of the whole process, so is disabled by default.
+    pub forward_stdio: bool,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            default_level: LevelFilter::Info,
+            target_levels: Vec::new(),
+            tag: None,
+            forward_stdio: false,
+        }
+    }
+}
+
+impl LogConfig {
+    fn logger(&self) -> AndroidLogger {
+        let mut filter = env_filter::Builder::new();
+        filter.filter_level(self.default_level);
+        for (target, level) in &self.target_levels {
+            filter.filter_module(target, *level);
+        }
+        let mut config = Config::default().with_filter(filter.build());
+        if let Some(tag) = &self.tag {
+            config = config.with_tag(tag.as_str());
+        }
+        AndroidLogger::new(config)
+    }
+
+    /// The most verbose level which any record could be logged at.
+    fn max_level(&self) -> LevelFilter {
+        self.target_levels
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default_level, Ord::max)
+    }
+}
+
+/// The global logger, which can be reconfigured (unlike [`android_logger`]'s own logger).
+struct ConfigurableLogger {
+    inner: RwLock<Option<AndroidLogger>>,
+}
+
+impl Log for ConfigurableLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.inner
+            .read()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|logger| logger.enabled(metadata))
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if let Some(logger) = &*self.inner.read().unwrap() {
+            logger.log(record);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: ConfigurableLogger = ConfigurableLogger {
+    inner: RwLock::new(None),
+};
+
+static STDIO_FORWARDER: Mutex<Option<StdioForwarder>> = Mutex::new(None);
+
+/// Set up (or change) how logs are written.
+///
+/// This can be called multiple times; each call replaces the previous configuration.
+/// If stdio forwarding was enabled and `config` disables it, the original stdout and stderr are
+/// restored.
+pub fn configure(config: &LogConfig) {
+    *LOGGER.inner.write().unwrap() = Some(config.logger());
+    if log::set_logger(&LOGGER).is_err() && !std::ptr::addr_eq(log::logger(), &LOGGER) {
+        // Our records will go to that logger instead, so this is still worth reporting.
+        log::warn!("Another library set up logging first, so Vello's log configuration is unused");
+    }
+    log::set_max_level(config.max_level());
+
+    let mut forwarder = STDIO_FORWARDER.lock().unwrap();
+    match (config.forward_stdio, forwarder.is_some()) {
+        (true, false) => match StdioForwarder::start() {
+            Ok(started) => *forwarder = Some(started),
+            Err(e) => log::error!("Failed to forward stdout and stderr to logcat: {e:?}"),
+        },
+        (false, true) => forwarder.take().unwrap().stop(),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use log::LevelFilter;
+
+    use super::LogConfig;
+
+    #[test]
+    fn max_level_includes_targets() {
+        let mut config = LogConfig::default();
+        assert_eq!(config.max_level(), LevelFilter::Info);
+        config
+            .target_levels
+            .push(("vello_jni::ffi".to_string(), LevelFilter::Trace));
+        config
+            .target_levels
+            .push(("wgpu_core".to_string(), LevelFilter::Error));
+        assert_eq!(config.max_level(), LevelFilter::Trace);
+    }
+}
@@ -0,0 +1,265 @@
+//! Parameter blocks, which let Kotlin change animatable values without a JNI call per value.
+//!
+//! A parameter block is a direct `ByteBuffer` shared between Kotlin and the render thread.
+//! It starts with a sequence number, followed by one `f32` slot for each of its targets, all in
+//! native byte order. Kotlin makes the sequence number odd whilst it writes to the slots, then
+//! even once it is done (a "seqlock"). The render thread reads the slots between two reads of
+//! the sequence number, and only uses the values if the sequence number was even and unchanged.
+//!
+//! Every slot is applied whenever the sequence number changes, so Kotlin must write the current
+//! value of each target to its slot before the block is registered.
+
+#![allow(
+    unsafe_code,
+    reason = "Higher-level deny is intended to be scoped in lib.rs module, but this is a submodule of that"
+)]
+
+use std::{
+    ptr::NonNull,
+    sync::atomic::{fence, AtomicU32, Ordering},
+};
+
+use crate::animation::AnimationTarget;
+
+/// The number of `u32`-sized slots before the values, which hold the sequence number.
+const HEADER_SLOTS: usize = 1;
+
+/// How many times we try to read a block whilst it is being written, before giving up until
+/// the next frame.
+const MAX_READ_ATTEMPTS: usize = 64;
+
+/// Why a buffer couldn't be used as a parameter block.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParameterBlockError {
+    /// The buffer's address isn't aligned to 4 bytes.
+    ///
+    /// Direct buffers allocated by Java are always aligned.
+    Misaligned,
+    /// The buffer is too small for the number of targets; `needed` is in bytes.
+    TooSmall { needed: usize, len: usize },
+}
+
+/// The render thread's view of a parameter block.
+pub struct ParameterBlock {
+    /// The sequence number, followed by the slots.
+    ptr: NonNull<AtomicU32>,
+    targets: Vec<AnimationTarget>,
+    /// The sequence number of the last values we read.
+    last_read: u32,
+    /// Keeps the memory behind `ptr` alive, such as a global reference to the `ByteBuffer`.
+    _owner: Box<dyn Send>,
+}
+
+// Safety: We only access the memory through atomics, and `_owner` keeps it alive.
+unsafe impl Send for ParameterBlock {}
+
+impl ParameterBlock {
+    /// The size in bytes of a block with `targets` slots.
+    pub fn size(targets: usize) -> usize {
+        (HEADER_SLOTS + targets) * size_of::<u32>()
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` must point to `len` bytes which remain valid whilst `owner` is alive, and which
+    /// are only written using the protocol described in the [module docs](self).
+    pub unsafe fn from_raw(
+        ptr: NonNull<u8>,
+        len: usize,
+        targets: Vec<AnimationTarget>,
+        owner: Box<dyn Send>,
+    ) -> Result<Self, ParameterBlockError> {
+        let ptr = ptr.cast::<AtomicU32>();
+        if !ptr.is_aligned() {
+            return Err(ParameterBlockError::Misaligned);
+        }
+        let needed = Self::size(targets.len());
+        if len < needed {
+            return Err(ParameterBlockError::TooSmall { needed, len });
+        }
+        Ok(Self {
+            ptr,
+            targets,
+            last_read: 0,
+            _owner: owner,
+        })
+    }
+
+    pub fn targets(&self) -> &[AnimationTarget] {
+        &self.targets
+    }
+
+    fn slot(&self, index: usize) -> &AtomicU32 {
+        debug_assert!(index < HEADER_SLOTS + self.targets.len());
+        // Safety: `from_raw` checked that the memory is large enough and aligned, and it
+        // is kept alive by `_owner`.
+        unsafe { self.ptr.add(index).as_ref() }
+    }
+
+    /// Read the values of the block, if they have been written since the last read.
+    ///
+    /// The values are in the order of [`Self::targets`]. Returns `None` if nothing changed,
+    /// or if Kotlin was writing to the block throughout our attempts to read it, in which case
+    /// the new values are read in a later frame.
+    pub fn read_if_changed(&mut self) -> Option<Vec<f32>> {
+        let mut values = vec![0.; self.targets.len()];
+        for _ in 0..MAX_READ_ATTEMPTS {
+            let before = self.slot(0).load(Ordering::Acquire);
+            if before == self.last_read {
+                return None;
+            }
+            if before % 2 == 1 {
+                std::hint::spin_loop();
+                continue;
+            }
+            for (i, value) in values.iter_mut().enumerate() {
+                *value = f32::from_bits(self.slot(HEADER_SLOTS + i).load(Ordering::Relaxed));
+            }
+            // Make sure that the values were read before we check that they weren't changed.
+            fence(Ordering::Acquire);
+            if self.slot(0).load(Ordering::Relaxed) == before {
+                self.last_read = before;
+                return Some(values);
+            }
+        }
+        log::debug!("Parameter block was being written to throughout reading");
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ptr::NonNull,
+        sync::{
+            atomic::{fence, AtomicBool, AtomicU32, Ordering},
+            Arc,
+        },
+    };
+
+    use crate::{animation::AnimationTarget, test_variable_font, WGHT};
+
+    use super::{ParameterBlock, ParameterBlockError};
+
+    /// Memory shared with a [`ParameterBlock`], written like Kotlin's `ParameterBlock`.
+    struct Writer(Arc<[AtomicU32]>);
+
+    impl Writer {
+        fn new(targets: usize) -> Self {
+            Self((0..=targets).map(|_| AtomicU32::new(0)).collect())
+        }
+
+        fn block(&self) -> ParameterBlock {
+            self.block_for(vec![AnimationTarget::FontSize; self.0.len() - 1])
+        }
+
+        fn block_for(&self, targets: Vec<AnimationTarget>) -> ParameterBlock {
+            let len = self.0.len() * size_of::<u32>();
+            let ptr = NonNull::from(&self.0[0]).cast();
+            // Safety: The memory is kept alive by the owner, and only written by `write`.
+            unsafe { ParameterBlock::from_raw(ptr, len, targets, Box::new(self.0.clone())) }
+                .unwrap()
+        }
+
+        fn begin(&self) {
+            self.0[0].fetch_add(1, Ordering::Relaxed);
+            fence(Ordering::Release);
+        }
+
+        fn end(&self) {
+            self.0[0].fetch_add(1, Ordering::Release);
+        }
+
+        fn write(&self, values: &[f32]) {
+            self.begin();
+            for (slot, value) in self.0[1..].iter().zip(values) {
+                slot.store(value.to_bits(), Ordering::Relaxed);
+            }
+            self.end();
+        }
+
+        /// Write to the single slot `index`, like `ParameterBlock.set` in Kotlin.
+        fn set(&self, index: usize, value: f32) {
+            self.begin();
+            self.0[1 + index].store(value.to_bits(), Ordering::Relaxed);
+            self.end();
+        }
+    }
+
+    #[test]
+    fn reads_only_completed_writes() {
+        let writer = Writer::new(2);
+        let mut block = writer.block();
+        assert_eq!(block.read_if_changed(), None);
+        writer.write(&[12., 400.]);
+        assert_eq!(block.read_if_changed(), Some(vec![12., 400.]));
+        assert_eq!(block.read_if_changed(), None);
+        // A write in progress isn't read, but is once it completes.
+        writer.begin();
+        assert_eq!(block.read_if_changed(), None);
+        writer.end();
+        assert_eq!(block.read_if_changed(), Some(vec![12., 400.]));
+    }
+
+    #[test]
+    fn concurrent_reads_are_not_torn() {
+        let writer = Writer::new(8);
+        let mut block = writer.block();
+        let done = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let done = done.clone();
+            std::thread::spawn(move || {
+                let mut value = 0.;
+                while !done.load(Ordering::Relaxed) {
+                    value += 1.;
+                    writer.write(&[value; 8]);
+                    std::thread::yield_now();
+                }
+            })
+        };
+        let mut reads = 0;
+        while reads < 1000 {
+            match block.read_if_changed() {
+                Some(values) => {
+                    assert!(values.iter().all(|value| *value == values[0]), "{values:?}");
+                    reads += 1;
+                }
+                None => std::thread::yield_now(),
+            }
+        }
+        done.store(true, Ordering::Relaxed);
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn setting_one_slot_keeps_the_others() {
+        let mut kind = test_variable_font("12:34", 20.);
+        // Kotlin writes the initial values before registering the block.
+        let writer = Writer::new(2);
+        writer.set(0, 20.);
+        writer.set(1, 400.);
+        let mut block =
+            writer.block_for(vec![AnimationTarget::FontSize, AnimationTarget::Axis(WGHT)]);
+        kind.apply_parameters(&mut block);
+        assert_eq!(kind.font_values(), [20., 400.]);
+        writer.set(1, 700.);
+        kind.apply_parameters(&mut block);
+        assert_eq!(kind.font_values(), [20., 700.]);
+        writer.set(0, 32.);
+        kind.apply_parameters(&mut block);
+        assert_eq!(kind.font_values(), [32., 700.]);
+    }
+
+    #[test]
+    fn rejects_small_buffers() {
+        let memory = Arc::<[AtomicU32]>::from([AtomicU32::new(0), AtomicU32::new(0)]);
+        let ptr = NonNull::from(&memory[0]).cast();
+        let targets = vec![AnimationTarget::FontSize; 2];
+        // Safety: The memory is kept alive by the owner.
+        let result = unsafe { ParameterBlock::from_raw(ptr, 8, targets, Box::new(memory.clone())) };
+        assert_eq!(
+            result.err(),
+            Some(ParameterBlockError::TooSmall { needed: 12, len: 8 })
+        );
+    }
+}
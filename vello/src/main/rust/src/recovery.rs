@@ -0,0 +1,355 @@
+//! Recovery from the loss of surfaces and devices.
+//!
+//! Surfaces can become outdated or lost (such as when the window is resized or hidden), and
+//! devices can be lost (such as after a driver reset). In both cases, we recreate what we need
+//! and render again from each surface's stored [`SurfaceKind`](crate::SurfaceKind).
+
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+};
+
+use wgpu::{DeviceLostReason, SurfaceError};
+
+use crate::SurfaceId;
+
+/// An event in the recovery from a lost resource, which is reported to Kotlin.
+///
+/// The discriminants of [`kind`](Self::kind) are used in the FFI, so must match
+/// `GpuEventKind` in Kotlin.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GpuEvent {
+    /// A surface was outdated or lost, and was reconfigured.
+    SurfaceReconfigured { surface_id: SurfaceId },
+    /// A surface wasn't updated this frame, such as because it didn't provide a texture in time,
+    /// or couldn't be configured for a recreated device.
+    FrameSkipped { surface_id: SurfaceId },
+    /// A device was lost, so its surfaces will be rendered again on a new device.
+    DeviceLost { dev_id: usize, reason: String },
+    /// A device was recreated after being lost, and its surfaces were rendered again.
+    DeviceRecovered { dev_id: usize },
+    /// Creating a new device failed, which will be retried in the next frame.
+    DeviceRecoveryFailed { dev_id: usize },
+}
+
+impl GpuEvent {
+    pub fn kind(&self) -> i32 {
+        match self {
+            Self::SurfaceReconfigured { .. } => 0,
+            Self::FrameSkipped { .. } => 1,
+            Self::DeviceLost { .. } => 2,
+            Self::DeviceRecovered { .. } => 3,
+            Self::DeviceRecoveryFailed { .. } => 4,
+        }
+    }
+
+    /// The id of the surface or device which this event is about.
+    pub fn id(&self) -> i64 {
+        match self {
+            Self::SurfaceReconfigured { surface_id } | Self::FrameSkipped { surface_id } => {
+                *surface_id
+            }
+            Self::DeviceLost { dev_id, .. }
+            | Self::DeviceRecovered { dev_id }
+            | Self::DeviceRecoveryFailed { dev_id } => (*dev_id).try_into().unwrap(),
+        }
+    }
+
+    pub fn message(&self) -> Option<&str> {
+        match self {
+            Self::DeviceLost { reason, .. } => Some(reason),
+            _ => None,
+        }
+    }
+}
+
+/// How to handle a failure to get a surface's next texture.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SurfaceAction {
+    /// Configure the surface again, then retry.
+    Reconfigure,
+    /// Don't update this surface in this frame.
+    SkipFrame,
+    /// The device has probably been lost, so needs to be recreated.
+    RecreateDevice,
+}
+
+impl SurfaceAction {
+    pub fn for_error(error: &SurfaceError) -> Self {
+        match error {
+            SurfaceError::Outdated | SurfaceError::Lost => Self::Reconfigure,
+            SurfaceError::Timeout => Self::SkipFrame,
+            SurfaceError::OutOfMemory => Self::RecreateDevice,
+        }
+    }
+}
+
+/// Whether a device has been lost, and why.
+///
+/// This is set from wgpu's device lost callback (which may run on any thread), and checked
+/// before each frame.
+#[derive(Clone, Debug, Default)]
+pub struct DeviceLostFlag(Arc<Mutex<Option<String>>>);
+
+impl DeviceLostFlag {
+    pub fn set(&self, reason: String) {
+        let mut lost = self.0.lock().unwrap();
+        // Keep the first reason, which is the most useful.
+        if lost.is_none() {
+            *lost = Some(reason);
+        }
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.0.lock().unwrap().is_some()
+    }
+
+    /// Clear the flag, returning the reason the device was lost if it was set.
+    pub fn take(&self) -> Option<String> {
+        self.0.lock().unwrap().take()
+    }
+
+    /// A device lost callback which sets this flag.
+    ///
+    /// The callback is also called when we drop the device ourselves, which isn't a loss.
+    pub fn callback(&self) -> impl Fn(DeviceLostReason, String) + Send + 'static {
+        let flag = self.clone();
+        move |reason, message| match reason {
+            DeviceLostReason::Dropped | DeviceLostReason::ReplacedCallback => {}
+            DeviceLostReason::Unknown
+            | DeviceLostReason::Destroyed
+            | DeviceLostReason::DeviceInvalid => {
+                log::error!("Device lost ({reason:?}): {message}");
+                flag.set(format!("{reason:?}: {message}"));
+            }
+        }
+    }
+}
+
+/// The devices which have been reported as lost, but haven't yet been recovered.
+#[derive(Debug, Default)]
+pub struct DeviceRecovery {
+    recovering: HashSet<usize>,
+    /// Surfaces which couldn't be configured for their recreated device.
+    ///
+    /// These keep their configuration for the lost device, so mustn't be presented until they
+    /// have been configured again.
+    unconfigured: HashSet<SurfaceId>,
+}
+
+impl DeviceRecovery {
+    /// Whether the device with `flag` is lost, or hasn't yet been recovered.
+    pub fn is_lost(&self, dev_id: usize, flag: &DeviceLostFlag) -> bool {
+        flag.is_set() || self.recovering.contains(&dev_id)
+    }
+
+    /// Whether `surface_id` needs to be configured before it is rendered.
+    pub fn is_unconfigured(&self, surface_id: SurfaceId) -> bool {
+        self.unconfigured.contains(&surface_id)
+    }
+
+    /// Stop tracking `surface_id`, which has been destroyed.
+    pub fn remove_surface(&mut self, surface_id: SurfaceId) {
+        self.unconfigured.remove(&surface_id);
+    }
+
+    /// Recreate each device whose flag in `lost` (indexed by `dev_id`) is set.
+    ///
+    /// `recreate` replaces the device, returning each of its surfaces and whether it could be
+    /// configured for the new device, or `None` if it couldn't, in which case the device stays
+    /// lost and is retried next time. Surfaces which weren't configured are retried by
+    /// [`configure_pending`](Self::configure_pending).
+    pub fn recover(
+        &mut self,
+        lost: &[DeviceLostFlag],
+        mut recreate: impl FnMut(usize) -> Option<Vec<(SurfaceId, bool)>>,
+        events: &mut Vec<GpuEvent>,
+        pending_renders: &mut Vec<SurfaceId>,
+    ) {
+        for (dev_id, flag) in lost.iter().enumerate() {
+            if !flag.is_set() {
+                continue;
+            }
+            // Each loss is only reported once, however many attempts recovery takes.
+            if self.recovering.insert(dev_id) {
+                let reason = flag.take().unwrap_or_default();
+                // Keep the device marked as lost until it has been replaced.
+                flag.set(reason.clone());
+                log::warn!("Device {dev_id} was lost ({reason}), so recreating it");
+                events.push(GpuEvent::DeviceLost { dev_id, reason });
+            }
+            let Some(surfaces) = recreate(dev_id) else {
+                log::error!("Failed to recreate device {dev_id}; will retry next frame");
+                events.push(GpuEvent::DeviceRecoveryFailed { dev_id });
+                continue;
+            };
+            flag.take();
+            self.recovering.remove(&dev_id);
+            for (surface_id, configured) in surfaces {
+                if !configured {
+                    self.unconfigured.insert(surface_id);
+                    events.push(GpuEvent::FrameSkipped { surface_id });
+                }
+                pending_renders.push(surface_id);
+            }
+            events.push(GpuEvent::DeviceRecovered { dev_id });
+        }
+    }
+
+    /// Configure any of `surfaces` which weren't configured for their recreated device.
+    ///
+    /// `configure` returns whether it succeeded. Surfaces which still aren't configured are removed
+    /// from `surfaces`, and moved to `pending_renders` to be retried next frame.
+    pub fn configure_pending(
+        &mut self,
+        surfaces: &mut Vec<SurfaceId>,
+        mut configure: impl FnMut(SurfaceId) -> bool,
+        events: &mut Vec<GpuEvent>,
+        pending_renders: &mut Vec<SurfaceId>,
+    ) {
+        surfaces.retain(|&surface_id| {
+            if !self.unconfigured.contains(&surface_id) || configure(surface_id) {
+                self.unconfigured.remove(&surface_id);
+                return true;
+            }
+            events.push(GpuEvent::FrameSkipped { surface_id });
+            pending_renders.push(surface_id);
+            false
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wgpu::{DeviceLostReason, SurfaceError};
+
+    use super::{DeviceLostFlag, DeviceRecovery, GpuEvent, SurfaceAction};
+
+    #[test]
+    fn surface_errors() {
+        assert_eq!(
+            SurfaceAction::for_error(&SurfaceError::Outdated),
+            SurfaceAction::Reconfigure
+        );
+        assert_eq!(
+            SurfaceAction::for_error(&SurfaceError::Lost),
+            SurfaceAction::Reconfigure
+        );
+        assert_eq!(
+            SurfaceAction::for_error(&SurfaceError::Timeout),
+            SurfaceAction::SkipFrame
+        );
+        assert_eq!(
+            SurfaceAction::for_error(&SurfaceError::OutOfMemory),
+            SurfaceAction::RecreateDevice
+        );
+    }
+
+    #[test]
+    fn simulated_device_loss_sets_flag() {
+        let flag = DeviceLostFlag::default();
+        let callback = flag.callback();
+        callback(DeviceLostReason::Dropped, "Dropped by us".to_string());
+        assert!(!flag.is_set());
+        callback(DeviceLostReason::Unknown, "Driver reset".to_string());
+        callback(DeviceLostReason::Destroyed, "Later loss".to_string());
+        assert!(flag.is_set());
+        let reason = flag.take().unwrap();
+        assert!(reason.contains("Driver reset"), "{reason}");
+        assert!(!flag.is_set());
+        let event = GpuEvent::DeviceLost { dev_id: 1, reason };
+        assert_eq!((event.kind(), event.id()), (2, 1));
+    }
+
+    #[test]
+    fn lost_devices_are_recovered_once() {
+        let lost = [DeviceLostFlag::default(), DeviceLostFlag::default()];
+        let mut recovery = DeviceRecovery::default();
+        let mut events = Vec::new();
+        let mut pending = Vec::new();
+        let mut attempts = Vec::new();
+        let mut recover = |recovery: &mut DeviceRecovery, succeed: bool| {
+            recovery.recover(
+                &lost,
+                |dev_id| {
+                    attempts.push(dev_id);
+                    succeed.then(|| vec![(10, true), (11, true)])
+                },
+                &mut events,
+                &mut pending,
+            );
+        };
+        recover(&mut recovery, true);
+        lost[1].set("Driver reset".to_string());
+        recover(&mut recovery, false);
+        recover(&mut recovery, false);
+        assert!(recovery.is_lost(1, &lost[1]));
+        assert!(!recovery.is_lost(0, &lost[0]));
+        recover(&mut recovery, true);
+        assert_eq!(attempts, [1, 1, 1]);
+        // The loss is reported once, however many attempts recovery takes.
+        assert_eq!(
+            events,
+            [
+                GpuEvent::DeviceLost {
+                    dev_id: 1,
+                    reason: "Driver reset".to_string()
+                },
+                GpuEvent::DeviceRecoveryFailed { dev_id: 1 },
+                GpuEvent::DeviceRecoveryFailed { dev_id: 1 },
+                GpuEvent::DeviceRecovered { dev_id: 1 },
+            ]
+        );
+        assert_eq!(pending, [10, 11]);
+        assert!(!recovery.is_lost(1, &lost[1]));
+    }
+
+    #[test]
+    fn unconfigured_surfaces_wait_to_be_configured() {
+        let lost = [DeviceLostFlag::default()];
+        lost[0].set("Driver reset".to_string());
+        let mut recovery = DeviceRecovery::default();
+        let mut events = Vec::new();
+        let mut pending = Vec::new();
+        recovery.recover(
+            &lost,
+            |_| Some(vec![(10, true), (11, false)]),
+            &mut events,
+            &mut pending,
+        );
+        assert_eq!(
+            events[1..],
+            [
+                GpuEvent::FrameSkipped { surface_id: 11 },
+                GpuEvent::DeviceRecovered { dev_id: 0 },
+            ]
+        );
+        assert!(recovery.is_unconfigured(11));
+        assert!(!recovery.is_unconfigured(10));
+
+        // Until it is configured, the surface isn't rendered, but is retried each frame.
+        let mut configured = Vec::new();
+        let mut configure_frame = |recovery: &mut DeviceRecovery, succeed: bool| {
+            let mut surfaces = std::mem::take(&mut pending);
+            let mut events = Vec::new();
+            recovery.configure_pending(
+                &mut surfaces,
+                |surface_id| {
+                    configured.push(surface_id);
+                    succeed
+                },
+                &mut events,
+                &mut pending,
+            );
+            (surfaces, events)
+        };
+        assert_eq!(
+            configure_frame(&mut recovery, false),
+            (vec![10], vec![GpuEvent::FrameSkipped { surface_id: 11 }])
+        );
+        assert_eq!(configure_frame(&mut recovery, true), (vec![11], vec![]));
+        assert!(pending.is_empty());
+        assert_eq!(configured, [11, 11]);
+        assert!(!recovery.is_unconfigured(11));
+    }
+}
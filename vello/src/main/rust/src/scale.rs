@@ -0,0 +1,99 @@
+//! The conversion of text sizes to pixels, following the density of the display and the
+//! user's font size setting.
+//!
+//! Font sizes are given in sp, as in Compose, and Parley scales them (and the rest of the
+//! layout's metrics) to pixels when the text is laid out. Everything else, such as the layout
+//! and pointer positions, is in pixels.
+
+/// How text sizes in sp are converted to pixels, which is the `Density` of Compose.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextScale {
+    /// The number of pixels in a dp.
+    pub density: f32,
+    /// The number of dp in an sp, which is the user's font size setting.
+    pub font_scale: f32,
+}
+
+impl Default for TextScale {
+    fn default() -> Self {
+        Self {
+            density: 1.,
+            font_scale: 1.,
+        }
+    }
+}
+
+impl TextScale {
+    /// The number of pixels in an sp, which Parley multiplies font sizes by.
+    pub fn pixels_per_sp(self) -> f32 {
+        self.density * self.font_scale
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{borrow::Cow, time::Instant};
+
+    use crate::{
+        command::SurfaceUpdate, selection::SelectionChange, test_contexts, test_variable_font,
+        SurfaceKind,
+    };
+
+    use super::TextScale;
+
+    /// The width and height of the layout of `kind`.
+    fn size(kind: &SurfaceKind) -> (f32, f32) {
+        let (mut font_ctx, mut layout_ctx) = test_contexts();
+        let layout = kind.layout(&mut font_ctx, &mut layout_ctx).unwrap();
+        (layout.width(), layout.height())
+    }
+
+    #[test]
+    fn text_is_scaled_by_density_and_font_scale() {
+        let mut kind = test_variable_font("12:34", 20.);
+        let (width, height) = size(&kind);
+        SurfaceUpdate::SetTextScale(TextScale {
+            density: 2.,
+            font_scale: 1.5,
+        })
+        .apply(&mut kind);
+        let (scaled_width, scaled_height) = size(&kind);
+        assert!((scaled_width - width * 3.).abs() < 0.1, "{scaled_width}");
+        assert!((scaled_height - height * 3.).abs() < 0.1, "{scaled_height}");
+    }
+
+    #[test]
+    fn editors_are_laid_out_again() {
+        let (mut font_ctx, mut layout_ctx) = test_contexts();
+        let mut kind = SurfaceKind::Unset;
+        SurfaceUpdate::SetTextEditor {
+            text: "12".to_string(),
+            multiline: false,
+            size: 20.,
+        }
+        .apply(&mut kind);
+        let SurfaceKind::TextEditor(editor) = &mut kind else {
+            unreachable!();
+        };
+        // Editing keeps the layout, so the next frame can borrow it.
+        editor.set_selection(
+            SelectionChange::Bytes {
+                anchor: 0,
+                focus: 0,
+            },
+            Instant::now(),
+            &mut font_ctx,
+            &mut layout_ctx,
+        );
+        let layout = editor.layout(&mut font_ctx, &mut layout_ctx);
+        assert!(matches!(layout, Cow::Borrowed(_)));
+        let width = layout.width();
+        SurfaceUpdate::SetTextScale(TextScale {
+            density: 2.,
+            font_scale: 1.,
+        })
+        .apply(&mut kind);
+        let (scaled_width, _) = size(&kind);
+        assert!((scaled_width - width * 2.).abs() < 0.1, "{scaled_width}");
+    }
+}
@@ -0,0 +1,417 @@
+//! Text selection, and the caret, drawn over the text of a surface.
+//!
+//! The selection is stored as byte offsets into the surface's text, as the layout is rebuilt
+//! every frame. Parley's [`Cursor`] is used to map between offsets and positions in the layout.
+//! The caret blinks on the render thread's clock, so that Kotlin doesn't need to request a
+//! frame for each blink.
+
+use std::{
+    ops::Range,
+    time::{Duration, Instant},
+};
+
+use parley::{Affinity, Cursor, Layout};
+use vello::{
+    kurbo::{Affine, Rect},
+    peniko::{Brush, Color, Fill},
+    Scene,
+};
+
+/// How long the caret is shown (and then hidden) for whilst blinking.
+pub const CARET_BLINK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The width of the caret, in pixels.
+const CARET_WIDTH: f32 = 2.;
+
+/// The colour drawn behind selected text.
+const SELECTION_COLOR: Color = Color::rgba8(0x33, 0x99, 0xff, 0x66);
+
+/// The selected range of a surface's text.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextSelection {
+    /// The byte offset where the selection started.
+    pub anchor: usize,
+    /// The byte offset where the selection ends, which is where the caret is drawn.
+    pub focus: usize,
+    /// When the selection last changed, which restarts the caret's blinking.
+    changed_at: Instant,
+    /// Whether the caret is currently in the visible part of its blink.
+    caret_visible: bool,
+}
+
+/// A change to a [`TextSelection`], sent from Kotlin.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SelectionChange {
+    /// Select between two byte offsets into the text.
+    Bytes { anchor: usize, focus: usize },
+    /// Select between two UTF-16 offsets into the text, as used by Kotlin's `String`.
+    Utf16 { anchor: usize, focus: usize },
+    /// Move the focus to the text closest to `(x, y)`, in pixels from the top left of the
+    /// surface. If `extend` is set, the anchor is kept (if there was a selection).
+    Point { x: f32, y: f32, extend: bool },
+    /// Stop showing the selection and caret.
+    Clear,
+}
+
+/// The selection read back by Kotlin, such as to copy it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SelectedText {
+    /// The UTF-16 offsets of the anchor and focus.
+    pub anchor: usize,
+    pub focus: usize,
+    pub text: String,
+}
+
+impl TextSelection {
+    /// A selection from `anchor` to `focus`, which are moved back to character boundaries of
+    /// `text` if needed.
+    pub fn new(text: &str, anchor: usize, focus: usize, now: Instant) -> Self {
+        let anchor = floor_char_boundary(text, anchor);
+        let focus = floor_char_boundary(text, focus);
+        Self {
+            anchor,
+            focus,
+            changed_at: now,
+            caret_visible: anchor == focus,
+        }
+    }
+
+    /// Apply `change` to `selection`, where `layout` is a layout of `text`.
+    ///
+    /// `layout` is only called if it is needed.
+    pub fn apply(
+        selection: Option<Self>,
+        change: SelectionChange,
+        text: &str,
+        layout: impl FnOnce() -> Layout<Brush>,
+        now: Instant,
+    ) -> Option<Self> {
+        match change {
+            SelectionChange::Bytes { anchor, focus } => Some(Self::new(text, anchor, focus, now)),
+            SelectionChange::Utf16 { anchor, focus } => Some(Self::new(
+                text,
+                utf16_to_byte(text, anchor),
+                utf16_to_byte(text, focus),
+                now,
+            )),
+            SelectionChange::Point { x, y, extend } => {
+                // The start of the cursor's text range accounts for which side of the cluster
+                // the point is on.
+                let focus = Cursor::from_point(&layout(), x, y).text_range().start;
+                let anchor = match selection {
+                    Some(selection) if extend => selection.anchor,
+                    _ => focus,
+                };
+                Some(Self::new(text, anchor, focus, now))
+            }
+            SelectionChange::Clear => None,
+        }
+    }
+
+    /// The selected byte range, which is empty if only the caret is shown.
+    pub fn range(&self) -> Range<usize> {
+        self.anchor.min(self.focus)..self.anchor.max(self.focus)
+    }
+
+    /// Whether nothing is selected, in which case the caret is shown.
+    pub fn is_collapsed(&self) -> bool {
+        self.anchor == self.focus
+    }
+
+    /// This selection with its offsets moved by `f`, such as into a layout with inserted marks.
+    #[must_use]
+    pub(crate) fn map(self, f: impl Fn(usize) -> usize) -> Self {
+        Self {
+            anchor: f(self.anchor),
+            focus: f(self.focus),
+            ..self
+        }
+    }
+
+    /// Keep this selection within `text`, after the text has changed.
+    #[must_use]
+    pub fn clamped(self, text: &str) -> Self {
+        Self {
+            anchor: floor_char_boundary(text, self.anchor),
+            focus: floor_char_boundary(text, self.focus),
+            ..self
+        }
+    }
+
+    /// Update the caret's blink to `now`, returning whether its visibility changed.
+    pub fn blink(&mut self, now: Instant) -> bool {
+        let visible = self.is_collapsed() && self.blink_phase(now).is_multiple_of(2);
+        let changed = visible != self.caret_visible;
+        self.caret_visible = visible;
+        changed
+    }
+
+    /// The time after `now` at which the caret next appears or disappears.
+    ///
+    /// This is `None` if the caret isn't shown, as the selection isn't collapsed.
+    pub fn next_blink(&self, now: Instant) -> Option<Instant> {
+        if !self.is_collapsed() {
+            return None;
+        }
+        Some(self.changed_at + CARET_BLINK_INTERVAL * (self.blink_phase(now) + 1))
+    }
+
+    fn blink_phase(&self, now: Instant) -> u32 {
+        let elapsed = now.saturating_duration_since(self.changed_at);
+        (elapsed.as_nanos() / CARET_BLINK_INTERVAL.as_nanos())
+            .try_into()
+            .unwrap_or(u32::MAX)
+    }
+
+    /// The selection as Kotlin sees it.
+    pub fn selected_text(&self, text: &str) -> SelectedText {
+        SelectedText {
+            anchor: byte_to_utf16(text, self.anchor),
+            focus: byte_to_utf16(text, self.focus),
+            text: text[self.range()].to_string(),
+        }
+    }
+
+    /// Whether the caret is currently drawn.
+    pub fn caret_visible(&self) -> bool {
+        self.caret_visible
+    }
+
+    /// Draw the selection highlight and (if visible) the caret, in the coordinates of `layout`
+    /// moved by `transform`.
+    ///
+    /// The highlight is drawn on top of the text, so is translucent.
+    pub fn draw(
+        &self,
+        scene: &mut Scene,
+        transform: Affine,
+        layout: &Layout<Brush>,
+        text: &str,
+        caret: Color,
+    ) {
+        let range = self.range();
+        highlight_rects(layout, &range, |rect| {
+            scene.fill(Fill::NonZero, transform, SELECTION_COLOR, None, &rect);
+        });
+        if self.caret_visible {
+            if let Some(rect) = caret_rect(layout, text, self.focus) {
+                scene.fill(Fill::NonZero, transform, caret, None, &rect);
+            }
+        }
+    }
+}
+
+/// Call `f` with the rectangles covering the clusters of `layout` which start in `range`.
+///
+/// This merges adjacent clusters within each line, in the same way as Parley's `Selection`.
+pub(crate) fn highlight_rects(
+    layout: &Layout<Brush>,
+    range: &Range<usize>,
+    mut f: impl FnMut(Rect),
+) {
+    if range.is_empty() {
+        return;
+    }
+    for line in layout.lines() {
+        let metrics = line.metrics();
+        let rect = |start: f32, end: f32| {
+            Rect::new(
+                start.into(),
+                metrics.min_coord.into(),
+                end.into(),
+                metrics.max_coord.into(),
+            )
+        };
+        let mut x = metrics.offset;
+        let mut start = None;
+        for run in line.runs() {
+            for cluster in run.visual_clusters() {
+                let selected = range.contains(&cluster.text_range().start);
+                match start {
+                    None if selected => start = Some(x),
+                    Some(start_x) if !selected => {
+                        f(rect(start_x, x));
+                        start = None;
+                    }
+                    _ => {}
+                }
+                x += cluster.advance();
+            }
+        }
+        if let Some(start_x) = start {
+            f(rect(start_x, x));
+        }
+    }
+}
+
+/// The Parley cursor which draws the caret at the byte offset `index` of `text`.
+pub(crate) fn caret_cursor(layout: &Layout<Brush>, text: &str, index: usize) -> Cursor {
+    if index >= text.len() {
+        // There is no cluster after the end of the text, so use the trailing edge of the
+        // last one.
+        let last = text.char_indices().next_back().map_or(0, |(i, _)| i);
+        Cursor::from_index(layout, last, Affinity::Upstream)
+    } else {
+        Cursor::from_index(layout, index, Affinity::Downstream)
+    }
+}
+
+/// The rectangle of the caret at the byte offset `index` of `text`, if `layout` has any lines.
+pub(crate) fn caret_rect(layout: &Layout<Brush>, text: &str, index: usize) -> Option<Rect> {
+    caret_cursor(layout, text, index).strong_geometry(layout, CARET_WIDTH)
+}
+
+/// The byte range of the glyph cluster containing the byte offset `index`.
+pub(crate) fn cluster_range(layout: &Layout<Brush>, index: usize) -> Option<Range<usize>> {
+    let cursor = Cursor::from_index(layout, index, Affinity::Downstream);
+    let cluster = cursor.cluster_path().cluster(layout)?;
+    Some(cluster.text_range())
+}
+
+/// The largest character boundary of `text` which is at most `index`.
+fn floor_char_boundary(text: &str, index: usize) -> usize {
+    let mut index = index.min(text.len());
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+/// The byte offset in `text` of the UTF-16 offset `index`.
+///
+/// Offsets within a surrogate pair are moved back to the start of the pair.
+pub fn utf16_to_byte(text: &str, index: usize) -> usize {
+    let mut utf16 = 0;
+    for (byte, c) in text.char_indices() {
+        utf16 += c.len_utf16();
+        if utf16 > index {
+            return byte;
+        }
+    }
+    text.len()
+}
+
+/// The UTF-16 offset of the byte offset `index` in `text`.
+pub fn byte_to_utf16(text: &str, index: usize) -> usize {
+    text[..floor_char_boundary(text, index)]
+        .encode_utf16()
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use parley::Layout;
+    use vello::peniko::Brush;
+
+    use crate::{test_contexts, test_variable_font};
+
+    use super::{
+        byte_to_utf16, highlight_rects, utf16_to_byte, SelectionChange, TextSelection,
+        CARET_BLINK_INTERVAL,
+    };
+
+    fn layout(text: &str) -> Layout<Brush> {
+        let (mut font_ctx, mut layout_ctx) = test_contexts();
+        let kind = test_variable_font(text, 40.);
+        kind.layout(&mut font_ctx, &mut layout_ctx)
+            .unwrap()
+            .into_owned()
+    }
+
+    #[test]
+    fn converts_utf16_offsets() {
+        // "é" is two bytes and one UTF-16 unit, and "𝄞" is four bytes and two UTF-16 units.
+        let text = "aé𝄞b";
+        assert_eq!(utf16_to_byte(text, 0), 0);
+        assert_eq!(utf16_to_byte(text, 2), 3);
+        assert_eq!(utf16_to_byte(text, 3), 3);
+        assert_eq!(utf16_to_byte(text, 4), 7);
+        assert_eq!(utf16_to_byte(text, 100), 8);
+        assert_eq!(byte_to_utf16(text, 3), 2);
+        assert_eq!(byte_to_utf16(text, 5), 2);
+        assert_eq!(byte_to_utf16(text, 8), 5);
+    }
+
+    #[test]
+    fn reads_selected_text() {
+        let now = Instant::now();
+        let text = "aé𝄞b";
+        let never = || unreachable!("Offsets don't need a layout");
+        let selection = TextSelection::apply(
+            None,
+            SelectionChange::Utf16 {
+                anchor: 4,
+                focus: 1,
+            },
+            text,
+            never,
+            now,
+        )
+        .unwrap();
+        assert_eq!(selection.range(), 1..7);
+        let selected = selection.selected_text(text);
+        assert_eq!((selected.anchor, selected.focus), (4, 1));
+        assert_eq!(selected.text, "é𝄞");
+        // Shortening the text keeps the selection within it.
+        assert_eq!(selection.clamped("aé").range(), 1..3);
+    }
+
+    #[test]
+    fn selects_from_points() {
+        let text = "12:34";
+        let layout = layout(text);
+        let now = Instant::now();
+        let y = layout.height() / 2.;
+        let start = TextSelection::apply(
+            None,
+            SelectionChange::Point {
+                x: 1.,
+                y,
+                extend: false,
+            },
+            text,
+            || self::layout(text),
+            now,
+        );
+        assert_eq!(start.map(|selection| selection.range()), Some(0..0));
+        let extended = TextSelection::apply(
+            start,
+            SelectionChange::Point {
+                x: layout.width() - 1.,
+                y,
+                extend: true,
+            },
+            text,
+            || self::layout(text),
+            now,
+        )
+        .unwrap();
+        assert_eq!(extended.selected_text(text).text, "12:34");
+        let mut rects = Vec::new();
+        highlight_rects(&layout, &extended.range(), |rect| rects.push(rect));
+        let [rect] = rects[..] else {
+            panic!("Expected a single rectangle, got {rects:?}");
+        };
+        assert_eq!(rect.width(), f64::from(layout.width()));
+    }
+
+    #[test]
+    fn caret_blinks_only_when_collapsed() {
+        let now = Instant::now();
+        let mut caret = TextSelection::new("12", 1, 1, now);
+        assert!(!caret.blink(now));
+        assert_eq!(caret.next_blink(now), Some(now + CARET_BLINK_INTERVAL));
+        let hidden = now + CARET_BLINK_INTERVAL * 3 / 2;
+        assert!(caret.blink(hidden));
+        assert_eq!(
+            caret.next_blink(hidden),
+            Some(now + CARET_BLINK_INTERVAL * 2)
+        );
+        assert!(!caret.blink(hidden));
+        let mut range = TextSelection::new("12", 0, 2, now);
+        assert!(!range.blink(now));
+        assert_eq!(range.next_blink(hidden), None);
+    }
+}
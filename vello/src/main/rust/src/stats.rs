@@ -0,0 +1,361 @@
+//! Timing of each phase of a frame, and rolling statistics of those timings.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+use wgpu::{
+    Buffer, BufferDescriptor, BufferUsages, CommandEncoder, CommandEncoderDescriptor, Device,
+    QuerySet, QuerySetDescriptor, QueryType, Queue,
+};
+
+/// A part of a frame which we time.
+///
+/// The discriminants are used in the FFI, so must match `FramePhase` in Kotlin.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// Building the scenes for each surface and combining them into the atlas scene.
+    SceneBuild = 0,
+    /// Encoding (and submitting) the Vello render of the atlas, in `render_to_texture`.
+    Render = 1,
+    /// Acquiring each surface's texture and encoding the blit into it.
+    Blit = 2,
+    /// Submitting the blit commands.
+    Submit = 3,
+    /// Presenting each surface.
+    Present = 4,
+    /// The CPU time of the whole frame.
+    Total = 5,
+    /// The GPU time from the start of the Vello render until the end of the blits.
+    Gpu = 6,
+}
+
+impl Phase {
+    pub const ALL: [Self; 7] = [
+        Self::SceneBuild,
+        Self::Render,
+        Self::Blit,
+        Self::Submit,
+        Self::Present,
+        Self::Total,
+        Self::Gpu,
+    ];
+
+    pub fn from_index(index: usize) -> Option<Self> {
+        Self::ALL.get(index).copied()
+    }
+}
+
+/// The timings of a single frame.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameTimings {
+    pub frame_id: u64,
+    pub scene_build: Duration,
+    pub render: Duration,
+    pub blit: Duration,
+    pub submit: Duration,
+    pub present: Duration,
+    pub total: Duration,
+    /// The most recent GPU timing which became available during this frame.
+    ///
+    /// GPU timings are read back asynchronously, so this is generally for an earlier frame.
+    pub gpu: Option<GpuTiming>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct GpuTiming {
+    /// The frame which was measured.
+    pub frame_id: u64,
+    pub duration: Duration,
+}
+
+impl FrameTimings {
+    fn get(&self, phase: Phase) -> Option<Duration> {
+        Some(match phase {
+            Phase::SceneBuild => self.scene_build,
+            Phase::Render => self.render,
+            Phase::Blit => self.blit,
+            Phase::Submit => self.submit,
+            Phase::Present => self.present,
+            Phase::Total => self.total,
+            Phase::Gpu => return self.gpu.map(|gpu| gpu.duration),
+        })
+    }
+}
+
+/// A summary of the recent durations of a [`Phase`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PhaseSummary {
+    pub count: usize,
+    pub mean: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+/// The durations of each [`Phase`] over the last few frames.
+pub struct FrameStatistics {
+    capacity: usize,
+    samples: [VecDeque<Duration>; Phase::ALL.len()],
+}
+
+impl FrameStatistics {
+    /// The number of frames kept by default; 4 seconds at 120fps.
+    pub const DEFAULT_CAPACITY: usize = 480;
+
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "Statistics need room for at least one frame");
+        Self {
+            capacity,
+            samples: std::array::from_fn(|_| VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    pub fn record(&mut self, timings: &FrameTimings) {
+        for phase in Phase::ALL {
+            let Some(duration) = timings.get(phase) else {
+                continue;
+            };
+            let samples = &mut self.samples[phase as usize];
+            if samples.len() == self.capacity {
+                samples.pop_front();
+            }
+            samples.push_back(duration);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for samples in &mut self.samples {
+            samples.clear();
+        }
+    }
+
+    pub fn summary(&self, phase: Phase) -> PhaseSummary {
+        let samples = &self.samples[phase as usize];
+        if samples.is_empty() {
+            return PhaseSummary::default();
+        }
+        let mut sorted = samples.iter().copied().collect::<Vec<_>>();
+        sorted.sort_unstable();
+        let percentile = |p: usize| sorted[((sorted.len() - 1) * p).div_ceil(100)];
+        let count = sorted.len();
+        PhaseSummary {
+            count,
+            mean: sorted.iter().sum::<Duration>() / u32::try_from(count).unwrap(),
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: sorted[count - 1],
+        }
+    }
+
+    /// Count the recent durations of `phase` into `buckets`, each of which is `bucket_width` wide.
+    ///
+    /// Durations beyond the last bucket are counted in the last bucket.
+    pub fn histogram(&self, phase: Phase, bucket_width: Duration, buckets: &mut [u32]) {
+        buckets.fill(0);
+        if buckets.is_empty() || bucket_width.is_zero() {
+            return;
+        }
+        let last = buckets.len() - 1;
+        for sample in &self.samples[phase as usize] {
+            let index = usize::try_from(sample.as_nanos() / bucket_width.as_nanos())
+                .unwrap_or(usize::MAX)
+                .min(last);
+            buckets[index] += 1;
+        }
+    }
+}
+
+impl Default for FrameStatistics {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_CAPACITY)
+    }
+}
+
+/// Measures the GPU time of frames using timestamp queries.
+///
+/// Only one frame is measured at a time; frames which start whilst a previous frame's
+/// results are being read back are not measured.
+pub(crate) struct GpuTimer {
+    query_set: QuerySet,
+    resolve_buffer: Buffer,
+    readback_buffer: Buffer,
+    /// The number of nanoseconds per timestamp tick.
+    period: f32,
+    /// The frame being measured, and whether its readback has been mapped successfully.
+    in_flight: Option<(u64, Arc<OnceLock<bool>>)>,
+    /// Whether timestamps were written in the current frame.
+    recording: bool,
+}
+
+impl GpuTimer {
+    const SIZE: u64 = 2 * size_of::<u64>() as u64;
+
+    pub(crate) fn new(device: &Device, queue: &Queue) -> Self {
+        Self {
+            query_set: device.create_query_set(&QuerySetDescriptor {
+                label: Some("vello_jni.gpu_timer"),
+                ty: QueryType::Timestamp,
+                count: 2,
+            }),
+            resolve_buffer: device.create_buffer(&BufferDescriptor {
+                label: Some("vello_jni.gpu_timer.resolve"),
+                size: Self::SIZE,
+                usage: BufferUsages::QUERY_RESOLVE | BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }),
+            readback_buffer: device.create_buffer(&BufferDescriptor {
+                label: Some("vello_jni.gpu_timer.readback"),
+                size: Self::SIZE,
+                usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            period: queue.get_timestamp_period(),
+            in_flight: None,
+            recording: false,
+        }
+    }
+
+    /// Write the start timestamp of a frame, if we aren't still waiting on an earlier frame.
+    ///
+    /// This must be called before any work of the frame is submitted.
+    pub(crate) fn begin(&mut self, device: &Device, queue: &Queue) {
+        self.recording = self.in_flight.is_none();
+        if !self.recording {
+            return;
+        }
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("vello_jni.gpu_timer.begin"),
+        });
+        encoder.write_timestamp(&self.query_set, 0);
+        queue.submit([encoder.finish()]);
+    }
+
+    /// Write the end timestamp of the frame, into the frame's final encoder.
+    pub(crate) fn end(&mut self, encoder: &mut CommandEncoder) {
+        if !self.recording {
+            return;
+        }
+        encoder.write_timestamp(&self.query_set, 1);
+        encoder.resolve_query_set(&self.query_set, 0..2, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &self.readback_buffer,
+            0,
+            Self::SIZE,
+        );
+    }
+
+    /// Start reading back the timestamps, once the encoder passed to [`end`](Self::end) has
+    /// been submitted.
+    pub(crate) fn after_submit(&mut self, frame_id: u64) {
+        if !self.recording {
+            return;
+        }
+        self.recording = false;
+        let mapped = Arc::new(OnceLock::new());
+        let callback_mapped = mapped.clone();
+        self.readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let _ = callback_mapped.set(result.is_ok());
+            });
+        self.in_flight = Some((frame_id, mapped));
+    }
+
+    /// Get the GPU time of the measured frame, if it has become available.
+    ///
+    /// The device must have been polled for this to make progress.
+    pub(crate) fn collect(&mut self) -> Option<GpuTiming> {
+        let (frame_id, mapped) = self.in_flight.as_ref()?;
+        let success = *mapped.get()?;
+        let frame_id = *frame_id;
+        self.in_flight = None;
+        if !success {
+            log::warn!("Failed to read back GPU timestamps for frame {frame_id}");
+            return None;
+        }
+        let view = self.readback_buffer.slice(..).get_mapped_range();
+        let [start, end]: [u64; 2] = bytemuck::pod_read_unaligned(&view);
+        drop(view);
+        self.readback_buffer.unmap();
+        let ticks = end.saturating_sub(start);
+        Some(GpuTiming {
+            frame_id,
+            duration: Duration::from_nanos((ticks as f64 * f64::from(self.period)) as u64),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{FrameStatistics, FrameTimings, GpuTiming, Phase};
+
+    fn frame(total_ms: u64) -> FrameTimings {
+        FrameTimings {
+            total: Duration::from_millis(total_ms),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn summary_percentiles() {
+        let mut stats = FrameStatistics::new(100);
+        for ms in 1..=100 {
+            stats.record(&frame(ms));
+        }
+        let summary = stats.summary(Phase::Total);
+        assert_eq!(summary.count, 100);
+        assert_eq!(summary.p50, Duration::from_millis(51));
+        assert_eq!(summary.p90, Duration::from_millis(91));
+        assert_eq!(summary.p99, Duration::from_millis(100));
+        assert_eq!(summary.max, Duration::from_millis(100));
+        assert_eq!(summary.mean, Duration::from_micros(50_500));
+    }
+
+    #[test]
+    fn old_frames_are_dropped() {
+        let mut stats = FrameStatistics::new(4);
+        for ms in [50, 50, 1, 2, 3, 4] {
+            stats.record(&frame(ms));
+        }
+        let summary = stats.summary(Phase::Total);
+        assert_eq!(summary.count, 4);
+        assert_eq!(summary.max, Duration::from_millis(4));
+    }
+
+    #[test]
+    fn gpu_only_recorded_when_available() {
+        let mut stats = FrameStatistics::new(10);
+        stats.record(&frame(1));
+        stats.record(&FrameTimings {
+            gpu: Some(GpuTiming {
+                frame_id: 0,
+                duration: Duration::from_millis(3),
+            }),
+            ..frame(1)
+        });
+        assert_eq!(stats.summary(Phase::Total).count, 2);
+        let gpu = stats.summary(Phase::Gpu);
+        assert_eq!(gpu.count, 1);
+        assert_eq!(gpu.max, Duration::from_millis(3));
+    }
+
+    #[test]
+    fn histogram_buckets() {
+        let mut stats = FrameStatistics::new(10);
+        for ms in [0, 1, 4, 5, 9, 100] {
+            stats.record(&frame(ms));
+        }
+        let mut buckets = [0; 3];
+        stats.histogram(Phase::Total, Duration::from_millis(4), &mut buckets);
+        assert_eq!(buckets, [2, 2, 2]);
+    }
+}
@@ -0,0 +1,546 @@
+//! Tracing of the render pipeline, using [`tracing`] spans.
+//!
+//! The spans can be written to any combination of logcat, system trace markers (i.e. ATrace,
+//! which show up in Perfetto), and a Chrome trace file which can be loaded into
+//! `ui.perfetto.dev` or `chrome://tracing` for offline analysis.
+
+use std::{
+    ffi::{CStr, CString},
+    fmt::{Debug, Write as _},
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    Event, Level, Subscriber,
+};
+use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, Layer, Registry};
+
+/// Which subscribers receive the render pipeline's spans.
+#[derive(Clone, Debug, Default)]
+pub struct TraceConfig {
+    /// Log the duration of each span to logcat, at the level of the span.
+    pub logcat: bool,
+    /// Emit system trace markers for each span, where supported.
+    pub system_trace: bool,
+    /// Write each span to a file in the Chrome trace event format.
+    pub chrome_trace_path: Option<String>,
+}
+
+/// The Chrome trace being written, if any, so that it can be finished.
+static CHROME_TRACE: Mutex<Option<ChromeTraceWriter>> = Mutex::new(None);
+
+/// Set up the global tracing subscriber.
+///
+/// This can only be called once per process; later calls log an error.
+pub fn init(config: &TraceConfig) {
+    let logcat = config.logcat.then_some(LogcatLayer);
+    let markers = if config.system_trace {
+        let markers = platform_markers();
+        if markers.is_none() {
+            log::warn!("System trace markers aren't supported on this platform");
+        }
+        markers.map(MarkerLayer::new)
+    } else {
+        None
+    };
+    let chrome = config.chrome_trace_path.as_ref().and_then(|path| {
+        ChromeTraceLayer::file(path)
+            .inspect_err(|e| log::error!("Failed to create Chrome trace at {path:?}: {e:?}"))
+            .ok()
+    });
+    let chrome = chrome.map(|(layer, writer)| {
+        *CHROME_TRACE.lock().unwrap() = Some(writer);
+        layer
+    });
+    let subscriber = Registry::default().with(logcat).with(markers).with(chrome);
+    if let Err(e) = tracing::subscriber::set_global_default(subscriber) {
+        log::error!("Failed to set up tracing: {e}");
+    }
+}
+
+/// Complete the Chrome trace started by [`init`], if any.
+///
+/// Spans which are ended after this are not written.
+pub fn finish_chrome_trace() {
+    if let Some(writer) = CHROME_TRACE.lock().unwrap().take() {
+        if let Err(e) = writer.finish() {
+            log::error!("Failed to finish Chrome trace: {e:?}");
+        }
+    }
+}
+
+/// Formats the fields of a span as ` name=value` pairs.
+#[derive(Default)]
+struct TextFields(String);
+
+impl Visit for TextFields {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        let _ = write!(self.0, " {}={value:?}", field.name());
+    }
+}
+
+/// Logs the duration of each span when it closes.
+pub struct LogcatLayer;
+
+/// The state of a span in the [`LogcatLayer`].
+struct LogcatSpan {
+    fields: TextFields,
+    start: Instant,
+}
+
+fn log_level(level: &Level) -> log::Level {
+    match *level {
+        Level::ERROR => log::Level::Error,
+        Level::WARN => log::Level::Warn,
+        Level::INFO => log::Level::Info,
+        Level::DEBUG => log::Level::Debug,
+        Level::TRACE => log::Level::Trace,
+    }
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for LogcatLayer {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("Span was just created");
+        let mut fields = TextFields::default();
+        attrs.record(&mut fields);
+        span.extensions_mut().insert(LogcatSpan {
+            fields,
+            start: Instant::now(),
+        });
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("Span being recorded exists");
+        let mut extensions = span.extensions_mut();
+        if let Some(state) = extensions.get_mut::<LogcatSpan>() {
+            values.record(&mut state.fields);
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+        let metadata = event.metadata();
+        let mut fields = TextFields::default();
+        event.record(&mut fields);
+        log::log!(
+            target: metadata.target(),
+            log_level(metadata.level()),
+            "{}{}",
+            metadata.name(),
+            fields.0
+        );
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let span = ctx.span(&id).expect("Span being closed exists");
+        let extensions = span.extensions();
+        let Some(state) = extensions.get::<LogcatSpan>() else {
+            return;
+        };
+        let metadata = span.metadata();
+        log::log!(
+            target: metadata.target(),
+            log_level(metadata.level()),
+            "{}{} took {:?}",
+            metadata.name(),
+            state.fields.0,
+            state.start.elapsed()
+        );
+    }
+}
+
+/// A system tracing API, which marks the start and end of sections on the current thread.
+///
+/// On Android, this is ATrace, which is recorded by Perfetto.
+pub trait TraceMarkers: Send + Sync + 'static {
+    /// Whether a trace is being recorded, so markers are worth emitting.
+    fn is_enabled(&self) -> bool;
+    fn begin_section(&self, name: &CStr);
+    /// End the most recently begun section on this thread.
+    fn end_section(&self);
+}
+
+/// The [`TraceMarkers`] of the current platform, if it has any.
+pub fn platform_markers() -> Option<Box<dyn TraceMarkers>> {
+    #[cfg(target_os = "android")]
+    {
+        Some(Box::new(android::ATrace))
+    }
+    #[cfg(not(target_os = "android"))]
+    {
+        None
+    }
+}
+
+/// Emits a [`TraceMarkers`] section whilst each span is entered.
+pub struct MarkerLayer {
+    markers: Box<dyn TraceMarkers>,
+}
+
+impl MarkerLayer {
+    pub fn new(markers: Box<dyn TraceMarkers>) -> Self {
+        Self { markers }
+    }
+}
+
+/// The state of a span in the [`MarkerLayer`].
+struct MarkerSpan {
+    name: CString,
+    /// How many sections we have begun for this span, which we must end.
+    ///
+    /// Tracing can be enabled or disabled whilst a span is entered, so we can't only rely
+    /// on [`TraceMarkers::is_enabled`] to keep sections balanced.
+    begun: u32,
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for MarkerLayer {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("Span was just created");
+        let mut fields = TextFields(attrs.metadata().name().to_string());
+        attrs.record(&mut fields);
+        fields.0.retain(|c| c != '\0');
+        span.extensions_mut().insert(MarkerSpan {
+            name: CString::new(fields.0).expect("Nul bytes were removed"),
+            begun: 0,
+        });
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        if !self.markers.is_enabled() {
+            return;
+        }
+        let span = ctx.span(id).expect("Span being entered exists");
+        let mut extensions = span.extensions_mut();
+        if let Some(state) = extensions.get_mut::<MarkerSpan>() {
+            self.markers.begin_section(&state.name);
+            state.begun += 1;
+        }
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("Span being exited exists");
+        let mut extensions = span.extensions_mut();
+        if let Some(state) = extensions.get_mut::<MarkerSpan>() {
+            if state.begun > 0 {
+                self.markers.end_section();
+                state.begun -= 1;
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "android")]
+mod android {
+    #![allow(
+        unsafe_code,
+        reason = "Higher-level deny is intended to be scoped in lib.rs module, but this is a submodule of that"
+    )]
+
+    use std::ffi::CStr;
+
+    use super::TraceMarkers;
+
+    /// [`TraceMarkers`] using the NDK's ATrace API.
+    pub(super) struct ATrace;
+
+    impl TraceMarkers for ATrace {
+        fn is_enabled(&self) -> bool {
+            // Safety: This function has no preconditions
+            unsafe { ndk_sys::ATrace_isEnabled() }
+        }
+
+        fn begin_section(&self, name: &CStr) {
+            // Safety: `name` is a valid nul-terminated string
+            unsafe { ndk_sys::ATrace_beginSection(name.as_ptr()) }
+        }
+
+        fn end_section(&self) {
+            // Safety: This function has no preconditions
+            unsafe { ndk_sys::ATrace_endSection() }
+        }
+    }
+}
+
+/// Writes spans in the Chrome trace event format, as a duration event when each span is
+/// entered and exited.
+pub struct ChromeTraceLayer {
+    writer: Arc<Mutex<Option<ChromeTraceOutput>>>,
+    start: Instant,
+}
+
+struct ChromeTraceOutput {
+    out: Box<dyn Write + Send>,
+    /// Whether any events have been written, so the next one needs a separating comma.
+    has_events: bool,
+}
+
+/// The handle used to finish writing the trace of a [`ChromeTraceLayer`].
+pub struct ChromeTraceWriter {
+    writer: Arc<Mutex<Option<ChromeTraceOutput>>>,
+}
+
+impl ChromeTraceWriter {
+    /// Complete the trace, and flush it to its output.
+    pub fn finish(self) -> std::io::Result<()> {
+        let Some(mut output) = self.writer.lock().unwrap().take() else {
+            return Ok(());
+        };
+        output.out.write_all(b"\n]\n")?;
+        output.out.flush()
+    }
+}
+
+/// The state of a span in the [`ChromeTraceLayer`].
+struct ChromeTraceSpan {
+    /// The span's fields, as the members of a JSON object.
+    args: JsonFields,
+}
+
+impl ChromeTraceLayer {
+    pub fn new(out: impl Write + Send + 'static) -> std::io::Result<(Self, ChromeTraceWriter)> {
+        let mut out: Box<dyn Write + Send> = Box::new(out);
+        out.write_all(b"[")?;
+        let writer = Arc::new(Mutex::new(Some(ChromeTraceOutput {
+            out,
+            has_events: false,
+        })));
+        Ok((
+            Self {
+                writer: writer.clone(),
+                start: Instant::now(),
+            },
+            ChromeTraceWriter { writer },
+        ))
+    }
+
+    pub fn file(path: impl AsRef<Path>) -> std::io::Result<(Self, ChromeTraceWriter)> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+
+    fn write_event(&self, phase: char, name: &str, args: Option<&str>) {
+        let timestamp = self.start.elapsed().as_secs_f64() * 1_000_000.;
+        let mut event = String::new();
+        event.push_str("{\"name\":");
+        write_json_string(&mut event, name);
+        let _ = write!(
+            event,
+            ",\"ph\":\"{phase}\",\"ts\":{timestamp:.3},\"pid\":{},\"tid\":{}",
+            std::process::id(),
+            thread_index()
+        );
+        if let Some(args) = args {
+            let _ = write!(event, ",\"args\":{{{args}}}");
+        }
+        event.push('}');
+
+        let mut writer = self.writer.lock().unwrap();
+        let Some(output) = &mut *writer else {
+            return;
+        };
+        let separator: &[u8] = if output.has_events { b",\n" } else { b"\n" };
+        output.has_events = true;
+        if let Err(e) = output
+            .out
+            .write_all(separator)
+            .and_then(|()| output.out.write_all(event.as_bytes()))
+        {
+            log::error!("Failed to write to Chrome trace, so stopping it: {e:?}");
+            *writer = None;
+        }
+    }
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for ChromeTraceLayer {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("Span was just created");
+        let mut args = JsonFields::default();
+        attrs.record(&mut args);
+        span.extensions_mut().insert(ChromeTraceSpan { args });
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("Span being recorded exists");
+        let mut extensions = span.extensions_mut();
+        if let Some(state) = extensions.get_mut::<ChromeTraceSpan>() {
+            values.record(&mut state.args);
+        }
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("Span being entered exists");
+        let extensions = span.extensions();
+        let args = extensions
+            .get::<ChromeTraceSpan>()
+            .map(|state| state.args.0.as_str());
+        self.write_event('B', span.name(), args);
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("Span being exited exists");
+        self.write_event('E', span.name(), None);
+    }
+}
+
+/// Formats fields as the members of a JSON object.
+#[derive(Default)]
+struct JsonFields(String);
+
+impl JsonFields {
+    fn key(&mut self, field: &Field) {
+        if !self.0.is_empty() {
+            self.0.push(',');
+        }
+        write_json_string(&mut self.0, field.name());
+        self.0.push(':');
+    }
+}
+
+impl Visit for JsonFields {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        if value.is_finite() {
+            self.key(field);
+            let _ = write!(self.0, "{value}");
+        } else {
+            self.record_debug(field, &value);
+        }
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.key(field);
+        let _ = write!(self.0, "{value}");
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.key(field);
+        let _ = write!(self.0, "{value}");
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.key(field);
+        let _ = write!(self.0, "{value}");
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.key(field);
+        write_json_string(&mut self.0, value);
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.key(field);
+        write_json_string(&mut self.0, &format!("{value:?}"));
+    }
+}
+
+fn write_json_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", u32::from(c));
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// A small, stable id for the current thread, for use as the Chrome trace `tid`.
+fn thread_index() -> u64 {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    thread_local! {
+        static INDEX: u64 = NEXT.fetch_add(1, Ordering::Relaxed);
+    }
+    INDEX.with(|index| *index)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ffi::CStr,
+        io::Write,
+        sync::{Arc, Mutex},
+    };
+
+    use tracing_subscriber::{prelude::*, Registry};
+
+    use super::{ChromeTraceLayer, MarkerLayer, TraceMarkers};
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn chrome_trace_events() {
+        let buffer = SharedBuffer::default();
+        let (layer, writer) = ChromeTraceLayer::new(buffer.clone()).unwrap();
+        tracing::subscriber::with_default(Registry::default().with(layer), || {
+            let _frame = tracing::info_span!("frame", frame_id = 3).entered();
+            let _surface =
+                tracing::info_span!("surface_scene", surface_id = -1, kind = "\"text\"").entered();
+        });
+        writer.finish().unwrap();
+        let trace = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let events = trace.lines().collect::<Vec<_>>();
+        assert_eq!(events.first(), Some(&"["));
+        assert_eq!(events.last(), Some(&"]"));
+        let events = &events[1..events.len() - 1];
+        assert_eq!(events.len(), 4);
+        assert!(events[0].starts_with(r#"{"name":"frame","ph":"B""#));
+        assert!(events[0].ends_with(r#""args":{"frame_id":3}},"#));
+        assert!(events[1].ends_with(r#""args":{"surface_id":-1,"kind":"\"text\""}},"#));
+        assert!(events[2].starts_with(r#"{"name":"surface_scene","ph":"E""#));
+        assert!(events[3].starts_with(r#"{"name":"frame","ph":"E""#));
+    }
+
+    #[derive(Clone, Default)]
+    struct RecordingMarkers(Arc<Mutex<Vec<String>>>);
+
+    impl TraceMarkers for RecordingMarkers {
+        fn is_enabled(&self) -> bool {
+            true
+        }
+
+        fn begin_section(&self, name: &CStr) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(name.to_string_lossy().into_owned());
+        }
+
+        fn end_section(&self) {
+            self.0.lock().unwrap().push("end".to_string());
+        }
+    }
+
+    #[test]
+    fn markers_are_balanced() {
+        let markers = RecordingMarkers::default();
+        let layer = MarkerLayer::new(Box::new(markers.clone()));
+        tracing::subscriber::with_default(Registry::default().with(layer), || {
+            let _frame = tracing::info_span!("frame").entered();
+            let _blit = tracing::info_span!("blit_surface", surface_id = 7, width = 100).entered();
+        });
+        assert_eq!(
+            *markers.0.lock().unwrap(),
+            ["frame", "blit_surface surface_id=7 width=100", "end", "end"]
+        );
+    }
+}
@@ -5,58 +5,114 @@
 
 use std::{
     fs::File,
-    io::{BufRead, BufReader},
-    os::fd::{FromRawFd, RawFd},
-    sync::LazyLock,
+    io::{BufRead, BufReader, Write},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    thread::JoinHandle,
 };
 
-use android_logger::Config;
-
-pub(crate) static INIT: LazyLock<()> = LazyLock::new(|| {
-    abort_on_panic(|| {
-        // TODO: Make this configurable or otherwise optional
-        log::set_max_level(log::LevelFilter::Debug);
-        android_logger::init_once(Config::default().with_max_level(log::LevelFilter::Info));
-        forward_stdio_to_logcat();
-    });
-});
-
-fn forward_stdio_to_logcat() -> std::thread::JoinHandle<std::io::Result<()>> {
-    // XXX: make this stdout/stderr redirection an optional / opt-in feature?...
-
-    // Safety: Trivial from libc function usage
-    let file = unsafe {
-        let mut logpipe: [RawFd; 2] = Default::default();
-        libc::pipe2(logpipe.as_mut_ptr(), libc::O_CLOEXEC);
-        libc::dup2(logpipe[1], libc::STDOUT_FILENO);
-        libc::dup2(logpipe[1], libc::STDERR_FILENO);
-        libc::close(logpipe[1]);
+/// Forwards everything written to stdout and stderr into the log, until [stopped](Self::stop).
+///
+/// This replaces the stdout and stderr file descriptors of the whole process, so affects all
+/// native libraries in the process, not just our own.
+pub(crate) struct StdioForwarder {
+    original_stdout: OwnedFd,
+    original_stderr: OwnedFd,
+    thread: JoinHandle<std::io::Result<()>>,
+}
 
-        File::from_raw_fd(logpipe[0])
-    };
+impl StdioForwarder {
+    pub(crate) fn start() -> std::io::Result<Self> {
+        flush_stdio();
+        // Safety: Trivial from libc function usage. We check each result before using the fds.
+        let (original_stdout, original_stderr, file) = unsafe {
+            let original_stdout = owned_fd(libc::dup(libc::STDOUT_FILENO))?;
+            let original_stderr = owned_fd(libc::dup(libc::STDERR_FILENO))?;
+            let mut logpipe: [RawFd; 2] = Default::default();
+            if libc::pipe2(logpipe.as_mut_ptr(), libc::O_CLOEXEC) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let read = File::from_raw_fd(logpipe[0]);
+            let write = OwnedFd::from_raw_fd(logpipe[1]);
+            if libc::dup2(write.as_raw_fd(), libc::STDOUT_FILENO) < 0
+                || libc::dup2(write.as_raw_fd(), libc::STDERR_FILENO) < 0
+            {
+                let error = std::io::Error::last_os_error();
+                libc::dup2(original_stdout.as_raw_fd(), libc::STDOUT_FILENO);
+                return Err(error);
+            }
+            (original_stdout, original_stderr, read)
+        };
 
-    std::thread::Builder::new()
-        .name("stdio-to-logcat".to_string())
-        .spawn(move || {
-            let mut reader = BufReader::new(file);
-            let mut buffer = String::new();
-            loop {
-                buffer.clear();
-                let len = match reader.read_line(&mut buffer) {
-                    Ok(len) => len,
-                    Err(e) => {
-                        log::error!("Logcat forwarder failed to read stdin/stderr: {e:?}");
-                        break Err(e);
+        let thread = std::thread::Builder::new()
+            .name("stdio-to-logcat".to_string())
+            .spawn(move || {
+                let mut reader = BufReader::new(file);
+                let mut buffer = String::new();
+                loop {
+                    buffer.clear();
+                    let len = match reader.read_line(&mut buffer) {
+                        Ok(len) => len,
+                        Err(e) => {
+                            log::error!("Logcat forwarder failed to read stdin/stderr: {e:?}");
+                            break Err(e);
+                        }
+                    };
+                    if len == 0 {
+                        break Ok(());
+                    } else {
+                        log::info!(target: "VelloStdoutStderr", "{}", buffer.trim_end_matches('\n'));
                     }
-                };
-                if len == 0 {
-                    break Ok(());
-                } else {
-                    log::info!(target: "VelloStdoutStderr", "{buffer}");
                 }
-            }
+            })
+            .expect("Failed to start stdout/stderr to logcat forwarder thread");
+        Ok(Self {
+            original_stdout,
+            original_stderr,
+            thread,
         })
-        .expect("Failed to start stdout/stderr to logcat forwarder thread")
+    }
+
+    /// Restore the original stdout and stderr, and wait for the forwarder to log everything
+    /// written before this was called.
+    ///
+    /// If the forwarded descriptors were duplicated elsewhere (such as into a child process),
+    /// this waits until those duplicates are also closed.
+    pub(crate) fn stop(self) {
+        flush_stdio();
+        // Safety: Trivial from libc function usage
+        unsafe {
+            // This closes the last copies of the write end of the pipe, so the forwarder
+            // thread sees the end of its input.
+            libc::dup2(self.original_stdout.as_raw_fd(), libc::STDOUT_FILENO);
+            libc::dup2(self.original_stderr.as_raw_fd(), libc::STDERR_FILENO);
+        }
+        match self.thread.join() {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => log::warn!("Stdio forwarder stopped with an error: {e:?}"),
+            Err(panic) => log_panic(panic),
+        }
+    }
+}
+
+/// Take ownership of an fd returned from a libc function, which is negative on failure.
+///
+/// # Safety
+///
+/// `fd` must be open and owned by nothing else, if it is non-negative.
+unsafe fn owned_fd(fd: RawFd) -> std::io::Result<OwnedFd> {
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // Safety: Precondition of this function
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// Make sure anything buffered is written to the current stdout and stderr before they change.
+fn flush_stdio() {
+    let _ = std::io::stdout().flush();
+    let _ = std::io::stderr().flush();
+    // Safety: Flushing all C streams is always valid
+    unsafe { libc::fflush(std::ptr::null_mut()) };
 }
 
 pub(crate) fn log_panic(panic: Box<dyn std::any::Any + Send>) {
@@ -87,3 +143,32 @@ pub(crate) fn abort_on_panic<R>(f: impl FnOnce() -> R) -> R {
         std::process::abort();
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use std::os::fd::RawFd;
+
+    use super::StdioForwarder;
+
+    fn inode(fd: RawFd) -> libc::ino_t {
+        // Safety: `stat` is a valid pointer for the duration of the call
+        unsafe {
+            let mut stat = std::mem::zeroed::<libc::stat>();
+            assert_eq!(libc::fstat(fd, &mut stat), 0);
+            stat.st_ino
+        }
+    }
+
+    #[test]
+    fn stdio_forwarding_restores_fds() {
+        let stdout = inode(libc::STDOUT_FILENO);
+        let stderr = inode(libc::STDERR_FILENO);
+        let forwarder = StdioForwarder::start().unwrap();
+        assert_ne!(inode(libc::STDOUT_FILENO), stdout);
+        assert_eq!(inode(libc::STDERR_FILENO), inode(libc::STDOUT_FILENO));
+        // This returning at all shows that the forwarder thread was joined.
+        forwarder.stop();
+        assert_eq!(inode(libc::STDOUT_FILENO), stdout);
+        assert_eq!(inode(libc::STDERR_FILENO), stderr);
+    }
+}
@@ -0,0 +1,181 @@
+//! Sources of vsync timing, used when the render thread paces its own frames.
+//!
+//! When enabled, this lets Vello frames continue even if the main thread (and so the Compose
+//! frame clock) is stalled.
+
+use std::time::{Duration, Instant};
+
+/// A source of display refresh timings.
+///
+/// This is used on the render thread only.
+pub trait VsyncSource {
+    /// Block until the next vsync, returning the time at which it happened.
+    fn wait_for_vsync(&mut self) -> Instant;
+}
+
+/// Create the best [`VsyncSource`] for the current platform.
+///
+/// This must be called on the thread which will use the source.
+pub fn platform_vsync_source() -> Box<dyn VsyncSource> {
+    #[cfg(target_os = "android")]
+    {
+        Box::new(android::ChoreographerVsync::new())
+    }
+    #[cfg(not(target_os = "android"))]
+    {
+        Box::new(TimerVsync::new(TimerVsync::DEFAULT_PERIOD))
+    }
+}
+
+/// A [`VsyncSource`] which approximates a display using a fixed period timer.
+///
+/// This is used where there is no platform vsync source, such as in tests on the host.
+pub struct TimerVsync {
+    period: Duration,
+    next: Option<Instant>,
+}
+
+impl TimerVsync {
+    /// The refresh period of a 60Hz display.
+    pub const DEFAULT_PERIOD: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+    pub fn new(period: Duration) -> Self {
+        assert!(!period.is_zero(), "Vsync period must be non-zero");
+        Self { period, next: None }
+    }
+}
+
+impl VsyncSource for TimerVsync {
+    fn wait_for_vsync(&mut self) -> Instant {
+        let now = Instant::now();
+        let mut next = self.next.unwrap_or(now);
+        if next < now {
+            // Like a real display, if we missed some vsyncs we wait for the next one
+            // rather than producing a burst of frames to catch up.
+            let missed = (now - next).as_nanos() / self.period.as_nanos();
+            next += self.period * u32::try_from(missed).unwrap_or(u32::MAX);
+            if next < now {
+                next += self.period;
+            }
+        }
+        std::thread::sleep(next - now);
+        self.next = Some(next + self.period);
+        next
+    }
+}
+
+#[cfg(target_os = "android")]
+mod android {
+    #![allow(
+        unsafe_code,
+        reason = "Higher-level deny is intended to be scoped in lib.rs module, but this is a submodule of that"
+    )]
+
+    use std::{
+        ffi::c_void,
+        ptr::NonNull,
+        time::{Duration, Instant},
+    };
+
+    use ndk::looper::ThreadLooper;
+
+    use super::VsyncSource;
+
+    /// A [`VsyncSource`] using `AChoreographer`, which must be used on the thread which created it.
+    pub(super) struct ChoreographerVsync {
+        looper: ThreadLooper,
+        choreographer: NonNull<ndk_sys::AChoreographer>,
+    }
+
+    impl ChoreographerVsync {
+        pub(super) fn new() -> Self {
+            // `AChoreographer_getInstance` requires the current thread to have a looper.
+            let looper = ThreadLooper::prepare();
+            // Safety: We just prepared a looper for this thread
+            let choreographer = unsafe { ndk_sys::AChoreographer_getInstance() };
+            Self {
+                looper,
+                choreographer: NonNull::new(choreographer)
+                    .expect("Thread with a looper has a choreographer"),
+            }
+        }
+    }
+
+    unsafe extern "C" fn frame_callback(frame_time_nanos: i64, data: *mut c_void) {
+        // Safety: `data` is the pointer to the `Option<i64>` on the stack of `wait_for_vsync`,
+        // which is still waiting for this callback to run.
+        unsafe { *data.cast::<Option<i64>>() = Some(frame_time_nanos) };
+    }
+
+    impl VsyncSource for ChoreographerVsync {
+        fn wait_for_vsync(&mut self) -> Instant {
+            let mut frame_time: Option<i64> = None;
+            // Safety: We poll the looper until the callback has run, so `frame_time` outlives it.
+            unsafe {
+                ndk_sys::AChoreographer_postFrameCallback64(
+                    self.choreographer.as_ptr(),
+                    Some(frame_callback),
+                    std::ptr::from_mut(&mut frame_time).cast(),
+                );
+            }
+            let frame_time = loop {
+                self.looper
+                    .poll_once()
+                    .expect("Polling the render thread's looper shouldn't fail");
+                if let Some(frame_time) = frame_time {
+                    break frame_time;
+                }
+            };
+            // The frame time is in `CLOCK_MONOTONIC`, as is `Instant`, but we can't
+            // construct an `Instant` directly.
+            let now = Instant::now();
+            let mut timespec = libc::timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            };
+            // Safety: `timespec` is a valid pointer
+            unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut timespec) };
+            #[allow(
+                clippy::useless_conversion,
+                reason = "`time_t` and `c_long` are 32 bits on 32-bit Android targets"
+            )]
+            let now_nanos =
+                i64::from(timespec.tv_sec) * 1_000_000_000 + i64::from(timespec.tv_nsec);
+            let since_vsync = u64::try_from(now_nanos - frame_time).unwrap_or(0);
+            now.checked_sub(Duration::from_nanos(since_vsync))
+                .unwrap_or(now)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{TimerVsync, VsyncSource};
+
+    #[test]
+    fn timer_vsync_is_paced() {
+        let period = Duration::from_millis(5);
+        let mut vsync = TimerVsync::new(period);
+        let first = vsync.wait_for_vsync();
+        let second = vsync.wait_for_vsync();
+        let third = vsync.wait_for_vsync();
+        assert_eq!(second - first, period);
+        assert_eq!(third - second, period);
+        assert!(Instant::now() >= third);
+    }
+
+    #[test]
+    fn timer_vsync_skips_missed_frames() {
+        let period = Duration::from_millis(5);
+        let mut vsync = TimerVsync::new(period);
+        let first = vsync.wait_for_vsync();
+        std::thread::sleep(period * 3 + period / 2);
+        let next = vsync.wait_for_vsync();
+        let elapsed = next - first;
+        // We should land on a vsync boundary after the stall, rather than the one just after `first`.
+        assert!(elapsed >= period * 4, "{elapsed:?}");
+        assert_eq!(elapsed.as_nanos() % period.as_nanos(), 0);
+    }
+}
@@ -0,0 +1,303 @@
+#!/usr/bin/env python3
+"""Build ComplexScripts.ttf, the test font for Devanagari and Thai shaping.
+
+The glyphs are boxes, but the font has the layout tables which make a shaper reorder a
+Devanagari pre-base vowel sign, form a conjunct, and stack Thai marks. See README.md.
+
+Run from this directory, using only the Python standard library:
+
+    python3 build_font.py
+"""
+
+import struct
+
+UNITS_PER_EM = 1000
+ASCENT = 900
+DESCENT = -300
+# The height of the anchors which marks above the base are attached to.
+MARK_HEIGHT = 700
+# How far a mark above another mark is raised.
+MARK_STACK = 200
+
+# (name, code point, advance, kind), where marks are drawn above their anchor at the origin.
+GLYPHS = [
+    (".notdef", None, 500, "base"),
+    ("space", 0x20, 250, "space"),
+    ("ka", 0x915, 600, "base"),
+    ("na", 0x928, 600, "base"),
+    ("ma", 0x92E, 600, "base"),
+    ("sa", 0x938, 600, "base"),
+    ("ta", 0x924, 600, "base"),
+    ("virama", 0x94D, 0, "mark"),
+    ("iMatra", 0x93F, 300, "base"),
+    ("aaMatra", 0x93E, 300, "base"),
+    ("eMatra", 0x947, 0, "mark"),
+    ("sa_ta", None, 800, "base"),
+    ("ba", 0x92C, 600, "base"),
+    ("koKai", 0xE01, 550, "base"),
+    ("thoThahan", 0xE17, 550, "base"),
+    ("noNu", 0xE19, 550, "base"),
+    ("saraIi", 0xE35, 0, "mark"),
+    ("maiEk", 0xE48, 0, "mark"),
+    ("saraAm", 0xE33, 450, "base"),
+]
+GID = {name: gid for gid, (name, _, _, _) in enumerate(GLYPHS)}
+MARKS = [gid for gid, glyph in enumerate(GLYPHS) if glyph[3] == "mark"]
+# The glyphs which marks above the base are attached to.
+BASES = [
+    GID[name]
+    for name in ["ka", "na", "ma", "sa", "ta", "sa_ta", "ba", "koKai", "thoThahan", "noNu"]
+]
+# Marks which other marks are stacked on.
+MARK_BASES = [GID["saraIi"]]
+
+
+def outline(advance, kind):
+    """The rectangle drawn for a glyph, as (x_min, y_min, x_max, y_max), or None."""
+    if kind == "space":
+        return None
+    if kind == "mark":
+        return (-100, MARK_HEIGHT + 50, 100, MARK_HEIGHT + 150)
+    return (50, 0, advance - 50, 600)
+
+
+def table_glyf():
+    glyf = b""
+    loca = []
+    for _, _, advance, kind in GLYPHS:
+        loca.append(len(glyf))
+        rect = outline(advance, kind)
+        if rect is None:
+            continue
+        x0, y0, x1, y1 = rect
+        # A single clockwise contour, with every point on the curve.
+        points = [(x0, y0), (x0, y1), (x1, y1), (x1, y0)]
+        data = struct.pack(">hhhhh", 1, x0, y0, x1, y1)
+        data += struct.pack(">HH", len(points) - 1, 0)
+        data += bytes([0x01] * len(points))
+        previous = (0, 0)
+        xs, ys = b"", b""
+        for x, y in points:
+            xs += struct.pack(">h", x - previous[0])
+            ys += struct.pack(">h", y - previous[1])
+            previous = (x, y)
+        data += xs + ys
+        glyf += data + b"\0" * (-len(data) % 4)
+    loca.append(len(glyf))
+    return glyf, b"".join(struct.pack(">I", offset) for offset in loca)
+
+
+def table_cmap():
+    mapping = sorted((code, gid) for gid, (_, code, _, _) in enumerate(GLYPHS) if code is not None)
+    # One segment per character, followed by the required final segment.
+    segments = [(code, code, (gid - code) % 0x10000) for code, gid in mapping]
+    segments.append((0xFFFF, 0xFFFF, 1))
+    count = len(segments)
+    search_range = 2 * (1 << (count.bit_length() - 1))
+    entry_selector = search_range.bit_length() - 2
+    subtable = struct.pack(
+        ">HHHHHHH", 4, 16 + 8 * count, 0, 2 * count, search_range, entry_selector,
+        2 * count - search_range,
+    )
+    subtable += b"".join(struct.pack(">H", end) for _, end, _ in segments)
+    subtable += struct.pack(">H", 0)
+    subtable += b"".join(struct.pack(">H", start) for start, _, _ in segments)
+    subtable += b"".join(struct.pack(">H", delta) for _, _, delta in segments)
+    subtable += b"".join(struct.pack(">H", 0) for _ in segments)
+    # The same subtable for Unicode and Windows platforms.
+    return struct.pack(">HHHHIHHI", 0, 2, 0, 3, 20, 3, 1, 20) + subtable
+
+
+def table_hmtx():
+    return b"".join(
+        struct.pack(">Hh", advance, rect[0] if rect else 0)
+        for _, _, advance, kind in GLYPHS
+        for rect in [outline(advance, kind)]
+    )
+
+
+def table_name():
+    names = [
+        (1, "Complex Scripts Test"),
+        (2, "Regular"),
+        (3, "Complex Scripts Test Regular"),
+        (4, "Complex Scripts Test Regular"),
+        (6, "ComplexScriptsTest-Regular"),
+    ]
+    records, strings = b"", b""
+    for name_id, text in names:
+        encoded = text.encode("utf-16-be")
+        records += struct.pack(">HHHHHH", 3, 1, 0x409, name_id, len(encoded), len(strings))
+        strings += encoded
+    return struct.pack(">HHH", 0, len(names), 6 + len(records)) + records + strings
+
+
+def table_os2():
+    codes = [code for _, code, _, _ in GLYPHS if code is not None]
+    return struct.pack(
+        ">HhHHH10hh10s4I4sHHHhhhHH2IhhHHH",
+        4, 500, 400, 5, 0,
+        650, 700, 0, 140, 650, 700, 0, 480, 50, 250,
+        0, bytes(10),
+        # Basic Latin, Devanagari and Thai.
+        (1 << 0) | (1 << 15) | (1 << 24), 0, 0, 0,
+        b"NONE", 0x40, min(codes), max(codes),
+        ASCENT, DESCENT, 0, ASCENT, -DESCENT,
+        1, 0,
+        500, 600, 0, 0x20, 3,
+    )
+
+
+def coverage(glyphs):
+    return struct.pack(">HH", 1, len(glyphs)) + b"".join(struct.pack(">H", g) for g in glyphs)
+
+
+def anchor(x, y):
+    return struct.pack(">Hhh", 1, x, y)
+
+
+def layout_table(features, lookups):
+    """A GSUB or GPOS table, with `features` (tag, lookup indices) under the default script."""
+    lang_sys = struct.pack(">HHH", 0, 0xFFFF, len(features))
+    lang_sys += b"".join(struct.pack(">H", index) for index in range(len(features)))
+    script = struct.pack(">HH", 4, 0) + lang_sys
+    script_list = struct.pack(">H4sH", 1, b"DFLT", 8) + script
+
+    feature_list = struct.pack(">H", len(features))
+    feature_tables = b""
+    offset = 2 + 6 * len(features)
+    for tag, indices in features:
+        feature_list += struct.pack(">4sH", tag, offset + len(feature_tables))
+        feature_tables += struct.pack(">HH", 0, len(indices))
+        feature_tables += b"".join(struct.pack(">H", index) for index in indices)
+    feature_list += feature_tables
+
+    lookup_list = struct.pack(">H", len(lookups))
+    lookup_tables = b""
+    offset = 2 + 2 * len(lookups)
+    for lookup_type, subtable in lookups:
+        lookup_list += struct.pack(">H", offset + len(lookup_tables))
+        lookup = struct.pack(">HHHH", lookup_type, 0, 1, 8) + subtable
+        lookup_tables += lookup + b"\0" * (len(lookup) % 2)
+    lookup_list += lookup_tables
+
+    header_size = 10
+    return (
+        struct.pack(
+            ">IHHH", 0x00010000, header_size, header_size + len(script_list),
+            header_size + len(script_list) + len(feature_list),
+        )
+        + script_list
+        + feature_list
+        + lookup_list
+    )
+
+
+def table_gsub():
+    # "स्त" forms a conjunct.
+    cover = coverage([GID["sa"]])
+    ligature = struct.pack(">HHHH", GID["sa_ta"], 3, GID["virama"], GID["ta"])
+    ligature_set = struct.pack(">HH", 1, 4) + ligature
+    subtable = struct.pack(">HHHH", 1, 8, 1, 8 + len(cover)) + cover + ligature_set
+    return layout_table([(b"cjct", [0])], [(4, subtable)])
+
+
+def attachment(marks, mark_anchor, bases, base_anchor):
+    """A mark-to-base or mark-to-mark subtable, with a single class of marks."""
+    mark_cover = coverage(marks)
+    base_cover = coverage(bases)
+    mark_array = struct.pack(">H", len(marks))
+    mark_array += b"".join(struct.pack(">HH", 0, 2 + 4 * len(marks)) for _ in marks)
+    mark_array += anchor(*mark_anchor)
+    base_array = struct.pack(">H", len(bases))
+    anchors = b""
+    for base in bases:
+        base_array += struct.pack(">H", 2 + 2 * len(bases) + len(anchors))
+        anchors += anchor(*base_anchor(base))
+    base_array += anchors
+    offset = 12
+    return (
+        struct.pack(
+            ">HHHHHH", 1, offset, offset + len(mark_cover), 1,
+            offset + len(mark_cover) + len(base_cover),
+            offset + len(mark_cover) + len(base_cover) + len(mark_array),
+        )
+        + mark_cover
+        + base_cover
+        + mark_array
+        + base_array
+    )
+
+
+def table_gpos():
+    # Marks are centred above their base, and Thai tone marks are stacked above vowel signs.
+    mark = attachment(
+        MARKS, (0, MARK_HEIGHT), BASES, lambda base: (GLYPHS[base][2] // 2, MARK_HEIGHT)
+    )
+    mkmk = attachment(
+        [GID["maiEk"]], (0, MARK_HEIGHT), MARK_BASES, lambda _: (0, MARK_HEIGHT + MARK_STACK)
+    )
+    return layout_table([(b"mark", [0]), (b"mkmk", [1])], [(4, mark), (6, mkmk)])
+
+
+def table_gdef():
+    classes = [3 if kind == "mark" else 1 for _, _, _, kind in GLYPHS]
+    class_def = struct.pack(">HHH", 1, 0, len(classes))
+    class_def += b"".join(struct.pack(">H", c) for c in classes)
+    return struct.pack(">IHHHH", 0x00010000, 12, 0, 0, 0) + class_def
+
+
+def checksum(data):
+    data += b"\0" * (-len(data) % 4)
+    return sum(struct.unpack(f">{len(data) // 4}I", data)) & 0xFFFFFFFF
+
+
+def build():
+    glyf, loca = table_glyf()
+    rects = [outline(advance, kind) for _, _, advance, kind in GLYPHS]
+    rects = [rect for rect in rects if rect]
+    bounds = [min(r[0] for r in rects), min(r[1] for r in rects)]
+    bounds += [max(r[2] for r in rects), max(r[3] for r in rects)]
+    tables = {
+        "GDEF": table_gdef(),
+        "GPOS": table_gpos(),
+        "GSUB": table_gsub(),
+        "OS/2": table_os2(),
+        "cmap": table_cmap(),
+        "glyf": glyf,
+        "head": struct.pack(
+            ">IIIIHHqqhhhhHHhhh", 0x00010000, 0x00010000, 0, 0x5F0F3CF5, 0x3, UNITS_PER_EM,
+            0, 0, *bounds, 0, 8, 2, 1, 0,
+        ),
+        "hhea": struct.pack(
+            ">IhhhHhhhhhhhhhhhH", 0x00010000, ASCENT, DESCENT, 0,
+            max(advance for _, _, advance, _ in GLYPHS), bounds[0], 0, bounds[2], 1, 0, 0,
+            0, 0, 0, 0, 0, len(GLYPHS),
+        ),
+        "hmtx": table_hmtx(),
+        "loca": loca,
+        "maxp": struct.pack(">IH13H", 0x00010000, len(GLYPHS), 4, 1, 0, 0, 2, *[0] * 8),
+        "name": table_name(),
+        "post": struct.pack(">IihhI4I", 0x00030000, 0, -100, 50, 0, 0, 0, 0, 0),
+    }
+    count = len(tables)
+    search_range = 16 * (1 << (count.bit_length() - 1))
+    font = struct.pack(
+        ">IHHHH", 0x00010000, count, search_range, (count.bit_length() - 1),
+        16 * count - search_range,
+    )
+    offset = 12 + 16 * count
+    body = b""
+    for tag, data in tables.items():
+        font += struct.pack(">4sIII", tag.encode(), checksum(data), offset + len(body), len(data))
+        body += data + b"\0" * (-len(data) % 4)
+    font += body
+    # Set `checksumAdjustment` in `head`, so that the whole font sums to the magic number.
+    (head,) = struct.unpack_from(">I", font, 12 + 16 * list(tables).index("head") + 8)
+    adjustment = (0xB1B0AFBA - checksum(font)) & 0xFFFFFFFF
+    return font[: head + 8] + struct.pack(">I", adjustment) + font[head + 12 :]
+
+
+if __name__ == "__main__":
+    with open("ComplexScripts.ttf", "wb") as file:
+        file.write(build())
@@ -0,0 +1,47 @@
+/**
+ * Stands in for the Kotlin {@code Vello} object in the tests of the notifications from the render
+ * thread, counting the calls to each of its callbacks.
+ */
+public final class RecordingListener {
+    public int calls;
+
+    public void onAnimationEnded(long surfaceId, long animationId, int reason, float[] value) {
+        calls++;
+    }
+
+    public void onPointerHit(long surfaceId, long pointerId, int kind, float x, float y, long hit) {
+        calls++;
+    }
+
+    public void onEditorChanged(
+            long surfaceId, String text, int anchor, int focus, int composingStart, int composingEnd) {
+        calls++;
+    }
+
+    public void onGpuEvent(int kind, long id, String message) {
+        calls++;
+    }
+
+    public void onFramePresented(
+            long frameId,
+            long[] requestIds,
+            long[] surfaceIds,
+            boolean coalesced,
+            long presentNanos,
+            long totalNanos) {
+        calls++;
+    }
+
+    public void onFrameTimings(
+            long frameId,
+            long sceneBuildNanos,
+            long renderNanos,
+            long blitNanos,
+            long submitNanos,
+            long presentNanos,
+            long totalNanos,
+            long gpuFrameId,
+            long gpuNanos) {
+        calls++;
+    }
+}